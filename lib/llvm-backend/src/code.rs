@@ -837,7 +837,10 @@ pub unsafe extern "C" fn callback_trampoline(
     callback: *mut BreakpointHandler,
 ) {
     let callback = Box::from_raw(callback);
-    let result: Result<(), Box<dyn std::any::Any>> = callback(BreakpointInfo { fault: None });
+    let result: Result<(), Box<dyn std::any::Any>> = callback(BreakpointInfo::new(
+        None,
+        wasmer_runtime_core::fault::get_current_ctx(),
+    ));
     match result {
         Ok(()) => *b = None,
         Err(e) => *b = Some(e),
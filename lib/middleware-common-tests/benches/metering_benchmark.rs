@@ -221,5 +221,30 @@ fn bench_metering(c: &mut Criterion) {
     );
 }
 
-criterion_group!(benches, bench_metering);
+/// A module with a single function made of a long straight run of operators.
+fn large_function_wat() -> String {
+    format!(
+        "(module (func (export \"nops\") {}))",
+        "nop ".repeat(100_000)
+    )
+}
+
+fn bench_metering_compile(c: &mut Criterion) {
+    let wasm_binary = wat2wasm(large_function_wat()).unwrap();
+    let metering_wasm_binary = wasm_binary.clone();
+
+    c.bench(
+        "Compile large function",
+        Benchmark::new("No Metering", move |b| {
+            let compiler = get_compiler(0, false);
+            b.iter(|| black_box(compile_with(&wasm_binary, &compiler).unwrap()))
+        })
+        .with_function("Built-in Metering", move |b| {
+            let compiler = get_compiler(std::u64::MAX, true);
+            b.iter(|| black_box(compile_with(&metering_wasm_binary, &compiler).unwrap()))
+        }),
+    );
+}
+
+criterion_group!(benches, bench_metering, bench_metering_compile);
 criterion_main!(benches);
@@ -3,26 +3,36 @@ mod tests {
     use wabt::wat2wasm;
 
     use wasmer_middleware_common::metering::*;
-    use wasmer_runtime_core::codegen::{MiddlewareChain, StreamingCompiler};
+    #[cfg(any(feature = "llvm", feature = "singlepass"))]
+    use wasmer_runtime_core::codegen::MiddlewareChain;
+    use wasmer_runtime_core::codegen::{FunctionMiddleware, StreamingCompiler};
     use wasmer_runtime_core::{backend::Compiler, compile_with, imports, Func};
 
     #[cfg(feature = "llvm")]
-    fn get_compiler(limit: u64) -> impl Compiler {
+    fn get_compiler_with<M, F>(middleware: F) -> impl Compiler
+    where
+        M: FunctionMiddleware + 'static,
+        F: Fn() -> M + 'static,
+    {
         use wasmer_llvm_backend::ModuleCodeGenerator as LLVMMCG;
         let c: StreamingCompiler<LLVMMCG, _, _, _, _> = StreamingCompiler::new(move || {
             let mut chain = MiddlewareChain::new();
-            chain.push(Metering::new(limit));
+            chain.push(middleware());
             chain
         });
         c
     }
 
     #[cfg(feature = "singlepass")]
-    fn get_compiler(limit: u64) -> impl Compiler {
+    fn get_compiler_with<M, F>(middleware: F) -> impl Compiler
+    where
+        M: FunctionMiddleware + 'static,
+        F: Fn() -> M + 'static,
+    {
         use wasmer_singlepass_backend::ModuleCodeGenerator as SinglePassMCG;
         let c: StreamingCompiler<SinglePassMCG, _, _, _, _> = StreamingCompiler::new(move || {
             let mut chain = MiddlewareChain::new();
-            chain.push(Metering::new(limit));
+            chain.push(middleware());
             chain
         });
         c
@@ -32,12 +42,20 @@ mod tests {
     compile_error!("compiler not specified, activate a compiler via features");
 
     #[cfg(feature = "clif")]
-    fn get_compiler(_limit: u64) -> impl Compiler {
+    fn get_compiler_with<M, F>(_middleware: F) -> impl Compiler
+    where
+        M: FunctionMiddleware + 'static,
+        F: Fn() -> M + 'static,
+    {
         compile_error!("cranelift does not implement metering");
         use wasmer_clif_backend::CraneliftCompiler;
         CraneliftCompiler::new()
     }
 
+    fn get_compiler(limit: u64) -> impl Compiler {
+        get_compiler_with(move || Metering::new(limit))
+    }
+
     // Assemblyscript
     // export function add_to(x: i32, y: i32): i32 {
     //    for(var i = 0; i < x; i++){
@@ -148,4 +166,3766 @@ mod tests {
         // verify it used the correct number of points
         assert_eq!(get_points_used(&instance), 109); // Used points will be slightly more than `limit` because of the way we do gas checking.
     }
+
+    // call_indirect with an index that is out of bounds for the 1-element table
+    static WAT_CALL_INDIRECT_OOB: &'static str = r#"
+        (module
+          (type $t0 (func))
+          (func $f0 (type $t0))
+          (func $call_oob (export "call_oob") (type $t0)
+            i32.const 5
+            call_indirect (type $t0))
+          (table $table 1 anyfunc)
+          (elem (i32.const 0) $f0))
+        "#;
+
+    #[test]
+    fn test_call_indirect_surcharge_charged_on_trap() {
+        use wasmer_runtime_core::error::RuntimeError;
+        let wasm_binary = wat2wasm(WAT_CALL_INDIRECT_OOB).unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).with_call_indirect_cost(10)),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 0u64);
+
+        let call_oob: Func<(), ()> = instance.func("call_oob").unwrap();
+        let err = call_oob.call().unwrap_err();
+        match err {
+            RuntimeError::Trap { .. } => {}
+            _ => unreachable!(),
+        }
+
+        // `i32.const` and `call_indirect` cost one point each, plus the surcharge.
+        assert_eq!(get_points_used(&instance), 2 + 10);
+    }
+
+    #[test]
+    fn test_on_limit_exceeded_fires_before_trap() {
+        use std::sync::{Arc, Mutex};
+        use wasmer_runtime_core::error::RuntimeError;
+        let wasm_binary = wat2wasm(WAT).unwrap();
+
+        let limit = 100u64;
+
+        let module = compile_with(&wasm_binary, &get_compiler(limit)).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 0u64);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        set_on_limit_exceeded(instance.context_mut(), move |func_index, overrun| {
+            events_clone.lock().unwrap().push((func_index, overrun));
+        });
+
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        let err = add_to.call(10_000_000, 4).unwrap_err();
+        match err {
+            RuntimeError::Error { data } => {
+                assert!(data.downcast_ref::<ExecutionLimitExceededError>().is_some());
+            }
+            _ => unreachable!(),
+        }
+
+        // `add_to` is function 0 and the trap fires with 109 points used.
+        assert_eq!(*events.lock().unwrap(), vec![(0, 9)]);
+    }
+
+    static WAT_UNREACHABLE: &'static str = r#"
+        (module
+          (type $t0 (func))
+          (func $work_then_trap (export "work_then_trap") (type $t0)
+            i32.const 1
+            i32.const 2
+            i32.add
+            drop
+            unreachable))
+        "#;
+
+    #[test]
+    fn test_work_before_unreachable_is_charged() {
+        use wasmer_runtime_core::error::RuntimeError;
+        let wasm_binary = wat2wasm(WAT_UNREACHABLE).unwrap();
+
+        let module = compile_with(&wasm_binary, &get_compiler(100)).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 0u64);
+
+        let work_then_trap: Func<(), ()> = instance.func("work_then_trap").unwrap();
+        let err = work_then_trap.call().unwrap_err();
+        match err {
+            RuntimeError::Trap { .. } => {}
+            _ => unreachable!(),
+        }
+
+        // Four operators of work plus the `unreachable` itself.
+        assert_eq!(get_points_used(&instance), 5);
+    }
+
+    static WAT_NESTED_CALLS: &'static str = r#"
+        (module
+          (type $t0 (func))
+          (func $inner (type $t0)
+            i32.const 1
+            drop)
+          (func $outer (export "outer") (type $t0)
+            call $inner
+            call $inner))
+        "#;
+
+    #[test]
+    fn test_last_call_cost_includes_nested_calls() {
+        let wasm_binary = wat2wasm(WAT_NESTED_CALLS).unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).with_last_call_cost()),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 5u64);
+
+        let outer: Func<(), ()> = instance.func("outer").unwrap();
+        outer.call().unwrap();
+
+        // Two calls of one point each, two `inner` bodies of three points each and the final
+        // `end` of `outer`.
+        assert_eq!(get_points_used(&instance), 14);
+        assert_eq!(last_call_cost(instance.context()), 9);
+
+        // Resetting the points used keeps the cost of the last call.
+        set_points_used(&mut instance, 0u64);
+        assert_eq!(last_call_cost(instance.context()), 9);
+    }
+
+    #[test]
+    fn test_points_of_large_function() {
+        let wat = format!(
+            "(module (func (export \"nops\") {}))",
+            "nop ".repeat(100_000)
+        );
+        let wasm_binary = wat2wasm(wat).unwrap();
+
+        let module = compile_with(&wasm_binary, &get_compiler(200_000)).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 0u64);
+
+        let nops: Func<(), ()> = instance.func("nops").unwrap();
+        nops.call().unwrap();
+
+        // The whole body is one block, charged at once before its `end`.
+        assert_eq!(get_points_used(&instance), 100_001);
+    }
+
+    #[test]
+    fn test_strip_reserved_sections() {
+        use wasmer_middleware_common::cost_table::{embedded_cost_table, CostTable};
+
+        let wasm_binary = wat2wasm(r#"(module (func (export "nop")))"#).unwrap();
+        // A custom section of another name, `x`, is kept.
+        let custom = [0x00, 0x02, 0x01, b'x'];
+        let wasm_binary = [&wasm_binary[..], &custom].concat();
+        let forged = embed_default_limit(&CostTable::new(0).embed(&wasm_binary), u64::max_value());
+        assert_eq!(strip_reserved_sections(&forged), wasm_binary);
+
+        let module = compile_with(
+            &strip_reserved_sections(&forged),
+            &get_compiler_with(Metering::with_runtime_limit),
+        )
+        .unwrap();
+        assert_eq!(embedded_default_limit(module.info()), None);
+        assert_eq!(embedded_cost_table(module.info()), None);
+        assert!(module.info().custom_sections.contains_key("x"));
+    }
+
+    #[test]
+    fn test_cost_table_prices_and_is_embedded() {
+        use wasmer_middleware_common::cost_table::{embedded_cost_table, CostTable};
+        use wasmer_middleware_common::opcode::OpcodeKind;
+
+        let wasm_binary = wat2wasm(r#"(module (func (export "nops") nop nop nop))"#).unwrap();
+        let table = CostTable::default().with_cost(OpcodeKind::Nop, 5);
+
+        let metering_table = table.clone();
+        let module = compile_with(
+            &table.embed(&wasm_binary),
+            &get_compiler_with(move || Metering::new(100).with_cost_table(metering_table.clone())),
+        )
+        .unwrap();
+
+        let embedded = embedded_cost_table(module.info()).unwrap();
+        assert_eq!(CostTable::from_bytes(embedded).unwrap(), table);
+        assert_eq!(
+            CostTable::cost_from_bytes(embedded, OpcodeKind::Nop).unwrap(),
+            5
+        );
+        assert_eq!(
+            CostTable::cost_from_bytes(embedded, OpcodeKind::End).unwrap(),
+            table.cost(OpcodeKind::End)
+        );
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 0u64);
+
+        let nops: Func<(), ()> = instance.func("nops").unwrap();
+        nops.call().unwrap();
+
+        // Three nops of five points each and the final `end`.
+        assert_eq!(get_points_used(&instance), 16);
+    }
+
+    #[test]
+    fn test_batched_flush() {
+        use std::sync::{Arc, Mutex};
+        let wasm_binary = wat2wasm(WAT).unwrap();
+
+        let flushes = Arc::new(Mutex::new(Vec::new()));
+        let flushes_clone = flushes.clone();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || {
+                let flushes = flushes_clone.clone();
+                Metering::new(std::u64::MAX).with_batched_flush(1000, move |_ctx, points| {
+                    flushes.lock().unwrap().push(points)
+                })
+            }),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 0u64);
+
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(238, 4).unwrap();
+
+        // Each iteration of the loop costs 21 points, on top of 11 points for the rest.
+        assert_eq!(get_points_used(&instance), 5009);
+
+        // Four full batches, then the rest when the function returns.
+        let flushes = flushes.lock().unwrap();
+        assert_eq!(flushes.len(), 5);
+        assert!(flushes[..4].iter().all(|&points| points >= 1000));
+        assert_eq!(flushes.iter().sum::<u64>(), 5009);
+    }
+
+    #[test]
+    fn test_total_configured_limit() {
+        let wasm_binary = wat2wasm(WAT).unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(Metering::with_runtime_limit),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instances: Vec<TrackedInstance> = [100u64, 200, 300]
+            .iter()
+            .map(|&limit| {
+                let mut instance =
+                    TrackedInstance::new(module.instantiate(&import_object).unwrap());
+                instance.set_execution_limit(limit);
+                instance
+            })
+            .collect();
+        assert_eq!(total_configured_limit(&module), 600);
+
+        drop(instances.remove(1));
+        assert_eq!(total_configured_limit(&module), 400);
+
+        instances.clear();
+        assert_eq!(total_configured_limit(&module), 0);
+    }
+
+    #[test]
+    fn test_cost_table_from_toml() {
+        use wasmer_middleware_common::{cost_table::CostTable, opcode::OpcodeKind};
+
+        let table = CostTable::from_toml(
+            r#"
+            base_cost = 2
+
+            [costs]
+            "i32.add" = 3
+            "memory.grow" = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(table.base_cost(), 2);
+        assert_eq!(table.cost(OpcodeKind::I32Add), 3);
+        assert_eq!(table.cost(OpcodeKind::MemoryGrow), 100);
+        assert_eq!(table.cost(OpcodeKind::Nop), 2);
+
+        let table = CostTable::from_toml("[costs]\nnop = 0\n").unwrap();
+        assert_eq!(table.base_cost(), 1);
+        assert_eq!(table.cost(OpcodeKind::Nop), 0);
+
+        let error = CostTable::from_toml("[costs]\n\"i32.frobnicate\" = 1\n").unwrap_err();
+        assert!(
+            error.contains("unknown opcode `i32.frobnicate`"),
+            "{}",
+            error
+        );
+        assert!(CostTable::from_toml("[costs]\nnop = -1\n").is_err());
+    }
+
+    // Writes a byte to each of the first `n` pages of a 10-page memory.
+    static WAT_SCATTER: &'static str = r#"
+        (module
+          (type $t0 (func (param i32)))
+          (func $scatter (export "scatter") (type $t0) (param $n i32)
+            block $B0
+              loop $L1
+                get_local $n
+                i32.eqz
+                br_if $B0
+                get_local $n
+                i32.const 1
+                i32.sub
+                tee_local $n
+                i32.const 65536
+                i32.mul
+                i32.const 1
+                i32.store8
+                br $L1
+              end
+            end)
+          (memory $memory 10))
+        "#;
+
+    #[test]
+    fn test_working_set_limit() {
+        use wasmer_middleware_common::working_set::*;
+        use wasmer_runtime_core::error::RuntimeError;
+        let wasm_binary = wat2wasm(WAT_SCATTER).unwrap();
+
+        let module =
+            compile_with(&wasm_binary, &get_compiler_with(|| WorkingSetLimit::new(4))).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        let scatter: Func<i32> = instance.func("scatter").unwrap();
+        scatter.call(4).unwrap();
+        assert_eq!(get_working_set_size(&instance), 4);
+
+        // Touching the same pages again does not grow the working set.
+        scatter.call(4).unwrap();
+        assert_eq!(get_working_set_size(&instance), 4);
+
+        match scatter.call(6).unwrap_err() {
+            RuntimeError::Error { data } => {
+                assert!(data
+                    .downcast_ref::<WorkingSetLimitExceededError>()
+                    .is_some());
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(get_working_set_size(&instance), 4);
+
+        reset_working_set(&mut instance);
+        assert_eq!(get_working_set_size(&instance), 0);
+        let scatter: Func<i32> = instance.func("scatter").unwrap();
+        scatter.call(3).unwrap();
+        assert_eq!(get_working_set_size(&instance), 3);
+    }
+
+    static WAT_EXPLAIN: &'static str = r#"
+        (module
+          (type $t0 (func (param i32) (result i32)))
+          (func $double_then_id (export "double_then_id") (type $t0) (param $p0 i32) (result i32)
+            get_local $p0
+            i32.const 2
+            i32.mul
+            call $id)
+          (func $id (type $t0) (param $p0 i32) (result i32)
+            get_local $p0))
+        "#;
+
+    #[test]
+    fn test_explain_matches_charged_points() {
+        use wasmer_middleware_common::{cost_table::CostTable, opcode::OpcodeKind};
+        let wasm_binary = wat2wasm(WAT_EXPLAIN).unwrap();
+
+        let table = CostTable::new(1)
+            .with_cost(OpcodeKind::I32Mul, 3)
+            .with_cost(OpcodeKind::Call, 10);
+        let costs = explain(
+            &wasm_binary,
+            Metering::new(100).with_cost_table(table.clone()),
+        )
+        .unwrap();
+
+        // local.get, i32.const, i32.mul, call and end, then local.get and end.
+        let opcodes: Vec<u8> = costs
+            .iter()
+            .map(|&(offset, _)| wasm_binary[offset])
+            .collect();
+        assert_eq!(opcodes, vec![0x20, 0x41, 0x6c, 0x10, 0x0b, 0x20, 0x0b]);
+        let points: Vec<u64> = costs.iter().map(|&(_, points)| points).collect();
+        assert_eq!(points, vec![1, 1, 3, 10, 1, 1, 1]);
+
+        // Every operator runs once, so the call is charged the sum of the costs.
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || Metering::new(100).with_cost_table(table.clone())),
+        )
+        .unwrap();
+        let import_object = imports! {};
+        let instance = module.instantiate(&import_object).unwrap();
+        let double_then_id: Func<i32, i32> = instance.func("double_then_id").unwrap();
+        assert_eq!(double_then_id.call(21).unwrap(), 42);
+        assert_eq!(get_points_used(&instance), points.iter().sum::<u64>());
+
+        // The surcharges are added to the operators they apply to. The `end` of the block is
+        // skipped by the `br_table`, so it is free for the call to be charged the sum.
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func (param i32) (result i32)))
+              (table 1 anyfunc)
+              (elem (i32.const 0) $id)
+              (func $dispatch (export "dispatch") (type $t0) (param $p0 i32) (result i32)
+                block
+                  get_local $p0
+                  br_table 0 0 0
+                end
+                get_local $p0
+                i32.const 0
+                call_indirect (type $t0))
+              (func $id (type $t0) (param $p0 i32) (result i32)
+                get_local $p0))
+            "#,
+        )
+        .unwrap();
+        let table = CostTable::new(1).with_cost(OpcodeKind::End, 0);
+        let metering = move || {
+            Metering::new(100)
+                .with_cost_table(table.clone())
+                .with_call_indirect_cost(5)
+                .with_br_table_target_cost(2)
+        };
+        let costs = explain(&wasm_binary, metering()).unwrap();
+
+        // block, local.get, br_table and end, then local.get, i32.const, call_indirect and end,
+        // then local.get and end.
+        let opcodes: Vec<u8> = costs
+            .iter()
+            .map(|&(offset, _)| wasm_binary[offset])
+            .collect();
+        assert_eq!(
+            opcodes,
+            vec![0x02, 0x20, 0x0e, 0x0b, 0x20, 0x41, 0x11, 0x0b, 0x20, 0x0b]
+        );
+        let points: Vec<u64> = costs.iter().map(|&(_, points)| points).collect();
+        // The `br_table` has 2 targets besides its default.
+        assert_eq!(points, vec![1, 1, 1 + 2 * 2, 0, 1, 1, 1 + 5, 0, 1, 0]);
+
+        let module = compile_with(&wasm_binary, &get_compiler_with(metering)).unwrap();
+        let instance = module.instantiate(&import_object).unwrap();
+        let dispatch: Func<i32, i32> = instance.func("dispatch").unwrap();
+        assert_eq!(dispatch.call(7).unwrap(), 7);
+        assert_eq!(get_points_used(&instance), points.iter().sum::<u64>());
+    }
+
+    #[test]
+    fn test_countdown() {
+        use wasmer_runtime_core::error::RuntimeError;
+        // 597 nops and a call charged at the call, then the called function and both `end`s.
+        let wat = format!(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $spend (export "spend") (type $t0)
+                {}
+                call $f1)
+              (func $f1 (type $t0)))
+            "#,
+            "nop\n".repeat(597)
+        );
+        let wasm_binary = wat2wasm(wat).unwrap();
+
+        let module = compile_with(&wasm_binary, &get_compiler_with(Metering::countdown)).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_remaining(instance.context_mut(), 1000);
+
+        let spend: Func<(), ()> = instance.func("spend").unwrap();
+        spend.call().unwrap();
+        assert_eq!(get_remaining(instance.context()), 400);
+
+        // The budget runs out before the call, which traps.
+        match spend.call().unwrap_err() {
+            RuntimeError::Error { data } => {
+                assert!(data.downcast_ref::<ExecutionLimitExceededError>().is_some());
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(get_remaining(instance.context()), 0);
+    }
+
+    #[test]
+    fn test_br_table_target_surcharge() {
+        // A `br_table` with 100 targets, all to the same block.
+        let wat = format!(
+            r#"
+            (module
+              (type $t0 (func (param i32)))
+              (func $dispatch (export "dispatch") (type $t0) (param $p0 i32)
+                block $B0
+                  get_local $p0
+                  br_table {} $B0
+                end))
+            "#,
+            "$B0 ".repeat(100)
+        );
+        let wasm_binary = wat2wasm(wat).unwrap();
+
+        let points_used = |target_cost: u64| {
+            let module = compile_with(
+                &wasm_binary,
+                &get_compiler_with(move || {
+                    Metering::new(std::u64::MAX).with_br_table_target_cost(target_cost)
+                }),
+            )
+            .unwrap();
+            let import_object = imports! {};
+            let instance = module.instantiate(&import_object).unwrap();
+            let dispatch: Func<i32> = instance.func("dispatch").unwrap();
+            dispatch.call(7).unwrap();
+            get_points_used(&instance)
+        };
+
+        assert_eq!(points_used(3), points_used(0) + 100 * 3);
+    }
+
+    #[test]
+    fn test_compile_with_trace_and_metering() {
+        #[cfg(feature = "llvm")]
+        use wasmer_llvm_backend::ModuleCodeGenerator as MCG;
+        use wasmer_middleware_common::cost_table::CostTable;
+        #[cfg(feature = "singlepass")]
+        use wasmer_singlepass_backend::ModuleCodeGenerator as MCG;
+        let wasm_binary = wat2wasm(WAT).unwrap();
+
+        let table = CostTable::default();
+        let (module, trace, static_cost) =
+            compile_with_trace_and_metering::<MCG, _, _, _>(&wasm_binary, &table).unwrap();
+
+        assert!(trace.contains("FunctionBegin(0)"));
+        assert!(trace.contains("I32RemS"));
+        assert_eq!(
+            static_cost,
+            explain(
+                &wasm_binary,
+                Metering::with_runtime_limit().with_cost_table(table.clone())
+            )
+            .unwrap()
+            .iter()
+            .map(|&(_, points)| points)
+            .sum::<u64>()
+        );
+        assert!(static_cost > 0);
+
+        // The module is metered.
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_execution_limit(&mut instance, std::u64::MAX);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(3, 4).unwrap();
+        assert_eq!(get_points_used(&instance), 11 + 21 * 3);
+    }
+
+    #[test]
+    fn test_split_budget() {
+        let wasm_binary = wat2wasm(WAT).unwrap();
+        let module = compile_with(&wasm_binary, &get_compiler_with(Metering::countdown)).unwrap();
+
+        let import_object = imports! {};
+        let mut parent = module.instantiate(&import_object).unwrap();
+        let mut child = module.instantiate(&import_object).unwrap();
+
+        set_remaining(parent.context_mut(), 1000);
+        split_budget(&mut parent, &mut child, 0.5);
+        assert_eq!(get_remaining(parent.context()), 500);
+        assert_eq!(get_remaining(child.context()), 500);
+
+        // The fraction is clamped, so at most the whole budget is moved.
+        split_budget(&mut parent, &mut child, 2.0);
+        assert_eq!(get_remaining(parent.context()), 0);
+        assert_eq!(get_remaining(child.context()), 1000);
+        split_budget(&mut child, &mut parent, -1.0);
+        assert_eq!(get_remaining(parent.context()), 0);
+        assert_eq!(get_remaining(child.context()), 1000);
+    }
+
+    static WAT_MEMORY_GROW: &'static str = r#"
+        (module
+          (type $t0 (func (result i32)))
+          (func $grow (export "grow") (type $t0) (result i32)
+            i32.const 1
+            memory.grow)
+          (memory $memory 1))
+        "#;
+
+    #[test]
+    fn test_disabled_special_case_charges_flat_cost() {
+        let wasm_binary = wat2wasm(WAT_MEMORY_GROW).unwrap();
+
+        let points_used = |features: MeteringFeatures| {
+            let module = compile_with(
+                &wasm_binary,
+                &get_compiler_with(move || {
+                    Metering::new(std::u64::MAX)
+                        .with_memory_grow_cost(100)
+                        .with_features(features)
+                }),
+            )
+            .unwrap();
+            let import_object = imports! {};
+            let instance = module.instantiate(&import_object).unwrap();
+            let grow: Func<(), i32> = instance.func("grow").unwrap();
+            assert_eq!(grow.call().unwrap(), 1);
+            get_points_used(&instance)
+        };
+
+        // `i32.const`, `memory.grow` and `end` cost one point each.
+        assert_eq!(points_used(MeteringFeatures::all()), 3 + 100);
+        let mut features = MeteringFeatures::all();
+        features.remove(MeteringFeatures::MEMORY_GROW);
+        assert_eq!(points_used(features), 3);
+        assert_eq!(points_used(MeteringFeatures::CALL_INDIRECT), 3);
+    }
+
+    #[test]
+    fn test_function_cost_callback_reports_exclusive_costs() {
+        use std::sync::{Arc, Mutex};
+        let wasm_binary = wat2wasm(WAT_NESTED_CALLS).unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).with_function_costs()),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        let costs = Arc::new(Mutex::new(Vec::new()));
+        let costs_clone = costs.clone();
+        set_function_cost_callback(instance.context_mut(), move |func_index, points| {
+            costs_clone.lock().unwrap().push((func_index, points))
+        });
+
+        let outer: Func<(), ()> = instance.func("outer").unwrap();
+        outer.call().unwrap();
+
+        // `inner` is function 0 and costs three points. `outer` is charged for its two calls and
+        // its `end`, but not for the body of `inner`.
+        assert_eq!(*costs.lock().unwrap(), vec![(0, 3), (0, 3), (1, 3)]);
+        assert_eq!(get_points_used(&instance), 9);
+    }
+
+    #[test]
+    fn test_local_init_cost() {
+        // Two functions with the same body, declaring one and a thousand locals.
+        let wat = format!(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $few (export "few") (type $t0)
+                (local i32)
+                nop)
+              (func $many (export "many") (type $t0)
+                (local {})
+                nop))
+            "#,
+            "i32 ".repeat(1000)
+        );
+        let wasm_binary = wat2wasm(wat).unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(std::u64::MAX).with_local_init_cost(2)),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // `nop` and `end`, then two points for each local.
+        let few: Func<(), ()> = instance.func("few").unwrap();
+        few.call().unwrap();
+        assert_eq!(get_points_used(&instance), 2 + 2);
+
+        set_points_used(&mut instance, 0);
+        let many: Func<(), ()> = instance.func("many").unwrap();
+        many.call().unwrap();
+        assert_eq!(get_points_used(&instance), 2 + 2 * 1000);
+    }
+
+    // Counts down from its argument by recursing, returning the number of calls it made
+    static WAT_RECURSIVE: &'static str = r#"
+        (module
+          (type $t0 (func (param i32) (result i32)))
+          (func $countdown (export "countdown") (type $t0) (param $p0 i32) (result i32)
+            get_local $p0
+            i32.eqz
+            if $I0 (result i32)
+              i32.const 0
+            else
+              get_local $p0
+              i32.const 1
+              i32.sub
+              call $countdown
+              i32.const 1
+              i32.add
+            end))
+        "#;
+
+    #[test]
+    fn test_max_call_depth() {
+        use wasmer_runtime_core::error::RuntimeError;
+        let wasm_binary = wat2wasm(WAT_RECURSIVE).unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(std::u64::MAX).with_max_call_depth(100)),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // `countdown(n)` runs n + 1 deep.
+        let countdown: Func<i32, i32> = instance.func("countdown").unwrap();
+        assert_eq!(countdown.call(99).unwrap(), 99);
+
+        match countdown.call(100).unwrap_err() {
+            RuntimeError::Error { data } => {
+                assert!(data.downcast_ref::<CallDepthExceededError>().is_some());
+            }
+            _ => unreachable!(),
+        }
+
+        // The depth is left raised by the trap until the instance is reset.
+        set_points_used(&mut instance, 0);
+        let countdown: Func<i32, i32> = instance.func("countdown").unwrap();
+        assert_eq!(countdown.call(99).unwrap(), 99);
+    }
+
+    #[test]
+    fn test_event_trace_warns_on_deprecated_opcodes() {
+        use std::collections::HashSet;
+        use std::io::{self, Write};
+        use std::sync::{Arc, Mutex};
+        use wasmer_middleware_common::{event_trace::EventTrace, opcode::OpcodeKind};
+
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // `i32.mul` is only used by `$mul`, function 2 after the import and `$add`.
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func $f))
+              (func $add (result i32)
+                i32.const 1
+                i32.const 2
+                i32.add)
+              (func $mul (result i32)
+                i32.const 1
+                i32.const 2
+                i32.mul))
+            "#,
+        )
+        .unwrap();
+
+        let trace = SharedBuffer::default();
+        let writer = trace.clone();
+        let deprecated: HashSet<_> = vec![OpcodeKind::I32Mul].into_iter().collect();
+        compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || {
+                EventTrace::with_writer(writer.clone()).warn_on(deprecated.clone())
+            }),
+        )
+        .unwrap();
+
+        let trace = String::from_utf8(trace.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        let warnings: Vec<usize> = (0..lines.len())
+            .filter(|&i| lines[i].starts_with("WARN:"))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            lines[warnings[0]],
+            "WARN: deprecated opcode i32.mul in fn 2"
+        );
+        // The operator is still traced.
+        assert_eq!(lines[warnings[0] + 1], "I32Mul");
+    }
+
+    #[test]
+    fn test_timing_wrapper() {
+        use wasmer_middleware_common::timing::{Timer, TimingWrapper};
+
+        let wasm_binary = wat2wasm(WAT).unwrap();
+
+        let timer = Timer::default();
+        let metering_timer = timer.clone();
+        let compiler = get_compiler_with(move || {
+            TimingWrapper::new(Metering::new(100), metering_timer.clone())
+        });
+
+        compile_with(&wasm_binary, &compiler).unwrap();
+        let first = timer.elapsed();
+        assert!(first > std::time::Duration::from_secs(0));
+
+        // The time of every compilation is added to the same timer.
+        compile_with(&wasm_binary, &compiler).unwrap();
+        assert!(timer.elapsed() > first);
+    }
+
+    // The same load, declared with its natural alignment and with a one byte alignment
+    static WAT_ALIGNMENT: &'static str = r#"
+        (module
+          (type $t0 (func))
+          (func $aligned (export "aligned") (type $t0)
+            i32.const 0
+            i32.load align=4
+            drop)
+          (func $unaligned (export "unaligned") (type $t0)
+            i32.const 0
+            i32.load align=1
+            drop)
+          (memory $memory (export "memory") 1))
+        "#;
+
+    #[test]
+    fn test_unaligned_access_surcharge() {
+        let wasm_binary = wat2wasm(WAT_ALIGNMENT).unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).with_unaligned_access_surcharge(10)),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // `i32.const`, `i32.load`, `drop` and `end`.
+        let aligned: Func<(), ()> = instance.func("aligned").unwrap();
+        aligned.call().unwrap();
+        assert_eq!(get_points_used(&instance), 4);
+
+        set_points_used(&mut instance, 0);
+        let unaligned: Func<(), ()> = instance.func("unaligned").unwrap();
+        unaligned.call().unwrap();
+        assert_eq!(get_points_used(&instance), 4 + 10);
+    }
+
+    #[test]
+    fn test_magnitude_cost() {
+        use wasmer_middleware_common::opcode::OpcodeKind;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $small (export "small") (type $t0)
+                i32.const 7
+                drop)
+              (func $large (export "large") (type $t0)
+                i32.const -2000000
+                drop))
+            "#,
+        )
+        .unwrap();
+
+        // One point for every thousand of the magnitude of a constant.
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| {
+                Metering::new(std::u64::MAX).with_magnitude_cost(|kind, magnitude| match kind {
+                    OpcodeKind::I32Const | OpcodeKind::I64Const => magnitude / 1000,
+                    _ => 0,
+                })
+            }),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // `i32.const`, `drop` and `end`.
+        let small: Func<(), ()> = instance.func("small").unwrap();
+        small.call().unwrap();
+        assert_eq!(get_points_used(&instance), 3);
+
+        set_points_used(&mut instance, 0);
+        let large: Func<(), ()> = instance.func("large").unwrap();
+        large.call().unwrap();
+        assert_eq!(get_points_used(&instance), 3 + 2000);
+    }
+
+    #[test]
+    fn test_registered_internal_fields() {
+        use std::collections::HashSet;
+        use wasmer_middleware_common::{register_internal_field, registered_internal_fields};
+        use wasmer_runtime_core::vm::InternalField;
+
+        static CUSTOM_FIELD: InternalField = InternalField::allocate();
+        register_internal_field("tests.custom", &CUSTOM_FIELD);
+
+        let fields = registered_internal_fields();
+        let index_of = |name| {
+            fields
+                .iter()
+                .find(|field| field.0 == name)
+                .map(|field| field.1)
+        };
+        assert!(index_of("metering.points_used").is_some());
+        assert!(index_of("metering.limit").is_some());
+        assert_ne!(index_of("metering.points_used"), index_of("metering.limit"));
+        assert_eq!(index_of("tests.custom"), Some(CUSTOM_FIELD.index()));
+
+        // No two fields share an index.
+        let indices: HashSet<usize> = fields.iter().map(|field| field.1).collect();
+        assert_eq!(indices.len(), fields.len());
+    }
+
+    #[test]
+    fn test_free_opcodes() {
+        use std::collections::HashSet;
+        use wasmer_middleware_common::{cost_table::CostTable, opcode::OpcodeKind};
+
+        let wasm_binary = wat2wasm(format!(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $nops (export "nops") (type $t0)
+                {}))
+            "#,
+            "nop\n".repeat(100)
+        ))
+        .unwrap();
+
+        // The `end` of the body is free too, so that only `nop`s are left.
+        let free: HashSet<_> = vec![OpcodeKind::Nop, OpcodeKind::End].into_iter().collect();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || {
+                Metering::new(100)
+                    .with_cost_table(CostTable::new(5).with_cost(OpcodeKind::Nop, 3))
+                    .with_free_opcodes(free.clone())
+            }),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let instance = module.instantiate(&import_object).unwrap();
+
+        let nops: Func<(), ()> = instance.func("nops").unwrap();
+        nops.call().unwrap();
+        assert_eq!(get_points_used(&instance), 0);
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        use std::sync::{Arc, Mutex};
+
+        // Three functions defined by the module, after an imported one.
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func $f))
+              (func $a nop)
+              (func $b nop)
+              (func $c nop))
+            "#,
+        )
+        .unwrap();
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let progress_clone = progress.clone();
+        compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || {
+                let progress = progress_clone.clone();
+                Metering::new(100).with_progress_callback(move |current, total| {
+                    progress.lock().unwrap().push((current, total))
+                })
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(*progress.lock().unwrap(), vec![(1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[test]
+    fn test_build_chain() {
+        #[cfg(feature = "llvm")]
+        use wasmer_llvm_backend::ModuleCodeGenerator as MCG;
+        use wasmer_middleware_common::{build_chain, event_trace::EventTrace};
+        #[cfg(feature = "singlepass")]
+        use wasmer_singlepass_backend::ModuleCodeGenerator as MCG;
+
+        let wasm_binary = wat2wasm(WAT).unwrap();
+
+        let compiler: StreamingCompiler<MCG, _, _, _, _> = StreamingCompiler::new(|| {
+            let mws: Vec<Box<dyn FunctionMiddleware<Error = String>>> = vec![
+                Box::new(EventTrace::with_writer(std::io::sink())),
+                Box::new(Metering::new(std::u64::MAX)),
+            ];
+            build_chain(mws)
+        });
+        let module = compile_with(&wasm_binary, &compiler).unwrap();
+
+        // The module is metered as when compiled with Metering alone.
+        let import_object = imports! {};
+        let instance = module.instantiate(&import_object).unwrap();
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(3, 4).unwrap();
+        assert_eq!(get_points_used(&instance), 11 + 21 * 3);
+    }
+
+    #[test]
+    fn test_stack_frame_cost() {
+        // The same body, with a frame of 8 bytes and of 4 + 100 * 8 bytes.
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func $small (export "small") (param i32)
+                (local f32)
+                nop)
+              (func $large (export "large") (param i32)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                nop))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(std::u64::MAX).with_stack_frame_cost_per_byte(1)),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // `nop` and `end`, then a point for each byte of the frame.
+        let small: Func<i32, ()> = instance.func("small").unwrap();
+        small.call(0).unwrap();
+        let small_points = get_points_used(&instance);
+        assert_eq!(small_points, 2 + 8);
+
+        set_points_used(&mut instance, 0);
+        let large: Func<i32, ()> = instance.func("large").unwrap();
+        large.call(0).unwrap();
+        assert_eq!(get_points_used(&instance), 2 + 4 + 100 * 8);
+        assert!(get_points_used(&instance) > small_points);
+    }
+
+    #[test]
+    fn test_call_breakpoints() {
+        use std::sync::{Arc, Mutex};
+        use wasmer_middleware_common::call_breakpoints::{CallBreakpoints, Callee};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $f (type $t0))
+              (func $g (type $t0))
+              (func $run (export "run") (type $t0)
+                call $f
+                call $g))
+            "#,
+        )
+        .unwrap();
+
+        let callees = Arc::new(Mutex::new(Vec::new()));
+        let callees_clone = callees.clone();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || {
+                let callees = callees_clone.clone();
+                CallBreakpoints::new(move |callee| callees.lock().unwrap().push(callee))
+            }),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let instance = module.instantiate(&import_object).unwrap();
+
+        let run: Func<(), ()> = instance.func("run").unwrap();
+        run.call().unwrap();
+        assert_eq!(
+            *callees.lock().unwrap(),
+            vec![Callee::Function(0), Callee::Function(1)]
+        );
+    }
+
+    #[test]
+    fn test_uniform_cost() {
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $straight (export "straight") (type $t0)
+                i32.const 1
+                memory.grow
+                drop
+                nop
+                nop)
+              (memory $memory 1))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).uniform(1).with_memory_grow_cost(10)),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let instance = module.instantiate(&import_object).unwrap();
+
+        let straight: Func<(), ()> = instance.func("straight").unwrap();
+        straight.call().unwrap();
+        // Six operators counting the `end` of the body, and the `memory.grow` surcharge.
+        assert_eq!(get_points_used(&instance), 6 + 10);
+    }
+
+    #[test]
+    fn test_opcode_profiler_reset() {
+        use wasmer_middleware_common::{opcode::OpcodeKind, opcode_profiler::OpcodeProfiler};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func (param i32)))
+              (func $sub_loop (export "sub_loop") (type $t0) (param $n i32)
+                loop
+                  get_local $n
+                  i32.const 1
+                  i32.sub
+                  tee_local $n
+                  br_if 0
+                end)
+              (func $mul_loop (export "mul_loop") (type $t0) (param $n i32)
+                loop
+                  get_local $n
+                  i32.const 1
+                  i32.sub
+                  tee_local $n
+                  i32.const 2
+                  i32.mul
+                  br_if 0
+                end))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(&wasm_binary, &get_compiler_with(OpcodeProfiler::new)).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        let sub_loop: Func<i32, ()> = instance.func("sub_loop").unwrap();
+        sub_loop.call(5).unwrap();
+        let histogram = OpcodeProfiler::histogram(instance.context());
+        assert_eq!(histogram.get(&OpcodeKind::I32Sub), Some(&5));
+        assert_eq!(histogram.get(&OpcodeKind::I32Mul), None);
+
+        OpcodeProfiler::reset(instance.context_mut());
+        assert!(OpcodeProfiler::histogram(instance.context()).is_empty());
+
+        let mul_loop: Func<i32, ()> = instance.func("mul_loop").unwrap();
+        mul_loop.call(3).unwrap();
+        let histogram = OpcodeProfiler::histogram(instance.context());
+        assert_eq!(histogram.get(&OpcodeKind::I32Sub), Some(&3));
+        assert_eq!(histogram.get(&OpcodeKind::I32Mul), Some(&3));
+        assert_eq!(histogram.get(&OpcodeKind::Loop), Some(&1));
+    }
+
+    #[test]
+    fn test_swap_limit() {
+        let wasm_binary = wat2wasm(WAT).unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(Metering::with_runtime_limit),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_execution_limit(&mut instance, 1000);
+
+        assert_eq!(swap_limit(instance.context_mut(), 200), 1000);
+        assert_eq!(get_execution_limit(&instance), 200);
+
+        // add_to(40, 3) costs 11 + 21 * 40 points, over the tighter limit.
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        assert!(add_to.call(40, 3).is_err());
+        set_points_used(&mut instance, 0);
+
+        assert_eq!(swap_limit(instance.context_mut(), 1000), 200);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        assert_eq!(add_to.call(40, 3).unwrap(), 783);
+        assert_eq!(get_points_used(&instance), 11 + 21 * 40);
+    }
+
+    #[test]
+    fn test_loop_counter() {
+        use wasmer_middleware_common::loop_counter::{
+            loop_counts, reset_loop_counts, LoopCounter, LoopId,
+        };
+
+        // for (i = 0; i < outer; i++) { for (j = 0; j < inner; j++) {} }
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func (param i32 i32)))
+              (func $nested (export "nested") (type $t0) (param $outer i32) (param $inner i32)
+                (local $i i32) (local $j i32)
+                loop $L0
+                  i32.const 0
+                  set_local $j
+                  loop $L1
+                    get_local $j
+                    i32.const 1
+                    i32.add
+                    tee_local $j
+                    get_local $inner
+                    i32.lt_s
+                    br_if $L1
+                  end
+                  get_local $i
+                  i32.const 1
+                  i32.add
+                  tee_local $i
+                  get_local $outer
+                  i32.lt_s
+                  br_if $L0
+                end))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(&wasm_binary, &get_compiler_with(LoopCounter::new)).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        let nested: Func<(i32, i32), ()> = instance.func("nested").unwrap();
+        nested.call(3, 4).unwrap();
+        let counts = loop_counts(instance.context());
+        let outer = LoopId {
+            func_index: 0,
+            loop_index: 0,
+        };
+        let inner = LoopId {
+            func_index: 0,
+            loop_index: 1,
+        };
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&outer], 3);
+        assert_eq!(counts[&inner], 3 * 4);
+
+        reset_loop_counts(instance.context_mut());
+        assert!(loop_counts(instance.context()).is_empty());
+    }
+
+    #[test]
+    fn test_cross_module_call_cost() {
+        let callee_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $f (export "f") (type $t0)))
+            "#,
+        )
+        .unwrap();
+        let caller_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func))
+              (import "callee" "f" (func $imported (type $t0)))
+              (func $local (type $t0))
+              (func $call_local (export "call_local") (type $t0)
+                call $local)
+              (func $call_imported (export "call_imported") (type $t0)
+                call $imported))
+            "#,
+        )
+        .unwrap();
+
+        let compiler = get_compiler_with(|| Metering::new(100).with_cross_module_call_cost(10));
+        let callee = compile_with(&callee_binary, &compiler)
+            .unwrap()
+            .instantiate(&imports! {})
+            .unwrap();
+        let mut import_object = imports! {};
+        import_object.register("callee", callee);
+        let mut instance = compile_with(&caller_binary, &compiler)
+            .unwrap()
+            .instantiate(&import_object)
+            .unwrap();
+
+        // A `call` and an `end` in the caller, and the `end` of the local callee.
+        let call_local: Func<(), ()> = instance.func("call_local").unwrap();
+        call_local.call().unwrap();
+        assert_eq!(get_points_used(&instance), 3);
+
+        // The imported callee charges its own instance.
+        set_points_used(&mut instance, 0);
+        let call_imported: Func<(), ()> = instance.func("call_imported").unwrap();
+        call_imported.call().unwrap();
+        assert_eq!(get_points_used(&instance), 2 + 10);
+    }
+
+    #[test]
+    fn test_is_special_cased() {
+        use std::collections::HashSet;
+        use wasmer_middleware_common::opcode::OpcodeKind;
+
+        let special: HashSet<OpcodeKind> = OpcodeKind::ALL
+            .iter()
+            .cloned()
+            .filter(|&kind| is_special_cased(kind))
+            .collect();
+        let expected: HashSet<OpcodeKind> = vec![
+            OpcodeKind::Call,
+            OpcodeKind::CallIndirect,
+            OpcodeKind::BrTable,
+            OpcodeKind::MemoryGrow,
+            OpcodeKind::MemoryCopy,
+            OpcodeKind::MemoryFill,
+            OpcodeKind::MemoryInit,
+        ]
+        .into_iter()
+        .collect();
+        // The memory accesses are special-cased for their alignment.
+        let accesses: HashSet<OpcodeKind> = special
+            .iter()
+            .cloned()
+            .filter(|&kind| MeteringFeatures::of(kind) == MeteringFeatures::UNALIGNED_ACCESS)
+            .collect();
+        assert_eq!(
+            special
+                .difference(&accesses)
+                .cloned()
+                .collect::<HashSet<_>>(),
+            expected
+        );
+        for kind in &[
+            OpcodeKind::I32Load,
+            OpcodeKind::I64Store8,
+            OpcodeKind::I32AtomicRmwCmpxchg,
+            OpcodeKind::V128Load,
+        ] {
+            assert!(accesses.contains(kind), "{} is not special-cased", kind);
+        }
+        for kind in &[OpcodeKind::I32Add, OpcodeKind::Fence, OpcodeKind::TableGrow] {
+            assert!(!special.contains(kind), "{} is special-cased", kind);
+        }
+    }
+
+    #[test]
+    fn test_graceful_exhaustion() {
+        use wasmer_runtime_core::types::Value;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func (result i32)))
+              (func $spin (export "spin") (type $t0) (result i32)
+                loop
+                  br 0
+                end
+                i32.const 0))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(1000).with_graceful_exhaustion()),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_graceful_exhaustion(instance.context_mut(), 0, &[Value::I32(-1)]);
+        let spin: Func<(), i32> = instance.func("spin").unwrap();
+        assert_eq!(spin.call().unwrap(), -1);
+        assert!(get_points_used(&instance) >= 1000);
+
+        // Without a default, or with a default of the wrong type, the function traps.
+        set_points_used(&mut instance, 0);
+        set_graceful_exhaustion(instance.context_mut(), 0, &[Value::I64(-1)]);
+        let spin: Func<(), i32> = instance.func("spin").unwrap();
+        assert!(spin.call().is_err());
+
+        set_points_used(&mut instance, 0);
+        clear_graceful_exhaustion(instance.context_mut(), 0);
+        let spin: Func<(), i32> = instance.func("spin").unwrap();
+        assert!(spin.call().is_err());
+    }
+
+    #[test]
+    fn test_graceful_exhaustion_notifies_once() {
+        use std::sync::{Arc, Mutex};
+        use wasmer_runtime_core::types::Value;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func (result i32)))
+              (func $spin (type $t0) (result i32)
+                loop
+                  br 0
+                end
+                i32.const 0)
+              (func $spin_twice (export "spin_twice") (type $t0) (result i32)
+                call $spin
+                call $spin
+                i32.add))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(1000).with_graceful_exhaustion()),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_graceful_exhaustion(instance.context_mut(), 0, &[Value::I32(-1)]);
+        set_graceful_exhaustion(instance.context_mut(), 1, &[Value::I32(-2)]);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        set_on_limit_exceeded(instance.context_mut(), move |func_index, _| {
+            events_clone.lock().unwrap().push(func_index);
+        });
+
+        // `spin` exceeds the limit and returns gracefully, then the check of `spin_twice` finds
+        // the limit exceeded too, without firing the callback again.
+        let spin_twice: Func<(), i32> = instance.func("spin_twice").unwrap();
+        assert_eq!(spin_twice.call().unwrap(), -2);
+        assert_eq!(*events.lock().unwrap(), vec![0]);
+
+        // The next call from the host, still past the limit, fires it again.
+        assert_eq!(spin_twice.call().unwrap(), -2);
+        assert_eq!(events.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_opcode_count_limit() {
+        use wasmer_middleware_common::{opcode::OpcodeKind, opcode_count_limit::OpcodeCountLimit};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $grow_twice (type $t0)
+                i32.const 1
+                memory.grow
+                drop
+                i32.const 1
+                memory.grow
+                drop)
+              (func $grow_thrice (type $t0)
+                i32.const 1
+                memory.grow
+                drop
+                i32.const 1
+                memory.grow
+                drop
+                i32.const 1
+                memory.grow
+                drop)
+              (memory $memory 1))
+            "#,
+        )
+        .unwrap();
+
+        let compiler = get_compiler_with(|| OpcodeCountLimit::new(OpcodeKind::MemoryGrow, 2));
+        let error = compile_with(&wasm_binary, &compiler).err().unwrap();
+        assert!(format!("{:?}", error).contains("function 1 has more than 2 memory.grow operators"));
+
+        let compiler = get_compiler_with(|| OpcodeCountLimit::new(OpcodeKind::MemoryGrow, 3));
+        assert!(compile_with(&wasm_binary, &compiler).is_ok());
+    }
+
+    #[test]
+    fn test_import_count_limit() {
+        use wasmer_middleware_common::import_count_limit::ImportCountLimit;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func))
+              (import "env" "g" (func))
+              (import "env" "memory" (memory 1))
+              (func (export "run")))
+            "#,
+        )
+        .unwrap();
+
+        let compiler = get_compiler_with(|| ImportCountLimit::new(2));
+        let error = compile_with(&wasm_binary, &compiler).err().unwrap();
+        assert!(format!("{:?}", error).contains("module has 3 imports, more than 2"));
+
+        let compiler = get_compiler_with(|| ImportCountLimit::new(3));
+        assert!(compile_with(&wasm_binary, &compiler).is_ok());
+
+        // A module defining no function is checked too.
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func))
+              (import "env" "g" (func))
+              (import "env" "memory" (memory 1))
+              (export "f" (func 0)))
+            "#,
+        )
+        .unwrap();
+
+        let compiler = get_compiler_with(|| ImportCountLimit::new(2));
+        let error = compile_with(&wasm_binary, &compiler).err().unwrap();
+        assert!(format!("{:?}", error).contains("module has 3 imports, more than 2"));
+    }
+
+    #[test]
+    fn test_check_compatible() {
+        use wasmer_middleware_common::opcode::OpcodeKind;
+
+        let wasm_binary = wat2wasm(WAT).unwrap();
+        assert_eq!(check_compatible(&wasm_binary), Ok(()));
+
+        // A function running `table.copy`, whose cost grows with its length operand.
+        let wasm_binary: &[u8] = &[
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+            0x03, 0x02, 0x01, 0x00, // function section
+            0x0a, 0x0e, 0x01, 0x0c, 0x00, // code section, one body without locals
+            0x41, 0x00, 0x41, 0x00, 0x41, 0x00, // i32.const 0, three times
+            0xfc, 0x0e, 0x00, 0x00, // table.copy
+            0x0b, // end
+        ];
+        assert_eq!(
+            check_compatible(wasm_binary),
+            Err(IncompatibilityReason::UnpricedOperator {
+                kind: OpcodeKind::TableCopy,
+                offset: 29,
+            })
+        );
+
+        match check_compatible(&wasm_binary[..20]) {
+            Err(IncompatibilityReason::Malformed(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    /// Returns a module with a function `() -> ()` whose body, without locals, is `body`.
+    fn module_with_body(body: &[u8]) -> Vec<u8> {
+        let mut wasm_binary = vec![
+            0x00,
+            0x61,
+            0x73,
+            0x6d,
+            0x01,
+            0x00,
+            0x00,
+            0x00, // header
+            0x01,
+            0x04,
+            0x01,
+            0x60,
+            0x00,
+            0x00, // type section: () -> ()
+            0x03,
+            0x02,
+            0x01,
+            0x00, // function section
+            0x0a, // code section
+            body.len() as u8 + 3,
+            0x01,
+            body.len() as u8 + 1,
+            0x00, // no locals
+        ];
+        wasm_binary.extend_from_slice(body);
+        wasm_binary
+    }
+
+    #[test]
+    fn test_tail_calls_fail_to_compile() {
+        // `return_call 0` and `return_call_indirect 0`, which the parser does not decode.
+        for body in &[&[0x12, 0x00, 0x0b][..], &[0x13, 0x00, 0x00, 0x0b][..]] {
+            let wasm_binary = module_with_body(body);
+            let compiler = get_compiler_with(|| Metering::new(100));
+            let error = compile_with(&wasm_binary, &compiler).err().unwrap();
+            assert!(format!("{:?}", error).contains("Unknown opcode"));
+        }
+    }
+
+    #[test]
+    fn test_select_flat_charge() {
+        use wasmer_middleware_common::{cost_table::CostTable, opcode::OpcodeKind};
+
+        // The typed `select` of the reference types proposal is not decoded.
+        let wasm_binary = module_with_body(&[
+            0x41, 0x01, 0x41, 0x02, 0x41, 0x00, // i32.const 1, 2 and 0
+            0x1c, 0x01, 0x7f, // select (result i32)
+            0x1a, // drop
+            0x0b, // end
+        ]);
+        let compiler = get_compiler_with(|| Metering::new(100));
+        let error = compile_with(&wasm_binary, &compiler).err().unwrap();
+        assert!(format!("{:?}", error).contains("Unknown opcode"));
+
+        // An untyped `select` costs the same whatever the type of its operands.
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (result i32)
+                i32.const 1
+                i32.const 2
+                i32.const 0
+                select)
+              (func (result f64)
+                f64.const 1
+                f64.const 2
+                i32.const 0
+                select))
+            "#,
+        )
+        .unwrap();
+        let table = CostTable::new(1).with_cost(OpcodeKind::Select, 7);
+        let selects: Vec<u64> = explain(&wasm_binary, Metering::new(100).with_cost_table(table))
+            .unwrap()
+            .iter()
+            .filter(|&&(offset, _)| wasm_binary[offset] == 0x1b)
+            .map(|&(_, points)| points)
+            .collect();
+        assert_eq!(selects, vec![7, 7]);
+    }
+
+    #[test]
+    fn test_metering_does_not_buffer_operators() {
+        use std::sync::{Arc, Mutex};
+        #[cfg(feature = "llvm")]
+        use wasmer_llvm_backend::ModuleCodeGenerator as MCG;
+        use wasmer_middleware_common::build_chain;
+        use wasmer_runtime_core::codegen::{Event, EventSink};
+        use wasmer_runtime_core::module::ModuleInfo;
+        #[cfg(feature = "singlepass")]
+        use wasmer_singlepass_backend::ModuleCodeGenerator as MCG;
+
+        /// Logs the operators of the module it is fed with, after its name.
+        struct Recorder {
+            name: &'static str,
+            log: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl FunctionMiddleware for Recorder {
+            type Error = String;
+            fn feed_event<'a, 'b: 'a>(
+                &mut self,
+                op: Event<'a, 'b>,
+                _module_info: &ModuleInfo,
+                sink: &mut EventSink<'a, 'b>,
+            ) -> Result<(), Self::Error> {
+                if let Event::Wasm(op) = op {
+                    let entry = format!("{} {:?}", self.name, op);
+                    self.log.lock().unwrap().push(entry);
+                }
+                sink.push(op);
+                Ok(())
+            }
+        }
+
+        let wasm_binary = wat2wasm("(module (func nop i32.const 1 drop))").unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let compiler: StreamingCompiler<MCG, _, _, _, _> = {
+            let log = log.clone();
+            StreamingCompiler::new(move || {
+                let mws: Vec<Box<dyn FunctionMiddleware<Error = String>>> = vec![
+                    Box::new(Recorder {
+                        name: "fed",
+                        log: log.clone(),
+                    }),
+                    Box::new(Metering::new(std::u64::MAX)),
+                    Box::new(Recorder {
+                        name: "got",
+                        log: log.clone(),
+                    }),
+                ];
+                build_chain(mws)
+            })
+        };
+        compile_with(&wasm_binary, &compiler).unwrap();
+
+        // Each operator is passed on before the next one is fed.
+        let expected: Vec<String> = ["Nop", "I32Const { value: 1 }", "Drop", "End"]
+            .iter()
+            .flat_map(|op| vec![format!("fed {}", op), format!("got {}", op)])
+            .collect();
+        assert_eq!(*log.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_category_breakdown() {
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func (param i32) (result f64)))
+              (func $mixed (export "mixed") (type $t0) (param $n i32) (result f64)
+                (local $acc f64)
+                loop
+                  i32.const 0
+                  get_local $n
+                  i32.store
+                  get_local $acc
+                  f64.const 1.5
+                  f64.add
+                  set_local $acc
+                  get_local $n
+                  i32.const 1
+                  i32.sub
+                  tee_local $n
+                  br_if 0
+                end
+                get_local $acc)
+              (memory $memory 1))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| {
+                Metering::new(10_000)
+                    .with_local_init_cost(2)
+                    .with_category_breakdown()
+            }),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        let mixed: Func<i32, f64> = instance.func("mixed").unwrap();
+        assert_eq!(mixed.call(3).unwrap(), 4.5);
+
+        let breakdown = category_breakdown(instance.context());
+        assert_eq!(breakdown.total(), get_points_used(&instance));
+        // One `i32.store` per iteration.
+        assert_eq!(breakdown.memory, 3);
+        assert_eq!(breakdown.float, 3 * 2);
+        assert_eq!(breakdown.integer, 3 * 3);
+        // The `loop`, the `br_if`s, both `end`s and the initialization of `$acc`.
+        assert_eq!(breakdown.control, 1 + 3 + 2 + 2);
+        assert_eq!(breakdown.other, 3 * 5 + 1);
+
+        reset_category_breakdown(instance.context_mut());
+        assert_eq!(category_breakdown(instance.context()).total(), 0);
+    }
+
+    #[test]
+    fn test_compute_fee() {
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func))
+              (func $nop (export "nop") (type $t0)
+                nop))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(&wasm_binary, &get_compiler_with(|| Metering::new(100))).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        let nop: Func<(), ()> = instance.func("nop").unwrap();
+        nop.call().unwrap();
+        assert_eq!(compute_fee(instance.context(), 1_000), 2 * 1_000);
+
+        // The product of the largest values takes the full 128 bits.
+        set_points_used(&mut instance, u64::max_value());
+        assert_eq!(
+            compute_fee(instance.context(), u64::max_value()),
+            u128::from(u64::max_value()) * u128::from(u64::max_value())
+        );
+        assert_eq!(compute_fee(instance.context(), 0), 0);
+    }
+
+    #[test]
+    fn test_event_trace_binary_round_trip() {
+        use std::io::{self, Write};
+        use std::sync::{Arc, Mutex};
+        use wasmer_middleware_common::{
+            event_trace::{decode_trace, EventTrace, TraceRecord},
+            opcode::OpcodeKind,
+        };
+
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func $f (param i32) (result i32)
+                block (result i32)
+                  get_local 0
+                  i32.const -300
+                  i32.add
+                  get_local 0
+                  br_table 0 0
+                end))
+            "#,
+        )
+        .unwrap();
+
+        let trace = SharedBuffer::default();
+        let writer = trace.clone();
+        compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || EventTrace::binary(writer.clone())),
+        )
+        .unwrap();
+
+        let operator = |kind, immediates: &[i64]| TraceRecord::Operator {
+            kind,
+            immediates: immediates.to_vec(),
+        };
+        let records = decode_trace(&trace.0.lock().unwrap()).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                TraceRecord::FunctionBegin(0),
+                // A block yielding an `i32`, encoded as -1.
+                operator(OpcodeKind::Block, &[-1]),
+                operator(OpcodeKind::GetLocal, &[0]),
+                operator(OpcodeKind::I32Const, &[-300]),
+                operator(OpcodeKind::I32Add, &[]),
+                operator(OpcodeKind::GetLocal, &[0]),
+                operator(OpcodeKind::BrTable, &[0, 0]),
+                operator(OpcodeKind::End, &[]),
+                operator(OpcodeKind::End, &[]),
+                TraceRecord::FunctionEnd,
+            ]
+        );
+
+        let truncated = trace.0.lock().unwrap().len() - 1;
+        assert!(decode_trace(&trace.0.lock().unwrap()[..truncated]).is_err());
+    }
+
+    #[test]
+    fn test_cold_page_surcharge() {
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (type $t0 (func (param i32 i32) (result i32)))
+              (func $load_two (export "load_two") (type $t0) (param $a i32) (param $b i32) (result i32)
+                get_local $a
+                i32.load
+                get_local $b
+                i32.load
+                i32.add)
+              (memory $memory 2))
+            "#,
+        )
+        .unwrap();
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(1000).with_cold_page_surcharge(100)),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // Six operators counting the `end` of the body.
+        let load_two: Func<(i32, i32), i32> = instance.func("load_two").unwrap();
+        // Both loads are in the first page, which is only charged once.
+        load_two.call(0, 4).unwrap();
+        assert_eq!(get_points_used(&instance), 6 + 100);
+
+        // Only the second page is cold.
+        set_points_used(&mut instance, 0);
+        let load_two: Func<(i32, i32), i32> = instance.func("load_two").unwrap();
+        load_two.call(8, 65536).unwrap();
+        assert_eq!(get_points_used(&instance), 6 + 100);
+
+        set_points_used(&mut instance, 0);
+        let load_two: Func<(i32, i32), i32> = instance.func("load_two").unwrap();
+        load_two.call(12, 65540).unwrap();
+        assert_eq!(get_points_used(&instance), 6);
+
+        reset_cold_pages(instance.context_mut());
+        set_points_used(&mut instance, 0);
+        let load_two: Func<(i32, i32), i32> = instance.func("load_two").unwrap();
+        load_two.call(0, 4).unwrap();
+        assert_eq!(get_points_used(&instance), 6 + 100);
+    }
+
+    #[test]
+    fn test_cost_table_metadata() {
+        use wasmer_middleware_common::cost_table::{embedded_cost_table, CostTable};
+        use wasmer_middleware_common::opcode::OpcodeKind;
+
+        let table = CostTable::default()
+            .with_cost(OpcodeKind::Nop, 5)
+            .with_metadata("schedule v2".to_string());
+        let round_tripped = CostTable::from_bytes(&table.to_bytes()).unwrap();
+        assert_eq!(round_tripped, table);
+        assert_eq!(round_tripped.metadata(), "schedule v2");
+
+        // Tables without metadata keep the format they had before metadata.
+        let plain = CostTable::default().with_cost(OpcodeKind::Nop, 5);
+        assert_eq!(plain.to_bytes()[0], 1);
+        assert_eq!(
+            CostTable::from_bytes(&plain.to_bytes()).unwrap().metadata(),
+            ""
+        );
+
+        let wasm_binary = wat2wasm(r#"(module (func (export "nops") nop nop nop))"#).unwrap();
+        let metering_table = table.clone();
+        let module = compile_with(
+            &table.embed(&wasm_binary),
+            &get_compiler_with(move || Metering::new(100).with_cost_table(metering_table.clone())),
+        )
+        .unwrap();
+
+        let embedded = CostTable::from_bytes(embedded_cost_table(module.info()).unwrap()).unwrap();
+        assert_eq!(embedded.metadata(), "schedule v2");
+    }
+
+    #[test]
+    fn test_abort_flag() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::{Duration, Instant};
+        use wasmer_runtime_core::error::RuntimeError;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func $nop)
+              (func (export "spin") (loop br 0))
+              (func (export "call_nop") call $nop))
+        "#,
+        )
+        .unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(std::u64::MAX).with_abort_checks()),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        let flag = Arc::new(AtomicBool::new(false));
+        set_abort_flag(instance.context_mut(), flag.clone());
+
+        let setter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::SeqCst);
+            Instant::now()
+        });
+
+        let spin: Func<(), ()> = instance.func("spin").unwrap();
+        let err = spin.call().unwrap_err();
+        let aborted_at = Instant::now();
+        let set_at = setter.join().unwrap();
+
+        match err {
+            RuntimeError::Error { data } => {
+                assert!(data.downcast_ref::<ExecutionAbortedError>().is_some());
+            }
+            _ => unreachable!(),
+        }
+        assert!(aborted_at.duration_since(set_at) < Duration::from_secs(1));
+
+        // The flag stays set until it is cleared.
+        assert!(instance.call("call_nop", &[]).is_err());
+        clear_abort_flag(instance.context_mut());
+        instance.call("call_nop", &[]).unwrap();
+    }
+
+    #[test]
+    fn test_opcode_profiler_diff() {
+        use wasmer_middleware_common::opcode::OpcodeKind;
+        use wasmer_middleware_common::opcode_profiler::{Histogram, OpcodeProfiler};
+
+        let mut baseline = Histogram::new();
+        baseline.insert(OpcodeKind::Nop, 3);
+        baseline.insert(OpcodeKind::Call, 2);
+        baseline.insert(OpcodeKind::End, 1);
+        let mut run = Histogram::new();
+        run.insert(OpcodeKind::Nop, 5);
+        run.insert(OpcodeKind::End, 1);
+        run.insert(OpcodeKind::Br, 4);
+
+        let diff = OpcodeProfiler::diff(&baseline, &run);
+        // `end` ran as often in both runs.
+        assert_eq!(diff.len(), 3);
+        assert!(diff.contains(&(OpcodeKind::Nop, 2)));
+        assert!(diff.contains(&(OpcodeKind::Call, -2)));
+        assert!(diff.contains(&(OpcodeKind::Br, 4)));
+        assert!(OpcodeProfiler::diff(&run, &run).is_empty());
+    }
+
+    #[test]
+    fn test_verify_function_event_balance() {
+        #[cfg(feature = "llvm")]
+        use wasmer_llvm_backend::ModuleCodeGenerator as MCG;
+        use wasmer_middleware_common::{
+            verify_function_event_balance, verify_function_event_balance_with,
+        };
+        use wasmer_runtime_core::codegen::{Event, EventSink, InternalEvent};
+        use wasmer_runtime_core::module::ModuleInfo;
+        #[cfg(feature = "singlepass")]
+        use wasmer_singlepass_backend::ModuleCodeGenerator as MCG;
+
+        /// Drops the `FunctionEnd` events.
+        struct DropFunctionEnd;
+
+        impl FunctionMiddleware for DropFunctionEnd {
+            type Error = String;
+            fn feed_event<'a, 'b: 'a>(
+                &mut self,
+                op: Event<'a, 'b>,
+                _module_info: &ModuleInfo,
+                sink: &mut EventSink<'a, 'b>,
+            ) -> Result<(), Self::Error> {
+                if let Event::Internal(InternalEvent::FunctionEnd) = op {
+                    return Ok(());
+                }
+                sink.push(op);
+                Ok(())
+            }
+        }
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func $f))
+              (func (export "a") call $f)
+              (func (export "b") nop))
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            verify_function_event_balance::<MCG, _, _, _>(&wasm_binary),
+            Ok(())
+        );
+        // The defined functions are numbered after the import.
+        assert_eq!(
+            verify_function_event_balance_with::<MCG, _, _, _, _>(&wasm_binary, || vec![Box::new(
+                DropFunctionEnd
+            )]),
+            Err("function 1 has no FunctionEnd event before function 2 begins".to_string())
+        );
+    }
+
+    #[test]
+    fn test_host_transition_cost() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use wasmer_runtime_core::{func, vm::Ctx};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "host" (func $host))
+              (func (export "run")
+                call $host
+                call $host
+                call $host))
+            "#,
+        )
+        .unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).with_host_transition_cost(10)),
+        )
+        .unwrap();
+
+        let calls = Rc::new(Cell::new(0));
+        let host_calls = calls.clone();
+        let import_object = imports! {
+            "env" => {
+                "host" => func!(move |_: &mut Ctx| host_calls.set(host_calls.get() + 1)),
+            },
+        };
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, 0);
+
+        let run: Func<(), ()> = instance.func("run").unwrap();
+        run.call().unwrap();
+        assert_eq!(calls.get(), 3);
+
+        // Three calls and the final `end`, and the transition into the host for each call.
+        assert_eq!(get_points_used(&instance), 4 + 3 * 10);
+    }
+
+    #[test]
+    fn test_dump_instrumented() {
+        #[cfg(feature = "llvm")]
+        use wasmer_llvm_backend::ModuleCodeGenerator as MCG;
+        use wasmer_middleware_common::cost_table::CostTable;
+        use wasmer_middleware_common::event_trace::TraceRecord;
+        use wasmer_middleware_common::opcode::OpcodeKind;
+        #[cfg(feature = "singlepass")]
+        use wasmer_singlepass_backend::ModuleCodeGenerator as MCG;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func $g)
+              (func (export "f") call $g))
+            "#,
+        )
+        .unwrap();
+        let table = CostTable::default().with_cost(OpcodeKind::Call, 7);
+        let ops = dump_instrumented::<MCG, _, _, _>(&wasm_binary, 1, &table).unwrap();
+
+        let op = |kind, immediates: &[i64]| TraceRecord::Operator {
+            kind,
+            immediates: immediates.to_vec(),
+        };
+        let position = |wanted: &InstrumentedOp| ops.iter().position(|op| op == wanted).unwrap();
+
+        let originals: Vec<_> = ops
+            .iter()
+            .filter_map(|op| match op {
+                InstrumentedOp::Original(record) => Some(record.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            originals,
+            vec![op(OpcodeKind::Call, &[0]), op(OpcodeKind::End, &[])]
+        );
+
+        // The call is charged and the limit checked before it, and the final `end` is charged
+        // before it.
+        let call = position(&InstrumentedOp::Original(op(OpcodeKind::Call, &[0])));
+        let end = position(&InstrumentedOp::Original(op(OpcodeKind::End, &[])));
+        let charge_call = position(&InstrumentedOp::Injected(op(OpcodeKind::I64Const, &[7])));
+        let check = position(&InstrumentedOp::Injected(TraceRecord::Breakpoint));
+        let charge_end = position(&InstrumentedOp::Injected(op(OpcodeKind::I64Const, &[1])));
+        assert!(charge_call < check && check < call);
+        assert!(call < charge_end && charge_end < end);
+        assert_eq!(
+            ops[charge_call + 1],
+            InstrumentedOp::Injected(op(OpcodeKind::I64Add, &[]))
+        );
+
+        assert!(dump_instrumented::<MCG, _, _, _>(&wasm_binary, 2, &table).is_err());
+    }
+
+    #[test]
+    fn test_set_time_limit() {
+        let wasm_binary = wat2wasm(WAT).unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(Metering::with_runtime_limit),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_time_limit(instance.context_mut(), 5, 100);
+        assert_eq!(get_execution_limit(&instance), 500);
+
+        // add_to(40, 3) costs 11 + 21 * 40 points, over the budget.
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        assert!(add_to.call(40, 3).is_err());
+        set_points_used(&mut instance, 0);
+
+        set_time_limit(instance.context_mut(), 10, 100);
+        assert_eq!(get_execution_limit(&instance), 1000);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        assert_eq!(add_to.call(40, 3).unwrap(), 783);
+        assert_eq!(get_points_used(&instance), 11 + 21 * 40);
+
+        set_time_limit(instance.context_mut(), std::u64::MAX, 2);
+        assert_eq!(get_execution_limit(&instance), std::u64::MAX);
+    }
+
+    #[test]
+    fn test_points_used_per_thread() {
+        let wasm_binary = wat2wasm(WAT).unwrap();
+        let module = compile_with(&wasm_binary, &get_compiler(std::u64::MAX)).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, 0);
+        assert_eq!(current_thread(instance.context()), 0);
+
+        // add_to(n, 3) costs 11 + 21 * n points.
+        set_current_thread(instance.context_mut(), 1);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(10, 3).unwrap();
+
+        set_current_thread(instance.context_mut(), 2);
+        assert_eq!(get_points_used(&instance), 0);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(20, 3).unwrap();
+
+        set_current_thread(instance.context_mut(), 1);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(10, 3).unwrap();
+
+        assert_eq!(current_thread(instance.context()), 1);
+        let ctx = instance.context();
+        assert_eq!(get_points_used_thread(ctx, 1), 2 * (11 + 21 * 10));
+        assert_eq!(get_points_used_thread(ctx, 2), 11 + 21 * 20);
+        assert_eq!(get_points_used_thread(ctx, 0), 0);
+        assert_eq!(get_points_used_thread(ctx, 3), 0);
+    }
+
+    #[test]
+    fn test_countdown_budget_per_thread() {
+        let wasm_binary = wat2wasm(WAT).unwrap();
+        let module = compile_with(&wasm_binary, &get_compiler_with(Metering::countdown)).unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // add_to(10, 3) costs 11 + 21 * 10 points.
+        set_current_thread(instance.context_mut(), 1);
+        set_remaining(instance.context_mut(), 1000);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(10, 3).unwrap();
+        assert_eq!(get_remaining(instance.context()), 1000 - 221);
+
+        // A new thread gets no budget, not a share of the budget of thread 1.
+        set_current_thread(instance.context_mut(), 2);
+        assert_eq!(get_remaining(instance.context()), 0);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        assert!(add_to.call(10, 3).is_err());
+        set_remaining(instance.context_mut(), 500);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(10, 3).unwrap();
+
+        // Each thread draws on its own budget only.
+        set_current_thread(instance.context_mut(), 1);
+        assert_eq!(get_remaining(instance.context()), 1000 - 221);
+        let add_to: Func<(i32, i32), i32> = instance.func("add_to").unwrap();
+        add_to.call(10, 3).unwrap();
+
+        let ctx = instance.context();
+        assert_eq!(get_points_used_thread(ctx, 1), 1000 - 2 * 221);
+        assert_eq!(get_points_used_thread(ctx, 2), 500 - 221);
+    }
+
+    #[test]
+    fn test_charge_before_traps() {
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (export "div") (param i32) (result i32)
+                i32.const 1
+                i32.const 2
+                i32.add
+                drop
+                i32.const 10
+                get_local 0
+                i32.div_s))
+            "#,
+        )
+        .unwrap();
+        let import_object = imports! {};
+
+        // The operators up to the division are charged when it traps.
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).with_charge_before_traps()),
+        )
+        .unwrap();
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, 0);
+        let div: Func<i32, i32> = instance.func("div").unwrap();
+        assert!(div.call(0).is_err());
+        assert_eq!(get_points_used(&instance), 7);
+
+        // Without a trap, the final `end` is charged too, as without the option.
+        set_points_used(&mut instance, 0);
+        let div: Func<i32, i32> = instance.func("div").unwrap();
+        assert_eq!(div.call(2).unwrap(), 5);
+        assert_eq!(get_points_used(&instance), 8);
+
+        let module = compile_with(&wasm_binary, &get_compiler(100)).unwrap();
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, 0);
+        let div: Func<i32, i32> = instance.func("div").unwrap();
+        assert!(div.call(0).is_err());
+        assert_eq!(get_points_used(&instance), 0);
+    }
+
+    #[test]
+    fn test_surcharge_breakdown() {
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func (export "grow")
+                i32.const 1
+                memory.grow
+                drop
+                i32.const 1
+                memory.grow
+                drop
+                i32.const 1
+                memory.grow
+                drop))
+            "#,
+        )
+        .unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| {
+                Metering::new(1000)
+                    .with_memory_grow_cost(50)
+                    .with_surcharge_breakdown()
+            }),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, 0);
+
+        let grow: Func<(), ()> = instance.func("grow").unwrap();
+        grow.call().unwrap();
+
+        let pages_grown = instance.context().memory(0).size().0 as u64 - 1;
+        assert_eq!(pages_grown, 3);
+        let breakdown = surcharge_breakdown(instance.context());
+        assert_eq!(breakdown.memory_grow, pages_grown * 50);
+        assert_eq!(breakdown.total(), breakdown.memory_grow);
+        // Nine operators and the final `end` cost their instruction cost.
+        assert_eq!(get_points_used(&instance), 10 + breakdown.total());
+
+        reset_surcharge_breakdown(instance.context_mut());
+        assert_eq!(
+            surcharge_breakdown(instance.context()),
+            SurchargeBreakdown::default()
+        );
+    }
+
+    #[test]
+    fn test_exempt_functions() {
+        use std::collections::HashSet;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func $trusted (export "trusted")
+                nop
+                nop
+                nop)
+              (func $untrusted (export "untrusted")
+                nop
+                nop
+                nop)
+              (func (export "call_trusted")
+                call $trusted))
+            "#,
+        )
+        .unwrap();
+        let exempt: HashSet<u32> = vec![0].into_iter().collect();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || Metering::new(100).with_exempt_functions(exempt.clone())),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 0);
+        let trusted: Func<(), ()> = instance.func("trusted").unwrap();
+        trusted.call().unwrap();
+        assert_eq!(get_points_used(&instance), 0);
+
+        // Three nops and the final `end`.
+        let untrusted: Func<(), ()> = instance.func("untrusted").unwrap();
+        untrusted.call().unwrap();
+        assert_eq!(get_points_used(&instance), 4);
+
+        // The call into the exempt function is charged, but not its body.
+        set_points_used(&mut instance, 0);
+        let call_trusted: Func<(), ()> = instance.func("call_trusted").unwrap();
+        call_trusted.call().unwrap();
+        assert_eq!(get_points_used(&instance), 2);
+    }
+
+    #[test]
+    fn test_operators_cost() {
+        use wasmer_middleware_common::cost_table::CostTable;
+        use wasmer_middleware_common::opcode::OpcodeKind;
+        use wasmer_runtime_core::wasmparser::Operator;
+
+        let table = CostTable::new(2)
+            .with_cost(OpcodeKind::I32Add, 5)
+            .with_cost(OpcodeKind::Nop, 0);
+        // i32.const 5, i32.const 3, i32.add, drop, nop, end
+        let bytes = [0x41, 0x05, 0x41, 0x03, 0x6a, 0x1a, 0x01, 0x0b];
+        let expected: u64 = [
+            Operator::I32Const { value: 5 },
+            Operator::I32Const { value: 3 },
+            Operator::I32Add,
+            Operator::Drop,
+            Operator::Nop,
+            Operator::End,
+        ]
+        .iter()
+        .map(|op| table.operator_cost(op))
+        .sum();
+        assert_eq!(table.operators_cost(&bytes), Ok(expected));
+        // The nop is free.
+        assert_eq!(expected, 2 + 2 + 5 + 2 + 2);
+
+        // An `i32.const` missing its immediate.
+        assert!(table.operators_cost(&[0x41]).is_err());
+    }
+
+    #[test]
+    fn test_global_peak_points() {
+        let wasm_binary = wat2wasm(WAT).unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(std::u64::MAX).with_global_peak_points()),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut small = module.instantiate(&import_object).unwrap();
+        let mut large = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut small, 0);
+        set_points_used(&mut large, 0);
+        reset_global_peak_points();
+
+        // add_to(n, 3) costs 11 + 21 * n points.
+        let add_to: Func<(i32, i32), i32> = small.func("add_to").unwrap();
+        add_to.call(10, 3).unwrap();
+        let add_to: Func<(i32, i32), i32> = large.func("add_to").unwrap();
+        add_to.call(20, 3).unwrap();
+        assert_eq!(global_peak_points(), 11 + 21 * 20);
+
+        reset_global_peak_points();
+        assert_eq!(global_peak_points(), 0);
+        set_points_used(&mut small, 0);
+        let add_to: Func<(i32, i32), i32> = small.func("add_to").unwrap();
+        add_to.call(10, 3).unwrap();
+        assert_eq!(global_peak_points(), 11 + 21 * 10);
+    }
+
+    #[test]
+    fn test_result_copy_cost_per_value() {
+        // The backends do not support multi-value returns yet, so a function returning a value
+        // is compared with one returning none.
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (export "none")
+                i32.const 1
+                drop)
+              (func (export "one") (result i32)
+                i32.const 1))
+            "#,
+        )
+        .unwrap();
+        let import_object = imports! {};
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).with_result_copy_cost_per_value(5)),
+        )
+        .unwrap();
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, 0);
+        let none: Func<(), ()> = instance.func("none").unwrap();
+        none.call().unwrap();
+        assert_eq!(get_points_used(&instance), 3);
+
+        set_points_used(&mut instance, 0);
+        let one: Func<(), i32> = instance.func("one").unwrap();
+        assert_eq!(one.call().unwrap(), 1);
+        assert_eq!(get_points_used(&instance), 2 + 5);
+    }
+
+    #[test]
+    fn test_trap_logger() {
+        use wasmer_middleware_common::trap_logger::{
+            record_trap, reset_trap_log, trap_log, TrapKind, TrapLogger, TrapRecord,
+        };
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func $f0 (export "nop"))
+              (func $f1 (export "div") (param i32) (result i32)
+                i32.const 10
+                get_local 0
+                i32.div_u))
+            "#,
+        )
+        .unwrap();
+        let module = compile_with(&wasm_binary, &get_compiler_with(TrapLogger::new)).unwrap();
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // A call that does not trap leaves nothing to record.
+        let div: Func<i32, i32> = instance.func("div").unwrap();
+        assert_eq!(div.call(2).unwrap(), 5);
+        assert_eq!(record_trap(instance.context_mut()), None);
+
+        let div: Func<i32, i32> = instance.func("div").unwrap();
+        assert!(div.call(0).is_err());
+        let record = TrapRecord {
+            func_index: 1,
+            trap_kind: TrapKind::IntegerDivision,
+        };
+        assert_eq!(record_trap(instance.context_mut()), Some(record));
+        assert_eq!(record_trap(instance.context_mut()), None);
+        assert_eq!(trap_log(instance.context()), vec![record]);
+
+        reset_trap_log(instance.context_mut());
+        assert!(trap_log(instance.context()).is_empty());
+    }
+
+    #[test]
+    fn test_gas_overrun() {
+        use wasmer_runtime_core::error::RuntimeError;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (export "run")
+                nop
+                nop
+                nop
+                nop
+                nop
+                br 0))
+            "#,
+        )
+        .unwrap();
+        let module = compile_with(&wasm_binary, &get_compiler(2)).unwrap();
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+        assert_eq!(get_gas_overrun(instance.context()), 0);
+
+        // The limit is checked at the `br`, once the six operators are charged.
+        set_points_used(&mut instance, 0);
+        let run: Func<(), ()> = instance.func("run").unwrap();
+        match run.call().unwrap_err() {
+            RuntimeError::Error { data } => {
+                let error = data.downcast_ref::<ExecutionLimitExceededError>().unwrap();
+                assert_eq!(error.exceeded_by, 4);
+                assert_eq!(error.to_string(), "out of gas: exceeded limit by 4 points");
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(get_gas_overrun(instance.context()), 4);
+    }
+
+    #[test]
+    fn test_audit_coverage() {
+        use wasmer_middleware_common::{cost_table::CostTable, opcode::OpcodeKind};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (export "mul_add") (param i32) (result i32)
+                get_local 0
+                i32.const 3
+                i32.mul
+                i32.const 1
+                i32.add))
+            "#,
+        )
+        .unwrap();
+        let table = CostTable::new(1)
+            .with_cost(OpcodeKind::GetLocal, 1)
+            .with_cost(OpcodeKind::I32Const, 1)
+            .with_cost(OpcodeKind::I32Add, 1)
+            .with_cost(OpcodeKind::End, 1);
+        assert_eq!(
+            audit_coverage(&wasm_binary, &table).unwrap(),
+            vec![OpcodeKind::I32Mul]
+        );
+
+        let table = table.with_cost(OpcodeKind::I32Mul, 3);
+        assert!(audit_coverage(&wasm_binary, &table).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_branch_costs() {
+        // The `br_if` of `backward` does not branch, so its five operators are charged, while
+        // the `br` of `forward` skips the `end` of its block.
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (export "backward")
+                loop
+                  i32.const 0
+                  br_if 0
+                end)
+              (func (export "forward")
+                i32.const 1
+                if
+                  br 0
+                end))
+            "#,
+        )
+        .unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).with_branch_costs(1, 10)),
+        )
+        .unwrap();
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        set_points_used(&mut instance, 0);
+        let backward: Func<(), ()> = instance.func("backward").unwrap();
+        backward.call().unwrap();
+        assert_eq!(get_points_used(&instance), 5 + 10);
+
+        set_points_used(&mut instance, 0);
+        let forward: Func<(), ()> = instance.func("forward").unwrap();
+        forward.call().unwrap();
+        assert_eq!(get_points_used(&instance), 4 + 1);
+    }
+
+    #[test]
+    fn test_function_cost_bounds() {
+        use wasmer_middleware_common::{cost_table::CostTable, opcode::OpcodeKind};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (export "select_arm") (param i32) (result i32)
+                get_local 0
+                if (result i32)
+                  i32.const 1
+                else
+                  i32.const 2
+                  i32.const 3
+                  i32.add
+                end))
+            "#,
+        )
+        .unwrap();
+        let table = CostTable::new(1).with_cost(OpcodeKind::I32Add, 10);
+        let bounds = function_cost_bounds(&wasm_binary, &table).unwrap();
+        // The `else` is charged with the first arm, and the `end` of the block with the second.
+        assert_eq!(bounds, vec![(4 + 1, 2 + 12 + 1 + 1)]);
+
+        // The bounds are the points charged by the cheaper and by the more expensive arm.
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || Metering::new(100).with_cost_table(table.clone())),
+        )
+        .unwrap();
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, 0);
+        let select_arm: Func<i32, i32> = instance.func("select_arm").unwrap();
+        assert_eq!(select_arm.call(1).unwrap(), 1);
+        assert_eq!(get_points_used(&instance), bounds[0].0);
+
+        set_points_used(&mut instance, 0);
+        let select_arm: Func<i32, i32> = instance.func("select_arm").unwrap();
+        assert_eq!(select_arm.call(0).unwrap(), 5);
+        assert_eq!(get_points_used(&instance), bounds[0].1);
+    }
+
+    #[test]
+    fn test_charge_element_segments() {
+        let instantiate = |elements: &str| {
+            let wasm_binary = wat2wasm(format!(
+                r#"
+                (module
+                  (table 4 anyfunc)
+                  (func $f)
+                  (elem (i32.const 0) {}))
+                "#,
+                elements
+            ))
+            .unwrap();
+            let module = compile_with(
+                &wasm_binary,
+                &get_compiler_with(|| Metering::new(100).with_element_cost(2)),
+            )
+            .unwrap();
+            let import_object = imports! {};
+            module.instantiate(&import_object).unwrap()
+        };
+
+        let mut small = instantiate("$f");
+        assert_eq!(charge_element_segments(&mut small, 2), 2);
+        let mut large = instantiate("$f $f $f");
+        assert_eq!(charge_element_segments(&mut large, 2), 3 * 2);
+        assert!(get_points_used(&large) > get_points_used(&small));
+    }
+
+    #[test]
+    fn test_charge_element_segments_countdown() {
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (table 4 anyfunc)
+              (func $f)
+              (elem (i32.const 0) $f $f $f))
+            "#,
+        )
+        .unwrap();
+        let module = compile_with(
+            &embed_countdown(&wasm_binary),
+            &get_compiler_with(Metering::countdown),
+        )
+        .unwrap();
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+
+        // The charge is taken from the budget, down to zero.
+        set_remaining(instance.context_mut(), 10);
+        assert_eq!(charge_element_segments(&mut instance, 2), 3 * 2);
+        assert_eq!(get_remaining(instance.context()), 10 - 3 * 2);
+        assert_eq!(charge_element_segments(&mut instance, 2), 3 * 2);
+        assert_eq!(get_remaining(instance.context()), 0);
+        assert_eq!(instantiation_cost(&instance), 2 * 3 * 2);
+    }
+
+    #[test]
+    fn test_table_fill_cost() {
+        let instantiate = |elements: &str, table_fill_cost: Option<u64>| {
+            let mut wasm_binary = wat2wasm(format!(
+                r#"
+                (module
+                  (table 4 anyfunc)
+                  (func $f)
+                  (elem (i32.const 0) {}))
+                "#,
+                elements
+            ))
+            .unwrap();
+            if let Some(cost) = table_fill_cost {
+                wasm_binary = embed_table_fill_cost(&wasm_binary, cost);
+            }
+            let module =
+                compile_with(&wasm_binary, &get_compiler_with(|| Metering::new(100))).unwrap();
+            let import_object = imports! {};
+            let mut instance = module.instantiate(&import_object).unwrap();
+            let charged = apply_table_fill_cost(&mut instance);
+            (instance, charged)
+        };
+
+        // The fill is charged before any call, for each element copied into the table.
+        let (small, charged) = instantiate("$f", Some(2));
+        assert_eq!(charged, 2);
+        assert_eq!(get_points_used(&small), 2);
+        let (large, charged) = instantiate("$f $f $f", Some(2));
+        assert_eq!(charged, 3 * 2);
+        assert_eq!(get_points_used(&large), 3 * 2);
+        assert_eq!(instantiation_cost(&large), 3 * 2);
+
+        // Nothing is charged without an embedded cost.
+        let (unpriced, charged) = instantiate("$f $f $f", None);
+        assert_eq!(charged, 0);
+        assert_eq!(get_points_used(&unpriced), 0);
+    }
+
+    #[test]
+    fn test_scoped_limit() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use wasmer_runtime_core::{
+            error::CallResult, func, structures::TypedIndex, types::TableIndex, types::Value,
+            vm::Ctx,
+        };
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "callout" (func $callout))
+              (table 1 anyfunc)
+              (elem (i32.const 0) $spin)
+              (func $spin
+                loop
+                  br 0
+                end)
+              (func (export "run") (result i32)
+                call $callout
+                i32.const 7))
+            "#,
+        )
+        .unwrap();
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(Metering::with_runtime_limit),
+        )
+        .unwrap();
+
+        // The result of the scoped callout and the points it used.
+        type ScopedCall = (CallResult<Vec<Value>>, u64);
+
+        // The callout spins in its own budget of 10 points, until it traps.
+        let scoped: Rc<RefCell<Option<ScopedCall>>> = Rc::new(RefCell::new(None));
+        let callout_scoped = scoped.clone();
+        let import_object = imports! {
+            "env" => {
+                "callout" => func!(move |ctx: &mut Ctx| {
+                    *callout_scoped.borrow_mut() = Some(with_scoped_limit(ctx, 10, |ctx| {
+                        ctx.call_with_table_index(TableIndex::new(0), &[])
+                    }));
+                }),
+            },
+        };
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_execution_limit(&mut instance, 100);
+        set_points_used(&mut instance, 0);
+
+        let run: Func<(), i32> = instance.func("run").unwrap();
+        assert_eq!(run.call().unwrap(), 7);
+        let (result, scoped_used) = scoped.borrow_mut().take().unwrap();
+        assert!(result.is_err());
+        assert!(scoped_used >= 10);
+
+        // The outer call is charged its `call`, `i32.const` and `end`, under its own limit.
+        assert_eq!(get_points_used(&instance), 3);
+        assert_eq!(get_execution_limit(&instance), 100);
+    }
+
+    #[test]
+    fn test_checkpoint_counts() {
+        use std::collections::HashSet;
+        use std::time::Instant;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "host" (func))
+              (func (export "div") (param i32 i32) (result i32)
+                get_local 0
+                get_local 1
+                i32.div_u)
+              (func (export "spin")
+                loop
+                  br 0
+                end))
+            "#,
+        )
+        .unwrap();
+        // The `end` of `div`, and the `loop`, the `br` and the two `end`s of `spin`.
+        assert_eq!(
+            Metering::new(100).checkpoint_counts(&wasm_binary).unwrap(),
+            vec![1, 4]
+        );
+        assert_eq!(
+            Metering::new(100)
+                .with_charge_before_traps()
+                .checkpoint_counts(&wasm_binary)
+                .unwrap(),
+            vec![2, 4]
+        );
+        // The indices of the exempt functions count the imported function.
+        let exempt: HashSet<u32> = [2].iter().cloned().collect();
+        assert_eq!(
+            Metering::new(100)
+                .with_exempt_functions(exempt)
+                .checkpoint_counts(&wasm_binary)
+                .unwrap(),
+            vec![1, 0]
+        );
+        // Counting stops at the compile deadline, as the compilation does.
+        let error = Metering::new(100)
+            .with_compile_deadline(Instant::now())
+            .checkpoint_counts(&wasm_binary)
+            .unwrap_err();
+        assert!(error.contains("compilation deadline exceeded in function 1"));
+    }
+
+    #[test]
+    fn test_event_trace_offsets() {
+        use std::io::{self, Write};
+        use std::sync::{Arc, Mutex};
+        use wasmer_middleware_common::event_trace::EventTrace;
+
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (result i32)
+                i32.const 1
+                i32.const 2
+                i32.add)
+              (func
+                loop
+                  br 0
+                end))
+            "#,
+        )
+        .unwrap();
+
+        let trace = SharedBuffer::default();
+        let writer = trace.clone();
+        let wasm = wasm_binary.clone();
+        compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || {
+                EventTrace::with_writer(writer.clone())
+                    .with_offsets(&wasm)
+                    .unwrap()
+            }),
+        )
+        .unwrap();
+
+        let trace = String::from_utf8(trace.0.lock().unwrap().clone()).unwrap();
+        let mut functions: Vec<Vec<usize>> = Vec::new();
+        for line in trace.lines() {
+            if line.starts_with("FunctionBegin") {
+                functions.push(Vec::new());
+            } else if line.starts_with("@0x") {
+                let offset = &line[3..line.find(' ').unwrap()];
+                let offset = usize::from_str_radix(offset, 16).unwrap();
+                assert!(offset < wasm_binary.len());
+                functions.last_mut().unwrap().push(offset);
+            } else {
+                // Internal events have no offset.
+                assert!(!line.starts_with('@'));
+            }
+        }
+        // Every operator of both functions is traced with its offset, in increasing order.
+        assert_eq!(
+            functions.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![4, 4]
+        );
+        let offsets: Vec<usize> = functions.into_iter().flatten().collect();
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_opcode_histogram_round_trip() {
+        use wasmer_middleware_common::{opcode::OpcodeKind, opcode_profiler::OpcodeProfiler};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (export "sub_loop") (param $n i32)
+                loop
+                  get_local $n
+                  i32.const 1
+                  i32.sub
+                  tee_local $n
+                  br_if 0
+                end))
+            "#,
+        )
+        .unwrap();
+        let module = compile_with(&wasm_binary, &get_compiler_with(OpcodeProfiler::new)).unwrap();
+        let import_object = imports! {};
+
+        let first = module.instantiate(&import_object).unwrap();
+        let sub_loop: Func<i32, ()> = first.func("sub_loop").unwrap();
+        sub_loop.call(5).unwrap();
+        let saved = OpcodeProfiler::serialize(&OpcodeProfiler::histogram(first.context()));
+
+        let mut second = module.instantiate(&import_object).unwrap();
+        OpcodeProfiler::restore(
+            second.context_mut(),
+            OpcodeProfiler::deserialize(&saved).unwrap(),
+        );
+        let sub_loop: Func<i32, ()> = second.func("sub_loop").unwrap();
+        sub_loop.call(3).unwrap();
+
+        let histogram = OpcodeProfiler::histogram(second.context());
+        assert_eq!(histogram.get(&OpcodeKind::I32Sub), Some(&(5 + 3)));
+        assert_eq!(histogram.get(&OpcodeKind::BrIf), Some(&(5 + 3)));
+        assert_eq!(histogram.get(&OpcodeKind::Loop), Some(&2));
+
+        assert!(OpcodeProfiler::deserialize(&saved[..saved.len() - 1]).is_err());
+        assert!(OpcodeProfiler::deserialize(&[]).is_err());
+    }
+
+    #[test]
+    fn test_deployment_cost() {
+        let small = wat2wasm(
+            r#"
+            (module
+              (func (export "f") (result i32)
+                i32.const 1))
+            "#,
+        )
+        .unwrap();
+        let large = wat2wasm(
+            r#"
+            (module
+              (func (export "f") (result i32)
+                i32.const 1
+                i32.const 2
+                i32.add
+                i32.const 3
+                i32.mul)
+              (func (export "g") (result i32)
+                i32.const 4))
+            "#,
+        )
+        .unwrap();
+
+        assert!(deployment_cost(&large, 1) > deployment_cost(&small, 1));
+        // The cost is proportional to the code size.
+        assert_eq!(deployment_cost(&small, 7), 7 * deployment_cost(&small, 1));
+        assert_eq!(deployment_cost(&large, 7), 7 * deployment_cost(&large, 1));
+        // A data section does not change the cost.
+        let with_data = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (data (i32.const 0) "some data that is not code")
+              (func (export "f") (result i32)
+                i32.const 1))
+            "#,
+        )
+        .unwrap();
+        assert_eq!(deployment_cost(&with_data, 1), deployment_cost(&small, 1));
+    }
+
+    #[test]
+    fn test_soft_fail() {
+        use std::sync::{Arc, Mutex};
+        use wasmer_runtime_core::{
+            error::{CallError, RuntimeError},
+            types::Value,
+        };
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func $spin (result i32)
+                loop
+                  br 0
+                end
+                i32.const 1)
+              (func (export "run") (result i32)
+                call $spin
+                i32.const 7
+                i32.add))
+            "#,
+        )
+        .unwrap();
+        let import_object = imports! {};
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(100).soft_fail()),
+        )
+        .unwrap();
+        let mut instance = module.instantiate(&import_object).unwrap();
+        match call_soft(&mut instance, "run", &[]).unwrap_err() {
+            CallError::Runtime(RuntimeError::Error { data }) => {
+                let error = data.downcast_ref::<ExecutionLimitExceededError>().unwrap();
+                assert_eq!(error.exceeded_by, get_gas_overrun(instance.context()));
+            }
+            _ => unreachable!(),
+        }
+        // Without `call_soft`, the call returns the zero passed on by `run` from `$spin`.
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        set_on_limit_exceeded(instance.context_mut(), move |func_index, _| {
+            events_clone.lock().unwrap().push(func_index);
+        });
+        set_points_used(&mut instance, 0);
+        assert_eq!(instance.call("run", &[]).unwrap(), vec![Value::I32(0)]);
+        assert_eq!(*events.lock().unwrap(), vec![0]);
+        // The failure left pending is cleared by the next call, whose own failure fires the
+        // callback again.
+        set_points_used(&mut instance, 0);
+        assert_eq!(instance.call("run", &[]).unwrap(), vec![Value::I32(0)]);
+        assert_eq!(*events.lock().unwrap(), vec![0, 0]);
+
+        // By default, the call traps.
+        let module = compile_with(&wasm_binary, &get_compiler(100)).unwrap();
+        let instance = module.instantiate(&import_object).unwrap();
+        match instance.call("run", &[]).unwrap_err() {
+            CallError::Runtime(RuntimeError::Error { data }) => {
+                assert!(data.downcast_ref::<ExecutionLimitExceededError>().is_some());
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_export_worst_case() {
+        use wasmer_middleware_common::cost_table::CostTable;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "host" (func $host))
+              (export "host" (func $host))
+              (func (export "simple")
+                nop)
+              (func (export "complex") (param i32) (result i32)
+                get_local 0
+                if (result i32)
+                  i32.const 1
+                else
+                  loop
+                    get_local 0
+                    br_if 0
+                  end
+                  i32.const 2
+                  i32.const 3
+                  i32.add
+                end))
+            "#,
+        )
+        .unwrap();
+        let worst_cases = export_worst_case(&wasm_binary, &CostTable::new(1)).unwrap();
+        // The imported function has no body to estimate.
+        assert_eq!(worst_cases.len(), 2);
+        // `nop` and `end`.
+        assert_eq!(worst_cases["simple"], 2);
+        assert!(worst_cases["complex"] > worst_cases["simple"]);
+    }
+
+    #[test]
+    fn test_resource_meter() {
+        #[cfg(feature = "llvm")]
+        use wasmer_llvm_backend::ModuleCodeGenerator as MCG;
+        use wasmer_middleware_common::{
+            build_chain,
+            resource_meter::{ResourceLimitExceededError, ResourceMeter},
+        };
+        use wasmer_runtime_core::{error::RuntimeError, func, vm::Ctx, wasmparser::Operator};
+        #[cfg(feature = "singlepass")]
+        use wasmer_singlepass_backend::ModuleCodeGenerator as MCG;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "write" (func $write (param i32)))
+              (memory 1)
+              (func (export "store_twice")
+                i32.const 0
+                i32.const 1
+                i32.store
+                i32.const 4
+                i32.const 2
+                i32.store)
+              (func (export "write") (param i32)
+                get_local 0
+                call $write))
+            "#,
+        )
+        .unwrap();
+
+        // A storage meter of 10 bytes, charging 4 bytes per `i32.store`.
+        let meter = ResourceMeter::new(0, |op| match *op {
+            Operator::I32Store { .. } => 4,
+            _ => 0,
+        })
+        .with_limit(10);
+        let compiler_meter = meter.clone();
+        let compiler: StreamingCompiler<MCG, _, _, _, _> = StreamingCompiler::new(move || {
+            let mws: Vec<Box<dyn FunctionMiddleware<Error = String>>> = vec![
+                Box::new(compiler_meter.clone()),
+                Box::new(Metering::new(1000)),
+            ];
+            build_chain(mws)
+        });
+        let module = compile_with(&wasm_binary, &compiler).unwrap();
+        let host_meter = meter.clone();
+        let import_object = imports! {
+            "env" => {
+                "write" => func!(move |ctx: &mut Ctx, len: i32| host_meter.charge(ctx, len as u64)),
+            },
+        };
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, 0);
+
+        let store_twice: Func<(), ()> = instance.func("store_twice").unwrap();
+        store_twice.call().unwrap();
+        assert_eq!(meter.used(instance.context()), 8);
+        let write: Func<i32, ()> = instance.func("write").unwrap();
+        write.call(2).unwrap();
+        assert_eq!(meter.used(instance.context()), 10);
+
+        // The host function traps past the limit.
+        match write.call(1).unwrap_err() {
+            RuntimeError::Error { data } => {
+                let error = data.downcast_ref::<ResourceLimitExceededError>().unwrap();
+                assert_eq!((error.used, error.limit), (11, 10));
+            }
+            _ => unreachable!(),
+        }
+
+        // So does the metered code, while the gas is far from its limit.
+        meter.set_used(instance.context_mut(), 5);
+        let store_twice: Func<(), ()> = instance.func("store_twice").unwrap();
+        match store_twice.call().unwrap_err() {
+            RuntimeError::Error { data } => {
+                let error = data.downcast_ref::<ResourceLimitExceededError>().unwrap();
+                assert_eq!(error.used, 13);
+            }
+            _ => unreachable!(),
+        }
+        assert!(get_points_used(&instance) < 1000);
+    }
+
+    #[test]
+    fn test_event_trace_buffered_per_function() {
+        use std::io::{self, Write};
+        use std::sync::{Arc, Mutex};
+        use std::thread;
+        use wasmer_middleware_common::event_trace::EventTrace;
+
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (result i32)
+                i32.const 1
+                i32.const 2
+                i32.add)
+              (func
+                loop
+                  br 0
+                end))
+            "#,
+        )
+        .unwrap();
+
+        // Two modules compiled at once write to the same trace.
+        let trace = SharedBuffer::default();
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let writer = trace.clone();
+                let wasm_binary = wasm_binary.clone();
+                thread::spawn(move || {
+                    compile_with(
+                        &wasm_binary,
+                        &get_compiler_with(move || {
+                            EventTrace::with_writer(writer.clone()).buffered_per_function()
+                        }),
+                    )
+                    .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let trace = String::from_utf8(trace.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        let mut functions = 0;
+        let mut in_function = false;
+        for line in lines {
+            if line.starts_with("FunctionBegin") {
+                assert!(!in_function);
+                in_function = true;
+                functions += 1;
+            } else if line == "FunctionEnd" {
+                assert!(in_function);
+                in_function = false;
+            } else {
+                assert!(in_function);
+            }
+        }
+        assert!(!in_function);
+        assert_eq!(functions, 4);
+    }
+
+    #[test]
+    fn test_charge_import_validation() {
+        use wasmer_runtime_core::{func, vm::Ctx};
+
+        let instantiate = |imports: &str| {
+            let wasm_binary = wat2wasm(format!("(module {})", imports)).unwrap();
+            let module = compile_with(&wasm_binary, &get_compiler(100)).unwrap();
+            let import_object = imports! {
+                "env" => {
+                    "f" => func!(|_: &mut Ctx| {}),
+                    "g" => func!(|_: &mut Ctx| {}),
+                    "h" => func!(|_: &mut Ctx| {}),
+                },
+            };
+            module.instantiate(&import_object).unwrap()
+        };
+
+        let mut few = instantiate(r#"(import "env" "f" (func))"#);
+        assert_eq!(charge_import_validation(&mut few, 5), 5);
+        let mut many = instantiate(
+            r#"
+            (import "env" "f" (func))
+            (import "env" "g" (func))
+            (import "env" "h" (func))
+            "#,
+        );
+        assert_eq!(charge_import_validation(&mut many, 5), 3 * 5);
+        assert_eq!(get_points_used(&many) - get_points_used(&few), 2 * 5);
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        use std::sync::{Arc, Mutex};
+        use wasmer_runtime_core::error::{CallError, RuntimeError};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func (export "run")
+                nop
+                nop
+                nop))
+            "#,
+        )
+        .unwrap();
+        let import_object = imports! {};
+
+        // The points charged by a call, without overflowing.
+        let module = compile_with(&wasm_binary, &get_compiler(u64::max_value())).unwrap();
+        let mut instance = module.instantiate(&import_object).unwrap();
+        instance.call("run", &[]).unwrap();
+        let cost = get_points_used(&instance);
+        assert!(cost > 1);
+
+        // By default, the points used wrap around.
+        set_points_used(&mut instance, u64::max_value() - 1);
+        instance.call("run", &[]).unwrap();
+        assert_eq!(get_points_used(&instance), cost - 2);
+
+        let attempted = Arc::new(Mutex::new(Vec::new()));
+        let callback_attempted = attempted.clone();
+        let policy = OverflowPolicy::Callback(Arc::new(move |_ctx, points| {
+            callback_attempted.lock().unwrap().push(points);
+        }));
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(move || {
+                Metering::new(u64::max_value()).with_overflow_policy(policy.clone())
+            }),
+        )
+        .unwrap();
+        let mut instance = module.instantiate(&import_object).unwrap();
+        instance.call("run", &[]).unwrap();
+        assert_eq!(get_points_used(&instance), cost);
+        assert!(attempted.lock().unwrap().is_empty());
+        set_points_used(&mut instance, u64::max_value() - 1);
+        instance.call("run", &[]).unwrap();
+        assert_eq!(get_points_used(&instance), u64::max_value());
+        assert_eq!(*attempted.lock().unwrap(), vec![cost]);
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| {
+                Metering::new(u64::max_value()).with_overflow_policy(OverflowPolicy::Trap)
+            }),
+        )
+        .unwrap();
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_points_used(&mut instance, u64::max_value() - 1);
+        match instance.call("run", &[]).unwrap_err() {
+            CallError::Runtime(RuntimeError::Error { data }) => {
+                let error = data.downcast_ref::<PointsOverflowError>().unwrap();
+                assert_eq!(error.attempted, cost);
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(get_points_used(&instance), u64::max_value() - 1);
+    }
+
+    #[test]
+    fn test_points_at_breakpoint() {
+        use std::sync::{Arc, Mutex};
+        #[cfg(feature = "llvm")]
+        use wasmer_llvm_backend::ModuleCodeGenerator as MCG;
+        use wasmer_middleware_common::{build_chain, call_breakpoints::CallBreakpoints};
+        #[cfg(feature = "singlepass")]
+        use wasmer_singlepass_backend::ModuleCodeGenerator as MCG;
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (func $f)
+              (func $g)
+              (func (export "run")
+                call $f
+                nop
+                nop
+                call $g))
+            "#,
+        )
+        .unwrap();
+
+        let points = Arc::new(Mutex::new(Vec::new()));
+        let breakpoint_points = points.clone();
+        let compiler: StreamingCompiler<MCG, _, _, _, _> = StreamingCompiler::new(move || {
+            let points = breakpoint_points.clone();
+            let mws: Vec<Box<dyn FunctionMiddleware<Error = String>>> = vec![
+                Box::new(Metering::new(100).uniform(1)),
+                Box::new(CallBreakpoints::with_context(move |_, ctx| {
+                    points
+                        .lock()
+                        .unwrap()
+                        .push(ctx.map(points_at_breakpoint).unwrap());
+                })),
+            ];
+            build_chain(mws)
+        });
+        let module = compile_with(&wasm_binary, &compiler).unwrap();
+        let instance = module.instantiate(&imports! {}).unwrap();
+
+        let run: Func<(), ()> = instance.func("run").unwrap();
+        run.call().unwrap();
+        let points = points.lock().unwrap();
+        assert_eq!(points.len(), 2);
+        // The first `call` is charged before its breakpoint.
+        assert_eq!(points[0], 1);
+        // Between the two breakpoints, `$f` and the `nop`s with the second `call`.
+        assert_eq!(points[1] - points[0], 1 + 3);
+    }
+
+    #[test]
+    fn test_max_checkpoints_per_function() {
+        let mut body = String::new();
+        for _ in 0..50 {
+            body.push_str("(if (get_local 0) (then nop) (else nop nop))\n");
+            body.push_str("(block nop)\n");
+        }
+        let wasm_binary = wat2wasm(format!(
+            r#"(module (func (export "large") (param i32) {}))"#,
+            body
+        ))
+        .unwrap();
+
+        let fine = Metering::new(u64::max_value());
+        let coarse = Metering::new(u64::max_value()).with_max_checkpoints_per_function(8);
+        // The `if`, `else` and `end` of each `if`, the `block` and `end` of each block, and the
+        // final `end`.
+        assert_eq!(fine.checkpoint_counts(&wasm_binary).unwrap(), vec![251]);
+        assert_eq!(coarse.checkpoint_counts(&wasm_binary).unwrap(), vec![8]);
+
+        let fine_module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| Metering::new(u64::max_value())),
+        )
+        .unwrap();
+        let coarse_module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| {
+                Metering::new(u64::max_value()).with_max_checkpoints_per_function(8)
+            }),
+        )
+        .unwrap();
+        for &condition in &[0, 1] {
+            let points = |module: &wasmer_runtime_core::Module| {
+                let instance = module.instantiate(&imports! {}).unwrap();
+                let large: Func<i32, ()> = instance.func("large").unwrap();
+                large.call(condition).unwrap();
+                get_points_used(&instance)
+            };
+            let (fine_points, coarse_points) = (points(&fine_module), points(&coarse_module));
+            // The coarse metering charges the `else` of each `if`, the costlier path, whichever
+            // runs.
+            if condition == 0 {
+                assert!(coarse_points >= fine_points);
+            } else {
+                assert!(coarse_points > fine_points);
+            }
+        }
+    }
+
+    #[test]
+    fn test_imports_only() {
+        use wasmer_runtime_core::{func, vm::Ctx};
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (import "env" "host" (func $host))
+              (func (export "compute") (result i32) (local i32)
+                loop
+                  get_local 0
+                  i32.const 1
+                  i32.add
+                  tee_local 0
+                  i32.const 1000
+                  i32.lt_u
+                  br_if 0
+                end
+                get_local 0)
+              (func (export "io")
+                call $host
+                call $host
+                call $host))
+            "#,
+        )
+        .unwrap();
+        let import_object = imports! {
+            "env" => {
+                "host" => func!(|_: &mut Ctx| {}),
+            },
+        };
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| {
+                Metering::new(25)
+                    .imports_only()
+                    .with_cross_module_call_cost(10)
+            }),
+        )
+        .unwrap();
+        let instance = module.instantiate(&import_object).unwrap();
+
+        let compute: Func<(), i32> = instance.func("compute").unwrap();
+        assert_eq!(compute.call().unwrap(), 1000);
+        assert_eq!(get_points_used(&instance), 0);
+
+        // The limit is checked after each call is charged, so the third traps.
+        let io: Func<(), ()> = instance.func("io").unwrap();
+        assert!(io.call().is_err());
+        assert_eq!(get_points_used(&instance), 3 * 10);
+
+        let module = compile_with(
+            &wasm_binary,
+            &get_compiler_with(|| {
+                Metering::new(100)
+                    .imports_only()
+                    .with_cross_module_call_cost(10)
+            }),
+        )
+        .unwrap();
+        let instance = module.instantiate(&import_object).unwrap();
+        let io: Func<(), ()> = instance.func("io").unwrap();
+        io.call().unwrap();
+        assert_eq!(get_points_used(&instance), 3 * 10);
+    }
+
+    #[test]
+    fn test_range_cost() {
+        use wasmer_middleware_common::{cost_table::CostTable, opcode::OpcodeKind};
+        let wasm_binary = wat2wasm(WAT_EXPLAIN).unwrap();
+
+        let table = CostTable::new(1)
+            .with_cost(OpcodeKind::I32Mul, 3)
+            .with_cost(OpcodeKind::Call, 10);
+        // local.get, i32.const, i32.mul, call and end of `$double_then_id`.
+        let offsets: Vec<usize> = explain(
+            &wasm_binary,
+            Metering::new(0).with_cost_table(table.clone()),
+        )
+        .unwrap()
+        .iter()
+        .map(|&(offset, _)| offset)
+        .take(5)
+        .collect();
+
+        // From the i32.const to the end, excluded.
+        assert_eq!(
+            range_cost(&wasm_binary, 0, offsets[1], offsets[4], &table).unwrap(),
+            1 + 3 + 10
+        );
+        // Starting inside the two bytes of the local.get excludes it, and ending inside the
+        // i32.const includes it.
+        assert_eq!(
+            range_cost(&wasm_binary, 0, offsets[0] + 1, offsets[1] + 1, &table).unwrap(),
+            1
+        );
+        assert_eq!(
+            range_cost(&wasm_binary, 0, offsets[2], offsets[2], &table).unwrap(),
+            0
+        );
+        // The offsets of the other functions do not count.
+        assert_eq!(
+            range_cost(&wasm_binary, 1, offsets[0], offsets[4], &table).unwrap(),
+            0
+        );
+        assert!(range_cost(&wasm_binary, 2, 0, wasm_binary.len(), &table).is_err());
+        assert!(range_cost(&wasm_binary, 0, offsets[4], offsets[0], &table).is_err());
+    }
+
+    #[test]
+    fn test_reset_all() {
+        use wasmer_middleware_common::{
+            cost_table::{embedded_cost_table, CostTable},
+            opcode::OpcodeKind,
+        };
+
+        let wasm_binary = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (data (i32.const 0) "abcd")
+              (func (export "grow")
+                i32.const 1
+                memory.grow
+                drop)
+              (func (export "spin")
+                loop
+                  br 0
+                end))
+            "#,
+        )
+        .unwrap();
+        let table = CostTable::new(1).with_cost(OpcodeKind::Drop, 3);
+        let metering_table = table.clone();
+        let module = compile_with(
+            &table.embed(&wasm_binary),
+            &get_compiler_with(move || {
+                Metering::with_runtime_limit()
+                    .with_cost_table(metering_table.clone())
+                    .with_memory_grow_cost(50)
+                    .with_last_call_cost()
+                    .with_category_breakdown()
+                    .with_surcharge_breakdown()
+                    .with_global_peak_points()
+            }),
+        )
+        .unwrap();
+
+        let import_object = imports! {};
+        let mut instance = module.instantiate(&import_object).unwrap();
+        set_execution_limit(&mut instance, 1000);
+
+        let grow: Func<(), ()> = instance.func("grow").unwrap();
+        grow.call().unwrap();
+        let grow_cost = get_points_used(&instance);
+        assert_eq!(charge_data_segments(&mut instance, 2), 4 * 2);
+        let spin: Func<(), ()> = instance.func("spin").unwrap();
+        assert!(spin.call().is_err());
+
+        assert_ne!(get_points_used(&instance), 0);
+        assert_ne!(last_call_cost(instance.context()), 0);
+        assert_ne!(category_breakdown(instance.context()).total(), 0);
+        assert_ne!(surcharge_breakdown(instance.context()).total(), 0);
+        assert_ne!(instantiation_cost(&instance), 0);
+        assert_ne!(get_gas_overrun(instance.context()), 0);
+        assert_ne!(peak_points(instance.context()), 0);
+
+        reset_all(instance.context_mut());
+        assert_eq!(get_points_used(&instance), 0);
+        assert_eq!(last_call_cost(instance.context()), 0);
+        assert_eq!(
+            category_breakdown(instance.context()),
+            CategoryBreakdown::default()
+        );
+        assert_eq!(
+            surcharge_breakdown(instance.context()),
+            SurchargeBreakdown::default()
+        );
+        assert_eq!(instantiation_cost(&instance), 0);
+        assert_eq!(get_gas_overrun(instance.context()), 0);
+        assert_eq!(peak_points(instance.context()), 0);
+
+        // The limit and the cost table are kept.
+        assert_eq!(get_execution_limit(&instance), 1000);
+        let embedded = CostTable::from_bytes(embedded_cost_table(module.info()).unwrap()).unwrap();
+        assert_eq!(embedded, table);
+        let grow: Func<(), ()> = instance.func("grow").unwrap();
+        grow.call().unwrap();
+        assert_eq!(get_points_used(&instance), grow_cost);
+        let spin: Func<(), ()> = instance.func("spin").unwrap();
+        assert!(spin.call().is_err());
+    }
 }
@@ -0,0 +1,122 @@
+//! Break before each call, to step through the calls of a module in a debugger.
+
+use std::sync::Arc;
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
+    module::ModuleInfo,
+    vm::{Ctx, InternalField},
+    wasmparser::Operator,
+};
+
+/// The table entry called by the `call_indirect` hitting the breakpoint.
+static INTERNAL_FIELD_TABLE_ENTRY: InternalField = InternalField::allocate();
+
+/// The internal fields of `CallBreakpoints`, for `registered_internal_fields`.
+pub(crate) fn internal_fields() -> Vec<(&'static str, &'static InternalField)> {
+    vec![("call_breakpoints.table_entry", &INTERNAL_FIELD_TABLE_ENTRY)]
+}
+
+/// The function about to be called.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Callee {
+    /// A `call` of the function of the given index, counting the imported functions.
+    Function(u32),
+    /// A `call_indirect` of the given type, through the given entry of the table.
+    ///
+    /// The entry is `None` if the backend does not give the breakpoint the instance context.
+    Indirect {
+        type_index: u32,
+        table_entry: Option<u32>,
+    },
+}
+
+/// A callback receiving the callee, and the instance context if the backend gives it.
+type CallCallback = Arc<dyn Fn(Callee, Option<&Ctx>) + Send + Sync>;
+
+/// CallBreakpoints is a compiler middleware inserting a breakpoint before every `call` and
+/// `call_indirect`, which passes the callee to a host callback.
+pub struct CallBreakpoints {
+    callback: CallCallback,
+}
+
+impl CallBreakpoints {
+    /// Creates a CallBreakpoints calling `callback` before each call.
+    pub fn new<F>(callback: F) -> CallBreakpoints
+    where
+        F: Fn(Callee) + Send + Sync + 'static,
+    {
+        CallBreakpoints::with_context(move |callee, _| callback(callee))
+    }
+
+    /// Creates a CallBreakpoints calling `callback` before each call, with the instance context
+    /// if the backend gives it to the breakpoint.
+    ///
+    /// The context lets the callback read the state of the instance, such as the points used
+    /// with `metering::points_at_breakpoint`.
+    pub fn with_context<F>(callback: F) -> CallBreakpoints
+    where
+        F: Fn(Callee, Option<&Ctx>) + Send + Sync + 'static,
+    {
+        CallBreakpoints {
+            callback: Arc::new(callback),
+        }
+    }
+}
+
+impl FunctionMiddleware for CallBreakpoints {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        _module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        if let Event::Wasm(&ref op) | Event::WasmOwned(ref op) = op {
+            match *op {
+                Operator::Call { function_index } => {
+                    let callback = self.callback.clone();
+                    sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+                        move |mut info| {
+                            callback(
+                                Callee::Function(function_index),
+                                info.ctx().map(|ctx| &*ctx),
+                            );
+                            Ok(())
+                        },
+                    ))));
+                }
+                Operator::CallIndirect { index, .. } => {
+                    // Breakpoints cannot read the stack, so the table entry is moved to an
+                    // internal field for the breakpoint to read, then pushed back.
+                    sink.push(Event::WasmOwned(Operator::I64ExtendUI32));
+                    sink.push(Event::Internal(InternalEvent::SetInternal(
+                        INTERNAL_FIELD_TABLE_ENTRY.index() as _,
+                    )));
+                    let callback = self.callback.clone();
+                    sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+                        move |mut info| {
+                            let ctx = info.ctx().map(|ctx| &*ctx);
+                            let table_entry =
+                                ctx.map(|ctx| ctx.get_internal(&INTERNAL_FIELD_TABLE_ENTRY) as u32);
+                            callback(
+                                Callee::Indirect {
+                                    type_index: index,
+                                    table_entry,
+                                },
+                                ctx,
+                            );
+                            Ok(())
+                        },
+                    ))));
+                    sink.push(Event::Internal(InternalEvent::GetInternal(
+                        INTERNAL_FIELD_TABLE_ENTRY.index() as _,
+                    )));
+                    sink.push(Event::WasmOwned(Operator::I32WrapI64));
+                }
+                _ => {}
+            }
+        }
+        sink.push(op);
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+//! The cost in points of each kind of WebAssembly operator.
+
+use crate::opcode::OpcodeKind;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use wasmer_runtime_core::{
+    module::ModuleInfo,
+    wasmparser::{BinaryReader, Operator},
+};
+
+/// The name of the custom section holding the cost table a module was compiled with.
+pub const COST_TABLE_SECTION: &str = "wasmer.metering.cost_table";
+
+/// The name of the custom section holding the fingerprint of a module, see
+/// `CostTable::fingerprint`.
+pub const FINGERPRINT_SECTION: &str = "wasmer.metering.fingerprint";
+
+/// The version of the serialized form of cost tables.
+const FORMAT_VERSION: u8 = 1;
+/// The version of the serialized form of cost tables with metadata, which follows the costs.
+const FORMAT_VERSION_METADATA: u8 = 2;
+
+/// The cost in points of each kind of operator, used by `Metering`.
+///
+/// Operators without an explicit cost cost the base cost.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CostTable {
+    base_cost: u64,
+    costs: BTreeMap<OpcodeKind, u64>,
+    metadata: String,
+}
+
+impl Default for CostTable {
+    /// Returns a table where every operator costs one point.
+    fn default() -> CostTable {
+        CostTable::new(1)
+    }
+}
+
+impl CostTable {
+    /// Creates a table where every operator costs `base_cost` points.
+    pub fn new(base_cost: u64) -> CostTable {
+        CostTable {
+            base_cost,
+            costs: BTreeMap::new(),
+            metadata: String::new(),
+        }
+    }
+
+    /// Sets the cost of the operators of the given kind.
+    pub fn with_cost(mut self, kind: OpcodeKind, cost: u64) -> CostTable {
+        self.set_cost(kind, cost);
+        self
+    }
+
+    /// Sets the cost of the operators of the given kind.
+    pub fn set_cost(&mut self, kind: OpcodeKind, cost: u64) {
+        self.costs.insert(kind, cost);
+    }
+
+    /// Sets the metadata of the table, such as a label and a version telling where its costs come
+    /// from. The metadata is serialized with the table.
+    pub fn with_metadata(mut self, label: String) -> CostTable {
+        self.set_metadata(label);
+        self
+    }
+
+    /// Sets the metadata of the table.
+    pub fn set_metadata(&mut self, label: String) {
+        self.metadata = label;
+    }
+
+    /// Returns the metadata of the table, empty unless set.
+    pub fn metadata(&self) -> &str {
+        &self.metadata
+    }
+
+    /// Returns the cost of the operators without an explicit cost.
+    pub fn base_cost(&self) -> u64 {
+        self.base_cost
+    }
+
+    /// Returns the cost of the operators of the given kind.
+    pub fn cost(&self, kind: OpcodeKind) -> u64 {
+        self.explicit_cost(kind).unwrap_or(self.base_cost)
+    }
+
+    /// Returns the cost of the operators of the given kind, if it was set explicitly.
+    pub fn explicit_cost(&self, kind: OpcodeKind) -> Option<u64> {
+        self.costs.get(&kind).cloned()
+    }
+
+    /// Returns the cost of `op`.
+    pub fn operator_cost(&self, op: &Operator) -> u64 {
+        self.cost(OpcodeKind::of(op))
+    }
+
+    /// Returns the summed cost of the operators encoded in `bytes`, as in a function body
+    /// without its local declarations.
+    ///
+    /// The operators are decoded, not validated, so the sequence need not be a whole body.
+    pub fn operators_cost(&self, bytes: &[u8]) -> Result<u64, String> {
+        let mut reader = BinaryReader::new(bytes);
+        let mut cost: u64 = 0;
+        while !reader.eof() {
+            let op = reader.read_operator().map_err(|e| format!("{:?}", e))?;
+            cost = cost.saturating_add(self.operator_cost(&op));
+        }
+        Ok(cost)
+    }
+
+    /// Parses a table from TOML, such as:
+    ///
+    /// ```toml
+    /// base_cost = 1
+    ///
+    /// [costs]
+    /// "i64.div_u" = 8
+    /// "memory.grow" = 100
+    /// ```
+    ///
+    /// `base_cost` defaults to one point, and opcodes missing from `[costs]` cost the base cost.
+    /// Opcodes are named as in the WebAssembly text format.
+    pub fn from_toml(s: &str) -> Result<CostTable, String> {
+        fn to_cost(value: &toml::Value, key: &str) -> Result<u64, String> {
+            match value.as_integer() {
+                Some(cost) if cost >= 0 => Ok(cost as u64),
+                _ => Err(format!("`{}` must be a non-negative integer", key)),
+            }
+        }
+
+        let value: toml::Value = s
+            .parse()
+            .map_err(|e| format!("invalid cost table: {}", e))?;
+        let root = value
+            .as_table()
+            .ok_or_else(|| "invalid cost table: expected a table".to_string())?;
+        let mut table = match root.get("base_cost") {
+            Some(base_cost) => CostTable::new(to_cost(base_cost, "base_cost")?),
+            None => CostTable::default(),
+        };
+        if let Some(key) = root
+            .keys()
+            .find(|key| *key != "base_cost" && *key != "costs")
+        {
+            return Err(format!("unknown key `{}` in cost table", key));
+        }
+        if let Some(costs) = root.get("costs") {
+            let costs = costs
+                .as_table()
+                .ok_or_else(|| "`costs` must be a table".to_string())?;
+            for (name, cost) in costs {
+                let kind = OpcodeKind::from_name(name)
+                    .ok_or_else(|| format!("unknown opcode `{}` in cost table", name))?;
+                table.set_cost(kind, to_cost(cost, name)?);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Serializes the table.
+    ///
+    /// Explicit costs are written in opcode order, so equal tables serialize to the same bytes.
+    /// Tables without metadata are written in the format of the versions before metadata, so
+    /// their serialized form and their fingerprints do not change.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let version = if self.metadata.is_empty() {
+            FORMAT_VERSION
+        } else {
+            FORMAT_VERSION_METADATA
+        };
+        let mut bytes = vec![version];
+        bytes.extend_from_slice(&self.base_cost.to_le_bytes());
+        bytes.extend_from_slice(&(self.costs.len() as u32).to_le_bytes());
+        for (kind, cost) in &self.costs {
+            let name = kind.name();
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&cost.to_le_bytes());
+        }
+        if version == FORMAT_VERSION_METADATA {
+            bytes.extend_from_slice(&(self.metadata.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(self.metadata.as_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes a table serialized by `to_bytes`.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<CostTable, String> {
+        let version = take(&mut bytes, 1)?[0];
+        if version != FORMAT_VERSION && version != FORMAT_VERSION_METADATA {
+            return Err(format!("unsupported cost table version {}", version));
+        }
+        let mut table = CostTable::new(take_u64(&mut bytes)?);
+        let count = take_u32(&mut bytes)?;
+        for _ in 0..count {
+            let name_len = take(&mut bytes, 1)?[0] as usize;
+            let name = String::from_utf8_lossy(take(&mut bytes, name_len)?);
+            let kind = OpcodeKind::from_name(&name)
+                .ok_or_else(|| format!("unknown opcode `{}` in cost table", name))?;
+            table.set_cost(kind, take_u64(&mut bytes)?);
+        }
+        if version == FORMAT_VERSION_METADATA {
+            let len = take_u32(&mut bytes)? as usize;
+            table.metadata = String::from_utf8(take(&mut bytes, len)?.to_vec())
+                .map_err(|_| "invalid metadata in cost table".to_string())?;
+        }
+        if !bytes.is_empty() {
+            return Err("trailing bytes after cost table".to_string());
+        }
+        Ok(table)
+    }
+
+    /// Returns the cost of the operators of the given kind in a table serialized by `to_bytes`,
+    /// reading the explicit costs up to the one of `kind` rather than deserializing the table.
+    pub fn cost_from_bytes(mut bytes: &[u8], kind: OpcodeKind) -> Result<u64, String> {
+        let version = take(&mut bytes, 1)?[0];
+        if version != FORMAT_VERSION && version != FORMAT_VERSION_METADATA {
+            return Err(format!("unsupported cost table version {}", version));
+        }
+        let base_cost = take_u64(&mut bytes)?;
+        let count = take_u32(&mut bytes)?;
+        for _ in 0..count {
+            let name_len = take(&mut bytes, 1)?[0] as usize;
+            let name = take(&mut bytes, name_len)?;
+            let cost = take_u64(&mut bytes)?;
+            if name == kind.name().as_bytes() {
+                return Ok(cost);
+            }
+        }
+        Ok(base_cost)
+    }
+
+    /// Returns `wasm` with the table appended as a custom section, so that the compiled module
+    /// records the pricing it was metered with.
+    ///
+    /// The module should be compiled with a `Metering` using the same table. The section takes
+    /// precedence over any section of the same name already in `wasm`.
+    pub fn embed(&self, wasm: &[u8]) -> Vec<u8> {
+        append_custom_section(wasm, COST_TABLE_SECTION, &self.to_bytes())
+    }
+
+    /// Returns a 32-byte hash of `wasm` and of the table, identifying both the module and the
+    /// pricing it is metered with.
+    ///
+    /// `wasm` is hashed as given, so it should not have the table or the fingerprint embedded
+    /// yet. Equal bytes compiled with different tables have different fingerprints.
+    pub fn fingerprint(&self, wasm: &[u8]) -> [u8; 32] {
+        let table = self.to_bytes();
+        let hash = blake2b_simd::Params::new()
+            .hash_length(32)
+            .personal(b"wasmer-metering")
+            .to_state()
+            .update(&(wasm.len() as u64).to_le_bytes())
+            .update(wasm)
+            .update(&table)
+            .finalize();
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(hash.as_bytes());
+        fingerprint
+    }
+}
+
+/// Returns `wasm` with `fingerprint` appended as a custom section, so that the compiled module
+/// records it.
+///
+/// The section takes precedence over any section of the same name already in `wasm`.
+pub fn embed_fingerprint(wasm: &[u8], fingerprint: &[u8; 32]) -> Vec<u8> {
+    append_custom_section(wasm, FINGERPRINT_SECTION, fingerprint)
+}
+
+/// Returns the fingerprint embedded in a module by `embed_fingerprint`, if any.
+pub fn embedded_fingerprint(module_info: &ModuleInfo) -> Option<[u8; 32]> {
+    module_info
+        .custom_sections
+        .get(FINGERPRINT_SECTION)
+        .and_then(|bytes| bytes[..].try_into().ok())
+}
+
+/// Returns the serialized cost table embedded in a module by `CostTable::embed`, if any.
+pub fn embedded_cost_table(module_info: &ModuleInfo) -> Option<&[u8]> {
+    module_info
+        .custom_sections
+        .get(COST_TABLE_SECTION)
+        .map(|bytes| &bytes[..])
+}
+
+/// Splits the first `len` bytes off `bytes`, for reading serialized tables.
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if bytes.len() < len {
+        return Err("unexpected end of cost table".to_string());
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Result<u64, String> {
+    Ok(u64::from_le_bytes(take(bytes, 8)?.try_into().unwrap()))
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Result<u32, String> {
+    Ok(u32::from_le_bytes(take(bytes, 4)?.try_into().unwrap()))
+}
+
+/// Returns `wasm` with a custom section named `name` holding `data` appended.
+pub(crate) fn append_custom_section(wasm: &[u8], name: &str, data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(data.len() + name.len() + 5);
+    write_leb128(&mut payload, name.len() as u32);
+    payload.extend_from_slice(name.as_bytes());
+    payload.extend_from_slice(data);
+
+    let mut appended = Vec::with_capacity(wasm.len() + payload.len() + 6);
+    appended.extend_from_slice(wasm);
+    // A custom section has id zero.
+    appended.push(0);
+    write_leb128(&mut appended, payload.len() as u32);
+    appended.extend_from_slice(&payload);
+    appended
+}
+
+fn write_leb128(bytes: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
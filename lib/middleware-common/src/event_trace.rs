@@ -0,0 +1,505 @@
+use crate::opcode::OpcodeKind;
+use crate::working_set::memory_access;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::io::{self, Write};
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
+    module::ModuleInfo,
+    wasmparser::{
+        ModuleReader, Operator, SectionCode, Type as WpType, TypeOrFuncType as WpTypeOrFuncType,
+    },
+};
+
+/// EventTrace is a compiler middleware that writes the events it sees, one per line, and passes
+/// them on unchanged.
+///
+/// The events are written as they are compiled, not as they are executed. A middleware placed
+/// after it in the chain sees the same events, and the events it adds are not traced.
+pub struct EventTrace {
+    writer: Box<dyn Write>,
+    /// Whether the events are written as binary records, see `EventTrace::binary`.
+    binary: bool,
+    /// The events of the current function not written yet, with
+    /// `EventTrace::buffered_per_function`.
+    pending: Option<Vec<u8>>,
+    deprecated: HashSet<OpcodeKind>,
+    func_index: u32,
+    /// The offsets of the operators of each function defined by the module, with
+    /// `EventTrace::with_offsets`.
+    offsets: Option<Vec<Vec<usize>>>,
+    /// The index of the next operator of the current function in `offsets`.
+    next_operator: usize,
+}
+
+impl EventTrace {
+    /// Creates an EventTrace writing to stderr.
+    pub fn new() -> EventTrace {
+        EventTrace::with_writer(io::stderr())
+    }
+
+    /// Creates an EventTrace writing to `writer`.
+    pub fn with_writer<W: Write + 'static>(writer: W) -> EventTrace {
+        EventTrace {
+            writer: Box::new(writer),
+            binary: false,
+            pending: None,
+            deprecated: HashSet::new(),
+            func_index: 0,
+            offsets: None,
+            next_operator: 0,
+        }
+    }
+
+    /// Creates an EventTrace writing the events to `writer` as binary records, to be read back
+    /// with `decode_trace`.
+    ///
+    /// Each record is the LEB128 length of its payload followed by the payload: a tag byte, then
+    /// for an operator the LEB128 index of its `OpcodeKind`, the LEB128 number of its immediates
+    /// and each immediate as a signed LEB128, and for `FunctionBegin`, `GetInternal` and
+    /// `SetInternal` their LEB128 index. The deprecated opcode warnings are not written.
+    pub fn binary<W: Write + 'static>(writer: W) -> EventTrace {
+        EventTrace {
+            binary: true,
+            ..EventTrace::with_writer(writer)
+        }
+    }
+
+    /// Writes a `WARN: deprecated opcode <name> in fn <index>` line before tracing each operator
+    /// of a kind in `set`.
+    ///
+    /// The index is the index of the function in the module, counting the imported functions.
+    pub fn warn_on(mut self, set: HashSet<OpcodeKind>) -> EventTrace {
+        self.deprecated = set;
+        self
+    }
+
+    /// Prefixes each traced operator with its offset in `wasm`, the bytes of the module being
+    /// compiled, as in `@0x1f4 I32Const { value: 1 }`.
+    ///
+    /// The offsets are taken from `wasm` in the order of its operators, so they are only right if
+    /// the middlewares before this one pass the operators of the module on as they are. The
+    /// internal events and the operators added by middlewares have no offset and are traced
+    /// without one, as are all events of a binary trace.
+    pub fn with_offsets(mut self, wasm: &[u8]) -> Result<EventTrace, String> {
+        self.offsets = Some(operator_offsets(wasm)?);
+        Ok(self)
+    }
+
+    /// Writes the events of each function at once, when the function ends, so that the trace of
+    /// the function is contiguous even if other traces write to the same output.
+    ///
+    /// The events are written with a single call to `write_all`, so the writers locking each
+    /// call, such as stderr, keep the functions apart when modules are compiled concurrently.
+    pub fn buffered_per_function(mut self) -> EventTrace {
+        self.pending = Some(Vec::new());
+        self
+    }
+
+    /// Returns the output of the events, the buffer of the current function if they are
+    /// buffered.
+    fn output(&mut self) -> &mut dyn Write {
+        match self.pending {
+            Some(ref mut pending) => pending,
+            None => &mut *self.writer,
+        }
+    }
+
+    /// Writes the buffered events of the function that ended, if they are buffered.
+    fn flush_function(&mut self) -> io::Result<()> {
+        match self.pending {
+            Some(ref mut pending) => {
+                let written = self.writer.write_all(pending);
+                pending.clear();
+                written
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the prefix of the next operator of the module, see `EventTrace::with_offsets`.
+    fn next_offset_prefix(&mut self, module_info: &ModuleInfo) -> String {
+        let offsets = match self.offsets {
+            Some(ref offsets) => offsets,
+            None => return String::new(),
+        };
+        let offset = (self.func_index as usize)
+            .checked_sub(module_info.imported_functions.len())
+            .and_then(|local_index| offsets.get(local_index))
+            .and_then(|function| function.get(self.next_operator));
+        self.next_operator += 1;
+        match offset {
+            Some(offset) => format!("@{:#x} ", offset),
+            None => String::new(),
+        }
+    }
+
+    fn warn_if_deprecated(&mut self, op: &Operator) -> io::Result<()> {
+        let kind = OpcodeKind::of(op);
+        if !self.deprecated.contains(&kind) {
+            return Ok(());
+        }
+        let func_index = self.func_index;
+        writeln!(
+            self.output(),
+            "WARN: deprecated opcode {} in fn {}",
+            kind,
+            func_index
+        )
+    }
+}
+
+impl Default for EventTrace {
+    fn default() -> EventTrace {
+        EventTrace::new()
+    }
+}
+
+impl FunctionMiddleware for EventTrace {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        if self.binary {
+            let record = TraceRecord::of(&op)?.to_bytes();
+            let mut length = Vec::with_capacity(5);
+            write_leb128(&mut length, record.len() as u64);
+            let output = self.output();
+            output
+                .write_all(&length)
+                .and_then(|_| output.write_all(&record))
+                .and_then(|_| match op {
+                    Event::Internal(InternalEvent::FunctionEnd) => self.flush_function(),
+                    _ => Ok(()),
+                })
+                .map_err(|e| format!("failed to write the event trace: {}", e))?;
+            sink.push(op);
+            return Ok(());
+        }
+        let written = match op {
+            Event::Internal(InternalEvent::FunctionBegin(id)) => {
+                self.func_index = module_info.imported_functions.len() as u32 + id;
+                self.next_operator = 0;
+                writeln!(self.output(), "FunctionBegin({})", id)
+            }
+            Event::Internal(InternalEvent::FunctionEnd) => {
+                writeln!(self.output(), "{:?}", InternalEvent::FunctionEnd)
+                    .and_then(|_| self.flush_function())
+            }
+            Event::Internal(ref event) => writeln!(self.output(), "{:?}", event),
+            Event::Wasm(op) => {
+                let prefix = self.next_offset_prefix(module_info);
+                self.warn_if_deprecated(op)
+                    .and_then(|_| writeln!(self.output(), "{}{:?}", prefix, op))
+            }
+            Event::WasmOwned(ref op) => self
+                .warn_if_deprecated(op)
+                .and_then(|_| writeln!(self.output(), "{:?}", op)),
+        };
+        written.map_err(|e| format!("failed to write the event trace: {}", e))?;
+        sink.push(op);
+        Ok(())
+    }
+}
+
+/// Returns the offsets in `wasm` of the operators of each function defined by the module.
+fn operator_offsets(wasm: &[u8]) -> Result<Vec<Vec<usize>>, String> {
+    let mut offsets = Vec::new();
+    let mut reader = ModuleReader::new(wasm).map_err(|e| format!("{:?}", e))?;
+    while !reader.eof() {
+        let section = reader.read().map_err(|e| format!("{:?}", e))?;
+        if let SectionCode::Code = section.code {
+            let code = section
+                .get_code_section_reader()
+                .map_err(|e| format!("{:?}", e))?;
+            for body in code {
+                let operators = body
+                    .and_then(|body| body.get_operators_reader())
+                    .map_err(|e| format!("{:?}", e))?;
+                let function = operators
+                    .into_iter_with_offsets()
+                    .map(|operator| operator.map(|(_, offset)| offset))
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| format!("{:?}", e))?;
+                offsets.push(function);
+            }
+        }
+    }
+    Ok(offsets)
+}
+
+const TAG_OPERATOR: u8 = 0;
+const TAG_FUNCTION_BEGIN: u8 = 1;
+const TAG_FUNCTION_END: u8 = 2;
+const TAG_BREAKPOINT: u8 = 3;
+const TAG_GET_INTERNAL: u8 = 4;
+const TAG_SET_INTERNAL: u8 = 5;
+
+/// An event of a trace written by `EventTrace::binary`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceRecord {
+    FunctionBegin(u32),
+    FunctionEnd,
+    /// A breakpoint, whose callback is not recorded.
+    Breakpoint,
+    GetInternal(u32),
+    SetInternal(u32),
+    /// An operator, with its immediates in the order of the binary format.
+    ///
+    /// Integers are recorded as they are, floats and `v128` constants by their bits, and block
+    /// types as the signed value of their encoding, negative for a value type. The targets of a
+    /// `br_table` are followed by its default target.
+    Operator {
+        kind: OpcodeKind,
+        immediates: Vec<i64>,
+    },
+}
+
+impl TraceRecord {
+    /// Returns the record of `event`.
+    pub fn of(event: &Event) -> Result<TraceRecord, String> {
+        Ok(match *event {
+            Event::Internal(InternalEvent::FunctionBegin(id)) => TraceRecord::FunctionBegin(id),
+            Event::Internal(InternalEvent::FunctionEnd) => TraceRecord::FunctionEnd,
+            Event::Internal(InternalEvent::Breakpoint(_)) => TraceRecord::Breakpoint,
+            Event::Internal(InternalEvent::GetInternal(index)) => TraceRecord::GetInternal(index),
+            Event::Internal(InternalEvent::SetInternal(index)) => TraceRecord::SetInternal(index),
+            Event::Wasm(&ref op) | Event::WasmOwned(ref op) => TraceRecord::Operator {
+                kind: OpcodeKind::of(op),
+                immediates: immediates(op)?,
+            },
+        })
+    }
+
+    /// Returns the payload of the record.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match *self {
+            TraceRecord::FunctionBegin(id) => {
+                bytes.push(TAG_FUNCTION_BEGIN);
+                write_leb128(&mut bytes, u64::from(id));
+            }
+            TraceRecord::FunctionEnd => bytes.push(TAG_FUNCTION_END),
+            TraceRecord::Breakpoint => bytes.push(TAG_BREAKPOINT),
+            TraceRecord::GetInternal(index) => {
+                bytes.push(TAG_GET_INTERNAL);
+                write_leb128(&mut bytes, u64::from(index));
+            }
+            TraceRecord::SetInternal(index) => {
+                bytes.push(TAG_SET_INTERNAL);
+                write_leb128(&mut bytes, u64::from(index));
+            }
+            TraceRecord::Operator {
+                kind,
+                ref immediates,
+            } => {
+                bytes.push(TAG_OPERATOR);
+                write_leb128(&mut bytes, kind.index() as u64);
+                write_leb128(&mut bytes, immediates.len() as u64);
+                for &immediate in immediates {
+                    write_signed_leb128(&mut bytes, immediate);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Parses the payload of a record.
+    fn from_bytes(mut bytes: &[u8]) -> Result<TraceRecord, String> {
+        let read_u32 = |bytes: &mut &[u8]| -> Result<u32, String> {
+            read_leb128(bytes)?
+                .try_into()
+                .map_err(|_| "index out of range in event trace".to_string())
+        };
+        let (&tag, rest) = bytes
+            .split_first()
+            .ok_or_else(|| "empty record in event trace".to_string())?;
+        bytes = rest;
+        let record = match tag {
+            TAG_OPERATOR => {
+                let index = read_leb128(&mut bytes)? as usize;
+                let kind = OpcodeKind::from_index(index)
+                    .ok_or_else(|| format!("unknown opcode index {} in event trace", index))?;
+                let count = read_leb128(&mut bytes)?;
+                let immediates = (0..count)
+                    .map(|_| read_signed_leb128(&mut bytes))
+                    .collect::<Result<_, _>>()?;
+                TraceRecord::Operator { kind, immediates }
+            }
+            TAG_FUNCTION_BEGIN => TraceRecord::FunctionBegin(read_u32(&mut bytes)?),
+            TAG_FUNCTION_END => TraceRecord::FunctionEnd,
+            TAG_BREAKPOINT => TraceRecord::Breakpoint,
+            TAG_GET_INTERNAL => TraceRecord::GetInternal(read_u32(&mut bytes)?),
+            TAG_SET_INTERNAL => TraceRecord::SetInternal(read_u32(&mut bytes)?),
+            _ => return Err(format!("unknown record tag {} in event trace", tag)),
+        };
+        if !bytes.is_empty() {
+            return Err("trailing bytes in event trace record".to_string());
+        }
+        Ok(record)
+    }
+}
+
+/// Decodes a trace written by `EventTrace::binary` into its records, in order.
+pub fn decode_trace(mut bytes: &[u8]) -> Result<Vec<TraceRecord>, String> {
+    let mut records = Vec::new();
+    while !bytes.is_empty() {
+        let len = read_leb128(&mut bytes)? as usize;
+        if bytes.len() < len {
+            return Err("unexpected end of event trace".to_string());
+        }
+        let (record, rest) = bytes.split_at(len);
+        records.push(TraceRecord::from_bytes(record)?);
+        bytes = rest;
+    }
+    Ok(records)
+}
+
+/// Returns the immediates of `op`, see `TraceRecord::Operator`.
+fn immediates(op: &Operator) -> Result<Vec<i64>, String> {
+    use Operator::*;
+    if let Some((memarg, _, _)) = memory_access(op) {
+        return Ok(vec![i64::from(memarg.flags), i64::from(memarg.offset)]);
+    }
+    let immediates = match *op {
+        Block { ty } | Loop { ty } | If { ty } => vec![block_type(ty)?],
+        Br { relative_depth } | BrIf { relative_depth } => vec![i64::from(relative_depth)],
+        BrTable { ref table } => {
+            let (targets, default) = table.read_table().map_err(|e| format!("{:?}", e))?;
+            targets
+                .iter()
+                .chain(Some(&default))
+                .map(|&target| i64::from(target))
+                .collect()
+        }
+        Call { function_index } => vec![i64::from(function_index)],
+        CallIndirect { index, table_index } => vec![i64::from(index), i64::from(table_index)],
+        GetLocal { local_index } | SetLocal { local_index } | TeeLocal { local_index } => {
+            vec![i64::from(local_index)]
+        }
+        GetGlobal { global_index } | SetGlobal { global_index } => vec![i64::from(global_index)],
+        MemorySize { reserved } | MemoryGrow { reserved } => vec![i64::from(reserved)],
+        I32Const { value } => vec![i64::from(value)],
+        I64Const { value } => vec![value],
+        F32Const { value } => vec![i64::from(value.bits())],
+        F64Const { value } => vec![value.bits() as i64],
+        MemoryInit { segment }
+        | DataDrop { segment }
+        | TableInit { segment }
+        | ElemDrop { segment } => vec![i64::from(segment)],
+        TableGet { table } | TableSet { table } | TableGrow { table } | TableSize { table } => {
+            vec![i64::from(table)]
+        }
+        Fence { flags } => vec![i64::from(flags)],
+        V128Const { ref value } => value
+            .bytes()
+            .chunks(8)
+            .map(|half| i64::from_le_bytes(half.try_into().unwrap()))
+            .collect(),
+        I8x16ExtractLaneS { lane }
+        | I8x16ExtractLaneU { lane }
+        | I8x16ReplaceLane { lane }
+        | I16x8ExtractLaneS { lane }
+        | I16x8ExtractLaneU { lane }
+        | I16x8ReplaceLane { lane }
+        | I32x4ExtractLane { lane }
+        | I32x4ReplaceLane { lane }
+        | I64x2ExtractLane { lane }
+        | I64x2ReplaceLane { lane }
+        | F32x4ExtractLane { lane }
+        | F32x4ReplaceLane { lane }
+        | F64x2ExtractLane { lane }
+        | F64x2ReplaceLane { lane } => vec![i64::from(lane)],
+        V8x16Shuffle { ref lanes } => lanes.iter().map(|&lane| i64::from(lane)).collect(),
+        _ => vec![],
+    };
+    Ok(immediates)
+}
+
+/// Returns the signed value of the encoding of a block type.
+fn block_type(ty: WpTypeOrFuncType) -> Result<i64, String> {
+    Ok(match ty {
+        WpTypeOrFuncType::FuncType(index) => i64::from(index),
+        WpTypeOrFuncType::Type(ty) => match ty {
+            WpType::I32 => -0x01,
+            WpType::I64 => -0x02,
+            WpType::F32 => -0x03,
+            WpType::F64 => -0x04,
+            WpType::V128 => -0x05,
+            WpType::AnyFunc => -0x10,
+            WpType::AnyRef => -0x11,
+            WpType::Func => -0x20,
+            WpType::EmptyBlockType => -0x40,
+            WpType::Null => return Err("invalid block type".to_string()),
+        },
+    })
+}
+
+fn write_leb128(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_signed_leb128(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        // Stop once the rest is the sign extension of the last byte written.
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn read_leb128(bytes: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| "unexpected end of event trace".to_string())?;
+        *bytes = rest;
+        if shift >= 64 {
+            return Err("integer too large in event trace".to_string());
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn read_signed_leb128(bytes: &mut &[u8]) -> Result<i64, String> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| "unexpected end of event trace".to_string())?;
+        *bytes = rest;
+        if shift >= 64 {
+            return Err("integer too large in event trace".to_string());
+        }
+        value |= i64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if shift < 64 && byte & 0x40 != 0 {
+                value |= -1 << shift;
+            }
+            return Ok(value);
+        }
+    }
+}
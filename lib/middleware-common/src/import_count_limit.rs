@@ -0,0 +1,48 @@
+//! Reject the modules importing too much of their host.
+
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware},
+    module::ModuleInfo,
+};
+
+/// ImportCountLimit is a compiler middleware failing the compilation of any module importing
+/// more than `max` functions, memories, tables and globals in total.
+///
+/// The imports are read from the `ModuleInfo` once the sections before the code section are
+/// read, so the modules defining no function are checked too.
+pub struct ImportCountLimit {
+    max: u32,
+}
+
+impl ImportCountLimit {
+    pub fn new(max: u32) -> ImportCountLimit {
+        ImportCountLimit { max }
+    }
+}
+
+impl FunctionMiddleware for ImportCountLimit {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        _module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        sink.push(op);
+        Ok(())
+    }
+
+    fn check_precondition(&mut self, module_info: &ModuleInfo) -> Result<(), Self::Error> {
+        let count = module_info.imported_functions.len()
+            + module_info.imported_memories.len()
+            + module_info.imported_tables.len()
+            + module_info.imported_globals.len();
+        if count > self.max as usize {
+            return Err(format!(
+                "module has {} imports, more than {}",
+                count, self.max
+            ));
+        }
+        Ok(())
+    }
+}
@@ -10,5 +10,220 @@
 #![doc(html_favicon_url = "https://wasmer.io/static/icons/favicon.ico")]
 #![doc(html_logo_url = "https://avatars3.githubusercontent.com/u/44205449?s=200&v=4")]
 
+pub mod call_breakpoints;
 pub mod call_trace;
+pub mod cost_table;
+pub mod event_trace;
+pub mod import_count_limit;
+pub mod loop_counter;
 pub mod metering;
+pub mod opcode;
+pub mod opcode_count_limit;
+pub mod opcode_profiler;
+mod registry;
+pub mod resource_meter;
+pub mod timing;
+pub mod trap_logger;
+pub mod working_set;
+
+use lazy_static::lazy_static;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use wasmer_runtime_core::{
+    backend::RunnableModule,
+    codegen::{
+        Event, EventSink, FunctionCodeGenerator, FunctionMiddleware, InternalEvent,
+        MiddlewareChain, ModuleCodeGenerator, StreamingCompiler,
+    },
+    compile_with,
+    module::ModuleInfo,
+    vm::InternalField,
+    wasmparser::Type as WpType,
+};
+
+lazy_static! {
+    /// The fields registered with `register_internal_field`, starting with the fields of the
+    /// middlewares of this crate.
+    static ref INTERNAL_FIELDS: Mutex<Vec<(&'static str, &'static InternalField)>> = Mutex::new(
+        metering::internal_fields()
+            .into_iter()
+            .chain(working_set::internal_fields())
+            .chain(call_breakpoints::internal_fields())
+            .chain(loop_counter::internal_fields())
+            .chain(opcode_profiler::internal_fields())
+            .chain(trap_logger::internal_fields())
+            .chain(resource_meter::internal_fields())
+            .collect()
+    );
+}
+
+/// Registers `field` under `name`, for `registered_internal_fields`.
+///
+/// Middlewares defining their own internal fields can register them to check that they do not
+/// collide with the fields of other middlewares.
+pub fn register_internal_field(name: &'static str, field: &'static InternalField) {
+    INTERNAL_FIELDS.lock().unwrap().push((name, field));
+}
+
+/// Returns the name and the index of the registered internal fields, in registration order.
+///
+/// The fields of the middlewares of this crate are always registered. Their indices are
+/// allocated by this call if they were not used yet.
+pub fn registered_internal_fields() -> Vec<(&'static str, usize)> {
+    INTERNAL_FIELDS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|&(name, field)| (name, field.index()))
+        .collect()
+}
+
+/// Returns a chain running the middlewares of `mws` in order, the first one seeing the events
+/// first.
+///
+/// This allows the middlewares of a chain to be chosen at runtime.
+pub fn build_chain(mws: Vec<Box<dyn FunctionMiddleware<Error = String>>>) -> MiddlewareChain {
+    let mut chain = MiddlewareChain::new();
+    for mw in mws {
+        chain.push(BoxedMiddleware(mw));
+    }
+    chain
+}
+
+/// A boxed middleware, which can be pushed to a chain.
+struct BoxedMiddleware(Box<dyn FunctionMiddleware<Error = String>>);
+
+impl FunctionMiddleware for BoxedMiddleware {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        self.0.feed_event(op, module_info, sink)
+    }
+
+    fn feed_local(
+        &mut self,
+        ty: WpType,
+        n: usize,
+        module_info: &ModuleInfo,
+    ) -> Result<(), Self::Error> {
+        self.0.feed_local(ty, n, module_info)
+    }
+}
+
+/// Compiles `bytes` and checks that the event stream of each function starts with a
+/// `FunctionBegin` event and ends with a single `FunctionEnd` event, as middlewares expect.
+///
+/// Returns the index of the first function whose events are not paired, in the module's function
+/// index space, in the error. The backend is chosen with the first type parameter, the others are
+/// inferred:
+///
+/// ```ignore
+/// verify_function_event_balance::<SinglePassMCG, _, _, _>(&wasm)?;
+/// ```
+pub fn verify_function_event_balance<MCG, FCG, RM, E>(bytes: &[u8]) -> Result<(), String>
+where
+    MCG: ModuleCodeGenerator<FCG, RM, E>,
+    FCG: FunctionCodeGenerator<E>,
+    RM: RunnableModule + 'static,
+    E: Debug,
+{
+    verify_function_event_balance_with::<MCG, FCG, RM, E, _>(bytes, Vec::new)
+}
+
+/// Like `verify_function_event_balance`, but checks the events emitted by the middlewares
+/// returned by `mws`, which run in order before the check.
+pub fn verify_function_event_balance_with<MCG, FCG, RM, E, F>(
+    bytes: &[u8],
+    mws: F,
+) -> Result<(), String>
+where
+    MCG: ModuleCodeGenerator<FCG, RM, E>,
+    FCG: FunctionCodeGenerator<E>,
+    RM: RunnableModule + 'static,
+    E: Debug,
+    F: Fn() -> Vec<Box<dyn FunctionMiddleware<Error = String>>>,
+{
+    let balance = Arc::new(Mutex::new(EventBalance::default()));
+    let compiler: StreamingCompiler<MCG, FCG, RM, E, _> = {
+        let balance = balance.clone();
+        StreamingCompiler::new(move || {
+            let mut chain = build_chain(mws());
+            chain.push(EventBalanceRecorder(balance.clone()));
+            chain
+        })
+    };
+    let compiled = compile_with(bytes, &compiler);
+    let balance = balance.lock().unwrap();
+    if let Some(ref msg) = balance.error {
+        return Err(msg.clone());
+    }
+    compiled.map_err(|e| format!("{:?}", e))?;
+    match balance.open {
+        Some(func_index) => Err(format!("function {} has no FunctionEnd event", func_index)),
+        None => Ok(()),
+    }
+}
+
+/// The pairing of the function events seen by an `EventBalanceRecorder`.
+#[derive(Default)]
+struct EventBalance {
+    /// The function begun and not ended yet.
+    open: Option<u32>,
+    /// The function begun last.
+    last: Option<u32>,
+    /// The first imbalance seen.
+    error: Option<String>,
+}
+
+/// A middleware recording the pairing of the function events, for
+/// `verify_function_event_balance`.
+struct EventBalanceRecorder(Arc<Mutex<EventBalance>>);
+
+impl FunctionMiddleware for EventBalanceRecorder {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        let mut balance = self.0.lock().unwrap();
+        let error = match op {
+            Event::Internal(InternalEvent::FunctionBegin(id)) => {
+                let func_index = module_info.imported_functions.len() as u32 + id;
+                let error = balance.open.map(|open| {
+                    format!(
+                        "function {} has no FunctionEnd event before function {} begins",
+                        open, func_index
+                    )
+                });
+                balance.open = Some(func_index);
+                balance.last = Some(func_index);
+                error
+            }
+            Event::Internal(InternalEvent::FunctionEnd) => match balance.open.take() {
+                Some(_) => None,
+                None => Some(match balance.last {
+                    Some(last) => format!("function {} has more than one FunctionEnd event", last),
+                    None => "FunctionEnd event before any FunctionBegin event".to_string(),
+                }),
+            },
+            _ => match balance.open {
+                Some(_) => None,
+                None => Some(match balance.last {
+                    Some(last) => format!("event after the FunctionEnd event of function {}", last),
+                    None => "event before any FunctionBegin event".to_string(),
+                }),
+            },
+        };
+        if balance.error.is_none() {
+            balance.error = error;
+        }
+        sink.push(op);
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! Count the iterations of each loop executed by an instance.
+
+use crate::registry::InstanceRegistry;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
+    module::ModuleInfo,
+    vm::{Ctx, InternalField},
+    wasmparser::Operator,
+};
+
+/// The key of the loop counts of an instance in `LOOP_COUNTS`, or zero if it has none yet.
+static INTERNAL_FIELD_LOOP_COUNTS_ID: InternalField = InternalField::allocate();
+
+/// The internal fields of `LoopCounter`, for `registered_internal_fields`.
+pub(crate) fn internal_fields() -> Vec<(&'static str, &'static InternalField)> {
+    vec![(
+        "loop_counter.loop_counts_id",
+        &INTERNAL_FIELD_LOOP_COUNTS_ID,
+    )]
+}
+
+lazy_static! {
+    /// The loop counts of the instances, by the id in their `INTERNAL_FIELD_LOOP_COUNTS_ID`.
+    static ref LOOP_COUNTS: InstanceRegistry<HashMap<LoopId, u64>> =
+        InstanceRegistry::new(&INTERNAL_FIELD_LOOP_COUNTS_ID);
+}
+
+/// Identifies a loop of a module.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LoopId {
+    /// The index of the function of the loop, counting the imported functions.
+    pub func_index: u32,
+    /// The number of loops before it in the function, in the order of their `loop` operators.
+    pub loop_index: u32,
+}
+
+/// LoopCounter is a compiler middleware counting the iterations of each loop executed by an
+/// instance, for `loop_counts`.
+///
+/// An iteration is counted each time the body of a loop is entered, at the `loop` operator and
+/// at each branch back to it, so the back-edges taken are the iterations minus the times the
+/// loop was reached. Each iteration calls into the runtime to be counted, so this middleware is
+/// meant for profiling rather than for production code.
+#[derive(Default)]
+pub struct LoopCounter {
+    func_index: u32,
+    loop_index: u32,
+}
+
+impl LoopCounter {
+    pub fn new() -> LoopCounter {
+        LoopCounter::default()
+    }
+}
+
+impl FunctionMiddleware for LoopCounter {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        let is_loop = match op {
+            Event::Internal(InternalEvent::FunctionBegin(id)) => {
+                self.func_index = module_info.imported_functions.len() as u32 + id;
+                self.loop_index = 0;
+                false
+            }
+            Event::Wasm(&Operator::Loop { .. }) | Event::WasmOwned(Operator::Loop { .. }) => true,
+            _ => false,
+        };
+        sink.push(op);
+        if is_loop {
+            // Inside the loop, the breakpoint runs on every entry into its body.
+            let id = LoopId {
+                func_index: self.func_index,
+                loop_index: self.loop_index,
+            };
+            self.loop_index += 1;
+            sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+                move |mut info| {
+                    if let Some(ctx) = info.ctx() {
+                        count_iteration(ctx, id);
+                    }
+                    Ok(())
+                },
+            ))));
+        }
+        Ok(())
+    }
+}
+
+/// Adds an iteration of loop `id` to the counts of the instance of `ctx`, registering an entry for
+/// it first if needed.
+fn count_iteration(ctx: &mut Ctx, id: LoopId) {
+    LOOP_COUNTS.with(ctx, |counts| *counts.entry(id).or_insert(0) += 1);
+}
+
+/// Returns the iterations of each loop executed by the instance of `ctx` since it was created or
+/// last reset with `reset_loop_counts`. Loops never entered are left out.
+pub fn loop_counts(ctx: &Ctx) -> HashMap<LoopId, u64> {
+    LOOP_COUNTS
+        .get(ctx, |counts| counts.clone())
+        .unwrap_or_default()
+}
+
+/// Zeroes the loop counts of the instance of `ctx`, which is meant to be done between calls to
+/// count the iterations of each call.
+pub fn reset_loop_counts(ctx: &mut Ctx) {
+    LOOP_COUNTS.remove(ctx);
+}
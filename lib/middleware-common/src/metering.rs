@@ -1,12 +1,241 @@
+use crate::cost_table::{append_custom_section, CostTable};
+use crate::event_trace::{EventTrace, TraceRecord};
+use crate::opcode::OpcodeKind;
+use crate::registry::InstanceRegistry;
+use crate::working_set::{is_memory_access, mark_pages, memory_access, push_access_breakpoint};
+use lazy_static::lazy_static;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+use std::fmt::{self, Debug};
+use std::io::{self, Write};
+use std::ops::{BitOr, Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use wasmer_runtime_core::{
-    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
-    module::ModuleInfo,
+    backend::RunnableModule,
+    codegen::{
+        Event, EventSink, FunctionCodeGenerator, FunctionMiddleware, InternalEvent,
+        MiddlewareChain, ModuleCodeGenerator, StreamingCompiler,
+    },
+    compile_with,
+    error::{CallError, CallResult, CompileError, CompileResult, RuntimeError},
+    module::{Module, ModuleInfo},
+    structures::TypedIndex,
+    types::{FuncIndex, Type, Value},
     vm::{Ctx, InternalField},
-    wasmparser::{Operator, Type as WpType, TypeOrFuncType as WpTypeOrFuncType},
+    wasmparser::{
+        ExternalKind, ImportSectionEntryType, ModuleReader, Operator, SectionCode, Type as WpType,
+        TypeOrFuncType as WpTypeOrFuncType,
+    },
     Instance,
 };
 
-static INTERNAL_FIELD: InternalField = InternalField::allocate();
+/// The version of the instrumentation of `Metering`, bumped whenever a module compiled with a
+/// previous version may charge different points or read the internal fields differently.
+pub const METERING_ABI_VERSION: u32 = 1;
+
+/// The name of the custom section holding the metering ABI version of a module.
+pub const ABI_VERSION_SECTION: &str = "wasmer.metering.abi_version";
+
+/// The name of the custom section holding the checkpoint counts of the functions of a module.
+pub const CHECKPOINTS_SECTION: &str = "wasmer.metering.checkpoints";
+
+/// The name of the custom section holding the points limit the instances of a module start with.
+pub const DEFAULT_LIMIT_SECTION: &str = "wasmer.metering.default_limit";
+
+/// The name of the custom section holding the points charged for each element its instances
+/// copy into their tables, see `apply_table_fill_cost`.
+pub const TABLE_FILL_COST_SECTION: &str = "wasmer.metering.table_fill_cost";
+
+/// The name of the custom section marking a module compiled with `Metering::countdown`.
+pub const COUNTDOWN_SECTION: &str = "wasmer.metering.countdown";
+
+/// The prefix of the names of the custom sections embedded for the metering, see
+/// `strip_reserved_sections`.
+pub const RESERVED_SECTION_PREFIX: &str = "wasmer.metering.";
+
+static INTERNAL_FIELD_USED: InternalField = InternalField::allocate();
+/// The points limit of modules compiled with `Metering::with_runtime_limit`.
+static INTERNAL_FIELD_LIMIT: InternalField = InternalField::allocate();
+/// The points charged since the last flush, with `Metering::with_batched_flush`.
+static INTERNAL_FIELD_UNFLUSHED: InternalField = InternalField::allocate();
+/// The number of calls made by metered code that have not returned yet. It is the call depth
+/// checked by `Metering::with_max_call_depth`.
+static INTERNAL_FIELD_NESTED_CALLS: InternalField = InternalField::allocate();
+/// The points used when the last call from the host into metered code began.
+static INTERNAL_FIELD_CALL_START: InternalField = InternalField::allocate();
+/// Identifies the entry of an instance in `HOOKS`. Zero means the instance has no hooks.
+static INTERNAL_FIELD_HOOKS_ID: InternalField = InternalField::allocate();
+/// Set when a function exceeding the limit should return its default value, see
+/// `set_graceful_exhaustion`.
+static INTERNAL_FIELD_GRACEFUL_RETURN: InternalField = InternalField::allocate();
+/// The bits of the value returned by a function exhausting the limit gracefully.
+static INTERNAL_FIELD_GRACEFUL_VALUE: InternalField = InternalField::allocate();
+/// Set when a call exceeded the limit with `Metering::soft_fail`, until `call_soft` reports it
+/// or the next call from the host starts.
+static INTERNAL_FIELD_SOFT_FAILED: InternalField = InternalField::allocate();
+/// Set once the callback of `set_on_limit_exceeded` fired for the current call, with
+/// `Metering::with_graceful_exhaustion`, whose calls carry on past the limit.
+static INTERNAL_FIELD_LIMIT_NOTIFIED: InternalField = InternalField::allocate();
+/// The points by which the limit was last exceeded, see `get_gas_overrun`.
+static INTERNAL_FIELD_GAS_OVERRUN: InternalField = InternalField::allocate();
+/// The most points used by the instance, with `Metering::with_global_peak_points`.
+static INTERNAL_FIELD_PEAK_POINTS: InternalField = InternalField::allocate();
+/// The points charged by the instantiation charges, such as `charge_element_segments`.
+static INTERNAL_FIELD_INSTANTIATION_COST: InternalField = InternalField::allocate();
+/// An operand moved off the stack for a breakpoint to read, such as the operand of a branch that
+/// may return from the function with `Metering::with_function_costs`.
+static INTERNAL_FIELD_OPERAND: InternalField = InternalField::allocate();
+/// The points charged to each `OpcodeCategory`, with `Metering::with_category_breakdown`.
+static INTERNAL_FIELD_CATEGORIES: [InternalField; CATEGORY_COUNT] = [
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+];
+/// The points charged by each `Surcharge`, with `Metering::with_surcharge_breakdown`.
+static INTERNAL_FIELD_SURCHARGES: [InternalField; SURCHARGE_COUNT] = [
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+];
+
+/// The internal fields of `Metering`, for `registered_internal_fields`.
+pub(crate) fn internal_fields() -> Vec<(&'static str, &'static InternalField)> {
+    vec![
+        ("metering.points_used", &INTERNAL_FIELD_USED),
+        ("metering.limit", &INTERNAL_FIELD_LIMIT),
+        ("metering.unflushed", &INTERNAL_FIELD_UNFLUSHED),
+        ("metering.nested_calls", &INTERNAL_FIELD_NESTED_CALLS),
+        ("metering.call_start", &INTERNAL_FIELD_CALL_START),
+        ("metering.hooks_id", &INTERNAL_FIELD_HOOKS_ID),
+        ("metering.operand", &INTERNAL_FIELD_OPERAND),
+        ("metering.graceful_return", &INTERNAL_FIELD_GRACEFUL_RETURN),
+        ("metering.graceful_value", &INTERNAL_FIELD_GRACEFUL_VALUE),
+        ("metering.soft_failed", &INTERNAL_FIELD_SOFT_FAILED),
+        ("metering.limit_notified", &INTERNAL_FIELD_LIMIT_NOTIFIED),
+        ("metering.gas_overrun", &INTERNAL_FIELD_GAS_OVERRUN),
+        ("metering.peak_points", &INTERNAL_FIELD_PEAK_POINTS),
+        (
+            "metering.instantiation_cost",
+            &INTERNAL_FIELD_INSTANTIATION_COST,
+        ),
+        ("metering.integer_points", &INTERNAL_FIELD_CATEGORIES[0]),
+        ("metering.float_points", &INTERNAL_FIELD_CATEGORIES[1]),
+        ("metering.memory_points", &INTERNAL_FIELD_CATEGORIES[2]),
+        ("metering.control_points", &INTERNAL_FIELD_CATEGORIES[3]),
+        ("metering.other_points", &INTERNAL_FIELD_CATEGORIES[4]),
+        (
+            "metering.call_indirect_surcharge",
+            &INTERNAL_FIELD_SURCHARGES[0],
+        ),
+        (
+            "metering.br_table_targets_surcharge",
+            &INTERNAL_FIELD_SURCHARGES[1],
+        ),
+        (
+            "metering.memory_grow_surcharge",
+            &INTERNAL_FIELD_SURCHARGES[2],
+        ),
+        (
+            "metering.bulk_memory_surcharge",
+            &INTERNAL_FIELD_SURCHARGES[3],
+        ),
+        (
+            "metering.unaligned_access_surcharge",
+            &INTERNAL_FIELD_SURCHARGES[4],
+        ),
+        (
+            "metering.cross_module_call_surcharge",
+            &INTERNAL_FIELD_SURCHARGES[5],
+        ),
+        (
+            "metering.cold_pages_surcharge",
+            &INTERNAL_FIELD_SURCHARGES[6],
+        ),
+    ]
+}
+
+/// A callback receiving the index of the function that exceeded the limit, and by how many
+/// points it was exceeded.
+type LimitExceededCallback = Arc<dyn Fn(u32, u64) + Send + Sync>;
+
+/// A callback receiving the points charged since the previous flush.
+type FlushCallback = Arc<dyn Fn(&mut Ctx, u64) + Send + Sync>;
+
+/// A callback of `OverflowPolicy::Callback`, receiving the points that were to be charged.
+pub type OverflowCallback = Arc<dyn Fn(&mut Ctx, u64) + Send + Sync>;
+
+/// What `Metering` does when charging the points of a run of code would overflow the points used,
+/// see `Metering::with_overflow_policy`.
+#[derive(Clone)]
+pub enum OverflowPolicy {
+    /// Trap with `PointsOverflowError`.
+    Trap,
+    /// Set the points used to `u64::MAX`.
+    Saturate,
+    /// Call the callback with the points that were to be charged, then saturate.
+    Callback(OverflowCallback),
+}
+
+/// A callback receiving the index of a function that returned, and the points it was charged.
+type FunctionCostCallback = Arc<dyn Fn(u32, u64) + Send + Sync>;
+
+/// A function receiving the kind of an operator and the magnitude of a constant it takes, and
+/// returning the extra points it costs.
+type MagnitudeCost = Arc<dyn Fn(OpcodeKind, u64) -> u64 + Send + Sync>;
+
+/// Runtime hooks registered on an instance.
+#[derive(Default)]
+struct Hooks {
+    on_limit_exceeded: Option<LimitExceededCallback>,
+    on_function_cost: Option<FunctionCostCallback>,
+    /// The functions running on the instance, innermost last, while `on_function_cost` is set.
+    frames: Vec<Frame>,
+    /// The values returned by the functions exhausting the limit gracefully, by function index.
+    graceful_defaults: HashMap<u32, Vec<Value>>,
+    /// The memory pages accessed since the last `reset_cold_pages`, one bit per page.
+    warm_pages: Vec<u64>,
+    /// The flag aborting the calls when set, see `set_abort_flag`.
+    abort_flag: Option<Arc<AtomicBool>>,
+    /// The thread whose points are in the points field, see `set_current_thread`.
+    current_thread: u64,
+    /// The points used by the other threads, by thread id.
+    thread_points: HashMap<u64, u64>,
+}
+
+/// A running function, with `Metering::with_function_costs`.
+struct Frame {
+    func_index: u32,
+    points_at_entry: u64,
+    /// The points charged by the functions it called, including their own callees.
+    callee_points: u64,
+}
+
+lazy_static! {
+    static ref HOOKS: InstanceRegistry<Hooks> = InstanceRegistry::new(&INTERNAL_FIELD_HOOKS_ID);
+    /// The limits of the live `TrackedInstance`s, by module and by instance.
+    static ref CONFIGURED_LIMITS: Mutex<HashMap<usize, HashMap<u64, u64>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Runs `f` on the hooks of `ctx`, registering an entry for it first if needed.
+fn with_hooks<R>(ctx: &mut Ctx, f: impl FnOnce(&mut Hooks) -> R) -> R {
+    HOOKS.with(ctx, f)
+}
+
+/// Returns a clone of one of the hooks of `ctx`, without registering an entry for it.
+fn get_hook<T: Clone>(ctx: &Ctx, f: impl FnOnce(&Hooks) -> &Option<T>) -> Option<T> {
+    HOOKS
+        .get(ctx, |hooks| f(hooks).clone())
+        .and_then(|hook| hook)
+}
 
 /// Metering is a compiler middleware that calculates the cost of WebAssembly instructions at compile
 /// time and will count the cost of executed instructions at runtime. Within the Metering functionality,
@@ -20,111 +249,3343 @@ static INTERNAL_FIELD: InternalField = InternalField::allocate();
 /// Each compiler backend with Metering enabled should produce the same cost used at runtime for
 /// the same function calls so we can say that the metering is deterministic.
 ///
+/// The cost of the operators is accumulated as they stream through the middleware and charged
+/// at once before the next operator that may leave the current block, so no part of a function
+/// body is buffered.
+///
+/// There is no operator buffer to reuse across functions either: the events emitted are pushed
+/// straight to the `EventSink` the middleware is fed with. That sink belongs to
+/// `MiddlewareChain::run`, lives for a single event, keeps its first events inline, and holds
+/// events borrowing the operator being parsed, so it cannot be kept from one function to the
+/// next without erasing that lifetime.
+///
+/// The tail-call operators `return_call` and `return_call_indirect` are not priced: the parser
+/// of the backends does not decode them yet, so modules using them fail to compile before
+/// reaching the middleware.
+///
+/// `select` costs the same whatever the type of its operands. The parser does not decode the
+/// typed `select` of the reference types proposal either, and the type of the operands of an
+/// untyped `select` is not known to the middleware, which does not track the operand stack.
+///
 pub struct Metering {
-    limit: u64,
+    /// The compiled-in limit, or `None` to read the limit of the instance at runtime.
+    limit: Option<u64>,
+    /// Whether the points field holds the remaining budget, see `Metering::countdown`.
+    countdown: bool,
     current_block: u64,
+    cost_table: CostTable,
+    call_indirect_cost: u64,
+    cross_module_call_cost: u64,
+    br_table_target_cost: u64,
+    forward_branch_cost: u64,
+    backward_branch_cost: u64,
+    memory_grow_cost: u64,
+    bulk_memory_cost: u64,
+    element_cost: u64,
+    unaligned_access_surcharge: u64,
+    cold_page_surcharge: u64,
+    local_init_cost: u64,
+    /// The number of locals declared by the next function.
+    num_locals: u64,
+    stack_frame_cost_per_byte: u64,
+    result_copy_cost_per_value: u64,
+    /// The size in bytes of the locals declared by the next function.
+    locals_bytes: u64,
+    features: MeteringFeatures,
+    func_index: u32,
+    compile_deadline: Option<Instant>,
+    last_call_cost: bool,
+    function_costs: bool,
+    graceful_exhaustion: bool,
+    soft_fail: bool,
+    category_breakdown: bool,
+    /// The points of the current block charged to each `OpcodeCategory`.
+    category_block: CategoryPoints,
+    surcharge_breakdown: bool,
+    global_peak_points: bool,
+    /// The points of the current block charged by each `Surcharge` known at compile time.
+    surcharge_block: StaticSurchargePoints,
+    /// The result types of the current function.
+    results: Vec<Type>,
+    max_call_depth: Option<u32>,
+    abort_checks: bool,
+    charge_before_traps: bool,
+    batched_flush: Option<(u64, FlushCallback)>,
+    overflow_policy: Option<OverflowPolicy>,
+    max_checkpoints: Option<u32>,
+    imports_only: bool,
+    /// The number of checkpoints of the current function so far.
+    checkpoints: u32,
+    /// For each block open in the current function, the points left uncharged at its `if` with
+    /// their categories and surcharges, until its `else`. `None` for the other blocks.
+    uncharged_at_if: Vec<Option<UnchargedAtIf>>,
+    magnitude_cost: Option<MagnitudeCost>,
+    free_opcodes: HashSet<OpcodeKind>,
+    exempt_functions: HashSet<u32>,
+    /// Whether the current function is in `exempt_functions`.
+    exempt: bool,
+    progress_callback: Option<Box<dyn FnMut(u32, u32)>>,
+    /// The value of the previous operator if it is an `i32.const`, read as unsigned.
+    last_const: Option<u64>,
+    /// The number of blocks open in the current function, including the function body itself.
+    block_depth: u32,
+    /// Whether each block open in the current function is a loop, innermost last.
+    loop_blocks: Vec<bool>,
 }
 
 impl Metering {
     pub fn new(limit: u64) -> Metering {
         Metering {
-            limit,
+            limit: Some(limit),
+            countdown: false,
             current_block: 0,
+            cost_table: CostTable::default(),
+            call_indirect_cost: 0,
+            cross_module_call_cost: 0,
+            br_table_target_cost: 0,
+            forward_branch_cost: 0,
+            backward_branch_cost: 0,
+            memory_grow_cost: 0,
+            bulk_memory_cost: 0,
+            element_cost: 0,
+            unaligned_access_surcharge: 0,
+            cold_page_surcharge: 0,
+            local_init_cost: 0,
+            num_locals: 0,
+            stack_frame_cost_per_byte: 0,
+            result_copy_cost_per_value: 0,
+            locals_bytes: 0,
+            features: MeteringFeatures::all(),
+            func_index: 0,
+            compile_deadline: None,
+            last_call_cost: false,
+            function_costs: false,
+            graceful_exhaustion: false,
+            soft_fail: false,
+            category_breakdown: false,
+            category_block: [0; CATEGORY_COUNT],
+            surcharge_breakdown: false,
+            global_peak_points: false,
+            surcharge_block: [0; STATIC_SURCHARGE_COUNT],
+            results: Vec::new(),
+            max_call_depth: None,
+            abort_checks: false,
+            charge_before_traps: false,
+            batched_flush: None,
+            overflow_policy: None,
+            max_checkpoints: None,
+            imports_only: false,
+            checkpoints: 0,
+            uncharged_at_if: Vec::new(),
+            magnitude_cost: None,
+            free_opcodes: HashSet::new(),
+            exempt_functions: HashSet::new(),
+            exempt: false,
+            progress_callback: None,
+            last_const: None,
+            block_depth: 0,
+            loop_blocks: Vec::new(),
         }
     }
-}
 
-#[derive(Copy, Clone, Debug)]
-pub struct ExecutionLimitExceededError;
+    /// Creates a Metering whose limit is not compiled in, but read from the instance at runtime.
+    ///
+    /// The limit is set with `set_execution_limit` and defaults to zero, so an instance must be
+    /// given a limit before any of its metered functions can make a call or a branch.
+    pub fn with_runtime_limit() -> Metering {
+        Metering {
+            limit: None,
+            ..Metering::new(0)
+        }
+    }
 
-impl FunctionMiddleware for Metering {
-    type Error = String;
-    fn feed_event<'a, 'b: 'a>(
-        &mut self,
-        op: Event<'a, 'b>,
-        _module_info: &ModuleInfo,
-        sink: &mut EventSink<'a, 'b>,
-    ) -> Result<(), Self::Error> {
-        match op {
-            Event::Internal(InternalEvent::FunctionBegin(_)) => {
-                self.current_block = 0;
+    /// Creates a Metering counting down a budget rather than counting up the points used.
+    ///
+    /// The budget is set with `set_remaining`, and the points charged are subtracted from it
+    /// down to zero. A call traps at the first metering check where the budget is exhausted.
+    /// The points used of an instance hold its remaining budget, and a limit exceeded callback is
+    /// told that the limit was exceeded by zero points.
+    ///
+    /// The instrumentation does not tell the host the mode of a module, so the module is meant
+    /// to be marked with `embed_countdown`, for the instantiation charges such as
+    /// `charge_element_segments` to subtract from the budget rather than add to it.
+    pub fn countdown() -> Metering {
+        Metering {
+            limit: None,
+            countdown: true,
+            ..Metering::new(0)
+        }
+    }
+
+    /// Prices the operators with `table`, instead of charging one point for each.
+    pub fn with_cost_table(mut self, table: CostTable) -> Metering {
+        self.cost_table = table;
+        self
+    }
+
+    /// Charges `cost` points for every operator, whatever its kind, replacing the cost table.
+    ///
+    /// The surcharges of the special cases still apply on top, as do the other options.
+    pub fn uniform(self, cost: u64) -> Metering {
+        self.with_cost_table(CostTable::new(cost))
+    }
+
+    /// Charges `cost` extra points for every `call_indirect`, on top of its instruction cost.
+    ///
+    /// The surcharge pays for the table bounds check and the signature check. It is charged
+    /// before the call is attempted, so it is kept even when the call traps because of an
+    /// out-of-bounds index or a signature mismatch.
+    pub fn with_call_indirect_cost(mut self, cost: u64) -> Metering {
+        self.call_indirect_cost = cost;
+        self
+    }
+
+    /// Charges `cost` extra points for every `call` of an imported function, on top of its
+    /// instruction cost.
+    ///
+    /// Imports are resolved at instantiation, so the surcharge applies to the calls of host
+    /// functions as well as to the calls of functions exported by other modules.
+    ///
+    /// This prices the transition out of the module, such as the trampoline into a host
+    /// function. It is charged at each call site, unlike the costs of entering a function, such as
+    /// `with_local_init_cost`, which only apply to the functions defined by the module.
+    pub fn with_cross_module_call_cost(mut self, cost: u64) -> Metering {
+        self.cross_module_call_cost = cost;
+        self
+    }
+
+    /// Charges `cost` extra points for every transition into a host function, on top of the
+    /// instruction cost of its `call`, with `with_cross_module_call_cost`.
+    ///
+    /// The imports are only resolved at instantiation, so the functions of the host cannot be
+    /// told from the functions exported by other modules: the cost applies to every `call` of an
+    /// imported function.
+    pub fn with_host_transition_cost(self, cost: u64) -> Metering {
+        self.with_cross_module_call_cost(cost)
+    }
+
+    /// Charges `cost` extra points for each target of every `br_table`, on top of its
+    /// instruction cost.
+    ///
+    /// The surcharge pays for building the jump table, whose size is known at compile time. The
+    /// default target is not counted.
+    pub fn with_br_table_target_cost(mut self, cost: u64) -> Metering {
+        self.br_table_target_cost = cost;
+        self
+    }
+
+    /// Charges extra points for every `br` and `br_if`, on top of their instruction cost:
+    /// `backward_cost` for the branches to a loop, which start another iteration, and
+    /// `forward_cost` for the others.
+    ///
+    /// The target of a branch is known at compile time, so a `br_if` is charged whether or not it
+    /// branches.
+    pub fn with_branch_costs(mut self, forward_cost: u64, backward_cost: u64) -> Metering {
+        self.forward_branch_cost = forward_cost;
+        self.backward_branch_cost = backward_cost;
+        self
+    }
+
+    /// Charges `cost` extra points for every `memory.grow`, on top of its instruction cost.
+    pub fn with_memory_grow_cost(mut self, cost: u64) -> Metering {
+        self.memory_grow_cost = cost;
+        self
+    }
+
+    /// Charges `cost` extra points for every `memory.copy`, `memory.fill` and `memory.init`, on
+    /// top of their instruction cost.
+    pub fn with_bulk_memory_cost(mut self, cost: u64) -> Metering {
+        self.bulk_memory_cost = cost;
+        self
+    }
+
+    /// Charges `cost` extra points for each table element initialized by `table.init`, on top of
+    /// its instruction cost.
+    ///
+    /// The number of elements is its last operand. When it is pushed by an `i32.const` right
+    /// before it, the elements are charged with the straight-line code. Otherwise, they are
+    /// charged at runtime, and the limit is checked before the elements are initialized. The
+    /// elements of the active segments are initialized before any metered code runs, so they are
+    /// charged to an instance with `charge_element_segments`.
+    pub fn with_element_cost(mut self, cost: u64) -> Metering {
+        self.element_cost = cost;
+        self
+    }
+
+    /// Charges `cost` extra points for every memory access whose declared alignment is below the
+    /// natural alignment of its width, on top of its instruction cost.
+    ///
+    /// The alignment is the immediate of the operator, so the surcharge is known at compile time
+    /// and does not depend on the address accessed.
+    pub fn with_unaligned_access_surcharge(mut self, cost: u64) -> Metering {
+        self.unaligned_access_surcharge = cost;
+        self
+    }
+
+    /// Charges `cost` extra points for each memory page accessed for the first time since the
+    /// last `reset_cold_pages`, which is meant to be called between calls.
+    ///
+    /// The pages accessed by the loads, stores and bulk memory operators are recorded at
+    /// runtime, by a call into the host before each access, so this option makes memory accesses
+    /// much slower. The surcharge is checked against the limit at the next metering check, like
+    /// the other points. Modules using `v128.store` fail to compile with this option.
+    pub fn with_cold_page_surcharge(mut self, cost: u64) -> Metering {
+        self.cold_page_surcharge = cost;
+        self
+    }
+
+    /// Charges `cost` points for each local declared by a function, for zeroing it when the
+    /// function is entered.
+    ///
+    /// The parameters are not counted. The points are charged with the first operators of the
+    /// function.
+    pub fn with_local_init_cost(mut self, cost: u64) -> Metering {
+        self.local_init_cost = cost;
+        self
+    }
+
+    /// Charges the points returned by `cost` for the operators taking a constant, on top of their
+    /// instruction cost, so that their pricing can scale with the constant.
+    ///
+    /// `cost` is given the absolute value of the immediate of `i32.const` and `i64.const`, and
+    /// the delta of `memory.grow` and `table.grow` when it is pushed by an `i32.const` right
+    /// before them. It is called at compile time.
+    pub fn with_magnitude_cost<F>(mut self, cost: F) -> Metering
+    where
+        F: Fn(OpcodeKind, u64) -> u64 + Send + Sync + 'static,
+    {
+        self.magnitude_cost = Some(Arc::new(cost));
+        self
+    }
+
+    /// Charges nothing for the operators of the kinds in `set`, whatever their cost in the cost
+    /// table and their surcharges.
+    ///
+    /// Operator costs are summed at compile time, so free operators add no instrumentation of
+    /// their own. The operators ending a block are still metering checkpoints, and the end of a
+    /// function body is an `end` operator.
+    pub fn with_free_opcodes(mut self, set: HashSet<OpcodeKind>) -> Metering {
+        self.free_opcodes = set;
+        self
+    }
+
+    /// Leaves the functions whose indices are in `set` (in the module's function index space)
+    /// uninstrumented, so that trusted code runs without metering overhead.
+    ///
+    /// The operators of an exempt function charge nothing and it has no limit checks, so it can
+    /// run for as long as it likes. The calls from metered functions into exempt functions are
+    /// still charged as any call. The functions called by an exempt function are metered, but the
+    /// options counting the calls, such as `with_max_call_depth`, do not see the calls it makes.
+    pub fn with_exempt_functions(mut self, set: HashSet<u32>) -> Metering {
+        self.exempt_functions = set;
+        self
+    }
+
+    /// Calls `f` as each function of the module is compiled, with the number of the function
+    /// among the functions defined by the module, counting from one, and their total.
+    ///
+    /// The imported functions are not counted, and the last call is made with the number equal
+    /// to the total.
+    pub fn with_progress_callback<F>(mut self, f: F) -> Metering
+    where
+        F: FnMut(u32, u32) + 'static,
+    {
+        self.progress_callback = Some(Box::new(f));
+        self
+    }
+
+    /// Charges `cost` points for each byte of the stack frame of a function, when the function is
+    /// entered.
+    ///
+    /// The frame is sized from the parameters and the locals of the function. The operand stack
+    /// is not counted, as its depth is only known at the end of the function body while the
+    /// points must be charged with its first operators.
+    pub fn with_stack_frame_cost_per_byte(mut self, cost: u64) -> Metering {
+        self.stack_frame_cost_per_byte = cost;
+        self
+    }
+
+    /// Charges `cost` points for each result of a function when it returns, for copying the
+    /// results to the caller.
+    ///
+    /// The points are charged with the operators leaving the function: its final `end`,
+    /// `return`, and the branches to the function body. A `br_if` or `br_table` that may branch
+    /// out of the function is charged whether or not it does.
+    pub fn with_result_copy_cost_per_value(mut self, cost: u64) -> Metering {
+        self.result_copy_cost_per_value = cost;
+        self
+    }
+
+    /// Enables only the special cases in `features`. They are all enabled by default.
+    ///
+    /// Disabling a special case reverts its operators to their flat cost from the cost table,
+    /// whatever surcharge was configured for them.
+    pub fn with_features(mut self, features: MeteringFeatures) -> Metering {
+        self.features = features;
+        self
+    }
+
+    /// Aborts the compilation with an error if it is still running at `deadline`.
+    ///
+    /// The deadline is checked as each function is processed, so the compilation stops at the
+    /// first function boundary or metering checkpoint reached after it.
+    pub fn with_compile_deadline(mut self, deadline: Instant) -> Metering {
+        self.compile_deadline = Some(deadline);
+        self
+    }
+
+    /// Records the points used by each top-level call, for `last_call_cost`.
+    ///
+    /// A top-level call is a call from the host into a metered function. Its cost includes the
+    /// cost of the calls it makes in turn.
+    pub fn with_last_call_cost(mut self) -> Metering {
+        self.last_call_cost = true;
+        self
+    }
+
+    /// Reports the points charged by each function as it returns, to the callback registered with
+    /// `set_function_cost_callback`.
+    pub fn with_function_costs(mut self) -> Metering {
+        self.function_costs = true;
+        self
+    }
+
+    /// Lets the functions configured with `set_graceful_exhaustion` return a default value when
+    /// they exceed the limit, instead of trapping.
+    ///
+    /// Functions returning a `v128` or more than one value always trap.
+    pub fn with_graceful_exhaustion(mut self) -> Metering {
+        self.graceful_exhaustion = true;
+        self
+    }
+
+    /// Returns from the functions exceeding the limit instead of trapping, so that the call fails
+    /// without unwinding. The call must be made with `call_soft`, which returns the
+    /// `ExecutionLimitExceededError` of the call as a trap would.
+    ///
+    /// The function exceeding the limit returns zeros, and so do its metered callers once it
+    /// returns to them, up to the host. A call made otherwise, such as with `Instance::call`,
+    /// succeeds with these zeros. Functions returning a `v128` or more than one value still trap,
+    /// and so do the limit checks of the functions compiled without this option, such as the
+    /// exempt functions, which also carry on after a metered callee soft fails.
+    pub fn soft_fail(mut self) -> Metering {
+        self.soft_fail = true;
+        self
+    }
+
+    /// Counts the points charged to each `OpcodeCategory` apart, for `category_breakdown`.
+    ///
+    /// The categories are counted alongside the points used, at the same checkpoints, so they
+    /// sum to the points charged by the instrumentation. The points set by the host, such as with
+    /// `set_points_used`, are not counted.
+    pub fn with_category_breakdown(mut self) -> Metering {
+        self.category_breakdown = true;
+        self
+    }
+
+    /// Counts the points charged by each surcharge apart, for `surcharge_breakdown`.
+    ///
+    /// The surcharges are the special cases of `MeteringFeatures` and the surcharge of
+    /// `with_cold_page_surcharge`. They are counted alongside the points used, at the same
+    /// checkpoints.
+    pub fn with_surcharge_breakdown(mut self) -> Metering {
+        self.surcharge_breakdown = true;
+        self
+    }
+
+    /// Records the points used by the instances in a process-wide high-water mark, for
+    /// `global_peak_points`, and in a high-water mark of each instance, for `peak_points`.
+    ///
+    /// The mark is raised as the metered functions return and when a call exceeds the limit, by
+    /// calling into the runtime. It is not raised with `countdown`, where the points field holds
+    /// the remaining budget.
+    pub fn with_global_peak_points(mut self) -> Metering {
+        self.global_peak_points = true;
+        self
+    }
+
+    /// Traps with a `CallDepthExceededError` when a call would nest metered functions more than
+    /// `max_depth` deep, whatever points are left.
+    ///
+    /// The function called by the host is at depth one. The depth is counted at the call sites,
+    /// so a call that traps leaves it raised until the instance is reset with `set_points_used`
+    /// or `set_remaining`, as for the other traps.
+    pub fn with_max_call_depth(mut self, max_depth: u32) -> Metering {
+        self.max_call_depth = Some(max_depth);
+        self
+    }
+
+    /// Traps with an `ExecutionAbortedError` at the next limit check once the flag registered with
+    /// `set_abort_flag` is set, so that the host can cancel a call from another thread.
+    ///
+    /// The flag is read by calling into the runtime at each limit check, before the branches and
+    /// the calls, so aborting is prompt but makes the metered code much slower.
+    pub fn with_abort_checks(mut self) -> Metering {
+        self.abort_checks = true;
+        self
+    }
+
+    /// Charges the straight-line code before each operator that may trap, such as an integer
+    /// division, a float truncation or a memory access, so that a trap keeps the points of the
+    /// operators up to and including the one that trapped.
+    ///
+    /// Otherwise, the points of a run of code cut short by a trap are not charged. The points
+    /// charged when nothing traps are the same, but each operator that may trap adds a charge.
+    pub fn with_charge_before_traps(mut self) -> Metering {
+        self.charge_before_traps = true;
+        self
+    }
+
+    /// Applies `policy` when charging the points of a run of code would overflow the points used,
+    /// instead of wrapping around.
+    ///
+    /// Each charge then checks the points used before adding to them. The policy does not apply
+    /// to the points charged by the host, such as with `charge_element_segments`, nor with
+    /// `Metering::countdown`, whose budget stops at zero.
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Metering {
+        self.overflow_policy = Some(policy);
+        self
+    }
+
+    /// Only meters the calls of imported functions, each charged the points of
+    /// `Metering::with_cross_module_call_cost` and followed by a limit check, leaving the rest of
+    /// the code as it is.
+    ///
+    /// This bounds the interactions of a trusted module with its host at almost no cost to its own
+    /// computation, which is not charged at all: a loop without calls of imported functions runs
+    /// unbounded. The options pricing or checking the other operators have no effect.
+    pub fn imports_only(mut self) -> Metering {
+        self.imports_only = true;
+        self
+    }
+
+    /// Limits the checkpoints of each function to `max_checkpoints`, to bound the code added to
+    /// large functions, see `Metering::checkpoint_counts`.
+    ///
+    /// Once a function has one checkpoint less than the maximum, the rest of it is only charged
+    /// before the loops and the operators leaving its straight-line code for good: the branches,
+    /// the calls, the returns, `unreachable` and its final `end`. The points of the blocks in
+    /// between add up, and at the join of an `if`, the points of the costlier of its two paths are
+    /// charged whichever runs. The points used may then be more than with a checkpoint per block,
+    /// but never less, and `Metering::with_charge_before_traps` no longer applies to the rest of
+    /// the function. The operators above are charged whatever the maximum, so a function with
+    /// more of them still has more checkpoints.
+    pub fn with_max_checkpoints_per_function(mut self, max_checkpoints: u32) -> Metering {
+        self.max_checkpoints = Some(max_checkpoints);
+        self
+    }
+
+    /// Reports the points charged to the host in batches, by calling `flush_callback` with the
+    /// points charged since its previous call.
+    ///
+    /// The points are accumulated in the instance, and flushed once they reach `threshold` and
+    /// when a metered function returns. The points charged by a call that traps are flushed with
+    /// the next batch. The points used are counted as usual.
+    pub fn with_batched_flush<F>(mut self, threshold: u64, flush_callback: F) -> Metering
+    where
+        F: Fn(&mut Ctx, u64) + Send + Sync + 'static,
+    {
+        self.batched_flush = Some((threshold, Arc::new(flush_callback)));
+        self
+    }
+
+    /// Returns the number of checkpoints of each function defined by the module `bytes`, in the
+    /// order of the module, were it compiled with this configuration.
+    ///
+    /// A checkpoint is a point where the compiled code adds the points of the straight-line code
+    /// before it to the points used, so the count is a measure of the overhead of metering a
+    /// function. Exempt functions have none. The module is parsed, not validated.
+    ///
+    /// The deadline of `with_compile_deadline` applies to this pass too, which fails once it is
+    /// reached, as the compilation would.
+    pub fn checkpoint_counts(&self, bytes: &[u8]) -> Result<Vec<u32>, String> {
+        let mut counts = Vec::new();
+        let mut num_imported_functions = 0;
+        let mut reader = ModuleReader::new(bytes).map_err(|e| format!("{:?}", e))?;
+        while !reader.eof() {
+            let section = reader.read().map_err(|e| format!("{:?}", e))?;
+            match section.code {
+                SectionCode::Import => {
+                    let imports = section
+                        .get_import_section_reader()
+                        .map_err(|e| format!("{:?}", e))?;
+                    for import in imports {
+                        if let ImportSectionEntryType::Function(_) =
+                            import.map_err(|e| format!("{:?}", e))?.ty
+                        {
+                            num_imported_functions += 1;
+                        }
+                    }
+                }
+                SectionCode::Code => {
+                    let code = section
+                        .get_code_section_reader()
+                        .map_err(|e| format!("{:?}", e))?;
+                    for body in code {
+                        let operators = body
+                            .and_then(|body| body.get_operators_reader())
+                            .map_err(|e| format!("{:?}", e))?;
+                        let func_index = num_imported_functions + counts.len() as u32;
+                        self.check_compile_deadline(func_index)?;
+                        let mut count = 0;
+                        let mut block_depth = 1;
+                        for op in operators {
+                            let op = op.map_err(|e| format!("{:?}", e))?;
+                            if self.imports_only {
+                                if let Operator::Call { function_index } = op {
+                                    if function_index < num_imported_functions {
+                                        count += 1;
+                                    }
+                                }
+                                continue;
+                            }
+                            match op {
+                                Operator::Block { .. }
+                                | Operator::Loop { .. }
+                                | Operator::If { .. } => block_depth += 1,
+                                Operator::End => block_depth -= 1,
+                                _ => {}
+                            }
+                            if self.charges(&op, self.coarse(count) && block_depth != 0) {
+                                count += 1;
+                            }
+                        }
+                        if self.exempt_functions.contains(&func_index) {
+                            count = 0;
+                        }
+                        counts.push(count);
+                    }
+                }
+                _ => {}
             }
-            Event::Wasm(&ref op) | Event::WasmOwned(ref op) => {
-                self.current_block += 1;
-                match *op {
-                    Operator::Loop { .. }
-                    | Operator::Block { .. }
-                    | Operator::End
-                    | Operator::If { .. }
-                    | Operator::Else
-                    | Operator::Unreachable
-                    | Operator::Br { .. }
-                    | Operator::BrTable { .. }
-                    | Operator::BrIf { .. }
-                    | Operator::Call { .. }
-                    | Operator::CallIndirect { .. }
-                    | Operator::Return => {
-                        sink.push(Event::Internal(InternalEvent::GetInternal(
-                            INTERNAL_FIELD.index() as _,
-                        )));
-                        sink.push(Event::WasmOwned(Operator::I64Const {
-                            value: self.current_block as i64,
-                        }));
-                        sink.push(Event::WasmOwned(Operator::I64Add));
-                        sink.push(Event::Internal(InternalEvent::SetInternal(
-                            INTERNAL_FIELD.index() as _,
-                        )));
-                        self.current_block = 0;
+        }
+        Ok(counts)
+    }
+
+    fn check_compile_deadline(&self, func_index: u32) -> Result<(), String> {
+        match self.compile_deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(format!(
+                "compilation deadline exceeded in function {}",
+                func_index
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pushes the addition of `cost` to the points used, or its subtraction from the budget with
+    /// `Metering::countdown`.
+    fn push_charge<'a, 'b: 'a>(&self, sink: &mut EventSink<'a, 'b>, cost: u64) {
+        if self.countdown {
+            push_countdown(sink, cost);
+        } else if let (Some(ref policy), true) = (&self.overflow_policy, cost != 0) {
+            push_checked_charge(sink, cost, policy);
+        } else {
+            push_add_internal(sink, &INTERNAL_FIELD_USED, cost as i64);
+        }
+    }
+
+    /// Returns where the points charged by a breakpoint are recorded.
+    fn runtime_charge(&self) -> RuntimeCharge {
+        RuntimeCharge {
+            countdown: self.countdown,
+            batched_flush: self.batched_flush.is_some(),
+            category_breakdown: self.category_breakdown,
+            surcharge_breakdown: self.surcharge_breakdown,
+        }
+    }
+
+    /// Pushes the charge of the elements initialized by a `table.init` with a length only known
+    /// at runtime, then the check of the limit, before it runs.
+    fn push_element_charge<'a, 'b: 'a>(&self, sink: &mut EventSink<'a, 'b>) {
+        // Breakpoints cannot read the stack, so the length is moved to an internal field for the
+        // breakpoint to read, then pushed back.
+        sink.push(Event::WasmOwned(Operator::I64ExtendUI32));
+        sink.push(Event::Internal(InternalEvent::SetInternal(
+            INTERNAL_FIELD_OPERAND.index() as _,
+        )));
+        let (cost, charge) = (self.element_cost, self.runtime_charge());
+        sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+            move |mut info| {
+                if let Some(ctx) = info.ctx() {
+                    let elements = ctx.get_internal(&INTERNAL_FIELD_OPERAND);
+                    charge.apply(
+                        ctx,
+                        elements.saturating_mul(cost),
+                        OpcodeCategory::of(OpcodeKind::TableInit),
+                        None,
+                    );
+                }
+                Ok(())
+            },
+        ))));
+        sink.push(Event::Internal(InternalEvent::GetInternal(
+            INTERNAL_FIELD_OPERAND.index() as _,
+        )));
+        sink.push(Event::WasmOwned(Operator::I32WrapI64));
+        self.push_limit_check(sink);
+    }
+
+    /// Pushes the check of the points used against the limit, trapping or returning early once it
+    /// is reached.
+    fn push_limit_check<'a, 'b: 'a>(&self, sink: &mut EventSink<'a, 'b>) {
+        if self.abort_checks {
+            sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+                |mut info| match info.ctx() {
+                    Some(ctx) if is_aborted(ctx) => Err(Box::new(ExecutionAbortedError)),
+                    _ => Ok(()),
+                },
+            ))));
+        }
+        sink.push(Event::Internal(InternalEvent::GetInternal(
+            INTERNAL_FIELD_USED.index() as _,
+        )));
+        if self.countdown {
+            sink.push(Event::WasmOwned(Operator::I64Eqz));
+        } else {
+            match self.limit {
+                Some(limit) => sink.push(Event::WasmOwned(Operator::I64Const {
+                    value: limit as i64,
+                })),
+                None => sink.push(Event::Internal(InternalEvent::GetInternal(
+                    INTERNAL_FIELD_LIMIT.index() as _,
+                ))),
+            }
+            sink.push(Event::WasmOwned(Operator::I64GeU));
+        }
+        sink.push(Event::WasmOwned(Operator::If {
+            ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+        }));
+        let (func_index, limit, countdown) = (self.func_index, self.limit, self.countdown);
+        let graceful = self.graceful_exhaustion && self.returns_scalar();
+        let soft_fail = self.soft_fail && self.returns_scalar();
+        let results = self.results.clone();
+        let global_peak_points = self.global_peak_points;
+        // The calls of graceful functions carry on past the limit, up to the next check of a
+        // caller, so the callback is latched to fire once per call.
+        let latched = self.graceful_exhaustion;
+        // A soft failure unwinds the callers with no limit check of their own, and the callers
+        // that cannot return a default check the limit again, for the same overrun.
+        let soft_failing = self.soft_fail;
+        sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+            move |mut info| {
+                let mut exceeded_by = 0;
+                if let Some(ctx) = info.ctx() {
+                    let soft_failed =
+                        soft_failing && ctx.get_internal(&INTERNAL_FIELD_SOFT_FAILED) != 0;
+                    if soft_failed {
+                        exceeded_by = get_gas_overrun(ctx);
+                    } else if !countdown {
+                        let limit = limit.unwrap_or_else(|| get_execution_limit_ctx(ctx));
+                        if global_peak_points {
+                            raise_peak_points(ctx);
+                        }
+                        exceeded_by = get_points_used_ctx(ctx).saturating_sub(limit);
+                    }
+                    ctx.set_internal(&INTERNAL_FIELD_GAS_OVERRUN, exceeded_by);
+                    let notified = soft_failed
+                        || latched && ctx.get_internal(&INTERNAL_FIELD_LIMIT_NOTIFIED) != 0;
+                    if !notified {
+                        if latched {
+                            ctx.set_internal(&INTERNAL_FIELD_LIMIT_NOTIFIED, 1);
+                        }
+                        notify_limit_exceeded(ctx, func_index, exceeded_by);
+                    }
+                    if graceful && set_graceful_return(ctx, func_index, &results) {
+                        return Ok(());
+                    }
+                    if soft_fail {
+                        ctx.set_internal(&INTERNAL_FIELD_SOFT_FAILED, 1);
+                        return Ok(());
                     }
-                    _ => {}
                 }
-                match *op {
-                    Operator::Br { .. }
-                    | Operator::BrTable { .. }
-                    | Operator::BrIf { .. }
-                    | Operator::Call { .. }
-                    | Operator::CallIndirect { .. } => {
-                        sink.push(Event::Internal(InternalEvent::GetInternal(
-                            INTERNAL_FIELD.index() as _,
-                        )));
-                        sink.push(Event::WasmOwned(Operator::I64Const {
-                            value: self.limit as i64,
-                        }));
-                        sink.push(Event::WasmOwned(Operator::I64GeU));
-                        sink.push(Event::WasmOwned(Operator::If {
-                            ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
-                        }));
-                        sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(|_| {
-                            Err(Box::new(ExecutionLimitExceededError))
-                        }))));
-                        sink.push(Event::WasmOwned(Operator::End));
+                Err(Box::new(ExecutionLimitExceededError { exceeded_by }))
+            },
+        ))));
+        if graceful {
+            push_graceful_return(sink, self.results.first(), self.function_costs);
+        }
+        if soft_fail {
+            push_soft_return(sink, self.results.first(), self.function_costs);
+        }
+        sink.push(Event::WasmOwned(Operator::End));
+    }
+
+    /// Returns true if the current function returns nothing or a single value other than a
+    /// `v128`, as the functions that can return early when they exceed the limit do.
+    fn returns_scalar(&self) -> bool {
+        match *self.results {
+            [] => true,
+            [ty] => ty != Type::V128,
+            _ => false,
+        }
+    }
+
+    /// Returns true if a function with `checkpoints` checkpoints so far is only charged before the
+    /// loops and the operators leaving its straight-line code for good, see
+    /// `Metering::with_max_checkpoints_per_function`.
+    fn coarse(&self, checkpoints: u32) -> bool {
+        self.max_checkpoints
+            .map_or(false, |max| checkpoints.saturating_add(1) >= max)
+    }
+
+    /// Returns true if the points of the straight-line code are charged before `op`, or with
+    /// `coarse`, if `op` starts a loop or leaves the straight-line code for good.
+    fn charges(&self, op: &Operator, coarse: bool) -> bool {
+        if coarse {
+            return match *op {
+                Operator::Loop { .. }
+                | Operator::Unreachable
+                | Operator::Br { .. }
+                | Operator::BrTable { .. }
+                | Operator::BrIf { .. }
+                | Operator::Call { .. }
+                | Operator::CallIndirect { .. }
+                | Operator::Return => true,
+                _ => false,
+            };
+        }
+        match *op {
+            Operator::Loop { .. }
+            | Operator::Block { .. }
+            | Operator::End
+            | Operator::If { .. }
+            | Operator::Else
+            | Operator::Unreachable
+            | Operator::Br { .. }
+            | Operator::BrTable { .. }
+            | Operator::BrIf { .. }
+            | Operator::Call { .. }
+            | Operator::CallIndirect { .. }
+            | Operator::Return => true,
+            _ => self.charge_before_traps && may_trap(OpcodeKind::of(op)),
+        }
+    }
+    /// Resets the state of the metering for the body of function `id`, with the charges of setting
+    /// up its frame.
+    fn begin_function(&mut self, id: u32, module_info: &ModuleInfo) -> Result<(), String> {
+        self.func_index = module_info.imported_functions.len() as u32 + id;
+        let signature = &module_info.signatures
+            [module_info.func_assoc[FuncIndex::new(self.func_index as usize)]];
+        self.results = signature.returns().to_vec();
+        let params_bytes: u64 = signature.params().iter().map(|&ty| value_size(ty)).sum();
+        self.current_block = self.num_locals * self.local_init_cost
+            + (params_bytes + self.locals_bytes) * self.stack_frame_cost_per_byte;
+        // Setting up the frame is part of the call.
+        self.category_block = [0; CATEGORY_COUNT];
+        self.category_block[OpcodeCategory::Control as usize] = self.current_block;
+        self.surcharge_block = [0; STATIC_SURCHARGE_COUNT];
+        self.num_locals = 0;
+        self.locals_bytes = 0;
+        self.block_depth = 1;
+        self.loop_blocks = vec![false];
+        self.checkpoints = 0;
+        self.uncharged_at_if = vec![None];
+        self.last_const = None;
+        self.check_compile_deadline(self.func_index)?;
+        if let Some(ref mut callback) = self.progress_callback {
+            let total = module_info.func_assoc.len() - module_info.imported_functions.len();
+            callback(id + 1, total as u32);
+        }
+        self.exempt = self.exempt_functions.contains(&self.func_index);
+        Ok(())
+    }
+
+    /// Pushes the bookkeeping of the calls at the start of a function body, for
+    /// `Metering::with_last_call_cost`, `Metering::with_function_costs`,
+    /// `Metering::with_graceful_exhaustion` and `Metering::soft_fail`.
+    fn push_function_entry<'a, 'b: 'a>(&self, sink: &mut EventSink<'a, 'b>) {
+        if self.graceful_exhaustion || self.soft_fail {
+            // A call from the host starts with neither the callback of the limit fired nor a soft
+            // failure pending.
+            sink.push(Event::Internal(InternalEvent::GetInternal(
+                INTERNAL_FIELD_NESTED_CALLS.index() as _,
+            )));
+            sink.push(Event::WasmOwned(Operator::I64Eqz));
+            sink.push(Event::WasmOwned(Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            }));
+            for field in &[&INTERNAL_FIELD_LIMIT_NOTIFIED, &INTERNAL_FIELD_SOFT_FAILED] {
+                sink.push(Event::WasmOwned(Operator::I64Const { value: 0 }));
+                sink.push(Event::Internal(InternalEvent::SetInternal(
+                    field.index() as _
+                )));
+            }
+            sink.push(Event::WasmOwned(Operator::End));
+        }
+        if self.last_call_cost {
+            // Without a pending call from metered code, the call comes from the host.
+            sink.push(Event::Internal(InternalEvent::GetInternal(
+                INTERNAL_FIELD_NESTED_CALLS.index() as _,
+            )));
+            sink.push(Event::WasmOwned(Operator::I64Eqz));
+            sink.push(Event::WasmOwned(Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            }));
+            sink.push(Event::Internal(InternalEvent::GetInternal(
+                INTERNAL_FIELD_USED.index() as _,
+            )));
+            sink.push(Event::Internal(InternalEvent::SetInternal(
+                INTERNAL_FIELD_CALL_START.index() as _,
+            )));
+            sink.push(Event::WasmOwned(Operator::End));
+        }
+        if self.function_costs {
+            let func_index = self.func_index;
+            sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+                move |mut info| {
+                    if let Some(ctx) = info.ctx() {
+                        enter_function(ctx, func_index);
                     }
-                    _ => {}
+                    Ok(())
+                },
+            ))));
+        }
+    }
+
+    /// Adds the points of `op` to the straight-line code, and returns true if it is a
+    /// `table.init` whose elements are left to be charged at runtime.
+    fn price_operator(&mut self, op: &Operator, imported_functions: usize) -> Result<bool, String> {
+        let block_cost = self.current_block;
+        self.current_block += self.cost_table.operator_cost(op);
+        self.current_block += self.magnitude_points(op);
+        // Without a constant length, the elements are charged at runtime.
+        let mut uncounted_elements = false;
+        if let Operator::TableInit { .. } = *op {
+            match self.last_const {
+                Some(elements) => self.current_block += elements.saturating_mul(self.element_cost),
+                None => uncounted_elements = self.element_cost != 0,
+            }
+        }
+        self.last_const = match *op {
+            Operator::I32Const { value } => Some(u64::from(value as u32)),
+            _ => None,
+        };
+        if self.free_opcodes.contains(&OpcodeKind::of(op)) {
+            self.current_block = block_cost;
+        } else {
+            if let Some((surcharge, points)) = self.surcharge(op, imported_functions)? {
+                self.current_block += points;
+                self.surcharge_block[surcharge as usize] += points;
+            }
+            if let Operator::Br { relative_depth } | Operator::BrIf { relative_depth } = *op {
+                let to_loop = self.loop_blocks.iter().rev().nth(relative_depth as usize);
+                self.current_block += match to_loop {
+                    Some(true) => self.backward_branch_cost,
+                    _ => self.forward_branch_cost,
+                };
+            }
+        }
+        self.category_block[OpcodeCategory::of(OpcodeKind::of(op)) as usize] +=
+            self.current_block - block_cost;
+        Ok(uncounted_elements)
+    }
+
+    /// Returns the points of the immediate of `op`, with `Metering::with_magnitude_cost`.
+    fn magnitude_points(&self, op: &Operator) -> u64 {
+        let cost = match self.magnitude_cost {
+            Some(ref cost) => cost,
+            None => return 0,
+        };
+        let magnitude = match *op {
+            Operator::I32Const { value } => i64::from(value).abs() as u64,
+            Operator::I64Const { value } => value.wrapping_abs() as u64,
+            Operator::MemoryGrow { .. } | Operator::TableGrow { .. } => match self.last_const {
+                Some(magnitude) => magnitude,
+                None => return 0,
+            },
+            _ => return 0,
+        };
+        cost(OpcodeKind::of(op), magnitude)
+    }
+
+    /// Returns the surcharge of `op` and its points, if one of the enabled `MeteringFeatures`
+    /// applies to it, in a module importing `imported_functions` functions.
+    fn surcharge(
+        &self,
+        op: &Operator,
+        imported_functions: usize,
+    ) -> Result<Option<(Surcharge, u64)>, String> {
+        let features = self.features;
+        Ok(match *op {
+            Operator::CallIndirect { .. } if features.contains(MeteringFeatures::CALL_INDIRECT) => {
+                Some((Surcharge::CallIndirect, self.call_indirect_cost))
+            }
+            Operator::Call { function_index }
+                if features.contains(MeteringFeatures::CROSS_MODULE_CALL)
+                    && (function_index as usize) < imported_functions =>
+            {
+                Some((Surcharge::CrossModuleCall, self.cross_module_call_cost))
+            }
+            Operator::BrTable { ref table }
+                if features.contains(MeteringFeatures::BR_TABLE_TARGETS) =>
+            {
+                let (targets, _) = table.read_table().map_err(|e| format!("{:?}", e))?;
+                Some((
+                    Surcharge::BrTableTargets,
+                    targets.len() as u64 * self.br_table_target_cost,
+                ))
+            }
+            Operator::MemoryGrow { .. } if features.contains(MeteringFeatures::MEMORY_GROW) => {
+                Some((Surcharge::MemoryGrow, self.memory_grow_cost))
+            }
+            Operator::MemoryCopy | Operator::MemoryFill | Operator::MemoryInit { .. }
+                if features.contains(MeteringFeatures::BULK_MEMORY) =>
+            {
+                Some((Surcharge::BulkMemory, self.bulk_memory_cost))
+            }
+            _ if features.contains(MeteringFeatures::UNALIGNED_ACCESS) => match memory_access(op) {
+                // The alignment is encoded as its base 2 logarithm.
+                Some((memarg, width, _)) if memarg.flags < width.trailing_zeros() => {
+                    Some((Surcharge::UnalignedAccess, self.unaligned_access_surcharge))
                 }
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// Tracks the blocks entered and left by `op`, and returns true if it may leave the function,
+    /// adding the points of copying its results.
+    fn enter_blocks(&mut self, op: &Operator) -> Result<bool, String> {
+        let leaves_function = match *op {
+            Operator::Block { .. } | Operator::If { .. } => {
+                self.block_depth += 1;
+                self.loop_blocks.push(false);
+                false
             }
-            _ => {}
+            Operator::Loop { .. } => {
+                self.block_depth += 1;
+                self.loop_blocks.push(true);
+                false
+            }
+            Operator::End => {
+                self.block_depth -= 1;
+                self.loop_blocks.pop();
+                self.block_depth == 0
+            }
+            Operator::Return => true,
+            Operator::Br { relative_depth } | Operator::BrIf { relative_depth } => {
+                relative_depth + 1 == self.block_depth
+            }
+            Operator::BrTable { ref table } => {
+                let (targets, default) = table.read_table().map_err(|e| format!("{:?}", e))?;
+                targets
+                    .iter()
+                    .chain(Some(&default))
+                    .any(|&relative_depth| relative_depth + 1 == self.block_depth)
+            }
+            _ => false,
+        };
+        if leaves_function {
+            let points = self.results.len() as u64 * self.result_copy_cost_per_value;
+            self.current_block += points;
+            self.category_block[OpcodeCategory::Control as usize] += points;
         }
-        sink.push(op);
+        Ok(leaves_function)
+    }
+
+    /// Keeps the costlier of the paths into the code after an `else` or an `end` without a
+    /// checkpoint, as each carries its own points uncharged.
+    fn merge_uncharged_at_if(&mut self, op: &Operator, charges: bool) {
+        let uncharged_at_if = match *op {
+            Operator::Else => self.uncharged_at_if.last_mut().and_then(Option::take),
+            Operator::End => self.uncharged_at_if.pop().and_then(|uncharged| uncharged),
+            _ => None,
+        };
+        if let (Some((points, categories, surcharges)), false) = (uncharged_at_if, charges) {
+            if points > self.current_block {
+                self.current_block = points;
+                self.category_block = categories;
+                self.surcharge_block = surcharges;
+            }
+        }
+    }
+
+    /// Pushes the charge of the straight-line code, and of the options recording it, then starts
+    /// the next one.
+    fn push_checkpoint<'a, 'b: 'a>(
+        &mut self,
+        sink: &mut EventSink<'a, 'b>,
+        leaves_function: bool,
+    ) -> Result<(), String> {
+        self.checkpoints += 1;
+        self.push_charge(sink, self.current_block);
+        if let Some((threshold, ref callback)) = self.batched_flush {
+            push_batched_flush(
+                sink,
+                self.current_block,
+                threshold,
+                callback,
+                leaves_function,
+            );
+        }
+        if self.category_breakdown {
+            for (field, &points) in INTERNAL_FIELD_CATEGORIES.iter().zip(&self.category_block) {
+                if points != 0 {
+                    push_add_internal(sink, field, points as i64);
+                }
+            }
+        }
+        if self.surcharge_breakdown {
+            for (field, &points) in INTERNAL_FIELD_SURCHARGES.iter().zip(&self.surcharge_block) {
+                if points != 0 {
+                    push_add_internal(sink, field, points as i64);
+                }
+            }
+        }
+        if self.global_peak_points && leaves_function && !self.countdown {
+            sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+                |mut info| {
+                    if let Some(ctx) = info.ctx() {
+                        raise_peak_points(ctx);
+                    }
+                    Ok(())
+                },
+            ))));
+        }
+        self.current_block = 0;
+        self.category_block = [0; CATEGORY_COUNT];
+        self.surcharge_block = [0; STATIC_SURCHARGE_COUNT];
+        self.check_compile_deadline(self.func_index)
+    }
+
+    /// Pushes the report of the cost of the function before `op`, which may return from it, with
+    /// `Metering::with_function_costs`.
+    fn push_function_costs_exit<'a, 'b: 'a>(
+        &self,
+        sink: &mut EventSink<'a, 'b>,
+        op: &Operator,
+    ) -> Result<(), String> {
+        // The operand on top of the stack decides whether a `br_if` or a `br_table` returns:
+        // `leaves[operand]` tells, the last entry applying to the larger operands.
+        let leaves = match *op {
+            Operator::BrIf { .. } => Some(vec![false, true]),
+            Operator::BrTable { ref table } => {
+                let (targets, default) = table.read_table().map_err(|e| format!("{:?}", e))?;
+                Some(
+                    targets
+                        .iter()
+                        .chain(Some(&default))
+                        .map(|&relative_depth| relative_depth + 1 == self.block_depth)
+                        .collect(),
+                )
+            }
+            _ => None,
+        };
+        push_function_exit(sink, leaves);
         Ok(())
     }
+
+    /// Pushes the tracking of the pending calls before a call, and the check of
+    /// `Metering::with_max_call_depth`. Returns true if the call is to be tracked back out.
+    fn push_call_entry<'a, 'b: 'a>(&self, sink: &mut EventSink<'a, 'b>) -> bool {
+        let tracked = self.last_call_cost
+            || self.function_costs
+            || self.graceful_exhaustion
+            || self.soft_fail
+            || self.max_call_depth.is_some();
+        if tracked {
+            push_nested_calls_add(sink, 1);
+        }
+        if let Some(max_depth) = self.max_call_depth {
+            // The callee runs one deeper than the calls pending.
+            sink.push(Event::Internal(InternalEvent::GetInternal(
+                INTERNAL_FIELD_NESTED_CALLS.index() as _,
+            )));
+            sink.push(Event::WasmOwned(Operator::I64Const {
+                value: i64::from(max_depth),
+            }));
+            sink.push(Event::WasmOwned(Operator::I64GeU));
+            sink.push(Event::WasmOwned(Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            }));
+            sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(|_| {
+                Err(Box::new(CallDepthExceededError))
+            }))));
+            sink.push(Event::WasmOwned(Operator::End));
+        }
+        tracked
+    }
 }
 
-/// Returns the number of points used by an Instance.
-pub fn get_points_used(instance: &Instance) -> u64 {
-    instance.get_internal(&INTERNAL_FIELD)
+/// Returns true if `Metering` may charge the operators of the given kind more than their cost in
+/// the cost table, see `MeteringFeatures::of`.
+///
+/// The options pricing operators by their immediates or by the function they are in, such as
+/// `Metering::with_magnitude_cost`, are not special cases.
+pub fn is_special_cased(kind: OpcodeKind) -> bool {
+    MeteringFeatures::of(kind) != MeteringFeatures::empty()
 }
 
-/// Sets the number of points used by an Instance.
-pub fn set_points_used(instance: &mut Instance, value: u64) {
-    instance.set_internal(&INTERNAL_FIELD, value);
+/// Pushes the addition of `value` to the internal field `field`.
+pub(crate) fn push_add_internal<'a, 'b: 'a>(
+    sink: &mut EventSink<'a, 'b>,
+    field: &InternalField,
+    value: i64,
+) {
+    sink.push(Event::Internal(InternalEvent::GetInternal(
+        field.index() as _
+    )));
+    sink.push(Event::WasmOwned(Operator::I64Const { value }));
+    sink.push(Event::WasmOwned(Operator::I64Add));
+    sink.push(Event::Internal(InternalEvent::SetInternal(
+        field.index() as _
+    )));
 }
 
-/// Returns the number of points used in a Ctx.
-pub fn get_points_used_ctx(ctx: &Ctx) -> u64 {
-    ctx.get_internal(&INTERNAL_FIELD)
+/// Pushes the addition of the `points` of a checkpoint to the batch of
+/// `Metering::with_batched_flush`, and its flush to `callback` once it reaches `threshold`, or
+/// with `leaves_function`, when leaving the function.
+fn push_batched_flush<'a, 'b: 'a>(
+    sink: &mut EventSink<'a, 'b>,
+    points: u64,
+    threshold: u64,
+    callback: &FlushCallback,
+    leaves_function: bool,
+) {
+    push_add_internal(sink, &INTERNAL_FIELD_UNFLUSHED, points as i64);
+    sink.push(Event::Internal(InternalEvent::GetInternal(
+        INTERNAL_FIELD_UNFLUSHED.index() as _,
+    )));
+    sink.push(Event::WasmOwned(Operator::I64Const {
+        value: threshold as i64,
+    }));
+    sink.push(Event::WasmOwned(Operator::I64GeU));
+    sink.push(Event::WasmOwned(Operator::If {
+        ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+    }));
+    push_flush(sink, callback);
+    sink.push(Event::WasmOwned(Operator::End));
+    // Flush the rest of the batch when leaving the function.
+    if leaves_function {
+        sink.push(Event::Internal(InternalEvent::GetInternal(
+            INTERNAL_FIELD_UNFLUSHED.index() as _,
+        )));
+        sink.push(Event::WasmOwned(Operator::I64Eqz));
+        sink.push(Event::WasmOwned(Operator::I32Eqz));
+        sink.push(Event::WasmOwned(Operator::If {
+            ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+        }));
+        push_flush(sink, callback);
+        sink.push(Event::WasmOwned(Operator::End));
+    }
 }
 
-/// Sets the number of points used in a Ctx.
-pub fn set_points_used_ctx(ctx: &mut Ctx, value: u64) {
-    ctx.set_internal(&INTERNAL_FIELD, value);
+/// Pushes a breakpoint passing the unflushed points to `callback`.
+fn push_flush<'a, 'b: 'a>(sink: &mut EventSink<'a, 'b>, callback: &FlushCallback) {
+    let callback = callback.clone();
+    sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+        move |mut info| {
+            if let Some(ctx) = info.ctx() {
+                let unflushed = ctx.get_internal(&INTERNAL_FIELD_UNFLUSHED);
+                ctx.set_internal(&INTERNAL_FIELD_UNFLUSHED, 0);
+                callback(ctx, unflushed);
+            }
+            Ok(())
+        },
+    ))));
+}
+
+/// The error of a call trapping because it exceeded the points limit.
+#[derive(Copy, Clone, Debug)]
+pub struct ExecutionLimitExceededError {
+    /// The points used past the limit when the call trapped, zero with
+    /// `Metering::countdown` as the points used are not known.
+    pub exceeded_by: u64,
+}
+
+impl fmt::Display for ExecutionLimitExceededError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "out of gas: exceeded limit by {} points",
+            self.exceeded_by
+        )
+    }
+}
+
+/// The error of a call trapping because charging its points would overflow the points used,
+/// with `OverflowPolicy::Trap`.
+#[derive(Copy, Clone, Debug)]
+pub struct PointsOverflowError {
+    /// The points that were to be charged.
+    pub attempted: u64,
+}
+
+/// The error of a call trapping because of `Metering::with_max_call_depth`.
+#[derive(Copy, Clone, Debug)]
+pub struct CallDepthExceededError;
+
+/// The error of a call aborted with the flag registered with `set_abort_flag`.
+#[derive(Copy, Clone, Debug)]
+pub struct ExecutionAbortedError;
+
+/// A set of the operators priced specially by `Metering`, see `Metering::with_features`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MeteringFeatures(u32);
+
+impl MeteringFeatures {
+    /// The surcharge of `Metering::with_call_indirect_cost`.
+    pub const CALL_INDIRECT: MeteringFeatures = MeteringFeatures(1);
+    /// The surcharge of `Metering::with_br_table_target_cost`.
+    pub const BR_TABLE_TARGETS: MeteringFeatures = MeteringFeatures(1 << 1);
+    /// The surcharge of `Metering::with_memory_grow_cost`.
+    pub const MEMORY_GROW: MeteringFeatures = MeteringFeatures(1 << 2);
+    /// The surcharge of `Metering::with_bulk_memory_cost`.
+    pub const BULK_MEMORY: MeteringFeatures = MeteringFeatures(1 << 3);
+    /// The surcharge of `Metering::with_unaligned_access_surcharge`.
+    pub const UNALIGNED_ACCESS: MeteringFeatures = MeteringFeatures(1 << 4);
+    /// The surcharge of `Metering::with_cross_module_call_cost`.
+    pub const CROSS_MODULE_CALL: MeteringFeatures = MeteringFeatures(1 << 5);
+
+    /// Returns the set of no special cases.
+    pub const fn empty() -> MeteringFeatures {
+        MeteringFeatures(0)
+    }
+
+    /// Returns the set of all the special cases.
+    pub const fn all() -> MeteringFeatures {
+        MeteringFeatures(0b11_1111)
+    }
+
+    /// Returns the special cases that may apply to the operators of the given kind.
+    ///
+    /// The special case of `call` only applies to the calls of imported functions, and that of
+    /// memory accesses to the unaligned ones.
+    pub fn of(kind: OpcodeKind) -> MeteringFeatures {
+        match kind {
+            OpcodeKind::CallIndirect => MeteringFeatures::CALL_INDIRECT,
+            OpcodeKind::BrTable => MeteringFeatures::BR_TABLE_TARGETS,
+            OpcodeKind::MemoryGrow => MeteringFeatures::MEMORY_GROW,
+            OpcodeKind::MemoryCopy | OpcodeKind::MemoryFill | OpcodeKind::MemoryInit => {
+                MeteringFeatures::BULK_MEMORY
+            }
+            OpcodeKind::Call => MeteringFeatures::CROSS_MODULE_CALL,
+            kind if is_memory_access(kind) => MeteringFeatures::UNALIGNED_ACCESS,
+            _ => MeteringFeatures::empty(),
+        }
+    }
+
+    /// Returns true if all the special cases in `other` are in the set.
+    pub fn contains(self, other: MeteringFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Adds the special cases in `other` to the set.
+    pub fn insert(&mut self, other: MeteringFeatures) {
+        self.0 |= other.0;
+    }
+
+    /// Removes the special cases in `other` from the set.
+    pub fn remove(&mut self, other: MeteringFeatures) {
+        self.0 &= !other.0;
+    }
+}
+
+impl Default for MeteringFeatures {
+    fn default() -> MeteringFeatures {
+        MeteringFeatures::all()
+    }
+}
+
+impl BitOr for MeteringFeatures {
+    type Output = MeteringFeatures;
+
+    fn bitor(self, other: MeteringFeatures) -> MeteringFeatures {
+        MeteringFeatures(self.0 | other.0)
+    }
+}
+
+impl FunctionMiddleware for Metering {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        let mut returns_from_call = false;
+        match op {
+            Event::Internal(InternalEvent::FunctionBegin(_)) => {}
+            _ if self.exempt => {
+                sink.push(op);
+                return Ok(());
+            }
+            Event::Wasm(&ref op) | Event::WasmOwned(ref op) if self.imports_only => {
+                if let Operator::Call { function_index } = *op {
+                    if (function_index as usize) < module_info.imported_functions.len() {
+                        self.push_charge(sink, self.cross_module_call_cost);
+                        self.push_limit_check(sink);
+                    }
+                }
+            }
+            _ => {}
+        }
+        if self.imports_only {
+            if let Event::Wasm(_) | Event::WasmOwned(_) = op {
+                sink.push(op);
+                return Ok(());
+            }
+        }
+        match op {
+            Event::Internal(InternalEvent::FunctionBegin(id)) => {
+                self.begin_function(id, module_info)?;
+                if self.exempt {
+                    sink.push(op);
+                    return Ok(());
+                }
+                if self.last_call_cost
+                    || self.function_costs
+                    || self.graceful_exhaustion
+                    || self.soft_fail
+                {
+                    sink.push(op);
+                    self.push_function_entry(sink);
+                    return Ok(());
+                }
+            }
+            Event::Wasm(&ref op) | Event::WasmOwned(ref op) => {
+                let uncounted_elements =
+                    self.price_operator(op, module_info.imported_functions.len())?;
+                let leaves_function = self.enter_blocks(op)?;
+                // Charge the straight-line code before any operator that may leave it. This
+                // includes `unreachable`, which aborts the function before its `End` is reached.
+                let charges = self.charges(op, self.coarse(self.checkpoints) && !leaves_function);
+                self.merge_uncharged_at_if(op, charges);
+                if charges {
+                    self.push_checkpoint(sink, leaves_function)?;
+                }
+                match *op {
+                    Operator::If { .. } => self.uncharged_at_if.push(Some((
+                        self.current_block,
+                        self.category_block,
+                        self.surcharge_block,
+                    ))),
+                    Operator::Block { .. } | Operator::Loop { .. } => {
+                        self.uncharged_at_if.push(None)
+                    }
+                    _ => {}
+                }
+                match *op {
+                    Operator::Br { .. }
+                    | Operator::BrTable { .. }
+                    | Operator::BrIf { .. }
+                    | Operator::Call { .. }
+                    | Operator::CallIndirect { .. } => {
+                        self.push_limit_check(sink);
+                    }
+                    _ => {}
+                }
+                if self.function_costs && leaves_function {
+                    self.push_function_costs_exit(sink, op)?;
+                }
+                if let Operator::Call { .. } | Operator::CallIndirect { .. } = *op {
+                    returns_from_call = self.push_call_entry(sink);
+                }
+                if uncounted_elements {
+                    self.push_element_charge(sink);
+                }
+                if self.cold_page_surcharge != 0 {
+                    let cold_pages = ColdPages {
+                        surcharge: self.cold_page_surcharge,
+                        charge: self.runtime_charge(),
+                    };
+                    push_access_breakpoint(
+                        sink,
+                        op,
+                        "Metering::with_cold_page_surcharge",
+                        move |ctx, ranges| {
+                            cold_pages.charge(ctx, ranges);
+                            Ok(())
+                        },
+                    )?;
+                }
+            }
+            _ => {}
+        }
+        let soft_fail_check = self.soft_fail
+            && self.returns_scalar()
+            && match op {
+                Event::Wasm(&ref op) | Event::WasmOwned(ref op) => match *op {
+                    Operator::Call { .. } | Operator::CallIndirect { .. } => true,
+                    _ => false,
+                },
+                _ => false,
+            };
+        sink.push(op);
+        if returns_from_call {
+            push_nested_calls_add(sink, -1);
+        }
+        if soft_fail_check {
+            // Pass on the failure of the callee.
+            sink.push(Event::Internal(InternalEvent::GetInternal(
+                INTERNAL_FIELD_SOFT_FAILED.index() as _,
+            )));
+            sink.push(Event::WasmOwned(Operator::I64Eqz));
+            sink.push(Event::WasmOwned(Operator::I32Eqz));
+            sink.push(Event::WasmOwned(Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            }));
+            push_soft_return(sink, self.results.first(), self.function_costs);
+            sink.push(Event::WasmOwned(Operator::End));
+        }
+        Ok(())
+    }
+
+    fn feed_local(
+        &mut self,
+        ty: WpType,
+        n: usize,
+        _module_info: &ModuleInfo,
+    ) -> Result<(), Self::Error> {
+        self.num_locals += n as u64;
+        let size = match ty {
+            WpType::I32 => value_size(Type::I32),
+            WpType::I64 => value_size(Type::I64),
+            WpType::F32 => value_size(Type::F32),
+            WpType::F64 => value_size(Type::F64),
+            WpType::V128 => value_size(Type::V128),
+            // References are pointer sized.
+            _ => 8,
+        };
+        self.locals_bytes += n as u64 * size;
+        Ok(())
+    }
+}
+
+/// Returns the size in bytes of a value of type `ty`.
+fn value_size(ty: Type) -> u64 {
+    match ty {
+        Type::I32 | Type::F32 => 4,
+        Type::I64 | Type::F64 => 8,
+        Type::V128 => 16,
+    }
+}
+
+/// Pushes a breakpoint reporting the cost of the function, before an operator returning from it.
+///
+/// Without `leaves`, the operator always returns. Otherwise, the operand of the operator is
+/// stashed for the breakpoint to find out whether it returns.
+fn push_function_exit<'a, 'b: 'a>(sink: &mut EventSink<'a, 'b>, leaves: Option<Vec<bool>>) {
+    if leaves.is_some() {
+        sink.push(Event::WasmOwned(Operator::I64ExtendUI32));
+        sink.push(Event::Internal(InternalEvent::SetInternal(
+            INTERNAL_FIELD_OPERAND.index() as _,
+        )));
+    }
+    let condition = leaves.clone();
+    sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+        move |mut info| {
+            if let Some(ctx) = info.ctx() {
+                let returns = match condition {
+                    Some(ref leaves) => {
+                        let operand = ctx.get_internal(&INTERNAL_FIELD_OPERAND);
+                        leaves[(operand as usize).min(leaves.len() - 1)]
+                    }
+                    None => true,
+                };
+                if returns {
+                    exit_function(ctx);
+                }
+            }
+            Ok(())
+        },
+    ))));
+    if leaves.is_some() {
+        sink.push(Event::Internal(InternalEvent::GetInternal(
+            INTERNAL_FIELD_OPERAND.index() as _,
+        )));
+        sink.push(Event::WasmOwned(Operator::I32WrapI64));
+    }
+}
+
+/// Prepares the return of the default value of function `func_index`, and returns true if it
+/// exhausts the limit gracefully with a default value of the types in `results`.
+fn set_graceful_return(ctx: &mut Ctx, func_index: u32, results: &[Type]) -> bool {
+    let default = match get_graceful_default(ctx, func_index) {
+        Some(default) => default,
+        None => return false,
+    };
+    let types: Vec<Type> = default.iter().map(Value::ty).collect();
+    if types != results {
+        return false;
+    }
+    if let Some(value) = default.first() {
+        ctx.set_internal(&INTERNAL_FIELD_GRACEFUL_VALUE, value.to_u128() as u64);
+    }
+    ctx.set_internal(&INTERNAL_FIELD_GRACEFUL_RETURN, 1);
+    true
+}
+
+/// Pushes the return of the value prepared by `set_graceful_return`, if it prepared one.
+fn push_graceful_return<'a, 'b: 'a>(
+    sink: &mut EventSink<'a, 'b>,
+    result_type: Option<&Type>,
+    function_costs: bool,
+) {
+    sink.push(Event::Internal(InternalEvent::GetInternal(
+        INTERNAL_FIELD_GRACEFUL_RETURN.index() as _,
+    )));
+    sink.push(Event::WasmOwned(Operator::I64Eqz));
+    sink.push(Event::WasmOwned(Operator::I32Eqz));
+    sink.push(Event::WasmOwned(Operator::If {
+        ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+    }));
+    sink.push(Event::WasmOwned(Operator::I64Const { value: 0 }));
+    sink.push(Event::Internal(InternalEvent::SetInternal(
+        INTERNAL_FIELD_GRACEFUL_RETURN.index() as _,
+    )));
+    if function_costs {
+        push_function_exit(sink, None);
+    }
+    if let Some(&ty) = result_type {
+        sink.push(Event::Internal(InternalEvent::GetInternal(
+            INTERNAL_FIELD_GRACEFUL_VALUE.index() as _,
+        )));
+        match ty {
+            Type::I32 => sink.push(Event::WasmOwned(Operator::I32WrapI64)),
+            Type::F32 => {
+                sink.push(Event::WasmOwned(Operator::I32WrapI64));
+                sink.push(Event::WasmOwned(Operator::F32ReinterpretI32));
+            }
+            Type::F64 => sink.push(Event::WasmOwned(Operator::F64ReinterpretI64)),
+            Type::I64 | Type::V128 => {}
+        }
+    }
+    sink.push(Event::WasmOwned(Operator::Return));
+    sink.push(Event::WasmOwned(Operator::End));
+}
+
+/// Pushes the return of zero from the current function, for `Metering::soft_fail`.
+fn push_soft_return<'a, 'b: 'a>(
+    sink: &mut EventSink<'a, 'b>,
+    result_type: Option<&Type>,
+    function_costs: bool,
+) {
+    if function_costs {
+        push_function_exit(sink, None);
+    }
+    match result_type {
+        Some(Type::I32) => sink.push(Event::WasmOwned(Operator::I32Const { value: 0 })),
+        Some(Type::I64) => sink.push(Event::WasmOwned(Operator::I64Const { value: 0 })),
+        Some(Type::F32) => {
+            sink.push(Event::WasmOwned(Operator::I32Const { value: 0 }));
+            sink.push(Event::WasmOwned(Operator::F32ReinterpretI32));
+        }
+        Some(Type::F64) => {
+            sink.push(Event::WasmOwned(Operator::I64Const { value: 0 }));
+            sink.push(Event::WasmOwned(Operator::F64ReinterpretI64));
+        }
+        Some(Type::V128) | None => {}
+    }
+    sink.push(Event::WasmOwned(Operator::Return));
+}
+
+/// The options deciding where the points charged by a breakpoint are recorded.
+#[derive(Copy, Clone)]
+struct RuntimeCharge {
+    countdown: bool,
+    batched_flush: bool,
+    category_breakdown: bool,
+    surcharge_breakdown: bool,
+}
+
+impl RuntimeCharge {
+    /// Charges `points` to `ctx`, recording them in `category` and in `surcharge` if any.
+    fn apply(
+        self,
+        ctx: &mut Ctx,
+        points: u64,
+        category: OpcodeCategory,
+        surcharge: Option<Surcharge>,
+    ) {
+        if points == 0 {
+            return;
+        }
+        let add = |ctx: &mut Ctx, field: &InternalField| {
+            let value = ctx.get_internal(field).saturating_add(points);
+            ctx.set_internal(field, value);
+        };
+        if self.countdown {
+            let remaining = ctx
+                .get_internal(&INTERNAL_FIELD_USED)
+                .saturating_sub(points);
+            ctx.set_internal(&INTERNAL_FIELD_USED, remaining);
+        } else {
+            add(ctx, &INTERNAL_FIELD_USED);
+        }
+        if self.batched_flush {
+            add(ctx, &INTERNAL_FIELD_UNFLUSHED);
+        }
+        if self.category_breakdown {
+            add(ctx, &INTERNAL_FIELD_CATEGORIES[category as usize]);
+        }
+        if let (true, Some(surcharge)) = (self.surcharge_breakdown, surcharge) {
+            add(ctx, &INTERNAL_FIELD_SURCHARGES[surcharge as usize]);
+        }
+    }
+}
+
+/// The options of `Metering::with_cold_page_surcharge` read at runtime.
+#[derive(Copy, Clone)]
+struct ColdPages {
+    surcharge: u64,
+    charge: RuntimeCharge,
+}
+
+impl ColdPages {
+    /// Charges the surcharge for each page of `ranges` not accessed yet on `ctx`.
+    fn charge(self, ctx: &mut Ctx, ranges: &[(u64, u64)]) {
+        let memory_pages = ctx.memory(0).size().0 as u64;
+        let mut cold = 0;
+        with_hooks(ctx, |hooks| {
+            mark_pages::<()>(&mut hooks.warm_pages, ranges, memory_pages, || {
+                cold += 1;
+                Ok(())
+            })
+        })
+        .unwrap();
+        self.charge.apply(
+            ctx,
+            self.surcharge.saturating_mul(cold),
+            OpcodeCategory::Memory,
+            Some(Surcharge::ColdPages),
+        );
+    }
+}
+
+/// Returns true if the operators of the given kind may trap, for
+/// `Metering::with_charge_before_traps`.
+fn may_trap(kind: OpcodeKind) -> bool {
+    use OpcodeKind::*;
+    match kind {
+        I32DivS | I32DivU | I32RemS | I32RemU | I64DivS | I64DivU | I64RemS | I64RemU
+        | I32TruncSF32 | I32TruncUF32 | I32TruncSF64 | I32TruncUF64 | I64TruncSF32
+        | I64TruncUF32 | I64TruncSF64 | I64TruncUF64 | MemoryInit | MemoryCopy | MemoryFill
+        | TableInit | TableCopy | TableGet | TableSet => true,
+        _ => is_memory_access(kind),
+    }
+}
+
+/// Pushes the subtraction of `cost` from the remaining budget, stopping at zero.
+fn push_countdown<'a, 'b: 'a>(sink: &mut EventSink<'a, 'b>, cost: u64) {
+    sink.push(Event::Internal(InternalEvent::GetInternal(
+        INTERNAL_FIELD_USED.index() as _,
+    )));
+    sink.push(Event::WasmOwned(Operator::I64Const { value: cost as i64 }));
+    sink.push(Event::WasmOwned(Operator::I64Sub));
+    sink.push(Event::WasmOwned(Operator::I64Const { value: 0 }));
+    sink.push(Event::Internal(InternalEvent::GetInternal(
+        INTERNAL_FIELD_USED.index() as _,
+    )));
+    sink.push(Event::WasmOwned(Operator::I64Const { value: cost as i64 }));
+    sink.push(Event::WasmOwned(Operator::I64GeU));
+    sink.push(Event::WasmOwned(Operator::Select));
+    sink.push(Event::Internal(InternalEvent::SetInternal(
+        INTERNAL_FIELD_USED.index() as _,
+    )));
+}
+
+/// Pushes the addition of `cost` to the points used, applying `policy` if it would overflow.
+fn push_checked_charge<'a, 'b: 'a>(
+    sink: &mut EventSink<'a, 'b>,
+    cost: u64,
+    policy: &OverflowPolicy,
+) {
+    sink.push(Event::Internal(InternalEvent::GetInternal(
+        INTERNAL_FIELD_USED.index() as _,
+    )));
+    sink.push(Event::WasmOwned(Operator::I64Const {
+        value: (u64::max_value() - cost) as i64,
+    }));
+    sink.push(Event::WasmOwned(Operator::I64GtU));
+    sink.push(Event::WasmOwned(Operator::If {
+        ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+    }));
+    let policy = policy.clone();
+    sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+        move |mut info| {
+            if let Some(ctx) = info.ctx() {
+                match policy {
+                    OverflowPolicy::Trap => {
+                        return Err(Box::new(PointsOverflowError { attempted: cost }))
+                    }
+                    OverflowPolicy::Saturate => {}
+                    OverflowPolicy::Callback(ref callback) => callback(ctx, cost),
+                }
+                ctx.set_internal(&INTERNAL_FIELD_USED, u64::max_value());
+            }
+            Ok(())
+        },
+    ))));
+    sink.push(Event::WasmOwned(Operator::Else));
+    push_add_internal(sink, &INTERNAL_FIELD_USED, cost as i64);
+    sink.push(Event::WasmOwned(Operator::End));
+}
+
+fn push_nested_calls_add<'a, 'b: 'a>(sink: &mut EventSink<'a, 'b>, value: i64) {
+    push_add_internal(sink, &INTERNAL_FIELD_NESTED_CALLS, value);
+}
+
+/// Returns the number of points used by an Instance.
+pub fn get_points_used(instance: &Instance) -> u64 {
+    instance.get_internal(&INTERNAL_FIELD_USED)
+}
+
+/// Sets the number of points used by an Instance.
+///
+/// The cost reported by `last_call_cost` is kept. As no call can be running on the instance,
+/// this also forgets the calls left pending by a trap.
+pub fn set_points_used(instance: &mut Instance, value: u64) {
+    let last_call_cost = last_call_cost(instance.context());
+    instance.set_internal(
+        &INTERNAL_FIELD_CALL_START,
+        value.saturating_sub(last_call_cost),
+    );
+    instance.set_internal(&INTERNAL_FIELD_NESTED_CALLS, 0);
+    instance.set_internal(&INTERNAL_FIELD_LIMIT_NOTIFIED, 0);
+    instance.set_internal(&INTERNAL_FIELD_USED, value);
+}
+
+/// Returns the number of points used in a Ctx.
+pub fn get_points_used_ctx(ctx: &Ctx) -> u64 {
+    ctx.get_internal(&INTERNAL_FIELD_USED)
+}
+
+/// Returns the points used when a breakpoint fires, from the Ctx given to its callback, such as
+/// with `CallBreakpoints::with_context`.
+///
+/// `Metering` charges the code before each branch, call and `end`, so the points include the code
+/// before the breakpoint only if `Metering` comes before the middleware inserting it in the chain,
+/// and the breakpoint is inserted before such an operator. The difference between two breakpoints
+/// is then the cost of the code run between them.
+pub fn points_at_breakpoint(ctx: &Ctx) -> u64 {
+    get_points_used_ctx(ctx)
+}
+
+/// Sets the number of points used in a Ctx.
+pub fn set_points_used_ctx(ctx: &mut Ctx, value: u64) {
+    ctx.set_internal(&INTERNAL_FIELD_USED, value);
+}
+
+/// Makes the calls on `ctx` charge the points of thread `thread_id`, so that the threads running
+/// on the instance in turn are accounted apart. The threads start with zero points used, and the
+/// thread of an instance is thread 0 until this is called.
+///
+/// The points used are kept per thread by saving the points field of `ctx` for the current thread
+/// and loading the points of `thread_id` into it, so this must be called between calls, not from
+/// an imported function. Only the points used are per thread: the limit is shared, and applies to
+/// each thread separately, while the other counters, such as `last_call_cost` and the category
+/// breakdown, accumulate across threads.
+///
+/// In countdown mode, the points field holds the remaining budget, so each thread has a budget of
+/// its own rather than a share of one: a thread starts with a budget of zero, to be set with
+/// `set_remaining` once it is the current thread, and does not draw on the budgets of the other
+/// threads. A host enforcing a single budget across the threads splits it between them itself.
+///
+/// A Ctx runs one call at a time, so the Wasm threads of the threads proposal that share a memory
+/// get their own counters either by running on instances of their own, or by being given a thread
+/// id here before each of their calls.
+pub fn set_current_thread(ctx: &mut Ctx, thread_id: u64) {
+    let used = get_points_used_ctx(ctx);
+    let thread_used = with_hooks(ctx, |hooks| {
+        if hooks.current_thread == thread_id {
+            return used;
+        }
+        hooks.thread_points.insert(hooks.current_thread, used);
+        hooks.current_thread = thread_id;
+        hooks.thread_points.remove(&thread_id).unwrap_or(0)
+    });
+    set_points_used_ctx(ctx, thread_used);
+}
+
+/// Calls the exported function `name` of an instance compiled with `Metering::soft_fail`, and
+/// fails with `ExecutionLimitExceededError` if it exceeded the limit, as a trap would.
+///
+/// The error is returned as `RuntimeError::Error`, in the same shape as the error of a trapping
+/// limit check, so the hosts handle both modes alike.
+pub fn call_soft(instance: &mut Instance, name: &str, params: &[Value]) -> CallResult<Vec<Value>> {
+    instance
+        .context_mut()
+        .set_internal(&INTERNAL_FIELD_SOFT_FAILED, 0);
+    let results = instance.call(name, params)?;
+    if instance.context().get_internal(&INTERNAL_FIELD_SOFT_FAILED) == 0 {
+        return Ok(results);
+    }
+    instance
+        .context_mut()
+        .set_internal(&INTERNAL_FIELD_SOFT_FAILED, 0);
+    let exceeded_by = get_gas_overrun(instance.context());
+    Err(CallError::Runtime(RuntimeError::Error {
+        data: Box::new(ExecutionLimitExceededError { exceeded_by }),
+    }))
+}
+
+/// Returns the points by which the last call on `ctx` exceeding the limit exceeded it, as in
+/// its `ExecutionLimitExceededError`, or zero if no call exceeded it yet.
+pub fn get_gas_overrun(ctx: &Ctx) -> u64 {
+    ctx.get_internal(&INTERNAL_FIELD_GAS_OVERRUN)
+}
+
+/// Returns the thread whose points the calls on `ctx` charge, see `set_current_thread`.
+pub fn current_thread(ctx: &Ctx) -> u64 {
+    HOOKS.get(ctx, |hooks| hooks.current_thread).unwrap_or(0)
+}
+
+/// Returns the points used by thread `thread_id` on `ctx`, see `set_current_thread`.
+///
+/// In countdown mode, this is the remaining budget of the thread.
+pub fn get_points_used_thread(ctx: &Ctx, thread_id: u64) -> u64 {
+    if current_thread(ctx) == thread_id {
+        return get_points_used_ctx(ctx);
+    }
+    HOOKS
+        .get(ctx, |hooks| hooks.thread_points.get(&thread_id).cloned())
+        .and_then(|points| points)
+        .unwrap_or(0)
+}
+
+/// Returns the points used by the last top-level call on `ctx`, including the calls it made.
+///
+/// While a call is running, this is the cost of the call so far. The module must be compiled
+/// with `Metering::with_last_call_cost`.
+pub fn last_call_cost(ctx: &Ctx) -> u64 {
+    points_charged_since(ctx, ctx.get_internal(&INTERNAL_FIELD_CALL_START))
+}
+
+/// The high-water mark of `global_peak_points`.
+static GLOBAL_PEAK_POINTS: AtomicU64 = AtomicU64::new(0);
+
+/// Raises the high-water marks of `peak_points` and `global_peak_points` to the points used on
+/// `ctx`, if they are lower.
+fn raise_peak_points(ctx: &mut Ctx) {
+    let points = get_points_used_ctx(ctx);
+    if ctx.get_internal(&INTERNAL_FIELD_PEAK_POINTS) < points {
+        ctx.set_internal(&INTERNAL_FIELD_PEAK_POINTS, points);
+    }
+    let mut peak = GLOBAL_PEAK_POINTS.load(Ordering::SeqCst);
+    while peak < points {
+        match GLOBAL_PEAK_POINTS.compare_exchange(peak, points, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => return,
+            Err(current) => peak = current,
+        }
+    }
+}
+
+/// Returns the most points used by any instance of the process, compiled with
+/// `Metering::with_global_peak_points`, since the process started or the mark was last reset with
+/// `reset_global_peak_points`.
+///
+/// The points used are those of the instance when a metered function returns, so an instance
+/// whose points are not reset between calls raises the mark with its running total.
+pub fn global_peak_points() -> u64 {
+    GLOBAL_PEAK_POINTS.load(Ordering::SeqCst)
+}
+
+/// Zeroes the high-water mark of `global_peak_points`.
+pub fn reset_global_peak_points() {
+    GLOBAL_PEAK_POINTS.store(0, Ordering::SeqCst);
+}
+
+/// Returns the most points used by the instance of `ctx`, compiled with
+/// `Metering::with_global_peak_points`, since it was created or last reset with `reset_all`.
+///
+/// The mark is raised at the same points as `global_peak_points`.
+pub fn peak_points(ctx: &Ctx) -> u64 {
+    ctx.get_internal(&INTERNAL_FIELD_PEAK_POINTS)
+}
+
+/// Returns the fee of the points used on `ctx` at `price_per_point` each.
+///
+/// The fee is computed in 128 bits, so it does not overflow whatever the points and the price.
+pub fn compute_fee(ctx: &Ctx, price_per_point: u64) -> u128 {
+    u128::from(get_points_used_ctx(ctx)) * u128::from(price_per_point)
+}
+
+/// Forgets the memory pages accessed on `ctx`, so that the next access to each page is charged
+/// the surcharge of `Metering::with_cold_page_surcharge` again.
+pub fn reset_cold_pages(ctx: &mut Ctx) {
+    with_hooks(ctx, |hooks| hooks.warm_pages = Vec::new());
+}
+
+/// A category of operators, for `category_breakdown`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum OpcodeCategory {
+    /// The `i32` and `i64` operators that do not access memory.
+    Integer,
+    /// The `f32` and `f64` operators that do not access memory.
+    Float,
+    /// The loads, the stores and the operators on a whole memory, such as `memory.grow`.
+    Memory,
+    /// The structured control, branch, call and return operators.
+    Control,
+    /// The other operators, such as `local.get`, `drop` or the SIMD operators.
+    Other,
+}
+
+/// The number of `OpcodeCategory` variants.
+const CATEGORY_COUNT: usize = OpcodeCategory::Other as usize + 1;
+
+/// The points of a block charged to each `OpcodeCategory`.
+type CategoryPoints = [u64; CATEGORY_COUNT];
+
+impl OpcodeCategory {
+    /// Returns the category of the operators of the given kind.
+    pub fn of(kind: OpcodeKind) -> OpcodeCategory {
+        use OpcodeKind::*;
+        match kind {
+            _ if is_memory_access(kind) => OpcodeCategory::Memory,
+            MemoryGrow | MemorySize | MemoryCopy | MemoryFill | MemoryInit | DataDrop => {
+                OpcodeCategory::Memory
+            }
+            Unreachable | Nop | Block | Loop | If | Else | End | Br | BrIf | BrTable | Return
+            | Call | CallIndirect => OpcodeCategory::Control,
+            _ if kind.name().starts_with("f32.") || kind.name().starts_with("f64.") => {
+                OpcodeCategory::Float
+            }
+            _ if kind.name().starts_with("i32.") || kind.name().starts_with("i64.") => {
+                OpcodeCategory::Integer
+            }
+            _ => OpcodeCategory::Other,
+        }
+    }
+}
+
+/// The points charged to each `OpcodeCategory` on an instance.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CategoryBreakdown {
+    pub integer: u64,
+    pub float: u64,
+    pub memory: u64,
+    pub control: u64,
+    pub other: u64,
+}
+
+impl CategoryBreakdown {
+    /// Returns the points charged to all the categories.
+    pub fn total(&self) -> u64 {
+        self.integer + self.float + self.memory + self.control + self.other
+    }
+}
+
+/// Returns the points charged to each category on `ctx` since it was created or last reset with
+/// `reset_category_breakdown`.
+///
+/// The module must be compiled with `Metering::with_category_breakdown`.
+pub fn category_breakdown(ctx: &Ctx) -> CategoryBreakdown {
+    let category =
+        |category: OpcodeCategory| ctx.get_internal(&INTERNAL_FIELD_CATEGORIES[category as usize]);
+    CategoryBreakdown {
+        integer: category(OpcodeCategory::Integer),
+        float: category(OpcodeCategory::Float),
+        memory: category(OpcodeCategory::Memory),
+        control: category(OpcodeCategory::Control),
+        other: category(OpcodeCategory::Other),
+    }
+}
+
+/// Zeroes the points charged to each category on `ctx`.
+pub fn reset_category_breakdown(ctx: &mut Ctx) {
+    for field in &INTERNAL_FIELD_CATEGORIES {
+        ctx.set_internal(field, 0);
+    }
+}
+
+/// A surcharge of `Metering`, indexing `INTERNAL_FIELD_SURCHARGES`.
+#[derive(Copy, Clone)]
+enum Surcharge {
+    CallIndirect,
+    BrTableTargets,
+    MemoryGrow,
+    BulkMemory,
+    UnalignedAccess,
+    CrossModuleCall,
+    ColdPages,
+}
+
+/// The number of `Surcharge` variants.
+const SURCHARGE_COUNT: usize = Surcharge::ColdPages as usize + 1;
+
+/// The number of surcharges known at compile time: all but `Surcharge::ColdPages`, the last,
+/// which is charged at runtime.
+const STATIC_SURCHARGE_COUNT: usize = Surcharge::ColdPages as usize;
+
+/// The points of a block charged by each `Surcharge` known at compile time.
+type StaticSurchargePoints = [u64; STATIC_SURCHARGE_COUNT];
+
+/// The points left uncharged at an `if`, with their categories and surcharges.
+type UnchargedAtIf = (u64, CategoryPoints, StaticSurchargePoints);
+
+/// The points charged by each surcharge of `Metering` on an instance.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SurchargeBreakdown {
+    /// The points of `Metering::with_call_indirect_cost`.
+    pub call_indirect: u64,
+    /// The points of `Metering::with_br_table_target_cost`.
+    pub br_table_targets: u64,
+    /// The points of `Metering::with_memory_grow_cost`.
+    pub memory_grow: u64,
+    /// The points of `Metering::with_bulk_memory_cost`.
+    pub bulk_memory: u64,
+    /// The points of `Metering::with_unaligned_access_surcharge`.
+    pub unaligned_access: u64,
+    /// The points of `Metering::with_cross_module_call_cost`.
+    pub cross_module_call: u64,
+    /// The points of `Metering::with_cold_page_surcharge`.
+    pub cold_pages: u64,
+}
+
+impl SurchargeBreakdown {
+    /// Returns the points charged by all the surcharges.
+    pub fn total(&self) -> u64 {
+        self.call_indirect
+            + self.br_table_targets
+            + self.memory_grow
+            + self.bulk_memory
+            + self.unaligned_access
+            + self.cross_module_call
+            + self.cold_pages
+    }
+}
+
+/// Returns the points charged by each surcharge on `ctx` since it was created or last reset with
+/// `reset_surcharge_breakdown`. The other points used are the instruction costs.
+///
+/// The module must be compiled with `Metering::with_surcharge_breakdown`.
+pub fn surcharge_breakdown(ctx: &Ctx) -> SurchargeBreakdown {
+    let surcharge =
+        |surcharge: Surcharge| ctx.get_internal(&INTERNAL_FIELD_SURCHARGES[surcharge as usize]);
+    SurchargeBreakdown {
+        call_indirect: surcharge(Surcharge::CallIndirect),
+        br_table_targets: surcharge(Surcharge::BrTableTargets),
+        memory_grow: surcharge(Surcharge::MemoryGrow),
+        bulk_memory: surcharge(Surcharge::BulkMemory),
+        unaligned_access: surcharge(Surcharge::UnalignedAccess),
+        cross_module_call: surcharge(Surcharge::CrossModuleCall),
+        cold_pages: surcharge(Surcharge::ColdPages),
+    }
+}
+
+/// Zeroes the points charged by each surcharge on `ctx`.
+pub fn reset_surcharge_breakdown(ctx: &mut Ctx) {
+    for field in &INTERNAL_FIELD_SURCHARGES {
+        ctx.set_internal(field, 0);
+    }
+}
+
+/// Resets every metering counter of `ctx` at once, as if no call had run on it yet.
+///
+/// Reset to zero:
+/// - the points used, including those of the other threads of `set_current_thread`,
+/// - `last_call_cost` and the points not flushed yet with `Metering::with_batched_flush`,
+/// - the call depth and the running functions of `Metering::with_function_costs`,
+/// - `category_breakdown`, `surcharge_breakdown`, `instantiation_cost` and `peak_points`,
+/// - `get_gas_overrun`, the pending failure of `Metering::soft_fail` and whether the callback
+///   of `set_on_limit_exceeded` fired for the call,
+/// - the warm pages of `Metering::with_cold_page_surcharge`, as `reset_cold_pages` does.
+///
+/// Kept: the limit, the cost tables, which are compiled into the module or embedded in it, the
+/// callbacks, the abort flag, the graceful exhaustion settings and the current thread. The mark
+/// of `global_peak_points` is shared by all the instances of the process, so it is kept too,
+/// and reset with `reset_global_peak_points`.
+///
+/// With `Metering::countdown`, the points field holds the remaining budget, which is then zero,
+/// so it is meant to be set again with `set_points_used_ctx`. As with `set_points_used`, no call
+/// can be running on the instance.
+pub fn reset_all(ctx: &mut Ctx) {
+    for field in &[
+        &INTERNAL_FIELD_USED,
+        &INTERNAL_FIELD_UNFLUSHED,
+        &INTERNAL_FIELD_NESTED_CALLS,
+        &INTERNAL_FIELD_CALL_START,
+        &INTERNAL_FIELD_SOFT_FAILED,
+        &INTERNAL_FIELD_LIMIT_NOTIFIED,
+        &INTERNAL_FIELD_GAS_OVERRUN,
+        &INTERNAL_FIELD_PEAK_POINTS,
+        &INTERNAL_FIELD_INSTANTIATION_COST,
+        &INTERNAL_FIELD_OPERAND,
+    ] {
+        ctx.set_internal(field, 0);
+    }
+    reset_category_breakdown(ctx);
+    reset_surcharge_breakdown(ctx);
+    HOOKS.get(ctx, |hooks| {
+        hooks.frames.clear();
+        hooks.warm_pages = Vec::new();
+        hooks.thread_points.clear();
+    });
+}
+
+/// Returns the points charged on `ctx` since its points field held `points`.
+fn points_charged_since(ctx: &Ctx, points: u64) -> u64 {
+    let used = get_points_used_ctx(ctx);
+    // The points field counts down with `Metering::countdown`.
+    if used >= points {
+        used - points
+    } else {
+        points - used
+    }
+}
+
+/// Returns the remaining budget in a Ctx compiled with `Metering::countdown`.
+pub fn get_remaining(ctx: &Ctx) -> u64 {
+    ctx.get_internal(&INTERNAL_FIELD_USED)
+}
+
+/// Sets the remaining budget in a Ctx compiled with `Metering::countdown`.
+///
+/// As with `set_points_used`, the cost reported by `last_call_cost` is kept.
+pub fn set_remaining(ctx: &mut Ctx, value: u64) {
+    let last_call_cost = last_call_cost(ctx);
+    ctx.set_internal(
+        &INTERNAL_FIELD_CALL_START,
+        value.saturating_add(last_call_cost),
+    );
+    ctx.set_internal(&INTERNAL_FIELD_NESTED_CALLS, 0);
+    ctx.set_internal(&INTERNAL_FIELD_USED, value);
+}
+
+/// Moves `fraction` of the remaining budget of `parent` to the remaining budget of `child`, for
+/// instances compiled with `Metering::countdown`.
+///
+/// This is meant for forking an execution into a new instance. `fraction` is clamped to the range
+/// from 0.0 to 1.0, and the points moved are rounded down.
+pub fn split_budget(parent: &mut Instance, child: &mut Instance, fraction: f64) {
+    let fraction = fraction.max(0.0).min(1.0);
+    let parent_remaining = get_remaining(parent.context());
+    let moved = ((parent_remaining as f64 * fraction) as u64).min(parent_remaining);
+    set_remaining(parent.context_mut(), parent_remaining - moved);
+    let child_remaining = get_remaining(child.context());
+    set_remaining(child.context_mut(), child_remaining.saturating_add(moved));
+}
+
+/// Returns the points limit of an Instance compiled with `Metering::with_runtime_limit`.
+pub fn get_execution_limit(instance: &Instance) -> u64 {
+    instance.get_internal(&INTERNAL_FIELD_LIMIT)
+}
+
+/// Sets the points limit of an Instance compiled with `Metering::with_runtime_limit`.
+pub fn set_execution_limit(instance: &mut Instance, value: u64) {
+    instance.set_internal(&INTERNAL_FIELD_LIMIT, value);
+}
+
+/// Returns the points limit in a Ctx compiled with `Metering::with_runtime_limit`.
+pub fn get_execution_limit_ctx(ctx: &Ctx) -> u64 {
+    ctx.get_internal(&INTERNAL_FIELD_LIMIT)
+}
+
+/// Sets the points limit in a Ctx compiled with `Metering::with_runtime_limit`.
+pub fn set_execution_limit_ctx(ctx: &mut Ctx, value: u64) {
+    ctx.set_internal(&INTERNAL_FIELD_LIMIT, value);
+}
+
+/// Sets the points limit in a Ctx compiled with `Metering::with_runtime_limit`, and returns the
+/// limit it replaces.
+///
+/// This is meant for running code under a tighter limit, then restoring the outer limit.
+pub fn swap_limit(ctx: &mut Ctx, new_limit: u64) -> u64 {
+    let old_limit = get_execution_limit_ctx(ctx);
+    set_execution_limit_ctx(ctx, new_limit);
+    old_limit
+}
+
+/// Runs `f` under a budget of `limit` points of its own on a Ctx compiled with
+/// `Metering::with_runtime_limit`, then restores the limit and the points used of `ctx`, and
+/// returns the result of `f` with the points it used.
+///
+/// This is meant for host functions running metered code on their instance, such as a function
+/// of its table, with a budget of its own. Should the code exceed it, the trap ends the code run
+/// by `f`, while the calling code goes on under its own budget. The points used by `f` are not
+/// charged to the outer budget, they may be added to it with `set_points_used_ctx`.
+pub fn with_scoped_limit<R, F>(ctx: &mut Ctx, limit: u64, f: F) -> (R, u64)
+where
+    F: FnOnce(&mut Ctx) -> R,
+{
+    let outer_limit = swap_limit(ctx, limit);
+    let outer_used = get_points_used_ctx(ctx);
+    set_points_used_ctx(ctx, 0);
+    let result = f(ctx);
+    let scoped_used = get_points_used_ctx(ctx);
+    set_points_used_ctx(ctx, outer_used);
+    set_execution_limit_ctx(ctx, outer_limit);
+    (result, scoped_used)
+}
+
+/// Sets the points limit in a Ctx compiled with `Metering::with_runtime_limit` to a time budget
+/// of `millis` milliseconds, at a rate of `points_per_ms` points per millisecond, saturating at
+/// `u64::MAX`.
+///
+/// The limit is only as accurate as the rate: points price operators, not time, so a call may
+/// take more or less time than its budget depending on the operators it runs, the host functions
+/// it calls and the machine. The rate is meant to be calibrated on the machine running the calls.
+pub fn set_time_limit(ctx: &mut Ctx, millis: u64, points_per_ms: u64) {
+    set_execution_limit_ctx(ctx, millis.saturating_mul(points_per_ms));
+}
+
+/// Adds `cost` points for each element of the active element segments of the module of
+/// `instance` to the points it used, and returns the points added.
+///
+/// The segments are copied into the tables when the instance is created, so this is meant to be
+/// called right after instantiation, with the cost given to `Metering::with_element_cost`.
+/// With `Metering::countdown` and a module marked with `embed_countdown`, the points are
+/// subtracted from the remaining budget instead, down to zero, so the budget is meant to be set
+/// before.
+pub fn charge_element_segments(instance: &mut Instance, cost: u64) -> u64 {
+    let elements: u64 = instance
+        .module
+        .info
+        .elem_initializers
+        .iter()
+        .map(|initializer| initializer.elements.len() as u64)
+        .sum();
+    charge_instantiation(instance, elements.saturating_mul(cost))
+}
+
+/// Adds `cost_per_byte` points for each byte of the data segments of the module of `instance` to
+/// the points it used, and returns the points added.
+///
+/// The segments are copied into the memories when the instance is created, so this is meant to
+/// be called right after instantiation, as `charge_element_segments` is.
+pub fn charge_data_segments(instance: &mut Instance, cost_per_byte: u64) -> u64 {
+    let bytes: u64 = instance
+        .module
+        .info
+        .data_initializers
+        .iter()
+        .map(|initializer| initializer.data.len() as u64)
+        .sum();
+    charge_instantiation(instance, bytes.saturating_mul(cost_per_byte))
+}
+
+/// Adds `cost_per_import` points for each import of the module of `instance` to the points it
+/// used, and returns the points added.
+///
+/// The imports are checked against the declarations of the module when the instance is created,
+/// so this is meant to be called right after instantiation, as `charge_element_segments` is. The
+/// imported functions, memories, tables and globals are all counted.
+pub fn charge_import_validation(instance: &mut Instance, cost_per_import: u64) -> u64 {
+    let info = &instance.module.info;
+    let imports = info.imported_functions.len()
+        + info.imported_memories.len()
+        + info.imported_tables.len()
+        + info.imported_globals.len();
+    charge_instantiation(instance, (imports as u64).saturating_mul(cost_per_import))
+}
+
+/// Adds `points` to the points used by `instance`, or subtracts them from its remaining budget
+/// if its module is marked with `embed_countdown`, adds them to its `instantiation_cost`, and
+/// returns them.
+fn charge_instantiation(instance: &mut Instance, points: u64) -> u64 {
+    let used = get_points_used(instance);
+    let used = if is_countdown(instance.module().info()) {
+        used.saturating_sub(points)
+    } else {
+        used.saturating_add(points)
+    };
+    instance.set_internal(&INTERNAL_FIELD_USED, used);
+    let cost = instantiation_cost(instance);
+    instance.set_internal(
+        &INTERNAL_FIELD_INSTANTIATION_COST,
+        cost.saturating_add(points),
+    );
+    points
+}
+
+/// Returns the points charged to an Instance by `charge_element_segments`, `charge_data_segments`
+/// and `charge_import_validation`.
+///
+/// These points are part of the points used, but are kept apart to tell the cost of creating the
+/// instance from the cost of its calls. They are not changed by `set_points_used`.
+pub fn instantiation_cost(instance: &Instance) -> u64 {
+    instance.get_internal(&INTERNAL_FIELD_INSTANTIATION_COST)
+}
+
+/// Registers a callback that is invoked when a call on `ctx` exceeds the points limit.
+///
+/// The callback runs synchronously right before the execution limit trap unwinds, and receives
+/// the index of the function that exceeded the limit (in the module's function index space) and
+/// the number of points by which the limit was exceeded. The callback fires at most once per
+/// call: the trap ends the call, and with `Metering::with_graceful_exhaustion`, the limit checks
+/// of the rest of a call carrying on past the limit do not fire it again.
+///
+/// The callback stays registered until `clear_on_limit_exceeded` is called.
+pub fn set_on_limit_exceeded<F>(ctx: &mut Ctx, callback: F)
+where
+    F: Fn(u32, u64) + Send + Sync + 'static,
+{
+    with_hooks(ctx, |hooks| {
+        hooks.on_limit_exceeded = Some(Arc::new(callback))
+    });
+}
+
+/// Removes the callback registered with `set_on_limit_exceeded`.
+pub fn clear_on_limit_exceeded(ctx: &mut Ctx) {
+    with_hooks(ctx, |hooks| hooks.on_limit_exceeded = None);
+}
+
+/// Registers a flag that aborts the calls of `ctx` with an `ExecutionAbortedError` while it is
+/// set, on a Ctx compiled with `Metering::with_abort_checks`.
+///
+/// The flag may be set from any thread, such as on a timeout or a shutdown, and is checked at
+/// the limit checks: a call stuck in a host function is only aborted once it returns to metered
+/// code. The flag is not cleared by the abort, so the next calls abort too until it is cleared.
+///
+/// The flag stays registered until `clear_abort_flag` is called.
+pub fn set_abort_flag(ctx: &mut Ctx, flag: Arc<AtomicBool>) {
+    with_hooks(ctx, |hooks| hooks.abort_flag = Some(flag));
+}
+
+/// Removes the flag registered with `set_abort_flag`.
+pub fn clear_abort_flag(ctx: &mut Ctx) {
+    with_hooks(ctx, |hooks| hooks.abort_flag = None);
+}
+
+fn is_aborted(ctx: &Ctx) -> bool {
+    get_hook(ctx, |hooks| &hooks.abort_flag).map_or(false, |flag| flag.load(Ordering::SeqCst))
+}
+
+/// Makes function `func_index` return `default_values` when it exceeds the points limit of `ctx`,
+/// instead of trapping, on a Ctx compiled with `Metering::with_graceful_exhaustion`.
+///
+/// Only the limit checks in the function itself return gracefully: a function it calls that
+/// exceeds the limit still traps with the whole call. As the points used stay over the limit, the
+/// caller traps at its next check unless it is graceful too. The function is abandoned halfway,
+/// so the memory and globals it was updating may be left inconsistent, and its caller sees a
+/// value it did not compute. The function traps anyway if the types of `default_values` do not
+/// match its results.
+///
+/// The default stays registered until `clear_graceful_exhaustion` is called.
+pub fn set_graceful_exhaustion(ctx: &mut Ctx, func_index: u32, default_values: &[Value]) {
+    with_hooks(ctx, |hooks| {
+        hooks
+            .graceful_defaults
+            .insert(func_index, default_values.to_vec())
+    });
+}
+
+/// Makes function `func_index` trap again when it exceeds the points limit of `ctx`.
+pub fn clear_graceful_exhaustion(ctx: &mut Ctx, func_index: u32) {
+    with_hooks(ctx, |hooks| hooks.graceful_defaults.remove(&func_index));
+}
+
+fn get_graceful_default(ctx: &Ctx, func_index: u32) -> Option<Vec<Value>> {
+    HOOKS
+        .get(ctx, |hooks| {
+            hooks.graceful_defaults.get(&func_index).cloned()
+        })
+        .and_then(|default| default)
+}
+
+fn notify_limit_exceeded(ctx: &Ctx, func_index: u32, exceeded_by: u64) {
+    if let Some(callback) = get_hook(ctx, |hooks| &hooks.on_limit_exceeded) {
+        callback(func_index, exceeded_by);
+    }
+}
+
+/// Registers a callback that is invoked when a function returns, on a Ctx compiled with
+/// `Metering::with_function_costs`.
+///
+/// The callback receives the index of the function (in the module's function index space) and
+/// its exclusive cost: the points charged by its own operators, including the calls it makes but
+/// not the functions it calls. The inclusive cost of a call is the sum of the exclusive costs
+/// reported for it and for the calls it made. Functions ended by a trap are not reported.
+///
+/// The callback stays registered until `clear_function_cost_callback` is called.
+pub fn set_function_cost_callback<F>(ctx: &mut Ctx, callback: F)
+where
+    F: Fn(u32, u64) + Send + Sync + 'static,
+{
+    with_hooks(ctx, |hooks| {
+        hooks.on_function_cost = Some(Arc::new(callback));
+        hooks.frames.clear();
+    });
+}
+
+/// Removes the callback registered with `set_function_cost_callback`.
+pub fn clear_function_cost_callback(ctx: &mut Ctx) {
+    with_hooks(ctx, |hooks| {
+        hooks.on_function_cost = None;
+        hooks.frames.clear();
+    });
+}
+
+fn enter_function(ctx: &Ctx, func_index: u32) {
+    let points_at_entry = get_points_used_ctx(ctx);
+    // The frames left by a trap are dropped when the next call comes from the host.
+    let from_host = ctx.get_internal(&INTERNAL_FIELD_NESTED_CALLS) == 0;
+    HOOKS.get(ctx, |hooks| {
+        if hooks.on_function_cost.is_some() {
+            if from_host {
+                hooks.frames.clear();
+            }
+            hooks.frames.push(Frame {
+                func_index,
+                points_at_entry,
+                callee_points: 0,
+            });
+        }
+    });
+}
+
+fn exit_function(ctx: &Ctx) {
+    let report = HOOKS.get(ctx, |hooks| {
+        let frame = hooks.frames.pop()?;
+        let inclusive = points_charged_since(ctx, frame.points_at_entry);
+        if let Some(caller) = hooks.frames.last_mut() {
+            caller.callee_points += inclusive;
+        }
+        hooks.on_function_cost.clone().map(|callback| {
+            (
+                callback,
+                frame.func_index,
+                inclusive.saturating_sub(frame.callee_points),
+            )
+        })
+    });
+    if let Some((callback, func_index, exclusive)) = report.and_then(|report| report) {
+        callback(func_index, exclusive);
+    }
+}
+
+/// An instance whose points limit counts towards the `total_configured_limit` of its module.
+///
+/// The instance is registered for as long as it is alive: dropping it removes its limit from the
+/// total. Only the limits set through `TrackedInstance::set_execution_limit` are tracked.
+pub struct TrackedInstance {
+    instance: Instance,
+    module_key: usize,
+    id: u64,
+}
+
+impl TrackedInstance {
+    /// Starts tracking the current points limit of `instance`.
+    pub fn new(instance: Instance) -> TrackedInstance {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let tracked = TrackedInstance {
+            module_key: module_key(&instance.module()),
+            id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+            instance,
+        };
+        tracked.record_limit();
+        tracked
+    }
+
+    /// Sets the points limit of the instance, as `set_execution_limit` does.
+    pub fn set_execution_limit(&mut self, value: u64) {
+        set_execution_limit(&mut self.instance, value);
+        self.record_limit();
+    }
+
+    fn record_limit(&self) {
+        CONFIGURED_LIMITS
+            .lock()
+            .unwrap()
+            .entry(self.module_key)
+            .or_default()
+            .insert(self.id, get_execution_limit(&self.instance));
+    }
+}
+
+impl Deref for TrackedInstance {
+    type Target = Instance;
+
+    fn deref(&self) -> &Instance {
+        &self.instance
+    }
+}
+
+impl DerefMut for TrackedInstance {
+    fn deref_mut(&mut self) -> &mut Instance {
+        &mut self.instance
+    }
+}
+
+impl Drop for TrackedInstance {
+    fn drop(&mut self) {
+        let mut limits = CONFIGURED_LIMITS.lock().unwrap();
+        if let Some(module_limits) = limits.get_mut(&self.module_key) {
+            module_limits.remove(&self.id);
+            if module_limits.is_empty() {
+                limits.remove(&self.module_key);
+            }
+        }
+    }
+}
+
+/// Returns the sum of the points limits of the live `TrackedInstance`s of `module`.
+pub fn total_configured_limit(module: &Module) -> u64 {
+    CONFIGURED_LIMITS
+        .lock()
+        .unwrap()
+        .get(&module_key(module))
+        .map_or(0, |limits| {
+            limits
+                .values()
+                .fold(0u64, |total, &limit| total.saturating_add(limit))
+        })
+}
+
+/// Identifies a module, for as long as one of its instances or one of its clones is alive.
+fn module_key(module: &Module) -> usize {
+    module.info() as *const ModuleInfo as usize
+}
+
+/// Returns `wasm` without its custom sections named with `RESERVED_SECTION_PREFIX`, such as
+/// `DEFAULT_LIMIT_SECTION` and `cost_table::COST_TABLE_SECTION`.
+///
+/// The sections embedded for the metering are trusted by the host, which reads the limit the
+/// instances start with and the cost table from them, so an untrusted module is meant to be
+/// stripped of them before they are embedded and it is compiled: it could forge them otherwise.
+/// A module that cannot be parsed is returned as is, as it fails to compile anyway.
+pub fn strip_reserved_sections(wasm: &[u8]) -> Vec<u8> {
+    let mut reader = match ModuleReader::new(wasm) {
+        Ok(reader) => reader,
+        Err(_) => return wasm.to_vec(),
+    };
+    let mut stripped = Vec::with_capacity(wasm.len());
+    let mut kept_from = 0;
+    while !reader.eof() {
+        let start = reader.current_position();
+        let section = match reader.read() {
+            Ok(section) => section,
+            Err(_) => return wasm.to_vec(),
+        };
+        if let SectionCode::Custom { name, .. } = section.code {
+            if name.starts_with(RESERVED_SECTION_PREFIX) {
+                stripped.extend_from_slice(&wasm[kept_from..start]);
+                kept_from = reader.current_position();
+            }
+        }
+    }
+    stripped.extend_from_slice(&wasm[kept_from..]);
+    stripped
+}
+
+/// Returns `wasm` with `METERING_ABI_VERSION` appended as a custom section, so that the compiled
+/// module records the version of the instrumentation it should be compiled with.
+///
+/// The section takes precedence over any section of the same name already in `wasm`.
+pub fn embed_abi_version(wasm: &[u8]) -> Vec<u8> {
+    append_custom_section(
+        wasm,
+        ABI_VERSION_SECTION,
+        &METERING_ABI_VERSION.to_le_bytes(),
+    )
+}
+
+/// Returns `wasm` with the custom section marking a module compiled with `Metering::countdown`
+/// appended, see `COUNTDOWN_SECTION`, so that the instantiation charges of its instances
+/// subtract from their remaining budget.
+pub fn embed_countdown(wasm: &[u8]) -> Vec<u8> {
+    append_custom_section(wasm, COUNTDOWN_SECTION, &[])
+}
+
+/// Returns true if a module is marked by `embed_countdown`.
+fn is_countdown(module_info: &ModuleInfo) -> bool {
+    module_info.custom_sections.contains_key(COUNTDOWN_SECTION)
+}
+
+/// Returns the metering ABI version embedded in a module by `embed_abi_version`, if any.
+pub fn embedded_abi_version(module_info: &ModuleInfo) -> Option<u32> {
+    module_info
+        .custom_sections
+        .get(ABI_VERSION_SECTION)
+        .and_then(|bytes| bytes[..].try_into().ok())
+        .map(u32::from_le_bytes)
+}
+
+/// Returns `wasm` with `counts` appended as a custom section, so that the compiled module records
+/// the checkpoint counts of its functions, see `Metering::checkpoint_counts`.
+///
+/// The section takes precedence over any section of the same name already in `wasm`.
+pub fn embed_checkpoint_counts(wasm: &[u8], counts: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(counts.len() * 4);
+    for count in counts {
+        bytes.extend_from_slice(&count.to_le_bytes());
+    }
+    append_custom_section(wasm, CHECKPOINTS_SECTION, &bytes)
+}
+
+/// Returns the checkpoint counts embedded in a module by `embed_checkpoint_counts`, if any, one
+/// per function defined by the module.
+pub fn embedded_checkpoint_counts(module_info: &ModuleInfo) -> Option<Vec<u32>> {
+    let bytes = module_info.custom_sections.get(CHECKPOINTS_SECTION)?;
+    if bytes.len() % 4 != 0 {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect(),
+    )
+}
+
+/// Returns `wasm` with `limit` appended as a custom section, so that the compiled module records
+/// the points limit its instances start with, see `apply_default_limit`.
+///
+/// The section takes precedence over any section of the same name already in `wasm`.
+pub fn embed_default_limit(wasm: &[u8], limit: u64) -> Vec<u8> {
+    append_custom_section(wasm, DEFAULT_LIMIT_SECTION, &limit.to_le_bytes())
+}
+
+/// Returns the points limit embedded in a module by `embed_default_limit`, if any.
+pub fn embedded_default_limit(module_info: &ModuleInfo) -> Option<u64> {
+    module_info
+        .custom_sections
+        .get(DEFAULT_LIMIT_SECTION)
+        .and_then(|bytes| bytes[..].try_into().ok())
+        .map(u64::from_le_bytes)
+}
+
+/// Sets the points limit of a new Instance compiled with `Metering::with_runtime_limit` to the
+/// limit embedded in its module by `embed_default_limit`, if any.
+pub fn apply_default_limit(instance: &mut Instance) {
+    if let Some(limit) = embedded_default_limit(instance.module().info()) {
+        set_execution_limit(instance, limit);
+    }
+}
+
+/// Returns `wasm` with `table_fill_cost_per_element` appended as a custom section, so that the
+/// compiled module records the points its instances are charged for each element of its active
+/// element segments, see `apply_table_fill_cost`.
+///
+/// The section takes precedence over any section of the same name already in `wasm`.
+pub fn embed_table_fill_cost(wasm: &[u8], table_fill_cost_per_element: u64) -> Vec<u8> {
+    append_custom_section(
+        wasm,
+        TABLE_FILL_COST_SECTION,
+        &table_fill_cost_per_element.to_le_bytes(),
+    )
+}
+
+/// Returns the cost per element embedded in a module by `embed_table_fill_cost`, if any.
+pub fn embedded_table_fill_cost(module_info: &ModuleInfo) -> Option<u64> {
+    module_info
+        .custom_sections
+        .get(TABLE_FILL_COST_SECTION)
+        .and_then(|bytes| bytes[..].try_into().ok())
+        .map(u64::from_le_bytes)
+}
+
+/// Charges a new Instance for the elements its active element segments copied into its tables,
+/// with `charge_element_segments` and the cost per element embedded in its module by
+/// `embed_table_fill_cost`, and returns the points charged.
+///
+/// This is meant to be called right after instantiation, so that the charge is recorded before
+/// the first call. Nothing is charged if the module has no embedded cost.
+pub fn apply_table_fill_cost(instance: &mut Instance) -> u64 {
+    match embedded_table_fill_cost(instance.module().info()) {
+        Some(cost) => charge_element_segments(instance, cost),
+        None => 0,
+    }
+}
+
+/// Returns the one-time cost of deploying the module `bytes`, `cost_per_code_byte` points for
+/// each byte of its code section.
+///
+/// The code section holds the bodies of the functions, so the cost grows with the code to
+/// compile rather than with the data and the custom sections. It is not charged to any instance,
+/// it is meant to be charged by the host when the module is deployed. The module is parsed, not
+/// validated: a malformed module, which fails to compile anyway, costs the code read before the
+/// error.
+pub fn deployment_cost(bytes: &[u8], cost_per_code_byte: u64) -> u64 {
+    let mut code_bytes = 0u64;
+    if let Ok(mut reader) = ModuleReader::new(bytes) {
+        while !reader.eof() {
+            let section = match reader.read() {
+                Ok(section) => section,
+                Err(_) => break,
+            };
+            if let SectionCode::Code = section.code {
+                let range = section.range();
+                code_bytes += (range.end - range.start) as u64;
+            }
+        }
+    }
+    code_bytes.saturating_mul(cost_per_code_byte)
+}
+
+/// Returns the offset in `bytes` of each operator of the functions of the module `bytes`, with
+/// the points charged for it by `metering`.
+///
+/// This is meant for debugging the metering of a module: the points used by a call are the sum
+/// of the points of the operators it executes. The points include the surcharges of the special
+/// cases of `metering`, such as `Metering::with_call_indirect_cost` and
+/// `Metering::with_br_table_target_cost`, but not the points of setting up the frame of a
+/// function or copying its results, which are not charged for any operator. The module is
+/// parsed, not validated.
+pub fn explain(bytes: &[u8], mut metering: Metering) -> Result<Vec<(usize, u64)>, String> {
+    let mut costs = Vec::new();
+    let mut num_imported_functions = 0;
+    let mut reader = ModuleReader::new(bytes).map_err(|e| format!("{:?}", e))?;
+    while !reader.eof() {
+        let section = reader.read().map_err(|e| format!("{:?}", e))?;
+        match section.code {
+            SectionCode::Import => {
+                let imports = section
+                    .get_import_section_reader()
+                    .map_err(|e| format!("{:?}", e))?;
+                for import in imports {
+                    if let ImportSectionEntryType::Function(_) =
+                        import.map_err(|e| format!("{:?}", e))?.ty
+                    {
+                        num_imported_functions += 1;
+                    }
+                }
+            }
+            SectionCode::Code => {
+                let code = section
+                    .get_code_section_reader()
+                    .map_err(|e| format!("{:?}", e))?;
+                for (id, body) in code.into_iter().enumerate() {
+                    let operators = body
+                        .and_then(|body| body.get_operators_reader())
+                        .map_err(|e| format!("{:?}", e))?;
+                    let func_index = num_imported_functions + id as u32;
+                    let exempt = metering.exempt_functions.contains(&func_index);
+                    // The results are not known without the signatures, their copies are left out.
+                    metering.results.clear();
+                    metering.block_depth = 1;
+                    metering.loop_blocks = vec![false];
+                    metering.last_const = None;
+                    for operator in operators.into_iter_with_offsets() {
+                        let (op, offset) = operator.map_err(|e| format!("{:?}", e))?;
+                        metering.current_block = 0;
+                        metering.category_block = [0; CATEGORY_COUNT];
+                        metering.surcharge_block = [0; STATIC_SURCHARGE_COUNT];
+                        metering.price_operator(&op, num_imported_functions as usize)?;
+                        metering.enter_blocks(&op)?;
+                        let points = match op {
+                            _ if exempt => 0,
+                            Operator::Call { function_index }
+                                if metering.imports_only
+                                    && function_index < num_imported_functions =>
+                            {
+                                metering.cross_module_call_cost
+                            }
+                            _ if metering.imports_only => 0,
+                            _ => metering.current_block,
+                        };
+                        costs.push((offset, points));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(costs)
+}
+
+/// Returns the points charged by a `Metering` using `table` for the operators of function
+/// `func_index` of the module `bytes` that start from `start_offset` up to, but excluding,
+/// `end_offset`.
+///
+/// The function index counts the imported functions, and the offsets are those of `explain`, in
+/// `bytes`. A range cutting through an operator is rounded to the edges of the operators: an
+/// operator is in the range if it starts in it, even if it ends past it. Unlike `explain`, the
+/// surcharges of the special cases of `Metering` are not included. The module is parsed, not
+/// validated.
+pub fn range_cost(
+    bytes: &[u8],
+    func_index: u32,
+    start_offset: usize,
+    end_offset: usize,
+    table: &CostTable,
+) -> Result<u64, String> {
+    if start_offset > end_offset {
+        return Err(format!(
+            "range starts at {:#x} after it ends at {:#x}",
+            start_offset, end_offset
+        ));
+    }
+    let mut num_imported_functions = 0;
+    let mut reader = ModuleReader::new(bytes).map_err(|e| format!("{:?}", e))?;
+    while !reader.eof() {
+        let section = reader.read().map_err(|e| format!("{:?}", e))?;
+        match section.code {
+            SectionCode::Import => {
+                let imports = section
+                    .get_import_section_reader()
+                    .map_err(|e| format!("{:?}", e))?;
+                for import in imports {
+                    if let ImportSectionEntryType::Function(_) =
+                        import.map_err(|e| format!("{:?}", e))?.ty
+                    {
+                        num_imported_functions += 1;
+                    }
+                }
+            }
+            SectionCode::Code => {
+                let code = section
+                    .get_code_section_reader()
+                    .map_err(|e| format!("{:?}", e))?;
+                let body = func_index
+                    .checked_sub(num_imported_functions)
+                    .and_then(|index| code.into_iter().nth(index as usize));
+                let operators = match body {
+                    Some(body) => body
+                        .and_then(|body| body.get_operators_reader())
+                        .map_err(|e| format!("{:?}", e))?,
+                    None => break,
+                };
+                let mut cost: u64 = 0;
+                for operator in operators.into_iter_with_offsets() {
+                    let (op, offset) = operator.map_err(|e| format!("{:?}", e))?;
+                    if offset >= end_offset {
+                        break;
+                    }
+                    if offset >= start_offset {
+                        cost = cost.saturating_add(table.operator_cost(&op));
+                    }
+                }
+                return Ok(cost);
+            }
+            _ => {}
+        }
+    }
+    Err(format!(
+        "function {} is not defined by the module",
+        func_index
+    ))
+}
+
+/// Returns the kinds of the operators used by the functions of the module `bytes` that have no
+/// explicit cost in `table`, in opcode order.
+///
+/// These operators cost the base cost of the table, which is meant to be checked before the
+/// table is deployed. The module is parsed, not validated.
+pub fn audit_coverage(bytes: &[u8], table: &CostTable) -> Result<Vec<OpcodeKind>, String> {
+    let mut kinds = BTreeSet::new();
+    let mut reader = ModuleReader::new(bytes).map_err(|e| format!("{:?}", e))?;
+    while !reader.eof() {
+        let section = reader.read().map_err(|e| format!("{:?}", e))?;
+        if let SectionCode::Code = section.code {
+            let code = section
+                .get_code_section_reader()
+                .map_err(|e| format!("{:?}", e))?;
+            for body in code {
+                let operators = body
+                    .and_then(|body| body.get_operators_reader())
+                    .map_err(|e| format!("{:?}", e))?;
+                for op in operators {
+                    let kind = OpcodeKind::of(&op.map_err(|e| format!("{:?}", e))?);
+                    if table.explicit_cost(kind).is_none() {
+                        kinds.insert(kind);
+                    }
+                }
+            }
+        }
+    }
+    Ok(kinds.into_iter().collect())
+}
+
+/// The points charged on the paths through a block, see `function_cost_bounds`.
+struct BoundsFrame {
+    is_loop: bool,
+    /// The bounds of the paths reaching the end of the block, or `None` if none does yet.
+    exit: Option<(u64, u64)>,
+    /// The bounds of the paths reaching the `if` of the block, for its `else` arm.
+    if_entry: Option<Option<(u64, u64)>>,
+}
+
+/// Adds the paths reaching a branch to `relative_depth` to the paths leaving the block it
+/// targets, unless the block is a loop.
+fn branch_bounds(
+    frames: &mut [BoundsFrame],
+    relative_depth: u32,
+    current: Option<(u64, u64)>,
+) -> Result<(), String> {
+    let depth = frames
+        .len()
+        .checked_sub(relative_depth as usize + 1)
+        .ok_or("branch out of the function")?;
+    let frame = &mut frames[depth];
+    if !frame.is_loop {
+        frame.exit = merge_bounds(frame.exit, current);
+    }
+    Ok(())
+}
+
+/// Returns the bounds of `a` and `b`, either of which may not be reached.
+fn merge_bounds(a: Option<(u64, u64)>, b: Option<(u64, u64)>) -> Option<(u64, u64)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.max(b.1))),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Returns the fewest and the most points charged by a `Metering` using `table` to a call of
+/// each function of the module `bytes`, in the order of their bodies.
+///
+/// The bounds are those of the paths through the branches of the function, each loop being run
+/// once: the branches back to a loop are not followed. A path ends at the end of the function,
+/// at a `return` or at an `unreachable`, and a function without such a path has bounds of zero.
+/// Unlike `explain`, the surcharges of the special cases of `Metering` are not included. The
+/// module is parsed, not validated.
+pub fn function_cost_bounds(bytes: &[u8], table: &CostTable) -> Result<Vec<(u64, u64)>, String> {
+    let mut bounds = Vec::new();
+    let mut reader = ModuleReader::new(bytes).map_err(|e| format!("{:?}", e))?;
+    while !reader.eof() {
+        let section = reader.read().map_err(|e| format!("{:?}", e))?;
+        if let SectionCode::Code = section.code {
+            let code = section
+                .get_code_section_reader()
+                .map_err(|e| format!("{:?}", e))?;
+            for body in code {
+                let operators = body
+                    .and_then(|body| body.get_operators_reader())
+                    .map_err(|e| format!("{:?}", e))?;
+                // The bounds of the paths reaching the current operator, `None` once it is
+                // unreachable.
+                let mut current = Some((0u64, 0u64));
+                let mut frames = vec![BoundsFrame {
+                    is_loop: false,
+                    exit: None,
+                    if_entry: None,
+                }];
+                let mut function_bounds = None;
+                for op in operators {
+                    let op = op.map_err(|e| format!("{:?}", e))?;
+                    let cost = table.operator_cost(&op);
+                    current = current
+                        .map(|(min, max)| (min.saturating_add(cost), max.saturating_add(cost)));
+                    match op {
+                        Operator::Block { .. } => frames.push(BoundsFrame {
+                            is_loop: false,
+                            exit: None,
+                            if_entry: None,
+                        }),
+                        Operator::Loop { .. } => frames.push(BoundsFrame {
+                            is_loop: true,
+                            exit: None,
+                            if_entry: None,
+                        }),
+                        Operator::If { .. } => frames.push(BoundsFrame {
+                            is_loop: false,
+                            exit: None,
+                            if_entry: Some(current),
+                        }),
+                        Operator::Else => {
+                            let frame = frames.last_mut().ok_or("`else` outside of a block")?;
+                            frame.exit = merge_bounds(frame.exit, current);
+                            current = frame.if_entry.take().unwrap_or(None);
+                        }
+                        Operator::End => {
+                            let frame = frames.pop().ok_or("`end` outside of a block")?;
+                            // Without an `else`, the `if` may skip its block.
+                            let skipped = frame.if_entry.unwrap_or(None);
+                            current = merge_bounds(merge_bounds(frame.exit, current), skipped);
+                            if frames.is_empty() {
+                                function_bounds = current;
+                            }
+                        }
+                        Operator::Br { relative_depth } => {
+                            branch_bounds(&mut frames, relative_depth, current)?;
+                            current = None;
+                        }
+                        Operator::BrIf { relative_depth } => {
+                            branch_bounds(&mut frames, relative_depth, current)?
+                        }
+                        Operator::BrTable { ref table } => {
+                            let (targets, default) =
+                                table.read_table().map_err(|e| format!("{:?}", e))?;
+                            for &relative_depth in targets.iter().chain(Some(&default)) {
+                                branch_bounds(&mut frames, relative_depth, current)?;
+                            }
+                            current = None;
+                        }
+                        Operator::Return | Operator::Unreachable => {
+                            let frame = frames.first_mut().ok_or("operator after the function")?;
+                            frame.exit = merge_bounds(frame.exit, current);
+                            current = None;
+                        }
+                        _ => {}
+                    }
+                }
+                bounds.push(function_bounds.unwrap_or((0, 0)));
+            }
+        }
+    }
+    Ok(bounds)
+}
+
+/// Returns the most points charged by a `Metering` using `table` to a call of each exported
+/// function of the module `bytes`, by export name, as bounded by `function_cost_bounds`.
+///
+/// This is a static estimate meant to suggest a limit to the callers of the exports: each loop
+/// is counted once, and the functions called by an export are not included. The exported
+/// imported functions have no body and are left out. The module is parsed, not validated.
+pub fn export_worst_case(bytes: &[u8], table: &CostTable) -> Result<HashMap<String, u64>, String> {
+    let bounds = function_cost_bounds(bytes, table)?;
+    let mut num_imported_functions = 0;
+    let mut worst_cases = HashMap::new();
+    let mut reader = ModuleReader::new(bytes).map_err(|e| format!("{:?}", e))?;
+    while !reader.eof() {
+        let section = reader.read().map_err(|e| format!("{:?}", e))?;
+        match section.code {
+            SectionCode::Import => {
+                let imports = section
+                    .get_import_section_reader()
+                    .map_err(|e| format!("{:?}", e))?;
+                for import in imports {
+                    if let ImportSectionEntryType::Function(_) =
+                        import.map_err(|e| format!("{:?}", e))?.ty
+                    {
+                        num_imported_functions += 1;
+                    }
+                }
+            }
+            SectionCode::Export => {
+                let exports = section
+                    .get_export_section_reader()
+                    .map_err(|e| format!("{:?}", e))?;
+                for export in exports {
+                    let export = export.map_err(|e| format!("{:?}", e))?;
+                    if let ExternalKind::Function = export.kind {
+                        let local_index =
+                            (export.index as usize).checked_sub(num_imported_functions);
+                        if let Some(&(_, max)) = local_index.and_then(|index| bounds.get(index)) {
+                            worst_cases.insert(export.field.to_string(), max);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(worst_cases)
+}
+
+/// The operators doing an amount of work that `Metering` does not price: the bulk table
+/// operators and `table.grow` work on as many entries as their operands tell, and the atomic
+/// waits block for as long as other threads tell.
+const UNPRICED_OPCODES: &[OpcodeKind] = &[
+    OpcodeKind::TableInit,
+    OpcodeKind::TableCopy,
+    OpcodeKind::TableGrow,
+    OpcodeKind::I32Wait,
+    OpcodeKind::I64Wait,
+];
+
+/// The reason why a module cannot be metered faithfully, see `check_compatible`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IncompatibilityReason {
+    /// The module could not be parsed, with the error of the parser.
+    Malformed(String),
+    /// The module uses an operator of the given kind at the given offset, whose cost `Metering`
+    /// does not model.
+    UnpricedOperator { kind: OpcodeKind, offset: usize },
+}
+
+impl fmt::Display for IncompatibilityReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IncompatibilityReason::Malformed(error) => write!(f, "malformed module: {}", error),
+            IncompatibilityReason::UnpricedOperator { kind, offset } => write!(
+                f,
+                "operator {} at offset {} is not priced by metering",
+                kind, offset
+            ),
+        }
+    }
+}
+
+/// Checks that the points charged by `Metering` to the module `bytes` bound the work it does,
+/// and returns the reason why not for the first operator that breaks this.
+///
+/// Operators left out of the metering model, such as `table.copy` whose cost grows with its
+/// operands without any surcharge, are reported. So are the operators the parser does not
+/// decode, such as those of the tail-call proposal, as the module cannot be compiled. The module
+/// is parsed, not validated.
+pub fn check_compatible(bytes: &[u8]) -> Result<(), IncompatibilityReason> {
+    let malformed = |e| IncompatibilityReason::Malformed(format!("{:?}", e));
+    let mut reader = ModuleReader::new(bytes).map_err(malformed)?;
+    while !reader.eof() {
+        let section = reader.read().map_err(malformed)?;
+        if let SectionCode::Code = section.code {
+            for body in section.get_code_section_reader().map_err(malformed)? {
+                let operators = body
+                    .and_then(|body| body.get_operators_reader())
+                    .map_err(malformed)?;
+                for operator in operators.into_iter_with_offsets() {
+                    let (op, offset) = operator.map_err(malformed)?;
+                    let kind = OpcodeKind::of(&op);
+                    if UNPRICED_OPCODES.contains(&kind) {
+                        return Err(IncompatibilityReason::UnpricedOperator { kind, offset });
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compiles `bytes` with a `Metering` using `table`, tracing the events it is fed with an
+/// `EventTrace`.
+///
+/// Returns the module, the trace, and the static cost of the module: the sum of the points of
+/// all of its operators, as reported by `explain`. The module is compiled with
+/// `Metering::with_runtime_limit`, so its instances must be given a limit before calling them.
+/// The backend is chosen with the first type parameter, the others are inferred:
+///
+/// ```ignore
+/// let (module, trace, static_cost) =
+///     compile_with_trace_and_metering::<SinglePassMCG, _, _, _>(&wasm, &table)?;
+/// ```
+pub fn compile_with_trace_and_metering<MCG, FCG, RM, E>(
+    bytes: &[u8],
+    table: &CostTable,
+) -> CompileResult<(Module, String, u64)>
+where
+    MCG: ModuleCodeGenerator<FCG, RM, E>,
+    FCG: FunctionCodeGenerator<E>,
+    RM: RunnableModule + 'static,
+    E: Debug,
+{
+    // The static cost is explained with the metering pushed into the chain.
+    let metering = {
+        let table = table.clone();
+        move || Metering::with_runtime_limit().with_cost_table(table.clone())
+    };
+    let trace = SharedBuffer::default();
+    let compiler: StreamingCompiler<MCG, FCG, RM, E, _> = {
+        let (trace, metering) = (trace.clone(), metering.clone());
+        StreamingCompiler::new(move || {
+            let mut chain = MiddlewareChain::new();
+            chain.push(EventTrace::with_writer(trace.clone()));
+            chain.push(metering());
+            chain
+        })
+    };
+    let module = compile_with(bytes, &compiler)?;
+    let static_cost = explain(bytes, metering())
+        .map_err(|msg| CompileError::InternalError { msg })?
+        .iter()
+        .map(|&(_, points)| points)
+        .sum();
+    let trace = String::from_utf8_lossy(&trace.0.lock().unwrap()).into_owned();
+    Ok((module, trace, static_cost))
+}
+
+/// An event of a function instrumented by `Metering`, see `dump_instrumented`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InstrumentedOp {
+    /// An operator of the function body.
+    Original(TraceRecord),
+    /// An operator or an internal event injected by `Metering`.
+    Injected(TraceRecord),
+}
+
+/// Compiles `bytes` metered with `table` and returns the events of function `func_index` (in the
+/// module's function index space) as `Metering` emits them: the operators of its body
+/// interleaved with the charges and the limit checks injected around them.
+///
+/// The module is compiled with `Metering::with_runtime_limit`, so the limit checks read the limit
+/// from the instance. The backend is chosen with the first type parameter, the others are
+/// inferred:
+///
+/// ```ignore
+/// let ops = dump_instrumented::<SinglePassMCG, _, _, _>(&wasm, 1, &table)?;
+/// ```
+pub fn dump_instrumented<MCG, FCG, RM, E>(
+    bytes: &[u8],
+    func_index: u32,
+    table: &CostTable,
+) -> Result<Vec<InstrumentedOp>, String>
+where
+    MCG: ModuleCodeGenerator<FCG, RM, E>,
+    FCG: FunctionCodeGenerator<E>,
+    RM: RunnableModule + 'static,
+    E: Debug,
+{
+    let dump: InstrumentedDump = Arc::new(Mutex::new(None));
+    let compiler: StreamingCompiler<MCG, FCG, RM, E, _> = {
+        let (dump, table) = (dump.clone(), table.clone());
+        StreamingCompiler::new(move || {
+            let mut chain = MiddlewareChain::new();
+            chain.push(Metering::with_runtime_limit().with_cost_table(table.clone()));
+            chain.push(InstrumentedRecorder {
+                func_index,
+                recording: false,
+                dump: dump.clone(),
+            });
+            chain
+        })
+    };
+    compile_with(bytes, &compiler).map_err(|e| format!("{:?}", e))?;
+    let dump = dump.lock().unwrap().take();
+    dump.unwrap_or_else(|| {
+        Err(format!(
+            "function {} is not defined by the module",
+            func_index
+        ))
+    })
+}
+
+/// The events recorded by an `InstrumentedRecorder`, shared with `dump_instrumented`.
+type InstrumentedDump = Arc<Mutex<Option<Result<Vec<InstrumentedOp>, String>>>>;
+
+/// A middleware recording the events of a function after `Metering`, for `dump_instrumented`.
+///
+/// `Metering` passes the operators of the body on as they are fed, and emits the operators it
+/// injects as owned events, which tells them apart.
+struct InstrumentedRecorder {
+    func_index: u32,
+    recording: bool,
+    /// The events of the function once it is begun, or the first event that cannot be recorded.
+    dump: InstrumentedDump,
+}
+
+impl FunctionMiddleware for InstrumentedRecorder {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        match op {
+            Event::Internal(InternalEvent::FunctionBegin(id)) => {
+                self.recording =
+                    module_info.imported_functions.len() as u32 + id == self.func_index;
+                if self.recording {
+                    *self.dump.lock().unwrap() = Some(Ok(Vec::new()));
+                }
+            }
+            Event::Internal(InternalEvent::FunctionEnd) => self.recording = false,
+            _ if self.recording => {
+                let record = TraceRecord::of(&op).map(|record| match op {
+                    Event::Wasm(_) => InstrumentedOp::Original(record),
+                    _ => InstrumentedOp::Injected(record),
+                });
+                let mut dump = self.dump.lock().unwrap();
+                match (dump.as_mut(), record) {
+                    (Some(Ok(ops)), Ok(record)) => ops.push(record),
+                    (Some(Ok(_)), Err(msg)) => *dump = Some(Err(msg)),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        sink.push(op);
+        Ok(())
+    }
+}
+
+/// A buffer written to by the `EventTrace` of `compile_with_trace_and_metering`.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,60 @@
+//! Reject the functions using an operator too many times.
+
+use crate::opcode::OpcodeKind;
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
+    module::ModuleInfo,
+};
+
+/// OpcodeCountLimit is a compiler middleware failing the compilation of any function containing
+/// more than `max` operators of the given kind.
+///
+/// The operators are counted as they appear in the function body, not as they are executed, so
+/// this bounds costly operators such as `memory.grow` at compile time. The error names the
+/// function, by its index counting the imported functions.
+pub struct OpcodeCountLimit {
+    kind: OpcodeKind,
+    max: u32,
+    count: u32,
+    func_index: u32,
+}
+
+impl OpcodeCountLimit {
+    pub fn new(kind: OpcodeKind, max: u32) -> OpcodeCountLimit {
+        OpcodeCountLimit {
+            kind,
+            max,
+            count: 0,
+            func_index: 0,
+        }
+    }
+}
+
+impl FunctionMiddleware for OpcodeCountLimit {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        match op {
+            Event::Internal(InternalEvent::FunctionBegin(id)) => {
+                self.func_index = module_info.imported_functions.len() as u32 + id;
+                self.count = 0;
+            }
+            Event::Wasm(&ref op) | Event::WasmOwned(ref op) if OpcodeKind::of(op) == self.kind => {
+                self.count += 1;
+                if self.count > self.max {
+                    return Err(format!(
+                        "function {} has more than {} {} operators",
+                        self.func_index, self.max, self.kind
+                    ));
+                }
+            }
+            _ => {}
+        }
+        sink.push(op);
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! Count the operators executed by an instance, by kind.
+
+use crate::opcode::OpcodeKind;
+use crate::registry::InstanceRegistry;
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
+    module::ModuleInfo,
+    vm::{Ctx, InternalField},
+    wasmparser::Operator,
+};
+
+/// The key of the histogram of an instance in `HISTOGRAMS`, or zero if it has none yet.
+static INTERNAL_FIELD_HISTOGRAM_ID: InternalField = InternalField::allocate();
+
+/// The internal fields of `OpcodeProfiler`, for `registered_internal_fields`.
+pub(crate) fn internal_fields() -> Vec<(&'static str, &'static InternalField)> {
+    vec![("opcode_profiler.histogram_id", &INTERNAL_FIELD_HISTOGRAM_ID)]
+}
+
+/// The number of operators executed, by kind.
+pub type Histogram = BTreeMap<OpcodeKind, u64>;
+
+/// The version of the format written by `OpcodeProfiler::serialize`.
+const FORMAT_VERSION: u8 = 1;
+
+lazy_static! {
+    /// The histograms of the instances, by the id in their `INTERNAL_FIELD_HISTOGRAM_ID`.
+    static ref HISTOGRAMS: InstanceRegistry<Histogram> =
+        InstanceRegistry::new(&INTERNAL_FIELD_HISTOGRAM_ID);
+}
+
+/// OpcodeProfiler is a compiler middleware counting the operators executed by each instance, by
+/// kind.
+///
+/// The operators of a straight-line run of code are counted at once, before the next operator
+/// that may leave it, as `Metering` charges them. The operators of a run cut short by a trap are
+/// not counted. Counting takes a call into the host for each run, so profiled code is much
+/// slower than plain code.
+#[derive(Default)]
+pub struct OpcodeProfiler {
+    /// The operators seen since the last count.
+    pending: Histogram,
+}
+
+impl OpcodeProfiler {
+    pub fn new() -> OpcodeProfiler {
+        OpcodeProfiler::default()
+    }
+
+    /// Returns the operators executed by the instance of `ctx` since it was created or last
+    /// reset.
+    pub fn histogram(ctx: &Ctx) -> Histogram {
+        HISTOGRAMS
+            .get(ctx, |histogram| histogram.clone())
+            .unwrap_or_default()
+    }
+
+    /// Zeroes the counts of the instance of `ctx`, so that its histogram only holds the operators
+    /// executed from now on.
+    pub fn reset(ctx: &mut Ctx) {
+        HISTOGRAMS.remove(ctx);
+    }
+
+    /// Replaces the histogram of the instance of `ctx` with `histogram`, so that the operators
+    /// executed from now on are added to it.
+    ///
+    /// This restores a histogram saved with `serialize`, possibly from another instance.
+    pub fn restore(ctx: &mut Ctx, histogram: Histogram) {
+        HISTOGRAMS.with(ctx, |restored| *restored = histogram);
+    }
+
+    /// Serializes `histogram`.
+    ///
+    /// The kinds are written by name, in opcode order, so equal histograms serialize to the same
+    /// bytes and the bytes can be read back by later versions.
+    pub fn serialize(histogram: &Histogram) -> Vec<u8> {
+        let mut bytes = vec![FORMAT_VERSION];
+        bytes.extend_from_slice(&(histogram.len() as u32).to_le_bytes());
+        for (kind, count) in histogram {
+            let name = kind.name();
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes a histogram serialized by `serialize`.
+    pub fn deserialize(mut bytes: &[u8]) -> Result<Histogram, String> {
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+            if bytes.len() < len {
+                return Err("unexpected end of opcode histogram".to_string());
+            }
+            let (taken, rest) = bytes.split_at(len);
+            *bytes = rest;
+            Ok(taken)
+        }
+
+        let version = take(&mut bytes, 1)?[0];
+        if version != FORMAT_VERSION {
+            return Err(format!("unsupported opcode histogram version {}", version));
+        }
+        let len = u32::from_le_bytes(take(&mut bytes, 4)?.try_into().unwrap());
+        let mut histogram = Histogram::new();
+        for _ in 0..len {
+            let name_len = take(&mut bytes, 1)?[0] as usize;
+            let name = String::from_utf8_lossy(take(&mut bytes, name_len)?);
+            let kind = OpcodeKind::from_name(&name)
+                .ok_or_else(|| format!("unknown opcode `{}` in opcode histogram", name))?;
+            let count = u64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap());
+            histogram.insert(kind, count);
+        }
+        if !bytes.is_empty() {
+            return Err("trailing bytes after opcode histogram".to_string());
+        }
+        Ok(histogram)
+    }
+
+    /// Returns the change in the count of each kind of operator from histogram `a` to histogram
+    /// `b`, in opcode order. The kinds counted as often in both are left out.
+    pub fn diff(a: &Histogram, b: &Histogram) -> Vec<(OpcodeKind, i64)> {
+        let mut kinds: Vec<OpcodeKind> = a.keys().chain(b.keys()).cloned().collect();
+        kinds.sort();
+        kinds.dedup();
+        kinds
+            .into_iter()
+            .filter_map(|kind| {
+                let count = |histogram: &Histogram| histogram.get(&kind).cloned().unwrap_or(0);
+                let delta = count(b) as i64 - count(a) as i64;
+                if delta == 0 {
+                    None
+                } else {
+                    Some((kind, delta))
+                }
+            })
+            .collect()
+    }
+}
+
+/// Adds `counts` to the histogram of the instance of `ctx`.
+fn add_counts(ctx: &mut Ctx, counts: &[(OpcodeKind, u64)]) {
+    HISTOGRAMS.with(ctx, |histogram| {
+        for &(kind, count) in counts {
+            *histogram.entry(kind).or_insert(0) += count;
+        }
+    });
+}
+
+impl FunctionMiddleware for OpcodeProfiler {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        _module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        if let Event::Wasm(&ref op) | Event::WasmOwned(ref op) = op {
+            *self.pending.entry(OpcodeKind::of(op)).or_insert(0) += 1;
+            match *op {
+                Operator::Loop { .. }
+                | Operator::Block { .. }
+                | Operator::End
+                | Operator::If { .. }
+                | Operator::Else
+                | Operator::Unreachable
+                | Operator::Br { .. }
+                | Operator::BrTable { .. }
+                | Operator::BrIf { .. }
+                | Operator::Call { .. }
+                | Operator::CallIndirect { .. }
+                | Operator::Return => {
+                    let counts: Vec<_> = self.pending.iter().map(|(&k, &n)| (k, n)).collect();
+                    self.pending.clear();
+                    sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+                        move |mut info| {
+                            if let Some(ctx) = info.ctx() {
+                                add_counts(ctx, &counts);
+                            }
+                            Ok(())
+                        },
+                    ))));
+                }
+                _ => {}
+            }
+        }
+        sink.push(op);
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! The state kept by the middlewares for each instance, released when the instance is dropped.
+
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+use wasmer_runtime_core::vm::{Ctx, InternalField};
+
+/// The next key of an instance in an `InstanceRegistry`, shared by all the registries.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A value of type `T` for each instance, keyed by the id in an internal field of the instance.
+///
+/// The id is zero until the instance gets an entry. The entry is removed when the instance is
+/// dropped, with a finalizer registered on its `Ctx` along with the id.
+pub(crate) struct InstanceRegistry<T> {
+    id: &'static InternalField,
+    entries: Mutex<HashMap<u64, T>>,
+}
+
+impl<T: Default + Send + 'static> InstanceRegistry<T> {
+    /// Creates a registry keying the instances by the id in `id`.
+    pub(crate) fn new(id: &'static InternalField) -> InstanceRegistry<T> {
+        InstanceRegistry {
+            id,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `f` on the entry of the instance of `ctx`, creating it first if needed.
+    pub(crate) fn with<R>(&'static self, ctx: &mut Ctx, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut id = ctx.get_internal(self.id);
+        if id == 0 {
+            id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+            ctx.set_internal(self.id, id);
+            ctx.add_finalizer(move || {
+                self.entries.lock().unwrap().remove(&id);
+            });
+        }
+        f(self.entries.lock().unwrap().entry(id).or_default())
+    }
+
+    /// Runs `f` on the entry of the instance of `ctx`, if it has one.
+    pub(crate) fn get<R>(&self, ctx: &Ctx, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let id = ctx.get_internal(self.id);
+        if id == 0 {
+            return None;
+        }
+        self.entries.lock().unwrap().get_mut(&id).map(f)
+    }
+
+    /// Removes the entry of the instance of `ctx`, which gets a new one on the next `with`.
+    pub(crate) fn remove(&self, ctx: &Ctx) -> Option<T> {
+        let id = ctx.get_internal(self.id);
+        self.entries.lock().unwrap().remove(&id)
+    }
+}
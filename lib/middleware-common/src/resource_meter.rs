@@ -0,0 +1,173 @@
+//! Meter a resource of its own, apart from the points of `Metering`.
+
+use crate::metering::push_add_internal;
+use std::fmt;
+use std::sync::Arc;
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
+    module::ModuleInfo,
+    vm::{Ctx, InternalField},
+    wasmparser::{Operator, Type as WpType, TypeOrFuncType as WpTypeOrFuncType},
+};
+
+/// The number of resources an instance can meter apart, see `ResourceMeter::new`.
+pub const RESOURCE_COUNT: usize = 4;
+
+/// The units used of each resource.
+static INTERNAL_FIELD_USED: [InternalField; RESOURCE_COUNT] = [
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+];
+
+/// Returns the internal fields of this module, for `registered_internal_fields`.
+pub(crate) fn internal_fields() -> Vec<(&'static str, &'static InternalField)> {
+    vec![
+        ("resource_meter.used_0", &INTERNAL_FIELD_USED[0]),
+        ("resource_meter.used_1", &INTERNAL_FIELD_USED[1]),
+        ("resource_meter.used_2", &INTERNAL_FIELD_USED[2]),
+        ("resource_meter.used_3", &INTERNAL_FIELD_USED[3]),
+    ]
+}
+
+/// The price of an operator in units of the resource, see `ResourceMeter::new`.
+type ChargeFn = Arc<dyn Fn(&Operator) -> u64 + Send + Sync>;
+
+/// ResourceMeter is a compiler middleware counting the use of a resource of the embedder, such
+/// as the bytes written to storage, in an internal field of its own.
+///
+/// The resource is charged by the operators priced by the charge function, and by the host
+/// functions calling `ResourceMeter::charge`. Once more than the limit is used, the operator
+/// traps with `ResourceLimitExceededError`, apart from the limit of `Metering`.
+///
+/// The operators added by the middlewares before it in the chain are priced too, so it is meant
+/// to come first.
+///
+/// An instance has a fixed number of internal fields, so the units of each resource are kept in
+/// one of `RESOURCE_COUNT` fields set aside, shared by all the meters of that resource.
+#[derive(Clone)]
+pub struct ResourceMeter {
+    used: &'static InternalField,
+    limit: u64,
+    charge_fn: ChargeFn,
+}
+
+impl ResourceMeter {
+    /// Creates a ResourceMeter of the resource numbered `resource`, charging `charge_fn(op)`
+    /// units for each operator `op`, without a limit. The operators priced at zero are not
+    /// instrumented.
+    ///
+    /// The meters of the same resource count the same units, so a meter used by the compiler
+    /// can be created again for the host functions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `resource` is not below `RESOURCE_COUNT`.
+    pub fn new<F>(resource: usize, charge_fn: F) -> ResourceMeter
+    where
+        F: Fn(&Operator) -> u64 + Send + Sync + 'static,
+    {
+        assert!(
+            resource < RESOURCE_COUNT,
+            "resource {} out of the {} resources",
+            resource,
+            RESOURCE_COUNT
+        );
+        ResourceMeter {
+            used: &INTERNAL_FIELD_USED[resource],
+            limit: u64::max_value(),
+            charge_fn: Arc::new(charge_fn),
+        }
+    }
+
+    /// Traps once more than `limit` units are used.
+    pub fn with_limit(mut self, limit: u64) -> ResourceMeter {
+        self.limit = limit;
+        self
+    }
+
+    /// Returns the units used by the instance of `ctx`.
+    pub fn used(&self, ctx: &Ctx) -> u64 {
+        ctx.get_internal(self.used)
+    }
+
+    /// Sets the units used by the instance of `ctx`.
+    pub fn set_used(&self, ctx: &mut Ctx, value: u64) {
+        ctx.set_internal(self.used, value);
+    }
+
+    /// Charges `amount` units to the instance of `ctx`, from a host function.
+    ///
+    /// Fails if more than the limit is then used, in which case the host function is meant to
+    /// return the error to trap. The units are charged either way.
+    pub fn charge(&self, ctx: &mut Ctx, amount: u64) -> Result<(), ResourceLimitExceededError> {
+        let used = self.used(ctx).saturating_add(amount);
+        self.set_used(ctx, used);
+        if used > self.limit {
+            return Err(ResourceLimitExceededError {
+                used,
+                limit: self.limit,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl FunctionMiddleware for ResourceMeter {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        _module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        let cost = match op {
+            Event::Wasm(&ref op) | Event::WasmOwned(ref op) => (self.charge_fn)(op),
+            _ => 0,
+        };
+        if cost != 0 {
+            push_add_internal(sink, self.used, cost as i64);
+            sink.push(Event::Internal(InternalEvent::GetInternal(
+                self.used.index() as _,
+            )));
+            sink.push(Event::WasmOwned(Operator::I64Const {
+                value: self.limit as i64,
+            }));
+            sink.push(Event::WasmOwned(Operator::I64GtU));
+            sink.push(Event::WasmOwned(Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            }));
+            let (used, limit) = (self.used, self.limit);
+            sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+                move |mut info| {
+                    let used = info.ctx().map_or(0, |ctx| ctx.get_internal(used));
+                    Err(Box::new(ResourceLimitExceededError { used, limit }))
+                },
+            ))));
+            sink.push(Event::WasmOwned(Operator::End));
+        }
+        sink.push(op);
+        Ok(())
+    }
+}
+
+/// The error of a call trapping because it used more of a resource than the limit of its
+/// `ResourceMeter`.
+#[derive(Copy, Clone, Debug)]
+pub struct ResourceLimitExceededError {
+    /// The units used when the call trapped.
+    pub used: u64,
+    /// The limit of the meter.
+    pub limit: u64,
+}
+
+impl fmt::Display for ResourceLimitExceededError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "resource limit exceeded: used {} of {} units",
+            self.used, self.limit
+        )
+    }
+}
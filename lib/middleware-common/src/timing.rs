@@ -0,0 +1,81 @@
+//! Measure the time the middlewares of a chain spend processing events.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware},
+    module::ModuleInfo,
+    wasmparser::Type as WpType,
+};
+
+/// The total time spent by the middlewares wrapped in a `TimingWrapper` with this timer.
+///
+/// Clones share the same total, so a timer can be handed to the middleware chain generator of
+/// a compiler and read after each compilation.
+#[derive(Clone, Debug, Default)]
+pub struct Timer {
+    nanos: Arc<AtomicU64>,
+}
+
+impl Timer {
+    /// Returns the total time spent so far.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+    fn add(&self, elapsed: Duration) {
+        self.nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+/// TimingWrapper is a compiler middleware adding the time spent by the middleware it wraps to a
+/// `Timer`, and otherwise behaving as the wrapped middleware.
+///
+/// Only the time spent in the wrapped middleware is counted, not the time spent by the next
+/// middlewares of the chain or by the code generator.
+pub struct TimingWrapper<M> {
+    inner: M,
+    timer: Timer,
+}
+
+impl<M: FunctionMiddleware> TimingWrapper<M> {
+    /// Wraps `inner`, adding the time it spends to `timer`.
+    pub fn new(inner: M, timer: Timer) -> TimingWrapper<M> {
+        TimingWrapper { inner, timer }
+    }
+
+    /// Returns the total time of the timer, including the time of the other middlewares sharing
+    /// it.
+    pub fn elapsed(&self) -> Duration {
+        self.timer.elapsed()
+    }
+}
+
+impl<M: FunctionMiddleware> FunctionMiddleware for TimingWrapper<M> {
+    type Error = M::Error;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        let start = Instant::now();
+        let result = self.inner.feed_event(op, module_info, sink);
+        self.timer.add(start.elapsed());
+        result
+    }
+
+    fn feed_local(
+        &mut self,
+        ty: WpType,
+        n: usize,
+        module_info: &ModuleInfo,
+    ) -> Result<(), Self::Error> {
+        let start = Instant::now();
+        let result = self.inner.feed_local(ty, n, module_info);
+        self.timer.add(start.elapsed());
+        result
+    }
+}
@@ -0,0 +1,178 @@
+//! Log the traps of an instance, with the function and the kind of operator they come from.
+
+use crate::opcode::OpcodeKind;
+use crate::registry::InstanceRegistry;
+use crate::working_set::is_memory_access;
+use lazy_static::lazy_static;
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
+    module::ModuleInfo,
+    vm::{Ctx, InternalField},
+    wasmparser::Operator,
+};
+
+/// The operator running in the instance that may trap, encoded by `encode_site`, or zero if none
+/// is running.
+static INTERNAL_FIELD_TRAP_SITE: InternalField = InternalField::allocate();
+/// The key of the trap log of an instance in `TRAP_LOGS`, or zero if it has none yet.
+static INTERNAL_FIELD_TRAP_LOG_ID: InternalField = InternalField::allocate();
+
+/// The internal fields of `TrapLogger`, for `registered_internal_fields`.
+pub(crate) fn internal_fields() -> Vec<(&'static str, &'static InternalField)> {
+    vec![
+        ("trap_logger.trap_site", &INTERNAL_FIELD_TRAP_SITE),
+        ("trap_logger.trap_log_id", &INTERNAL_FIELD_TRAP_LOG_ID),
+    ]
+}
+
+lazy_static! {
+    /// The trap logs of the instances, by the id in their `INTERNAL_FIELD_TRAP_LOG_ID`.
+    static ref TRAP_LOGS: InstanceRegistry<Vec<TrapRecord>> =
+        InstanceRegistry::new(&INTERNAL_FIELD_TRAP_LOG_ID);
+}
+
+/// The kind of a trap, from the operator raising it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TrapKind {
+    /// `unreachable` was executed.
+    Unreachable,
+    /// An integer division or remainder divided by zero or overflowed.
+    IntegerDivision,
+    /// A float was truncated to an integer it does not fit in.
+    FloatToInt,
+    /// A memory access or a bulk memory operator was out of bounds.
+    MemoryAccess,
+    /// A table access or a bulk table operator was out of bounds.
+    TableAccess,
+    /// `call_indirect` called a missing function or a function of another signature.
+    IndirectCall,
+}
+
+impl TrapKind {
+    const ALL: [TrapKind; 6] = [
+        TrapKind::Unreachable,
+        TrapKind::IntegerDivision,
+        TrapKind::FloatToInt,
+        TrapKind::MemoryAccess,
+        TrapKind::TableAccess,
+        TrapKind::IndirectCall,
+    ];
+
+    /// Returns the kind of the traps the operators of the given kind may raise, if any.
+    pub fn of(kind: OpcodeKind) -> Option<TrapKind> {
+        use OpcodeKind::*;
+        match kind {
+            Unreachable => Some(TrapKind::Unreachable),
+            I32DivS | I32DivU | I32RemS | I32RemU | I64DivS | I64DivU | I64RemS | I64RemU => {
+                Some(TrapKind::IntegerDivision)
+            }
+            I32TruncSF32 | I32TruncUF32 | I32TruncSF64 | I32TruncUF64 | I64TruncSF32
+            | I64TruncUF32 | I64TruncSF64 | I64TruncUF64 => Some(TrapKind::FloatToInt),
+            MemoryInit | MemoryCopy | MemoryFill => Some(TrapKind::MemoryAccess),
+            TableInit | TableCopy | TableGet | TableSet => Some(TrapKind::TableAccess),
+            CallIndirect => Some(TrapKind::IndirectCall),
+            _ if is_memory_access(kind) => Some(TrapKind::MemoryAccess),
+            _ => None,
+        }
+    }
+}
+
+/// A trap logged by `record_trap`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TrapRecord {
+    /// The index of the function of the trapping operator, counting the imported functions.
+    pub func_index: u32,
+    /// The kind of the trap, from the kind of the trapping operator.
+    pub trap_kind: TrapKind,
+}
+
+fn encode_site(func_index: u32, trap_kind: TrapKind) -> u64 {
+    (u64::from(func_index) << 8) | (trap_kind as u64 + 1)
+}
+
+fn decode_site(site: u64) -> Option<TrapRecord> {
+    let trap_kind = *TrapKind::ALL.get((site & 0xff).checked_sub(1)? as usize)?;
+    Some(TrapRecord {
+        func_index: (site >> 8) as u32,
+        trap_kind,
+    })
+}
+
+/// TrapLogger is a compiler middleware marking the operators that may trap, so that the traps of
+/// an instance can be logged with `record_trap` and read with `trap_log`.
+///
+/// An operator that may trap stores its function and its kind into the instance while it runs.
+/// A trap leaves them behind, so the host calling into the instance records them when a call
+/// fails. Traps raised elsewhere, such as by the host functions or by `Metering`, leave nothing
+/// to record. Each marked operator takes two stores into the instance, so this middleware is
+/// meant for debugging rather than for production code.
+#[derive(Default)]
+pub struct TrapLogger {
+    func_index: u32,
+}
+
+impl TrapLogger {
+    pub fn new() -> TrapLogger {
+        TrapLogger::default()
+    }
+}
+
+impl FunctionMiddleware for TrapLogger {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        let trap_kind = match op {
+            Event::Internal(InternalEvent::FunctionBegin(id)) => {
+                self.func_index = module_info.imported_functions.len() as u32 + id;
+                None
+            }
+            Event::Wasm(&ref op) | Event::WasmOwned(ref op) => TrapKind::of(OpcodeKind::of(op)),
+            _ => None,
+        };
+        match trap_kind {
+            Some(trap_kind) => {
+                sink.push(Event::WasmOwned(Operator::I64Const {
+                    value: encode_site(self.func_index, trap_kind) as i64,
+                }));
+                sink.push(Event::Internal(InternalEvent::SetInternal(
+                    INTERNAL_FIELD_TRAP_SITE.index() as _,
+                )));
+                sink.push(op);
+                // Past the operator, it did not trap.
+                sink.push(Event::WasmOwned(Operator::I64Const { value: 0 }));
+                sink.push(Event::Internal(InternalEvent::SetInternal(
+                    INTERNAL_FIELD_TRAP_SITE.index() as _,
+                )));
+            }
+            None => sink.push(op),
+        }
+        Ok(())
+    }
+}
+
+/// Appends the trap left behind in the instance of `ctx` to its log and returns it, or returns
+/// `None` if there is none.
+///
+/// This is meant to be called when a call into the instance fails. The trap is cleared, so it is
+/// recorded once.
+pub fn record_trap(ctx: &mut Ctx) -> Option<TrapRecord> {
+    let record = decode_site(ctx.get_internal(&INTERNAL_FIELD_TRAP_SITE))?;
+    ctx.set_internal(&INTERNAL_FIELD_TRAP_SITE, 0);
+    TRAP_LOGS.with(ctx, |log| log.push(record));
+    Some(record)
+}
+
+/// Returns the traps recorded with `record_trap` for the instance of `ctx` since it was created or
+/// last reset with `reset_trap_log`, oldest first.
+pub fn trap_log(ctx: &Ctx) -> Vec<TrapRecord> {
+    TRAP_LOGS.get(ctx, |log| log.clone()).unwrap_or_default()
+}
+
+/// Empties the trap log of the instance of `ctx`.
+pub fn reset_trap_log(ctx: &mut Ctx) {
+    TRAP_LOGS.remove(ctx);
+}
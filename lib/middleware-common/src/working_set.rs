@@ -0,0 +1,485 @@
+//! Measure and limit the number of distinct linear memory pages touched by an instance.
+
+use crate::opcode::OpcodeKind;
+use crate::registry::InstanceRegistry;
+use lazy_static::lazy_static;
+use std::any::Any;
+use wasmer_runtime_core::{
+    codegen::{Event, EventSink, FunctionMiddleware, InternalEvent},
+    module::ModuleInfo,
+    vm::{Ctx, InternalField},
+    wasmparser::{MemoryImmediate, Operator, Type as WpType},
+    Instance,
+};
+
+/// The size of a WebAssembly page, in bytes.
+const PAGE_SIZE: u64 = 65536;
+
+/// The number of distinct pages touched by the instance.
+static INTERNAL_FIELD_PAGES_TOUCHED: InternalField = InternalField::allocate();
+/// Identifies the entry of an instance in `WORKING_SETS`. Zero means the instance has no entry.
+static INTERNAL_FIELD_WORKING_SET_ID: InternalField = InternalField::allocate();
+/// The operands of the memory access being recorded, the address first.
+static INTERNAL_FIELD_OPERANDS: [InternalField; 3] = [
+    InternalField::allocate(),
+    InternalField::allocate(),
+    InternalField::allocate(),
+];
+
+/// The internal fields of `WorkingSetLimit`, for `registered_internal_fields`.
+pub(crate) fn internal_fields() -> Vec<(&'static str, &'static InternalField)> {
+    vec![
+        ("working_set.pages_touched", &INTERNAL_FIELD_PAGES_TOUCHED),
+        ("working_set.id", &INTERNAL_FIELD_WORKING_SET_ID),
+        ("working_set.operand_0", &INTERNAL_FIELD_OPERANDS[0]),
+        ("working_set.operand_1", &INTERNAL_FIELD_OPERANDS[1]),
+        ("working_set.operand_2", &INTERNAL_FIELD_OPERANDS[2]),
+    ]
+}
+
+lazy_static! {
+    /// The pages touched by each instance, one bit per page.
+    static ref WORKING_SETS: InstanceRegistry<Vec<u64>> =
+        InstanceRegistry::new(&INTERNAL_FIELD_WORKING_SET_ID);
+}
+
+/// WorkingSetLimit is a compiler middleware that records the linear memory pages touched by the
+/// loads, stores and bulk memory operators of an instance, and traps once it touches more than
+/// `max_pages` distinct pages.
+///
+/// The pages are counted until `reset_working_set` is called, which should be done between calls
+/// to measure the working set of each call. The access that would touch a page beyond the limit
+/// traps before touching it, with a `WorkingSetLimitExceededError`.
+///
+/// Every memory access calls into the runtime to be recorded, so this middleware is meant to
+/// bound untrusted code rather than to run on hot paths. `v128.store` is not supported.
+pub struct WorkingSetLimit {
+    max_pages: u64,
+}
+
+impl WorkingSetLimit {
+    pub fn new(max_pages: u64) -> WorkingSetLimit {
+        WorkingSetLimit { max_pages }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct WorkingSetLimitExceededError;
+
+/// The memory ranges accessed by an operator, in terms of its operands.
+#[derive(Copy, Clone)]
+enum Access {
+    /// `width` bytes at the address plus `offset`.
+    Fixed { offset: u32, width: u32 },
+    /// `memory.copy`: the length is the third operand, the source the second.
+    Copy,
+    /// `memory.fill` and `memory.init`: the length is the third operand.
+    Fill,
+}
+
+const ADDRESS: &[WpType] = &[WpType::I32];
+const ADDRESS_I32: &[WpType] = &[WpType::I32, WpType::I32];
+const ADDRESS_I64: &[WpType] = &[WpType::I32, WpType::I64];
+const ADDRESS_F32: &[WpType] = &[WpType::I32, WpType::F32];
+const ADDRESS_F64: &[WpType] = &[WpType::I32, WpType::F64];
+const ADDRESS_I32_I32: &[WpType] = &[WpType::I32, WpType::I32, WpType::I32];
+const ADDRESS_I32_I64: &[WpType] = &[WpType::I32, WpType::I32, WpType::I64];
+const ADDRESS_I64_I64: &[WpType] = &[WpType::I32, WpType::I64, WpType::I64];
+const ADDRESS_V128: &[WpType] = &[WpType::I32, WpType::V128];
+
+/// Returns the operands of `op` on top of the stack and how it accesses memory, or `None` if it
+/// does not access memory. `middleware` names the middleware in the error for the operators
+/// whose accesses cannot be recorded.
+fn classify(
+    op: &Operator,
+    middleware: &str,
+) -> Result<Option<(&'static [WpType], Access)>, String> {
+    match *op {
+        Operator::MemoryCopy => Ok(Some((ADDRESS_I32_I32, Access::Copy))),
+        Operator::MemoryFill | Operator::MemoryInit { .. } => {
+            Ok(Some((ADDRESS_I32_I32, Access::Fill)))
+        }
+        Operator::V128Store { .. } => Err(format!("v128.store is not supported by {}", middleware)),
+        _ => Ok(memory_access(op).map(|(memarg, width, operands)| {
+            (
+                operands,
+                Access::Fixed {
+                    offset: memarg.offset,
+                    width,
+                },
+            )
+        })),
+    }
+}
+
+/// Returns the memory immediate of `op`, the number of bytes it accesses and its operands on
+/// top of the stack, or `None` if it does not access a fixed number of bytes of memory.
+pub(crate) fn memory_access<'a>(
+    op: &'a Operator,
+) -> Option<(&'a MemoryImmediate, u32, &'static [WpType])> {
+    use Operator::*;
+    let access = match *op {
+        I32Load8S { ref memarg }
+        | I32Load8U { ref memarg }
+        | I64Load8S { ref memarg }
+        | I64Load8U { ref memarg }
+        | I32AtomicLoad8U { ref memarg }
+        | I64AtomicLoad8U { ref memarg }
+        | I8x16LoadSplat { ref memarg } => (memarg, 1, ADDRESS),
+        I32Load16S { ref memarg }
+        | I32Load16U { ref memarg }
+        | I64Load16S { ref memarg }
+        | I64Load16U { ref memarg }
+        | I32AtomicLoad16U { ref memarg }
+        | I64AtomicLoad16U { ref memarg }
+        | I16x8LoadSplat { ref memarg } => (memarg, 2, ADDRESS),
+        I32Load { ref memarg }
+        | F32Load { ref memarg }
+        | I64Load32S { ref memarg }
+        | I64Load32U { ref memarg }
+        | I32AtomicLoad { ref memarg }
+        | I64AtomicLoad32U { ref memarg }
+        | I32x4LoadSplat { ref memarg } => (memarg, 4, ADDRESS),
+        I64Load { ref memarg }
+        | F64Load { ref memarg }
+        | I64AtomicLoad { ref memarg }
+        | I64x2LoadSplat { ref memarg } => (memarg, 8, ADDRESS),
+        V128Load { ref memarg } => (memarg, 16, ADDRESS),
+        V128Store { ref memarg } => (memarg, 16, ADDRESS_V128),
+
+        I32Store8 { ref memarg }
+        | I32AtomicStore8 { ref memarg }
+        | I32AtomicRmw8UAdd { ref memarg }
+        | I32AtomicRmw8USub { ref memarg }
+        | I32AtomicRmw8UAnd { ref memarg }
+        | I32AtomicRmw8UOr { ref memarg }
+        | I32AtomicRmw8UXor { ref memarg }
+        | I32AtomicRmw8UXchg { ref memarg } => (memarg, 1, ADDRESS_I32),
+        I32Store16 { ref memarg }
+        | I32AtomicStore16 { ref memarg }
+        | I32AtomicRmw16UAdd { ref memarg }
+        | I32AtomicRmw16USub { ref memarg }
+        | I32AtomicRmw16UAnd { ref memarg }
+        | I32AtomicRmw16UOr { ref memarg }
+        | I32AtomicRmw16UXor { ref memarg }
+        | I32AtomicRmw16UXchg { ref memarg } => (memarg, 2, ADDRESS_I32),
+        I32Store { ref memarg }
+        | I32AtomicStore { ref memarg }
+        | I32AtomicRmwAdd { ref memarg }
+        | I32AtomicRmwSub { ref memarg }
+        | I32AtomicRmwAnd { ref memarg }
+        | I32AtomicRmwOr { ref memarg }
+        | I32AtomicRmwXor { ref memarg }
+        | I32AtomicRmwXchg { ref memarg }
+        | Wake { ref memarg } => (memarg, 4, ADDRESS_I32),
+        F32Store { ref memarg } => (memarg, 4, ADDRESS_F32),
+        I64Store8 { ref memarg }
+        | I64AtomicStore8 { ref memarg }
+        | I64AtomicRmw8UAdd { ref memarg }
+        | I64AtomicRmw8USub { ref memarg }
+        | I64AtomicRmw8UAnd { ref memarg }
+        | I64AtomicRmw8UOr { ref memarg }
+        | I64AtomicRmw8UXor { ref memarg }
+        | I64AtomicRmw8UXchg { ref memarg } => (memarg, 1, ADDRESS_I64),
+        I64Store16 { ref memarg }
+        | I64AtomicStore16 { ref memarg }
+        | I64AtomicRmw16UAdd { ref memarg }
+        | I64AtomicRmw16USub { ref memarg }
+        | I64AtomicRmw16UAnd { ref memarg }
+        | I64AtomicRmw16UOr { ref memarg }
+        | I64AtomicRmw16UXor { ref memarg }
+        | I64AtomicRmw16UXchg { ref memarg } => (memarg, 2, ADDRESS_I64),
+        I64Store32 { ref memarg }
+        | I64AtomicStore32 { ref memarg }
+        | I64AtomicRmw32UAdd { ref memarg }
+        | I64AtomicRmw32USub { ref memarg }
+        | I64AtomicRmw32UAnd { ref memarg }
+        | I64AtomicRmw32UOr { ref memarg }
+        | I64AtomicRmw32UXor { ref memarg }
+        | I64AtomicRmw32UXchg { ref memarg } => (memarg, 4, ADDRESS_I64),
+        I64Store { ref memarg }
+        | I64AtomicStore { ref memarg }
+        | I64AtomicRmwAdd { ref memarg }
+        | I64AtomicRmwSub { ref memarg }
+        | I64AtomicRmwAnd { ref memarg }
+        | I64AtomicRmwOr { ref memarg }
+        | I64AtomicRmwXor { ref memarg }
+        | I64AtomicRmwXchg { ref memarg } => (memarg, 8, ADDRESS_I64),
+        F64Store { ref memarg } => (memarg, 8, ADDRESS_F64),
+
+        I32AtomicRmw8UCmpxchg { ref memarg } => (memarg, 1, ADDRESS_I32_I32),
+        I32AtomicRmw16UCmpxchg { ref memarg } => (memarg, 2, ADDRESS_I32_I32),
+        I32AtomicRmwCmpxchg { ref memarg } => (memarg, 4, ADDRESS_I32_I32),
+        I64AtomicRmw8UCmpxchg { ref memarg } => (memarg, 1, ADDRESS_I64_I64),
+        I64AtomicRmw16UCmpxchg { ref memarg } => (memarg, 2, ADDRESS_I64_I64),
+        I64AtomicRmw32UCmpxchg { ref memarg } => (memarg, 4, ADDRESS_I64_I64),
+        I64AtomicRmwCmpxchg { ref memarg } => (memarg, 8, ADDRESS_I64_I64),
+        I32Wait { ref memarg } => (memarg, 4, ADDRESS_I32_I64),
+        I64Wait { ref memarg } => (memarg, 8, ADDRESS_I64_I64),
+
+        _ => return None,
+    };
+    Some(access)
+}
+
+/// Returns true if `memory_access` returns an access for the operators of the given kind.
+pub(crate) fn is_memory_access(kind: OpcodeKind) -> bool {
+    use OpcodeKind::*;
+    match kind {
+        I32Load
+        | I64Load
+        | F32Load
+        | F64Load
+        | I32Load8S
+        | I32Load8U
+        | I32Load16S
+        | I32Load16U
+        | I64Load8S
+        | I64Load8U
+        | I64Load16S
+        | I64Load16U
+        | I64Load32S
+        | I64Load32U
+        | I32Store
+        | I64Store
+        | F32Store
+        | F64Store
+        | I32Store8
+        | I32Store16
+        | I64Store8
+        | I64Store16
+        | I64Store32
+        | Wake
+        | I32Wait
+        | I64Wait
+        | I32AtomicLoad
+        | I64AtomicLoad
+        | I32AtomicLoad8U
+        | I32AtomicLoad16U
+        | I64AtomicLoad8U
+        | I64AtomicLoad16U
+        | I64AtomicLoad32U
+        | I32AtomicStore
+        | I64AtomicStore
+        | I32AtomicStore8
+        | I32AtomicStore16
+        | I64AtomicStore8
+        | I64AtomicStore16
+        | I64AtomicStore32
+        | I32AtomicRmwAdd
+        | I64AtomicRmwAdd
+        | I32AtomicRmw8UAdd
+        | I32AtomicRmw16UAdd
+        | I64AtomicRmw8UAdd
+        | I64AtomicRmw16UAdd
+        | I64AtomicRmw32UAdd
+        | I32AtomicRmwSub
+        | I64AtomicRmwSub
+        | I32AtomicRmw8USub
+        | I32AtomicRmw16USub
+        | I64AtomicRmw8USub
+        | I64AtomicRmw16USub
+        | I64AtomicRmw32USub
+        | I32AtomicRmwAnd
+        | I64AtomicRmwAnd
+        | I32AtomicRmw8UAnd
+        | I32AtomicRmw16UAnd
+        | I64AtomicRmw8UAnd
+        | I64AtomicRmw16UAnd
+        | I64AtomicRmw32UAnd
+        | I32AtomicRmwOr
+        | I64AtomicRmwOr
+        | I32AtomicRmw8UOr
+        | I32AtomicRmw16UOr
+        | I64AtomicRmw8UOr
+        | I64AtomicRmw16UOr
+        | I64AtomicRmw32UOr
+        | I32AtomicRmwXor
+        | I64AtomicRmwXor
+        | I32AtomicRmw8UXor
+        | I32AtomicRmw16UXor
+        | I64AtomicRmw8UXor
+        | I64AtomicRmw16UXor
+        | I64AtomicRmw32UXor
+        | I32AtomicRmwXchg
+        | I64AtomicRmwXchg
+        | I32AtomicRmw8UXchg
+        | I32AtomicRmw16UXchg
+        | I64AtomicRmw8UXchg
+        | I64AtomicRmw16UXchg
+        | I64AtomicRmw32UXchg
+        | I32AtomicRmwCmpxchg
+        | I64AtomicRmwCmpxchg
+        | I32AtomicRmw8UCmpxchg
+        | I32AtomicRmw16UCmpxchg
+        | I64AtomicRmw8UCmpxchg
+        | I64AtomicRmw16UCmpxchg
+        | I64AtomicRmw32UCmpxchg
+        | V128Load
+        | V128Store
+        | I8x16LoadSplat
+        | I16x8LoadSplat
+        | I32x4LoadSplat
+        | I64x2LoadSplat => true,
+        _ => false,
+    }
+}
+
+impl FunctionMiddleware for WorkingSetLimit {
+    type Error = String;
+    fn feed_event<'a, 'b: 'a>(
+        &mut self,
+        op: Event<'a, 'b>,
+        _module_info: &ModuleInfo,
+        sink: &mut EventSink<'a, 'b>,
+    ) -> Result<(), Self::Error> {
+        if let Event::Wasm(&ref op) | Event::WasmOwned(ref op) = op {
+            let max_pages = self.max_pages;
+            push_access_breakpoint(sink, op, "WorkingSetLimit", move |ctx, ranges| {
+                record_access(ctx, ranges, max_pages)
+            })?;
+        }
+        sink.push(op);
+        Ok(())
+    }
+}
+
+/// Pushes a breakpoint calling `record` with the byte ranges `op` is about to access, as
+/// `(start, len)` pairs, if it accesses memory. The events are to be pushed right before `op`.
+pub(crate) fn push_access_breakpoint<'a, 'b: 'a, F>(
+    sink: &mut EventSink<'a, 'b>,
+    op: &Operator,
+    middleware: &str,
+    record: F,
+) -> Result<(), String>
+where
+    F: Fn(&mut Ctx, &[(u64, u64)]) -> Result<(), Box<dyn Any>> + Send + Sync + 'static,
+{
+    let (operands, access) = match classify(op, middleware)? {
+        Some(classified) => classified,
+        None => return Ok(()),
+    };
+    // Breakpoints cannot read the stack, so the operands are moved to internal fields for the
+    // breakpoint to read, then pushed back.
+    for (ty, field) in operands.iter().zip(&INTERNAL_FIELD_OPERANDS).rev() {
+        push_to_i64(sink, *ty);
+        sink.push(Event::Internal(InternalEvent::SetInternal(
+            field.index() as _
+        )));
+    }
+    sink.push(Event::Internal(InternalEvent::Breakpoint(Box::new(
+        move |mut info| match info.ctx() {
+            Some(ctx) => {
+                let operand = |i: usize| ctx.get_internal(&INTERNAL_FIELD_OPERANDS[i]);
+                let ranges = match access {
+                    Access::Fixed { offset, width } => {
+                        [(operand(0) + offset as u64, width as u64), (0, 0)]
+                    }
+                    Access::Copy => [(operand(0), operand(2)), (operand(1), operand(2))],
+                    Access::Fill => [(operand(0), operand(2)), (0, 0)],
+                };
+                record(ctx, &ranges)
+            }
+            None => Ok(()),
+        },
+    ))));
+    for (ty, field) in operands.iter().zip(&INTERNAL_FIELD_OPERANDS) {
+        sink.push(Event::Internal(InternalEvent::GetInternal(
+            field.index() as _
+        )));
+        push_from_i64(sink, *ty);
+    }
+    Ok(())
+}
+
+/// Sets the bits in `pages` of the pages overlapped by `ranges`, calling `on_new` before setting
+/// each bit not set yet. Stops at the first error of `on_new`, leaving that page unset.
+///
+/// Pages beyond the `memory_pages` of the memory are skipped, as accessing them traps anyway.
+pub(crate) fn mark_pages<E>(
+    pages: &mut Vec<u64>,
+    ranges: &[(u64, u64)],
+    memory_pages: u64,
+    mut on_new: impl FnMut() -> Result<(), E>,
+) -> Result<(), E> {
+    ranges
+        .iter()
+        .filter(|&&(_, len)| len != 0)
+        .flat_map(|&(start, len)| start / PAGE_SIZE..=(start + len - 1) / PAGE_SIZE)
+        .take_while(|&page| page < memory_pages)
+        .try_for_each(|page| {
+            let (word, bit) = ((page / 64) as usize, page % 64);
+            if pages.len() <= word {
+                pages.resize(word + 1, 0);
+            }
+            if pages[word] & (1 << bit) == 0 {
+                on_new()?;
+                pages[word] |= 1 << bit;
+            }
+            Ok(())
+        })
+}
+
+/// Pushes the conversion of the value of type `ty` on top of the stack to an `i64`.
+fn push_to_i64<'a, 'b: 'a>(sink: &mut EventSink<'a, 'b>, ty: WpType) {
+    match ty {
+        WpType::I32 => sink.push(Event::WasmOwned(Operator::I64ExtendUI32)),
+        WpType::F32 => {
+            sink.push(Event::WasmOwned(Operator::I32ReinterpretF32));
+            sink.push(Event::WasmOwned(Operator::I64ExtendUI32));
+        }
+        WpType::F64 => sink.push(Event::WasmOwned(Operator::I64ReinterpretF64)),
+        _ => {}
+    }
+}
+
+/// Pushes the conversion of the `i64` on top of the stack back to a value of type `ty`.
+fn push_from_i64<'a, 'b: 'a>(sink: &mut EventSink<'a, 'b>, ty: WpType) {
+    match ty {
+        WpType::I32 => sink.push(Event::WasmOwned(Operator::I32WrapI64)),
+        WpType::F32 => {
+            sink.push(Event::WasmOwned(Operator::I32WrapI64));
+            sink.push(Event::WasmOwned(Operator::F32ReinterpretI32));
+        }
+        WpType::F64 => sink.push(Event::WasmOwned(Operator::F64ReinterpretI64)),
+        _ => {}
+    }
+}
+
+/// Records the pages touched by an access to `ranges`.
+fn record_access(ctx: &mut Ctx, ranges: &[(u64, u64)], max_pages: u64) -> Result<(), Box<dyn Any>> {
+    let memory_pages = ctx.memory(0).size().0 as u64;
+    let mut touched = ctx.get_internal(&INTERNAL_FIELD_PAGES_TOUCHED);
+    let result = WORKING_SETS.with(ctx, |pages| {
+        mark_pages(pages, ranges, memory_pages, || {
+            if touched >= max_pages {
+                return Err(Box::new(WorkingSetLimitExceededError) as Box<dyn Any>);
+            }
+            touched += 1;
+            Ok(())
+        })
+    });
+    ctx.set_internal(&INTERNAL_FIELD_PAGES_TOUCHED, touched);
+    result
+}
+
+/// Returns the number of distinct pages touched by an Instance since its working set was reset.
+pub fn get_working_set_size(instance: &Instance) -> u64 {
+    instance.get_internal(&INTERNAL_FIELD_PAGES_TOUCHED)
+}
+
+/// Returns the number of distinct pages touched in a Ctx since its working set was reset.
+pub fn get_working_set_size_ctx(ctx: &Ctx) -> u64 {
+    ctx.get_internal(&INTERNAL_FIELD_PAGES_TOUCHED)
+}
+
+/// Forgets the pages touched by an Instance, and frees the memory used to record them.
+pub fn reset_working_set(instance: &mut Instance) {
+    reset_working_set_ctx(instance.context_mut());
+}
+
+/// Forgets the pages touched in a Ctx, and frees the memory used to record them.
+pub fn reset_working_set_ctx(ctx: &mut Ctx) {
+    WORKING_SETS.remove(ctx);
+    ctx.set_internal(&INTERNAL_FIELD_PAGES_TOUCHED, 0);
+}
@@ -39,6 +39,7 @@ fn main() {
         .with_header(WASMER_PRE_HEADER)
         .with_define("target_family", "windows", "_WIN32")
         .with_define("target_arch", "x86_64", "ARCH_X86_64")
+        .with_define("feature", "metering", "WASMER_METERING")
         .generate()
         .expect("Unable to generate C bindings")
         .write_to_file(out_wasmer_header_file.as_path());
@@ -52,6 +53,7 @@ fn main() {
         .with_header(WASMER_PRE_HEADER)
         .with_define("target_family", "windows", "_WIN32")
         .with_define("target_arch", "x86_64", "ARCH_X86_64")
+        .with_define("feature", "metering", "WASMER_METERING")
         .generate()
         .expect("Unable to generate C++ bindings")
         .write_to_file(out_wasmer_header_file.as_path());
@@ -22,6 +22,27 @@ pub(crate) fn take_last_error() -> Option<Box<dyn Error>> {
     LAST_ERROR.with(|prev| prev.borrow_mut().take())
 }
 
+/// Forget the most recent error, if any.
+pub(crate) fn clear_last_error() {
+    LAST_ERROR.with(|prev| *prev.borrow_mut() = None);
+}
+
+/// Clears the last error, so that `wasmer_last_error_length` returns 0 until another error
+/// occurs.
+///
+/// The metering functions clear the last error when they succeed, so that an error they
+/// reported before is not read back after a later call.
+///
+/// # Example
+///
+/// ```c
+/// wasmer_clear_last_error();
+/// ```
+#[no_mangle]
+pub extern "C" fn wasmer_clear_last_error() {
+    clear_last_error();
+}
+
 /// Gets the length in bytes of the last error.
 /// This can be used to dynamically allocate a buffer with the correct number of
 /// bytes needed to store a message.
@@ -455,7 +455,17 @@ pub unsafe extern "C" fn wasmer_export_func_call(
     let results: &mut [wasmer_value_t] = slice::from_raw_parts_mut(results, results_len as usize);
 
     let instance = &*named_export.instance;
+    #[cfg(feature = "metering")]
+    let points_before = crate::metering::points_before_call(
+        named_export.instance as *const crate::instance::wasmer_instance_t,
+    );
     let result = instance.call(&named_export.name, &params[..]);
+    #[cfg(feature = "metering")]
+    crate::metering::log_call(
+        named_export.instance as *const crate::instance::wasmer_instance_t,
+        points_before,
+        &result,
+    );
     match result {
         Ok(results_vec) => {
             if !results_vec.is_empty() {
@@ -483,6 +493,9 @@ pub unsafe extern "C" fn wasmer_export_func_call(
             wasmer_result_t::WASMER_OK
         }
         Err(err) => {
+            #[cfg(feature = "metering")]
+            crate::metering::update_last_call_error(err);
+            #[cfg(not(feature = "metering"))]
             update_last_error(err);
             wasmer_result_t::WASMER_ERROR
         }
@@ -195,7 +195,11 @@ pub unsafe extern "C" fn wasmer_instance_call(
     let func_name_r = func_name_c.to_str().unwrap();
 
     let results: &mut [wasmer_value_t] = slice::from_raw_parts_mut(results, results_len as usize);
+    #[cfg(feature = "metering")]
+    let points_before = crate::metering::points_before_call(instance);
     let result = (&*(instance as *mut Instance)).call(func_name_r, &params[..]);
+    #[cfg(feature = "metering")]
+    crate::metering::log_call(instance, points_before, &result);
 
     match result {
         Ok(results_vec) => {
@@ -224,6 +228,9 @@ pub unsafe extern "C" fn wasmer_instance_call(
             wasmer_result_t::WASMER_OK
         }
         Err(err) => {
+            #[cfg(feature = "metering")]
+            crate::metering::update_last_call_error(err);
+            #[cfg(not(feature = "metering"))]
             update_last_error(err);
             wasmer_result_t::WASMER_ERROR
         }
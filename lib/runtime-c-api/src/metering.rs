@@ -0,0 +1,1872 @@
+//! Compile modules with gas metering, and read and set the gas of instances.
+
+use crate::{
+    error::{clear_last_error, update_last_error, CApiError},
+    import::wasmer_import_t,
+    instance::{wasmer_instance_context_t, wasmer_instance_t},
+    module::{wasmer_module_destroy, wasmer_module_instantiate, wasmer_module_t},
+    value::wasmer_value_t,
+    wasmer_byte_array, wasmer_result_t,
+};
+use lazy_static::lazy_static;
+use libc::{c_char, c_int, c_void};
+use std::{
+    ffi::CStr,
+    ptr, slice, str,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    time::{Duration, Instant},
+};
+use wasmer_middleware_common::{
+    cost_table::{embed_fingerprint, embedded_cost_table, embedded_fingerprint, CostTable},
+    metering::{
+        self, embed_abi_version, embed_checkpoint_counts, embed_default_limit,
+        embedded_abi_version, embedded_checkpoint_counts, ExecutionLimitExceededError, Metering,
+    },
+    opcode::OpcodeKind,
+    opcode_profiler::OpcodeProfiler,
+};
+use wasmer_runtime::{compile_with, Instance, MiddlewareChain, Module, StreamingCompiler, Value};
+use wasmer_runtime_core::{
+    backend::Compiler,
+    error::{CallError, RuntimeError},
+    module::ExportIndex,
+    structures::TypedIndex,
+    vm::Ctx,
+};
+
+#[repr(C)]
+pub struct wasmer_cost_table_t;
+
+#[repr(C)]
+pub struct wasmer_metered_streaming_compiler_t;
+
+/// A backend compiling metered modules.
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum wasmer_metering_backend_t {
+    METERING_SINGLEPASS = 0,
+    METERING_LLVM = 1,
+}
+
+/// The backends able to compile metered modules, by decreasing priority. Cranelift does not run
+/// middlewares, so it is never used for metered modules.
+const METERING_BACKEND_PRIORITY: [wasmer_metering_backend_t; 2] = [
+    wasmer_metering_backend_t::METERING_SINGLEPASS,
+    wasmer_metering_backend_t::METERING_LLVM,
+];
+
+/// The kind of an event told to the callback set with `wasmer_set_metering_log_callback`, with
+/// the meaning of the payload it is given.
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum wasmer_metering_event_t {
+    /// A call with `wasmer_instance_call` or `wasmer_export_func_call` returned, having charged
+    /// the payload in points.
+    METERING_EVENT_CHARGE = 0,
+    /// The points limit of the instance was set to the payload.
+    METERING_EVENT_LIMIT_SET = 1,
+    /// The points used by the instance were set to the payload.
+    METERING_EVENT_RESET = 2,
+    /// A call with `wasmer_instance_call` or `wasmer_export_func_call` exceeded the limit of the
+    /// instance by the payload.
+    METERING_EVENT_TRAP = 3,
+}
+
+/// The callback set with `wasmer_set_metering_log_callback`.
+#[derive(Copy, Clone)]
+struct MeteringLogCallback {
+    callback: extern "C" fn(
+        event: wasmer_metering_event_t,
+        instance: *const wasmer_instance_t,
+        payload: u64,
+        user_data: *mut c_void,
+    ),
+    /// The `user_data` pointer, stored as an integer to be shared between threads.
+    user_data: usize,
+}
+
+lazy_static! {
+    /// The message set with `wasmer_set_gas_exhaustion_message`.
+    static ref GAS_EXHAUSTION_MESSAGE: RwLock<Option<String>> = RwLock::new(None);
+    /// The backend set with `wasmer_set_metering_backend`.
+    static ref METERING_BACKEND: RwLock<Option<wasmer_metering_backend_t>> = RwLock::new(None);
+    /// The callback set with `wasmer_set_metering_log_callback`.
+    static ref METERING_LOG_CALLBACK: RwLock<Option<MeteringLogCallback>> = RwLock::new(None);
+}
+
+/// Whether a callback is set with `wasmer_set_metering_log_callback`, read by the calls without
+/// taking the lock of the callback.
+static METERING_LOG_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(not(any(feature = "metering-singlepass", feature = "metering-llvm")))]
+compile_error!(
+    "the `metering` feature requires the `metering-singlepass` or `metering-llvm` feature"
+);
+
+/// Returns true if `backend` is compiled in.
+fn is_compiled_in(backend: wasmer_metering_backend_t) -> bool {
+    match backend {
+        wasmer_metering_backend_t::METERING_SINGLEPASS => cfg!(feature = "metering-singlepass"),
+        wasmer_metering_backend_t::METERING_LLVM => cfg!(feature = "metering-llvm"),
+    }
+}
+
+/// Returns a compiler running the metering middleware built by `metering` on every function,
+/// with the backend returned by `wasmer_metering_backend`.
+fn get_metered_compiler<F: Fn() -> Metering + 'static>(metering: F) -> Box<dyn Compiler> {
+    let generator = move || {
+        let mut chain = MiddlewareChain::new();
+        chain.push(metering());
+        chain
+    };
+    match wasmer_metering_backend() {
+        #[cfg(feature = "metering-singlepass")]
+        wasmer_metering_backend_t::METERING_SINGLEPASS => {
+            use wasmer_singlepass_backend::ModuleCodeGenerator as MeteredMCG;
+            let c: StreamingCompiler<MeteredMCG, _, _, _, _> = StreamingCompiler::new(generator);
+            Box::new(c)
+        }
+        #[cfg(feature = "metering-llvm")]
+        wasmer_metering_backend_t::METERING_LLVM => {
+            use wasmer_llvm_backend::ModuleCodeGenerator as MeteredMCG;
+            let c: StreamingCompiler<MeteredMCG, _, _, _, _> = StreamingCompiler::new(generator);
+            Box::new(c)
+        }
+        #[allow(unreachable_patterns)]
+        backend => unreachable!("the {:?} backend is not compiled in", backend),
+    }
+}
+
+/// Returns the backend compiling metered modules: the one set with
+/// `wasmer_set_metering_backend`, or else the first compiled-in backend among singlepass and
+/// LLVM, in that order.
+#[no_mangle]
+pub extern "C" fn wasmer_metering_backend() -> wasmer_metering_backend_t {
+    if let Some(backend) = *METERING_BACKEND.read().unwrap() {
+        return backend;
+    }
+    *METERING_BACKEND_PRIORITY
+        .iter()
+        .find(|&&backend| is_compiled_in(backend))
+        .unwrap()
+}
+
+/// Returns true if `backend` is compiled in, so that it can be set with
+/// `wasmer_set_metering_backend`.
+#[no_mangle]
+pub extern "C" fn wasmer_metering_backend_available(backend: wasmer_metering_backend_t) -> bool {
+    is_compiled_in(backend)
+}
+
+/// Sets the backend compiling metered modules, for all the compilations of the process, in place
+/// of the default one returned by `wasmer_metering_backend`.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` if the backend is not compiled in. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[no_mangle]
+pub extern "C" fn wasmer_set_metering_backend(
+    backend: wasmer_metering_backend_t,
+) -> wasmer_result_t {
+    if !is_compiled_in(backend) {
+        update_last_error(CApiError {
+            msg: format!("the {:?} backend is not compiled in", backend),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    *METERING_BACKEND.write().unwrap() = Some(backend);
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Returns `bytes` with the fingerprint, the metering ABI version and the checkpoint counts of the
+/// module compiled from them with `metering`, priced by `table`, see
+/// `wasmer_module_metered_fingerprint`, `wasmer_module_metering_abi_version` and
+/// `wasmer_module_function_checkpoint_count`.
+///
+/// The sections reserved for the metering are stripped from `bytes` first, so that the module
+/// cannot forge them, whether they are embedded by this function, by the caller, such as the cost
+/// table and the default limit, or not at all.
+///
+/// The checkpoint counts are left out if the module cannot be parsed or the deadline of `metering`
+/// is reached while counting them, as the module then fails to compile.
+fn embed_metering_sections(bytes: &[u8], table: &CostTable, metering: &Metering) -> Vec<u8> {
+    let bytes = &metering::strip_reserved_sections(bytes)[..];
+    let embedded = embed_abi_version(&embed_fingerprint(bytes, &table.fingerprint(bytes)));
+    match metering.checkpoint_counts(bytes) {
+        Ok(counts) => embed_checkpoint_counts(&embedded, &counts),
+        Err(_) => embedded,
+    }
+}
+
+/// Creates a new Module from the given wasm bytes, with gas metering.
+///
+/// The points limit of the instances of the module is not compiled in, it must be set with
+/// `wasmer_instance_set_execution_limit` before calling them.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_compile_with_gas_metering(
+    module: *mut *mut wasmer_module_t,
+    wasm_bytes: *mut u8,
+    wasm_bytes_len: u32,
+) -> wasmer_result_t {
+    let bytes: &[u8] = slice::from_raw_parts_mut(wasm_bytes, wasm_bytes_len as usize);
+    let metering = || Metering::with_runtime_limit().with_last_call_cost();
+    let bytes = embed_metering_sections(bytes, &CostTable::default(), &metering());
+    let compiler = get_metered_compiler(metering);
+    let new_module = match compile_with(&bytes, &*compiler) {
+        Ok(module) => module,
+        Err(error) => {
+            update_last_error(error);
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    *module = Box::into_raw(Box::new(new_module)) as *mut wasmer_module_t;
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Creates a new Instance from the given wasm bytes and imports, with gas metering and a points
+/// limit of `limit`.
+///
+/// This compiles the bytes as `wasmer_compile_with_gas_metering` does, instantiates the module
+/// as `wasmer_module_instantiate` does and sets the limit of the instance, for one-shot
+/// executions that do not need the module.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instantiate_with_gas_metering(
+    instance: *mut *mut wasmer_instance_t,
+    wasm_bytes: *mut u8,
+    wasm_bytes_len: u32,
+    imports: *mut wasmer_import_t,
+    imports_len: c_int,
+    limit: u64,
+) -> wasmer_result_t {
+    if wasm_bytes.is_null() {
+        update_last_error(CApiError {
+            msg: "wasm bytes ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let mut module: *mut wasmer_module_t = ptr::null_mut();
+    if let wasmer_result_t::WASMER_ERROR =
+        wasmer_compile_with_gas_metering(&mut module, wasm_bytes, wasm_bytes_len)
+    {
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    // The instance keeps the compiled code alive by itself.
+    let result = wasmer_module_instantiate(module, instance, imports, imports_len);
+    wasmer_module_destroy(module);
+    if let wasmer_result_t::WASMER_ERROR = result {
+        return result;
+    }
+    wasmer_instance_set_execution_limit(*instance, limit);
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Creates a new Module from the given wasm bytes, with gas metering, and sets `*static_cost` to
+/// its static cost, for admitting a module in one call.
+///
+/// The module is compiled as `wasmer_compile_with_gas_metering` does, and records
+/// `default_limit` as the points limit its instances start with, which
+/// `wasmer_instance_set_execution_limit` can change afterwards. The static cost is the sum of the
+/// points charged for the operators of its functions by that metering, as if each ran once.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, in which case neither output is set. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_admit_module(
+    module: *mut *mut wasmer_module_t,
+    static_cost: *mut u64,
+    wasm_bytes: *const u8,
+    wasm_bytes_len: u32,
+    default_limit: u64,
+) -> wasmer_result_t {
+    if module.is_null() || static_cost.is_null() {
+        update_last_error(CApiError {
+            msg: "module or static cost ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if wasm_bytes.is_null() {
+        update_last_error(CApiError {
+            msg: "wasm bytes ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let bytes: &[u8] = slice::from_raw_parts(wasm_bytes, wasm_bytes_len as usize);
+    let table = CostTable::default();
+    let metering = || Metering::with_runtime_limit().with_last_call_cost();
+    let cost = match metering::explain(bytes, metering()) {
+        Ok(costs) => costs
+            .iter()
+            .fold(0u64, |total, &(_, points)| total.saturating_add(points)),
+        Err(msg) => {
+            update_last_error(CApiError { msg });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    let bytes = embed_default_limit(
+        &embed_metering_sections(bytes, &table, &metering()),
+        default_limit,
+    );
+    let compiler = get_metered_compiler(metering);
+    let new_module = match compile_with(&bytes, &*compiler) {
+        Ok(module) => module,
+        Err(error) => {
+            update_last_error(error);
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    *module = Box::into_raw(Box::new(new_module)) as *mut wasmer_module_t;
+    *static_cost = cost;
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Creates a new Module from the given wasm bytes, with gas metering, giving up if the
+/// compilation takes longer than `timeout_millis` milliseconds.
+///
+/// The deadline is checked as the checkpoints of each function are counted for
+/// `wasmer_module_function_checkpoint_count`, then as each function is compiled, so the call may
+/// return slightly after it.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including a timeout. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_compile_with_gas_metering_timeout(
+    module: *mut *mut wasmer_module_t,
+    wasm_bytes: *mut u8,
+    wasm_bytes_len: u32,
+    timeout_millis: u64,
+) -> wasmer_result_t {
+    let bytes: &[u8] = slice::from_raw_parts_mut(wasm_bytes, wasm_bytes_len as usize);
+    // The checkpoints are counted within the deadline too.
+    let deadline = Instant::now() + Duration::from_millis(timeout_millis);
+    let metering = move || {
+        Metering::with_runtime_limit()
+            .with_last_call_cost()
+            .with_compile_deadline(deadline)
+    };
+    let bytes = embed_metering_sections(bytes, &CostTable::default(), &metering());
+    let compiler = get_metered_compiler(metering);
+    let new_module = match compile_with(&bytes, &*compiler) {
+        Ok(module) => module,
+        Err(_) if Instant::now() >= deadline => {
+            update_last_error(CApiError {
+                msg: format!("compilation timed out after {} ms", timeout_millis),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+        Err(error) => {
+            update_last_error(error);
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    *module = Box::into_raw(Box::new(new_module)) as *mut wasmer_module_t;
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Creates a new Module from the given wasm bytes, with gas metering, calling `callback` as each
+/// function is compiled.
+///
+/// `callback` is given the number of the function among the functions defined by the module,
+/// counting from one, their total and `data`. It is called on the thread compiling the module,
+/// before `wasmer_compile_with_gas_metering_progress` returns.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_compile_with_gas_metering_progress(
+    module: *mut *mut wasmer_module_t,
+    wasm_bytes: *mut u8,
+    wasm_bytes_len: u32,
+    callback: extern "C" fn(current: u32, total: u32, data: *mut c_void),
+    data: *mut c_void,
+) -> wasmer_result_t {
+    let bytes: &[u8] = slice::from_raw_parts_mut(wasm_bytes, wasm_bytes_len as usize);
+    let metering = move || {
+        Metering::with_runtime_limit()
+            .with_last_call_cost()
+            .with_progress_callback(move |current, total| callback(current, total, data))
+    };
+    let bytes = embed_metering_sections(bytes, &CostTable::default(), &metering());
+    let compiler = get_metered_compiler(metering);
+    let new_module = match compile_with(&bytes, &*compiler) {
+        Ok(module) => module,
+        Err(error) => {
+            update_last_error(error);
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    *module = Box::into_raw(Box::new(new_module)) as *mut wasmer_module_t;
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Creates a new Module from the given wasm bytes, with gas metering priced by `table`.
+///
+/// The table is embedded in the module, see `wasmer_module_cost_tables_equal`. The points limit
+/// of the instances of the module must be set with `wasmer_instance_set_execution_limit` before
+/// calling them.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_compile_with_cost_table(
+    module: *mut *mut wasmer_module_t,
+    wasm_bytes: *mut u8,
+    wasm_bytes_len: u32,
+    table: *const wasmer_cost_table_t,
+) -> wasmer_result_t {
+    if table.is_null() {
+        update_last_error(CApiError {
+            msg: "cost table ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let bytes: &[u8] = slice::from_raw_parts_mut(wasm_bytes, wasm_bytes_len as usize);
+    let table = (&*(table as *const CostTable)).clone();
+    let metering_table = table.clone();
+    let metering = move || {
+        Metering::with_runtime_limit()
+            .with_last_call_cost()
+            .with_cost_table(metering_table.clone())
+    };
+    let bytes = table.embed(&embed_metering_sections(bytes, &table, &metering()));
+    let compiler = get_metered_compiler(metering);
+    let new_module = match compile_with(&bytes, &*compiler) {
+        Ok(module) => module,
+        Err(error) => {
+            update_last_error(error);
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    *module = Box::into_raw(Box::new(new_module)) as *mut wasmer_module_t;
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Creates a new streaming compiler, compiling the bytes fed to it with gas metering as
+/// `wasmer_compile_with_gas_metering` does.
+///
+/// The compiler must be freed with `wasmer_metered_streaming_compiler_destroy`.
+#[no_mangle]
+pub extern "C" fn wasmer_metered_streaming_compiler_new() -> *mut wasmer_metered_streaming_compiler_t
+{
+    Box::into_raw(Box::new(Vec::<u8>::new())) as *mut wasmer_metered_streaming_compiler_t
+}
+
+/// Feeds the next `chunk_len` bytes of the module to the streaming compiler.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_metered_streaming_compiler_feed(
+    compiler: *mut wasmer_metered_streaming_compiler_t,
+    chunk: *const u8,
+    chunk_len: u32,
+) -> wasmer_result_t {
+    if compiler.is_null() {
+        update_last_error(CApiError {
+            msg: "streaming compiler ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if chunk.is_null() {
+        update_last_error(CApiError {
+            msg: "chunk ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let bytes = &mut *(compiler as *mut Vec<u8>);
+    bytes.extend_from_slice(slice::from_raw_parts(chunk, chunk_len as usize));
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Compiles the bytes fed to the streaming compiler into a new Module, with gas metering.
+///
+/// The function bodies are only compiled once the whole module has been fed, as the compiler
+/// parses a module in one pass. Finalizing before the end of the module fails without
+/// discarding the bytes fed so far, so more can be fed before finalizing again. The compiler
+/// must still be freed with `wasmer_metered_streaming_compiler_destroy`.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module is
+/// incomplete. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an error
+/// message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_metered_streaming_compiler_finalize(
+    compiler: *mut wasmer_metered_streaming_compiler_t,
+    module: *mut *mut wasmer_module_t,
+) -> wasmer_result_t {
+    if compiler.is_null() {
+        update_last_error(CApiError {
+            msg: "streaming compiler ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let bytes = &mut *(compiler as *mut Vec<u8>);
+    if !is_complete_module(bytes) {
+        update_last_error(CApiError {
+            msg: format!("incomplete module: {} bytes were fed", bytes.len()),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    wasmer_compile_with_gas_metering(module, bytes.as_mut_ptr(), bytes.len() as u32)
+}
+
+/// Frees memory for the given streaming compiler.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub extern "C" fn wasmer_metered_streaming_compiler_destroy(
+    compiler: *mut wasmer_metered_streaming_compiler_t,
+) {
+    if !compiler.is_null() {
+        unsafe { Box::from_raw(compiler as *mut Vec<u8>) };
+    }
+}
+
+/// Returns true if `bytes` holds the header of a module followed by whole sections.
+///
+/// The contents of the sections are not checked, compiling reports any error in them.
+fn is_complete_module(mut bytes: &[u8]) -> bool {
+    fn read_leb128(bytes: &mut &[u8]) -> Option<u32> {
+        let mut value = 0u32;
+        for (i, byte) in bytes.iter().enumerate().take(5) {
+            value |= u32::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                *bytes = &bytes[i + 1..];
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    // The magic number and the version.
+    if bytes.len() < 8 {
+        return false;
+    }
+    bytes = &bytes[8..];
+    while !bytes.is_empty() {
+        // The section id.
+        bytes = &bytes[1..];
+        match read_leb128(&mut bytes) {
+            Some(len) if len as usize <= bytes.len() => bytes = &bytes[len as usize..],
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Returns true if both modules were compiled with `wasmer_compile_with_cost_table` and equal
+/// cost tables.
+///
+/// Returns false if either module is null or has no cost table.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_module_cost_tables_equal(
+    a: *const wasmer_module_t,
+    b: *const wasmer_module_t,
+) -> bool {
+    if a.is_null() || b.is_null() {
+        return false;
+    }
+    let a = &*(a as *const Module);
+    let b = &*(b as *const Module);
+    match (embedded_cost_table(a.info()), embedded_cost_table(b.info())) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Sets `*cost` to the cost of the operators of the given opcode index in the cost table of a
+/// module compiled with `wasmer_compile_with_cost_table`.
+///
+/// The opcode indices are the ones of `wasmer_cost_table_set_cost`. The cost is read from the
+/// table embedded in the module, without the special cases of metering.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module has no cost
+/// table. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_module_opcode_cost(
+    module: *const wasmer_module_t,
+    opcode_index: u32,
+    cost: *mut u64,
+) -> wasmer_result_t {
+    if module.is_null() || cost.is_null() {
+        update_last_error(CApiError {
+            msg: "module or cost ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let kind = match OpcodeKind::from_index(opcode_index as usize) {
+        Some(kind) => kind,
+        None => {
+            update_last_error(CApiError {
+                msg: format!("unknown opcode index {}", opcode_index),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    let module = &*(module as *const Module);
+    let bytes = match embedded_cost_table(module.info()) {
+        Some(bytes) => bytes,
+        None => {
+            update_last_error(CApiError {
+                msg: "the module has no cost table".to_string(),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    match CostTable::cost_from_bytes(bytes, kind) {
+        Ok(value) => {
+            *cost = value;
+            clear_last_error();
+            wasmer_result_t::WASMER_OK
+        }
+        Err(msg) => {
+            update_last_error(CApiError { msg });
+            wasmer_result_t::WASMER_ERROR
+        }
+    }
+}
+
+/// Stores the metadata of the cost table of a module compiled with
+/// `wasmer_compile_with_cost_table` into the provided buffer, followed by a trailing null. On
+/// success, `*written` is set to the length of the metadata, without the trailing null.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module has no cost
+/// table or the buffer is too small to hold the metadata and the trailing null. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_module_cost_table_metadata(
+    module: *const wasmer_module_t,
+    buffer: *mut c_char,
+    buffer_len: u32,
+    written: *mut u32,
+) -> wasmer_result_t {
+    if module.is_null() {
+        update_last_error(CApiError {
+            msg: "module ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if buffer.is_null() {
+        update_last_error(CApiError {
+            msg: "buffer ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let module = &*(module as *const Module);
+    let table = match embedded_cost_table(module.info()).map(CostTable::from_bytes) {
+        Some(Ok(table)) => table,
+        Some(Err(msg)) => {
+            update_last_error(CApiError { msg });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+        None => {
+            update_last_error(CApiError {
+                msg: "the module has no cost table".to_string(),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    let metadata = table.metadata();
+    if metadata.len() >= buffer_len as usize {
+        update_last_error(CApiError {
+            msg: "buffer too small to hold the cost table metadata".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let buffer = slice::from_raw_parts_mut(buffer as *mut u8, buffer_len as usize);
+    buffer[..metadata.len()].copy_from_slice(metadata.as_bytes());
+    buffer[metadata.len()] = 0;
+    if !written.is_null() {
+        *written = metadata.len() as u32;
+    }
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Stores the 32-byte fingerprint of the module into `out`.
+///
+/// The fingerprint is a hash of the bytes the module was compiled from and of the cost table it
+/// is metered with, so that modules with equal fingerprints charge the same points. It is only
+/// recorded by `wasmer_compile_with_gas_metering`, `wasmer_compile_with_gas_metering_timeout`,
+/// `wasmer_compile_with_gas_metering_progress`, `wasmer_compile_with_cost_table` and the metered
+/// streaming compiler, which all price every operator at one point unless given a table.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module has no
+/// fingerprint. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an error
+/// message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_module_metered_fingerprint(
+    module: *const wasmer_module_t,
+    out: *mut u8,
+) -> wasmer_result_t {
+    if module.is_null() {
+        update_last_error(CApiError {
+            msg: "module ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if out.is_null() {
+        update_last_error(CApiError {
+            msg: "out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let module = &*(module as *const Module);
+    match embedded_fingerprint(module.info()) {
+        Some(fingerprint) => {
+            slice::from_raw_parts_mut(out, fingerprint.len()).copy_from_slice(&fingerprint);
+            clear_last_error();
+            wasmer_result_t::WASMER_OK
+        }
+        None => {
+            update_last_error(CApiError {
+                msg: "module was not compiled with gas metering".to_string(),
+            });
+            wasmer_result_t::WASMER_ERROR
+        }
+    }
+}
+
+/// Returns the current version of the metering instrumentation, the version of the modules
+/// compiled with gas metering.
+#[no_mangle]
+pub extern "C" fn wasmer_metering_abi_version() -> u32 {
+    metering::METERING_ABI_VERSION
+}
+
+/// Returns the version of the metering instrumentation the module was compiled with, or 0 if
+/// the module was not compiled with gas metering or is null.
+///
+/// The version is recorded by the functions recording a fingerprint, see
+/// `wasmer_module_metered_fingerprint`. A host can refuse modules compiled with another
+/// version than `wasmer_metering_abi_version`.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_module_metering_abi_version(module: *const wasmer_module_t) -> u32 {
+    if module.is_null() {
+        return 0;
+    }
+    let module = &*(module as *const Module);
+    embedded_abi_version(module.info()).unwrap_or(0)
+}
+
+/// Stores into `count_out` the number of checkpoints of the function `func_index` of the module,
+/// counting the imported functions.
+///
+/// A checkpoint is a point where the metered code adds the points of the straight-line code
+/// before it to the points used, so the count is a measure of the overhead of metering the
+/// function. It is recorded by the functions recording a fingerprint, see
+/// `wasmer_module_metered_fingerprint`.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module has no
+/// checkpoint counts or does not define the function, such as an imported function. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_module_function_checkpoint_count(
+    module: *const wasmer_module_t,
+    func_index: u32,
+    count_out: *mut u32,
+) -> wasmer_result_t {
+    if module.is_null() {
+        update_last_error(CApiError {
+            msg: "module ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if count_out.is_null() {
+        update_last_error(CApiError {
+            msg: "count_out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let module = &*(module as *const Module);
+    let counts = match embedded_checkpoint_counts(module.info()) {
+        Some(counts) => counts,
+        None => {
+            update_last_error(CApiError {
+                msg: "module was not compiled with gas metering".to_string(),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    let count = (func_index as usize)
+        .checked_sub(module.info().imported_functions.len())
+        .and_then(|local_index| counts.get(local_index));
+    match count {
+        Some(&count) => {
+            *count_out = count;
+            clear_last_error();
+            wasmer_result_t::WASMER_OK
+        }
+        None => {
+            update_last_error(CApiError {
+                msg: format!("function {} is not defined by the module", func_index),
+            });
+            wasmer_result_t::WASMER_ERROR
+        }
+    }
+}
+
+/// Returns the one-time cost of deploying the module of the given wasm bytes,
+/// `cost_per_code_byte` points for each byte of its code section, or 0 if the bytes are null.
+///
+/// The cost is not charged to any instance, it is meant to be charged by the host when the
+/// module is deployed, before or after compiling it.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_deployment_cost(
+    wasm_bytes: *const u8,
+    wasm_bytes_len: u32,
+    cost_per_code_byte: u64,
+) -> u64 {
+    if wasm_bytes.is_null() {
+        return 0;
+    }
+    let bytes: &[u8] = slice::from_raw_parts(wasm_bytes, wasm_bytes_len as usize);
+    metering::deployment_cost(bytes, cost_per_code_byte)
+}
+
+/// Stores the names and the function indices of the exported functions of the module, in the
+/// order of the module, into the provided arrays of `len` entries.
+///
+/// The function indices count the imported functions, as the function indices reported by
+/// metering do. The names point into the module and are valid as long as it is.
+///
+/// Returns the number of exported functions, which may be more than `len`, in which case only
+/// the first `len` are stored. The arrays may be null if `len` is 0, to get the number of
+/// exported functions first. Returns 0 if the module is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_module_exported_function_names(
+    module: *const wasmer_module_t,
+    names: *mut wasmer_byte_array,
+    func_indices: *mut u32,
+    len: u32,
+) -> u32 {
+    if module.is_null() {
+        return 0;
+    }
+    let module = &*(module as *const Module);
+    let functions: Vec<_> = module
+        .info()
+        .exports
+        .iter()
+        .filter_map(|(name, index)| match *index {
+            ExportIndex::Func(func_index) => Some((name, func_index.index() as u32)),
+            _ => None,
+        })
+        .collect();
+    let stored = functions.len().min(len as usize);
+    if stored > 0 {
+        let names = slice::from_raw_parts_mut(names, stored);
+        let func_indices = slice::from_raw_parts_mut(func_indices, stored);
+        for (i, &(name, func_index)) in functions.iter().take(stored).enumerate() {
+            names[i] = wasmer_byte_array {
+                bytes: name.as_ptr(),
+                bytes_len: name.len() as u32,
+            };
+            func_indices[i] = func_index;
+        }
+    }
+    functions.len() as u32
+}
+
+/// Creates a new cost table where every operator costs `base_cost` points.
+///
+/// The table must be freed with `wasmer_cost_table_destroy`.
+#[no_mangle]
+pub extern "C" fn wasmer_cost_table_new(base_cost: u64) -> *mut wasmer_cost_table_t {
+    Box::into_raw(Box::new(CostTable::new(base_cost))) as *mut wasmer_cost_table_t
+}
+
+/// Parses a cost table from the TOML in `bytes`, see `CostTable::from_toml`.
+///
+/// On success, `*table` is set to the new table, which must be freed with
+/// `wasmer_cost_table_destroy`.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_cost_table_from_toml(
+    bytes: *const u8,
+    bytes_len: u32,
+    table: *mut *mut wasmer_cost_table_t,
+) -> wasmer_result_t {
+    if bytes.is_null() {
+        update_last_error(CApiError {
+            msg: "toml ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if table.is_null() {
+        update_last_error(CApiError {
+            msg: "cost table out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let bytes: &[u8] = slice::from_raw_parts(bytes, bytes_len as usize);
+    let parsed = str::from_utf8(bytes)
+        .map_err(|e| format!("invalid cost table: {}", e))
+        .and_then(CostTable::from_toml);
+    match parsed {
+        Ok(parsed) => {
+            *table = Box::into_raw(Box::new(parsed)) as *mut wasmer_cost_table_t;
+            clear_last_error();
+            wasmer_result_t::WASMER_OK
+        }
+        Err(msg) => {
+            update_last_error(CApiError { msg });
+            wasmer_result_t::WASMER_ERROR
+        }
+    }
+}
+
+/// Sets the cost of the operators of the given opcode index.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_cost_table_set_cost(
+    table: *mut wasmer_cost_table_t,
+    opcode_index: u32,
+    cost: u64,
+) -> wasmer_result_t {
+    if table.is_null() {
+        update_last_error(CApiError {
+            msg: "cost table ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let kind = match OpcodeKind::from_index(opcode_index as usize) {
+        Some(kind) => kind,
+        None => {
+            update_last_error(CApiError {
+                msg: format!("unknown opcode index {}", opcode_index),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    let table = &mut *(table as *mut CostTable);
+    table.set_cost(kind, cost);
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Sets the metadata of the cost table to the UTF-8 string in `bytes`, see
+/// `CostTable::with_metadata`.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_cost_table_set_metadata(
+    table: *mut wasmer_cost_table_t,
+    bytes: *const u8,
+    bytes_len: u32,
+) -> wasmer_result_t {
+    if table.is_null() {
+        update_last_error(CApiError {
+            msg: "cost table ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if bytes.is_null() {
+        update_last_error(CApiError {
+            msg: "metadata ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let bytes: &[u8] = slice::from_raw_parts(bytes, bytes_len as usize);
+    let metadata = match str::from_utf8(bytes) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            update_last_error(CApiError {
+                msg: format!("invalid metadata: {}", e),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    let table = &mut *(table as *mut CostTable);
+    table.set_metadata(metadata.to_string());
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Returns the metadata of the cost table, which is not null-terminated and is valid until the
+/// table is modified or freed.
+///
+/// Returns an empty byte array if the table is null or has no metadata.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_cost_table_metadata(
+    table: *const wasmer_cost_table_t,
+) -> wasmer_byte_array {
+    if table.is_null() {
+        return wasmer_byte_array {
+            bytes: ptr::null(),
+            bytes_len: 0,
+        };
+    }
+    let metadata = (&*(table as *const CostTable)).metadata();
+    wasmer_byte_array {
+        bytes: metadata.as_ptr(),
+        bytes_len: metadata.len() as u32,
+    }
+}
+
+/// Sets `*cost_out` to the summed cost under the table of the operators encoded in
+/// `opcode_bytes`, as in a function body without its local declarations.
+///
+/// The operators are decoded, not validated, so the sequence need not be a whole body.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the bytes are not a
+/// sequence of operators. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get
+/// an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_price_opcodes(
+    table: *const wasmer_cost_table_t,
+    opcode_bytes: *const u8,
+    opcode_bytes_len: u32,
+    cost_out: *mut u64,
+) -> wasmer_result_t {
+    if table.is_null() {
+        update_last_error(CApiError {
+            msg: "cost table ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if opcode_bytes.is_null() {
+        update_last_error(CApiError {
+            msg: "opcode bytes ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if cost_out.is_null() {
+        update_last_error(CApiError {
+            msg: "cost out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let table = &*(table as *const CostTable);
+    let bytes: &[u8] = slice::from_raw_parts(opcode_bytes, opcode_bytes_len as usize);
+    match table.operators_cost(bytes) {
+        Ok(cost) => {
+            *cost_out = cost;
+            clear_last_error();
+            wasmer_result_t::WASMER_OK
+        }
+        Err(msg) => {
+            update_last_error(CApiError { msg });
+            wasmer_result_t::WASMER_ERROR
+        }
+    }
+}
+
+/// Stores the name of the operators of the given opcode index, such as `i32.add`, into the
+/// provided buffer, followed by a trailing null.
+///
+/// The opcode indices are the ones of `wasmer_cost_table_set_cost`. On success, `*written` is
+/// set to the length of the name, without the trailing null.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the buffer is too small
+/// to hold the name and the trailing null. Use `wasmer_last_error_length` and
+/// `wasmer_last_error_message` to get an error message.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_opcode_name(
+    opcode_index: u32,
+    buffer: *mut c_char,
+    buffer_len: u32,
+    written: *mut u32,
+) -> wasmer_result_t {
+    if buffer.is_null() {
+        update_last_error(CApiError {
+            msg: "buffer ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let name = match OpcodeKind::from_index(opcode_index as usize) {
+        Some(kind) => kind.name(),
+        None => {
+            update_last_error(CApiError {
+                msg: format!("unknown opcode index {}", opcode_index),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    if name.len() >= buffer_len as usize {
+        update_last_error(CApiError {
+            msg: format!(
+                "buffer too small to hold the name of opcode {}",
+                opcode_index
+            ),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let buffer = slice::from_raw_parts_mut(buffer as *mut u8, buffer_len as usize);
+    buffer[..name.len()].copy_from_slice(name.as_bytes());
+    buffer[name.len()] = 0;
+    if !written.is_null() {
+        *written = name.len() as u32;
+    }
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Frees memory for the given cost table.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub extern "C" fn wasmer_cost_table_destroy(table: *mut wasmer_cost_table_t) {
+    if !table.is_null() {
+        unsafe { Box::from_raw(table as *mut CostTable) };
+    }
+}
+
+/// Returns the number of points used by the instance.
+///
+/// Returns 0 if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_get_points_used(
+    instance: *const wasmer_instance_t,
+) -> u64 {
+    if instance.is_null() {
+        return 0;
+    }
+    let instance = &*(instance as *const Instance);
+    metering::get_points_used(instance)
+}
+
+/// Returns the points by which the last call on the instance exceeding its execution limit
+/// exceeded it.
+///
+/// The overrun is recorded by the metering of the instance, so it does not depend on the message
+/// set with `wasmer_set_gas_exhaustion_message`.
+///
+/// Returns 0 if no call exceeded the limit yet, or if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_get_gas_overrun(
+    instance: *const wasmer_instance_t,
+) -> u64 {
+    if instance.is_null() {
+        return 0;
+    }
+    let instance = &*(instance as *const Instance);
+    metering::get_gas_overrun(instance.context())
+}
+
+/// Sets the number of points used by the instance.
+///
+/// Does nothing if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_set_points_used(
+    instance: *mut wasmer_instance_t,
+    new_gas: u64,
+) {
+    if instance.is_null() {
+        return;
+    }
+    log_metering_event(
+        instance,
+        wasmer_metering_event_t::METERING_EVENT_RESET,
+        new_gas,
+    );
+    let instance = &mut *(instance as *mut Instance);
+    metering::set_points_used(instance, new_gas)
+}
+
+/// Resets every metering counter of the instance at once: the points used, the cost of the last
+/// call, the category and surcharge breakdowns, the instantiation cost, the gas overrun and the
+/// running functions. The execution limit and the cost table are kept.
+///
+/// See `metering::reset_all` for the full list. Does nothing if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_reset_all_metering(instance: *mut wasmer_instance_t) {
+    if instance.is_null() {
+        return;
+    }
+    log_metering_event(instance, wasmer_metering_event_t::METERING_EVENT_RESET, 0);
+    let instance = &mut *(instance as *mut Instance);
+    metering::reset_all(instance.context_mut())
+}
+
+/// Sets the number of points used by the instance, as `wasmer_instance_set_points_used` does.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` if the instance is null. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_set_points_used_checked(
+    instance: *mut wasmer_instance_t,
+    new_gas: u64,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    wasmer_instance_set_points_used(instance, new_gas);
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Writes the number of points used by the instance to `used_out`, and its points limit to
+/// `limit_out`, as `wasmer_instance_get_points_used` and `wasmer_instance_get_execution_limit`
+/// return them.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` if the instance or an output pointer is null, without
+/// writing anything. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an
+/// error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_get_metering_state(
+    instance: *const wasmer_instance_t,
+    used_out: *mut u64,
+    limit_out: *mut u64,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if used_out.is_null() {
+        update_last_error(CApiError {
+            msg: "used_out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if limit_out.is_null() {
+        update_last_error(CApiError {
+            msg: "limit_out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let instance = &*(instance as *const Instance);
+    *used_out = metering::get_points_used(instance);
+    *limit_out = metering::get_execution_limit(instance);
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Returns the number of points used by the last call to an exported function of the instance,
+/// including the calls it made in turn.
+///
+/// Returns 0 if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_get_last_call_cost(
+    instance: *const wasmer_instance_t,
+) -> u64 {
+    if instance.is_null() {
+        return 0;
+    }
+    let instance = &*(instance as *const Instance);
+    metering::last_call_cost(instance.context())
+}
+
+/// Charges the instance for its instantiation, `cost_per_data_byte` points for each byte of the
+/// data segments of its module, `cost_per_element` points for each element of its element
+/// segments and `cost_per_import` points for each of its imports, and returns the points charged.
+///
+/// This is meant to be called right after instantiation. The points are added to the points used
+/// and to `wasmer_instance_get_instantiation_cost`.
+///
+/// Returns 0 if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_charge_instantiation(
+    instance: *mut wasmer_instance_t,
+    cost_per_data_byte: u64,
+    cost_per_element: u64,
+    cost_per_import: u64,
+) -> u64 {
+    if instance.is_null() {
+        return 0;
+    }
+    let instance = &mut *(instance as *mut Instance);
+    metering::charge_data_segments(instance, cost_per_data_byte)
+        .saturating_add(metering::charge_element_segments(
+            instance,
+            cost_per_element,
+        ))
+        .saturating_add(metering::charge_import_validation(
+            instance,
+            cost_per_import,
+        ))
+}
+
+/// Returns the points charged to the instance by `wasmer_instance_charge_instantiation`, which
+/// are part of its points used.
+///
+/// Returns 0 if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_get_instantiation_cost(
+    instance: *const wasmer_instance_t,
+) -> u64 {
+    if instance.is_null() {
+        return 0;
+    }
+    let instance = &*(instance as *const Instance);
+    metering::instantiation_cost(instance)
+}
+
+/// Computes the fee of the points used by the instance at `price_per_point` each, and stores it
+/// as the high and the low 64 bits of a 128-bit integer, so that it does not overflow.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` if a pointer is null. Use `wasmer_last_error_length`
+/// and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_compute_fee(
+    instance: *const wasmer_instance_t,
+    price_per_point: u64,
+    fee_hi_out: *mut u64,
+    fee_lo_out: *mut u64,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if fee_hi_out.is_null() {
+        update_last_error(CApiError {
+            msg: "fee_hi_out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if fee_lo_out.is_null() {
+        update_last_error(CApiError {
+            msg: "fee_lo_out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let instance = &*(instance as *const Instance);
+    let fee = metering::compute_fee(instance.context(), price_per_point);
+    *fee_hi_out = (fee >> 64) as u64;
+    *fee_lo_out = fee as u64;
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Returns the points limit of the instance.
+///
+/// Returns 0 if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_get_execution_limit(
+    instance: *const wasmer_instance_t,
+) -> u64 {
+    if instance.is_null() {
+        return 0;
+    }
+    let instance = &*(instance as *const Instance);
+    metering::get_execution_limit(instance)
+}
+
+/// Sets the points limit of the instance. Calls trap once the points used reach the limit.
+///
+/// Does nothing if the instance is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_set_execution_limit(
+    instance: *mut wasmer_instance_t,
+    limit: u64,
+) {
+    if instance.is_null() {
+        return;
+    }
+    log_metering_event(
+        instance,
+        wasmer_metering_event_t::METERING_EVENT_LIMIT_SET,
+        limit,
+    );
+    let instance = &mut *(instance as *mut Instance);
+    metering::set_execution_limit(instance, limit)
+}
+
+/// Sets the points limit of the instance, as `wasmer_instance_set_execution_limit` does.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` if the instance is null. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_set_execution_limit_checked(
+    instance: *mut wasmer_instance_t,
+    limit: u64,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    wasmer_instance_set_execution_limit(instance, limit);
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Sets the points limit of the instance of `ctx`, and returns the limit it replaces.
+///
+/// This is meant for imported functions running code under a tighter limit, then restoring the
+/// outer limit.
+///
+/// Returns 0 and does nothing if the context is null.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_context_swap_execution_limit(
+    ctx: *mut wasmer_instance_context_t,
+    new_limit: u64,
+) -> u64 {
+    if ctx.is_null() {
+        return 0;
+    }
+    let ctx = &mut *(ctx as *mut Ctx);
+    metering::swap_limit(ctx, new_limit)
+}
+
+/// Sets the points limit of the instance to a time budget of `millis` milliseconds, at a rate of
+/// `points_per_ms` points per millisecond, see `metering::set_time_limit`.
+///
+/// The limit is approximate, as points price operators rather than time.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` if the instance is null. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_set_time_limit(
+    instance: *mut wasmer_instance_t,
+    millis: u64,
+    points_per_ms: u64,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let instance_ref = &mut *(instance as *mut Instance);
+    metering::set_time_limit(instance_ref.context_mut(), millis, points_per_ms);
+    log_metering_event(
+        instance,
+        wasmer_metering_event_t::METERING_EVENT_LIMIT_SET,
+        metering::get_execution_limit(instance_ref),
+    );
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Calls the exported function `name` of the instance with the provided parameters and no
+/// execution limit, and sets `*gas_out` to the points the call used.
+///
+/// This is meant to learn the cost of a call before charging for it. The points used and the
+/// limit of the instance are restored afterwards, but the call is otherwise a real call: its
+/// writes to memories, tables and globals happen, and its imported functions run. Calling the
+/// function again on the same state uses the same points, provided it is deterministic. The
+/// results of the call are discarded, and `wasmer_instance_get_last_call_cost` is not meaningful
+/// until the next call.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the call traps. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_simulate_gas(
+    instance: *mut wasmer_instance_t,
+    name: *const c_char,
+    params: *const wasmer_value_t,
+    params_len: u32,
+    gas_out: *mut u64,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if name.is_null() {
+        update_last_error(CApiError {
+            msg: "name ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if params.is_null() {
+        update_last_error(CApiError {
+            msg: "params ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if gas_out.is_null() {
+        update_last_error(CApiError {
+            msg: "gas_out ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let name = match CStr::from_ptr(name).to_str() {
+        Ok(name) => name,
+        Err(e) => {
+            update_last_error(CApiError {
+                msg: format!("invalid function name: {}", e),
+            });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    let params: &[wasmer_value_t] = slice::from_raw_parts(params, params_len as usize);
+    let params: Vec<Value> = params.iter().cloned().map(|x| x.into()).collect();
+
+    let instance = &mut *(instance as *mut Instance);
+    let (used, limit) = (
+        metering::get_points_used(instance),
+        metering::get_execution_limit(instance),
+    );
+    metering::set_points_used(instance, 0);
+    metering::set_execution_limit(instance, u64::max_value());
+    let result = instance.call(name, &params);
+    let gas = metering::get_points_used(instance);
+    metering::set_points_used(instance, used);
+    metering::set_execution_limit(instance, limit);
+
+    match result {
+        Ok(_) => {
+            *gas_out = gas;
+            clear_last_error();
+            wasmer_result_t::WASMER_OK
+        }
+        Err(err) => {
+            update_last_call_error(err);
+            wasmer_result_t::WASMER_ERROR
+        }
+    }
+}
+
+/// Zeroes the opcode histogram of the instance, so that it only counts the operators executed
+/// from now on. The histogram is kept by the modules compiled with the opcode profiler.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` if the instance is null. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_reset_opcode_histogram(
+    instance: *mut wasmer_instance_t,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let instance = &mut *(instance as *mut Instance);
+    OpcodeProfiler::reset(instance.context_mut());
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Stores the serialized opcode histogram of the instance into `buffer`, of `buffer_len` bytes,
+/// so that it can be restored with `wasmer_instance_restore_opcode_histogram`. The histogram is
+/// kept by the modules compiled with the opcode profiler.
+///
+/// `*written` is set to the length of the serialized histogram, including when the buffer is
+/// too small to hold it, so that a buffer of the right size can be allocated. `buffer` may be
+/// null if `buffer_len` is 0.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the buffer is too small.
+/// Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_opcode_histogram(
+    instance: *const wasmer_instance_t,
+    buffer: *mut u8,
+    buffer_len: u32,
+    written: *mut u32,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if written.is_null() {
+        update_last_error(CApiError {
+            msg: "written ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let instance = &*(instance as *const Instance);
+    let bytes = OpcodeProfiler::serialize(&OpcodeProfiler::histogram(instance.context()));
+    *written = bytes.len() as u32;
+    if bytes.len() > buffer_len as usize || buffer.is_null() {
+        update_last_error(CApiError {
+            msg: "buffer too small to hold the opcode histogram".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    slice::from_raw_parts_mut(buffer, bytes.len()).copy_from_slice(&bytes);
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Replaces the opcode histogram of the instance with the one serialized in `bytes`, of
+/// `bytes_len` bytes, by `wasmer_instance_opcode_histogram`, so that the operators executed from
+/// now on are added to it.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the bytes are not a
+/// serialized histogram. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an
+/// error message.
+#[allow(clippy::cast_ptr_alignment)]
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_instance_restore_opcode_histogram(
+    instance: *mut wasmer_instance_t,
+    bytes: *const u8,
+    bytes_len: u32,
+) -> wasmer_result_t {
+    if instance.is_null() {
+        update_last_error(CApiError {
+            msg: "instance ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    if bytes.is_null() {
+        update_last_error(CApiError {
+            msg: "bytes ptr is null".to_string(),
+        });
+        return wasmer_result_t::WASMER_ERROR;
+    }
+    let bytes: &[u8] = slice::from_raw_parts(bytes, bytes_len as usize);
+    let histogram = match OpcodeProfiler::deserialize(bytes) {
+        Ok(histogram) => histogram,
+        Err(msg) => {
+            update_last_error(CApiError { msg });
+            return wasmer_result_t::WASMER_ERROR;
+        }
+    };
+    let instance = &mut *(instance as *mut Instance);
+    OpcodeProfiler::restore(instance.context_mut(), histogram);
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Sets the error message of the calls that exceed the execution limit of their instance, for
+/// all the instances of the process.
+///
+/// The message is copied. Passing a null pointer restores the default message.
+///
+/// Returns `wasmer_result_t::WASMER_OK` upon success.
+///
+/// Returns `wasmer_result_t::WASMER_ERROR` if the message is not valid UTF-8. Use
+/// `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+#[no_mangle]
+pub unsafe extern "C" fn wasmer_set_gas_exhaustion_message(
+    bytes: *const u8,
+    bytes_len: u32,
+) -> wasmer_result_t {
+    let message = if bytes.is_null() {
+        None
+    } else {
+        let bytes: &[u8] = slice::from_raw_parts(bytes, bytes_len as usize);
+        match str::from_utf8(bytes) {
+            Ok(message) => Some(message.to_string()),
+            Err(e) => {
+                update_last_error(CApiError {
+                    msg: format!("invalid gas exhaustion message: {}", e),
+                });
+                return wasmer_result_t::WASMER_ERROR;
+            }
+        }
+    };
+    *GAS_EXHAUSTION_MESSAGE.write().unwrap() = message;
+    clear_last_error();
+    wasmer_result_t::WASMER_OK
+}
+
+/// Sets the callback told of the metering events of all the instances of the process, or
+/// removes it if `callback` is null. No callback is set by default.
+///
+/// `callback` is given the kind of the event, the instance, a payload whose meaning depends on
+/// the kind, see `wasmer_metering_event_t`, and `user_data`. It is called on the thread causing
+/// the event, before the function causing it returns: the calls with `wasmer_instance_call` and
+/// `wasmer_export_func_call`, and the setters of the points used and of the limit of an
+/// instance.
+#[no_mangle]
+pub extern "C" fn wasmer_set_metering_log_callback(
+    callback: Option<
+        extern "C" fn(
+            event: wasmer_metering_event_t,
+            instance: *const wasmer_instance_t,
+            payload: u64,
+            user_data: *mut c_void,
+        ),
+    >,
+    user_data: *mut c_void,
+) {
+    let mut log_callback = METERING_LOG_CALLBACK.write().unwrap();
+    *log_callback = callback.map(|callback| MeteringLogCallback {
+        callback,
+        user_data: user_data as usize,
+    });
+    METERING_LOG_ENABLED.store(log_callback.is_some(), Ordering::SeqCst);
+}
+
+/// Tells the callback set with `wasmer_set_metering_log_callback` of an event, if any is set.
+fn log_metering_event(
+    instance: *const wasmer_instance_t,
+    event: wasmer_metering_event_t,
+    payload: u64,
+) {
+    if !METERING_LOG_ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    // The lock is released before the callback runs, so that it may replace itself.
+    let log_callback = *METERING_LOG_CALLBACK.read().unwrap();
+    if let Some(log_callback) = log_callback {
+        (log_callback.callback)(
+            event,
+            instance,
+            payload,
+            log_callback.user_data as *mut c_void,
+        );
+    }
+}
+
+/// Returns the points used by the instance before a call, for `log_call`, or `None` if no
+/// callback is set with `wasmer_set_metering_log_callback`.
+pub(crate) unsafe fn points_before_call(instance: *const wasmer_instance_t) -> Option<u64> {
+    if !METERING_LOG_ENABLED.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(wasmer_instance_get_points_used(instance))
+}
+
+/// Tells the callback set with `wasmer_set_metering_log_callback` of the points charged by a call
+/// with `wasmer_instance_call` or `wasmer_export_func_call`, the instance having used
+/// `points_before` before the call, as returned by `points_before_call`, and whether the call
+/// exceeded the limit.
+pub(crate) unsafe fn log_call(
+    instance: *const wasmer_instance_t,
+    points_before: Option<u64>,
+    result: &Result<Vec<Value>, CallError>,
+) {
+    let points_before = match points_before {
+        Some(points_before) => points_before,
+        None => return,
+    };
+    let points_used = wasmer_instance_get_points_used(instance);
+    log_metering_event(
+        instance,
+        wasmer_metering_event_t::METERING_EVENT_CHARGE,
+        points_used.saturating_sub(points_before),
+    );
+    if let Err(CallError::Runtime(RuntimeError::Error { ref data })) = *result {
+        if let Some(exceeded) = data.downcast_ref::<ExecutionLimitExceededError>() {
+            log_metering_event(
+                instance,
+                wasmer_metering_event_t::METERING_EVENT_TRAP,
+                exceeded.exceeded_by,
+            );
+        }
+    }
+}
+
+/// Records the error of a failed call as the last error. If the call exceeded its execution
+/// limit, the error is the message set with `wasmer_set_gas_exhaustion_message`, or by default
+/// tells by how many points the limit was exceeded.
+pub(crate) fn update_last_call_error(error: CallError) {
+    if let CallError::Runtime(RuntimeError::Error { ref data }) = error {
+        if let Some(exceeded) = data.downcast_ref::<ExecutionLimitExceededError>() {
+            let msg = match *GAS_EXHAUSTION_MESSAGE.read().unwrap() {
+                Some(ref msg) => msg.clone(),
+                None => exceeded.to_string(),
+            };
+            update_last_error(CApiError { msg });
+            return;
+        }
+    }
+    update_last_error(error);
+}
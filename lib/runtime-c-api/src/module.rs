@@ -126,13 +126,16 @@ pub unsafe extern "C" fn wasmer_module_instantiate(
     }
 
     let module = &*(module as *const Module);
-    let new_instance = match module.instantiate(&import_object) {
+    #[allow(unused_mut)]
+    let mut new_instance = match module.instantiate(&import_object) {
         Ok(instance) => instance,
         Err(error) => {
             update_last_error(error);
             return wasmer_result_t::WASMER_ERROR;
         }
     };
+    #[cfg(feature = "metering")]
+    wasmer_middleware_common::metering::apply_default_limit(&mut new_instance);
 
     *instance = Box::into_raw(Box::new(new_instance)) as *mut wasmer_instance_t;
     wasmer_result_t::WASMER_OK
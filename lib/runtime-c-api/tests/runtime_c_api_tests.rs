@@ -4,7 +4,12 @@ use std::process::Command;
 fn test_c_api() {
     let project_tests_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests");
 
-    run_command("cmake", project_tests_dir, vec!["."]);
+    let mut cmake_args = vec!["."];
+    if cfg!(feature = "metering") {
+        cmake_args.push("-DWASMER_METERING=ON");
+    }
+
+    run_command("cmake", project_tests_dir, cmake_args);
     run_command("make", project_tests_dir, vec!["-Wdev", "-Werror=dev"]);
     run_command("make", project_tests_dir, vec!["test", "ARGS=\"-V\""]);
 }
@@ -0,0 +1,44 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    uint32_t current_version = wasmer_metering_abi_version();
+    printf("Current version: %u\n", current_version);
+    assert(current_version != 0);
+
+    wasmer_module_t *metered = NULL;
+    assert(wasmer_compile_with_gas_metering(&metered, bytes, len) == WASMER_OK);
+    assert(wasmer_module_metering_abi_version(metered) == current_version);
+
+    wasmer_cost_table_t *table = wasmer_cost_table_new(2);
+    wasmer_module_t *priced = NULL;
+    assert(wasmer_compile_with_cost_table(&priced, bytes, len, table) == WASMER_OK);
+    assert(wasmer_module_metering_abi_version(priced) == current_version);
+    wasmer_cost_table_destroy(table);
+
+    wasmer_module_t *plain = NULL;
+    assert(wasmer_compile(&plain, bytes, len) == WASMER_OK);
+    assert(wasmer_module_metering_abi_version(plain) == 0);
+    assert(wasmer_module_metering_abi_version(NULL) == 0);
+
+    printf("Destroy modules\n");
+    wasmer_module_destroy(metered);
+    wasmer_module_destroy(priced);
+    wasmer_module_destroy(plain);
+    free(bytes);
+    return 0;
+}
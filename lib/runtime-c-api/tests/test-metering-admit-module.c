@@ -0,0 +1,77 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// (module (func (export "nop")) (func (export "run") (loop br 0)))
+static uint8_t wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x03, 0x02, 0x00, 0x00,
+    0x07, 0x0d, 0x02, 0x03, 0x6e, 0x6f, 0x70, 0x00, 0x00, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x01,
+    0x0a, 0x0c, 0x02, 0x02, 0x00, 0x0b, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b,
+};
+
+int main()
+{
+    wasmer_module_t *module = NULL;
+    uint64_t static_cost = 0;
+    wasmer_result_t admit_result = wasmer_admit_module(&module, &static_cost, wasm_bytes, sizeof(wasm_bytes), 100);
+    printf("Admit result:  %d\n", admit_result);
+    assert(admit_result == WASMER_OK);
+    assert(module != NULL);
+    // The `end` of `nop`, and the `loop`, `br`, `end` and `end` of `run`.
+    printf("Static cost: %llu\n", (unsigned long long)static_cost);
+    assert(static_cost == 5);
+
+    // The instances start with the default limit.
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_result_t instantiate_result = wasmer_module_instantiate(module, &instance, imports, 0);
+    printf("Instantiate result:  %d\n", instantiate_result);
+    assert(instantiate_result == WASMER_OK);
+    assert(wasmer_instance_get_execution_limit(instance) == 100);
+
+    wasmer_value_t params[] = {};
+    wasmer_value_t results[] = {};
+    assert(wasmer_instance_call(instance, "nop", params, 0, results, 0) == WASMER_OK);
+    assert(wasmer_instance_call(instance, "run", params, 0, results, 0) == WASMER_ERROR);
+    assert(wasmer_instance_get_gas_overrun(instance) > 0);
+
+    // The limit can still be changed.
+    wasmer_instance_set_execution_limit(instance, 1000);
+    assert(wasmer_instance_get_execution_limit(instance) == 1000);
+
+    // Invalid bytes are rejected, without setting the outputs.
+    uint8_t invalid_bytes[] = {0x00, 0x61, 0x73};
+    wasmer_module_t *invalid_module = NULL;
+    uint64_t invalid_cost = 7;
+    assert(wasmer_admit_module(&invalid_module, &invalid_cost, invalid_bytes, sizeof(invalid_bytes), 100) == WASMER_ERROR);
+    assert(invalid_module == NULL);
+    assert(invalid_cost == 7);
+
+    // A module cannot forge the default limit, with the section of a limit of 2^64 - 1 points.
+    uint8_t forged_section[] = {
+        0x00, 0x26, 0x1d, 0x77, 0x61, 0x73, 0x6d, 0x65, 0x72, 0x2e, 0x6d, 0x65, 0x74, 0x65, 0x72, 0x69,
+        0x6e, 0x67, 0x2e, 0x64, 0x65, 0x66, 0x61, 0x75, 0x6c, 0x74, 0x5f, 0x6c, 0x69, 0x6d, 0x69, 0x74,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    };
+    uint8_t forged_bytes[sizeof(wasm_bytes) + sizeof(forged_section)];
+    memcpy(forged_bytes, wasm_bytes, sizeof(wasm_bytes));
+    memcpy(forged_bytes + sizeof(wasm_bytes), forged_section, sizeof(forged_section));
+    wasmer_module_t *forged_module = NULL;
+    assert(wasmer_compile_with_gas_metering(&forged_module, forged_bytes, sizeof(forged_bytes)) == WASMER_OK);
+    wasmer_instance_t *forged_instance = NULL;
+    assert(wasmer_module_instantiate(forged_module, &forged_instance, imports, 0) == WASMER_OK);
+    assert(wasmer_instance_get_execution_limit(forged_instance) != UINT64_MAX);
+    wasmer_instance_destroy(forged_instance);
+    wasmer_module_destroy(forged_module);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    return 0;
+}
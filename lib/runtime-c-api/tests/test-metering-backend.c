@@ -0,0 +1,56 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // The default backend is the first one compiled in, singlepass before LLVM.
+    bool singlepass = wasmer_metering_backend_available(METERING_SINGLEPASS);
+    bool llvm = wasmer_metering_backend_available(METERING_LLVM);
+    printf("Singlepass: %d, LLVM: %d\n", singlepass, llvm);
+    assert(singlepass || llvm);
+    wasmer_metering_backend_t expected = singlepass ? METERING_SINGLEPASS : METERING_LLVM;
+    assert(wasmer_metering_backend() == expected);
+
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_metering_backend_t backends[] = {METERING_SINGLEPASS, METERING_LLVM};
+    for (int i = 0; i < 2; i++)
+    {
+        wasmer_metering_backend_t backend = backends[i];
+        if (wasmer_metering_backend_available(backend))
+        {
+            assert(wasmer_set_metering_backend(backend) == WASMER_OK);
+            assert(wasmer_metering_backend() == backend);
+
+            wasmer_module_t *module = NULL;
+            assert(wasmer_compile_with_gas_metering(&module, bytes, len) == WASMER_OK);
+            wasmer_module_destroy(module);
+        }
+        else
+        {
+            wasmer_metering_backend_t before = wasmer_metering_backend();
+            assert(wasmer_set_metering_backend(backend) == WASMER_ERROR);
+            assert(wasmer_metering_backend() == before);
+
+            int error_len = wasmer_last_error_length();
+            char *error_str = malloc(error_len);
+            wasmer_last_error_message(error_str, error_len);
+            printf("Error str: `%s`\n", error_str);
+            free(error_str);
+        }
+    }
+
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,56 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void assert_last_error_contains(const char *expected)
+{
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(strstr(error_str, expected) != NULL);
+    free(error_str);
+}
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering(&module, bytes, len);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_result_t instantiate_result = wasmer_module_instantiate(module, &instance, imports, 0);
+    printf("Instantiate result:  %d\n", instantiate_result);
+    assert(instantiate_result == WASMER_OK);
+
+    assert(wasmer_instance_set_points_used_checked(NULL, 10) == WASMER_ERROR);
+    assert_last_error_contains("instance ptr is null");
+    assert(wasmer_instance_set_execution_limit_checked(NULL, 10) == WASMER_ERROR);
+    assert_last_error_contains("instance ptr is null");
+
+    assert(wasmer_instance_set_points_used_checked(instance, 10) == WASMER_OK);
+    assert(wasmer_instance_get_points_used(instance) == 10);
+    assert(wasmer_instance_set_execution_limit_checked(instance, 1000) == WASMER_OK);
+    assert(wasmer_instance_get_execution_limit(instance) == 1000);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(bytes);
+    return 0;
+}
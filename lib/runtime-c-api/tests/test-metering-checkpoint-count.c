@@ -0,0 +1,49 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// (module (func (export "nop")) (func (export "run") (loop br 0)))
+static uint8_t wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x03, 0x02, 0x00, 0x00,
+    0x07, 0x0d, 0x02, 0x03, 0x6e, 0x6f, 0x70, 0x00, 0x00, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x01,
+    0x0a, 0x0c, 0x02, 0x02, 0x00, 0x0b, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b,
+};
+
+int main()
+{
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering(&module, wasm_bytes, sizeof(wasm_bytes));
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    // `nop` is charged at its `end`.
+    uint32_t count = 0;
+    assert(wasmer_module_function_checkpoint_count(module, 0, &count) == WASMER_OK);
+    printf("Checkpoints of nop: %u\n", count);
+    assert(count == 1);
+
+    // `run` is charged at its `loop`, its `br` and its two `end`s.
+    assert(wasmer_module_function_checkpoint_count(module, 1, &count) == WASMER_OK);
+    printf("Checkpoints of run: %u\n", count);
+    assert(count == 4);
+
+    assert(wasmer_module_function_checkpoint_count(module, 2, &count) == WASMER_ERROR);
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(strcmp(error_str, "function 2 is not defined by the module") == 0);
+    free(error_str);
+
+    assert(wasmer_module_function_checkpoint_count(NULL, 0, &count) == WASMER_ERROR);
+    assert(wasmer_module_function_checkpoint_count(module, 0, NULL) == WASMER_ERROR);
+
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    return 0;
+}
@@ -0,0 +1,29 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    wasmer_cost_table_t *table = wasmer_cost_table_new(1);
+
+    // An unknown opcode index fails and sets the last error.
+    assert(wasmer_cost_table_set_cost(table, UINT32_MAX, 5) == WASMER_ERROR);
+    int error_len = wasmer_last_error_length();
+    printf("Error len: `%d`\n", error_len);
+    assert(error_len > 0);
+
+    wasmer_clear_last_error();
+    assert(wasmer_last_error_length() == 0);
+
+    // A successful metering call clears the error of a failed one.
+    assert(wasmer_cost_table_set_cost(table, UINT32_MAX, 5) == WASMER_ERROR);
+    assert(wasmer_last_error_length() > 0);
+    assert(wasmer_cost_table_set_cost(table, 0, 5) == WASMER_OK);
+    assert(wasmer_last_error_length() == 0);
+
+    wasmer_cost_table_destroy(table);
+    return 0;
+}
@@ -0,0 +1,53 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_module_t *module = NULL;
+    assert(wasmer_compile_with_gas_metering(&module, bytes, len) == WASMER_OK);
+    assert(wasmer_module_instantiate(module, &instance, imports, 0) == WASMER_OK);
+
+    uint64_t fee_hi = 0;
+    uint64_t fee_lo = 0;
+    wasmer_instance_set_points_used(instance, 300);
+    assert(wasmer_instance_compute_fee(instance, 7, &fee_hi, &fee_lo) == WASMER_OK);
+    assert(fee_hi == 0);
+    assert(fee_lo == 2100);
+
+    // (2^64 - 1)^2 = 2^128 - 2^65 + 1
+    wasmer_instance_set_points_used(instance, UINT64_MAX);
+    assert(wasmer_instance_compute_fee(instance, UINT64_MAX, &fee_hi, &fee_lo) == WASMER_OK);
+    printf("Fee: 0x%016llx%016llx\n", (unsigned long long)fee_hi, (unsigned long long)fee_lo);
+    assert(fee_hi == UINT64_MAX - 1);
+    assert(fee_lo == 1);
+
+    assert(wasmer_instance_compute_fee(instance, 7, &fee_hi, NULL) == WASMER_ERROR);
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(0 == strcmp(error_str, "fee_lo_out ptr is null"));
+    free(error_str);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,67 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_cost_table_t *table = wasmer_cost_table_new(1);
+    wasmer_byte_array metadata = wasmer_cost_table_metadata(table);
+    assert(metadata.bytes_len == 0);
+
+    const char *label = "schedule v2";
+    wasmer_result_t set_result = wasmer_cost_table_set_metadata(table, (const uint8_t *) label, strlen(label));
+    assert(set_result == WASMER_OK);
+    metadata = wasmer_cost_table_metadata(table);
+    assert(metadata.bytes_len == strlen(label));
+    assert(memcmp(metadata.bytes, label, metadata.bytes_len) == 0);
+
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_cost_table(&module, bytes, len, table);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    char buffer[64];
+    uint32_t written = 0;
+    wasmer_result_t metadata_result = wasmer_module_cost_table_metadata(module, buffer, sizeof(buffer), &written);
+    printf("Metadata result:  %d\n", metadata_result);
+    assert(metadata_result == WASMER_OK);
+    printf("Metadata: %s\n", buffer);
+    assert(written == strlen(label));
+    assert(strcmp(buffer, label) == 0);
+
+    // The buffer must also hold the trailing null.
+    metadata_result = wasmer_module_cost_table_metadata(module, buffer, strlen(label), &written);
+    assert(metadata_result == WASMER_ERROR);
+
+    wasmer_module_t *unmetered_module = NULL;
+    compile_result = wasmer_compile(&unmetered_module, bytes, len);
+    assert(compile_result == WASMER_OK);
+    metadata_result = wasmer_module_cost_table_metadata(unmetered_module, buffer, sizeof(buffer), &written);
+    assert(metadata_result == WASMER_ERROR);
+
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(0 == strcmp(error_str, "the module has no cost table"));
+    free(error_str);
+
+    printf("Destroy modules\n");
+    wasmer_module_destroy(module);
+    wasmer_module_destroy(unmetered_module);
+    wasmer_cost_table_destroy(table);
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,90 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// The index of `nop` in the opcode indices used by cost tables.
+#define OPCODE_NOP 1
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_cost_table_t *table = wasmer_cost_table_new(1);
+    wasmer_cost_table_t *same_table = wasmer_cost_table_new(1);
+    wasmer_cost_table_t *other_table = wasmer_cost_table_new(1);
+    wasmer_result_t set_result = wasmer_cost_table_set_cost(other_table, OPCODE_NOP, 2);
+    assert(set_result == WASMER_OK);
+
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_cost_table(&module, bytes, len, table);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_module_t *same_module = NULL;
+    compile_result = wasmer_compile_with_cost_table(&same_module, bytes, len, same_table);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_module_t *other_module = NULL;
+    compile_result = wasmer_compile_with_cost_table(&other_module, bytes, len, other_table);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_module_t *unmetered_module = NULL;
+    compile_result = wasmer_compile(&unmetered_module, bytes, len);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    assert(wasmer_module_cost_tables_equal(module, same_module));
+    assert(!wasmer_module_cost_tables_equal(module, other_module));
+    assert(!wasmer_module_cost_tables_equal(module, unmetered_module));
+    assert(!wasmer_module_cost_tables_equal(unmetered_module, unmetered_module));
+
+    // A module cannot forge the cost table it is compiled with, with the section of a table
+    // where every operator is free.
+    uint8_t forged_section[] = {
+        0x00, 0x28, 0x1a, 0x77, 0x61, 0x73, 0x6d, 0x65, 0x72, 0x2e, 0x6d, 0x65, 0x74, 0x65, 0x72, 0x69,
+        0x6e, 0x67, 0x2e, 0x63, 0x6f, 0x73, 0x74, 0x5f, 0x74, 0x61, 0x62, 0x6c, 0x65, 0x01, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    };
+    uint8_t *forged_bytes = malloc(len + sizeof(forged_section));
+    memcpy(forged_bytes, bytes, len);
+    memcpy(forged_bytes + len, forged_section, sizeof(forged_section));
+    wasmer_module_t *forged_module = NULL;
+    compile_result = wasmer_compile_with_cost_table(&forged_module, forged_bytes, len + sizeof(forged_section), table);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+    assert(wasmer_module_cost_tables_equal(module, forged_module));
+    uint64_t cost = 0;
+    assert(wasmer_module_opcode_cost(forged_module, OPCODE_NOP, &cost) == WASMER_OK);
+    assert(cost == 1);
+    wasmer_module_destroy(forged_module);
+    compile_result = wasmer_compile_with_gas_metering(&forged_module, forged_bytes, len + sizeof(forged_section));
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+    assert(!wasmer_module_cost_tables_equal(module, forged_module));
+    assert(wasmer_module_opcode_cost(forged_module, OPCODE_NOP, &cost) == WASMER_ERROR);
+    wasmer_module_destroy(forged_module);
+    free(forged_bytes);
+
+    printf("Destroy modules\n");
+    wasmer_module_destroy(module);
+    wasmer_module_destroy(same_module);
+    wasmer_module_destroy(other_module);
+    wasmer_module_destroy(unmetered_module);
+    wasmer_cost_table_destroy(table);
+    wasmer_cost_table_destroy(same_table);
+    wasmer_cost_table_destroy(other_table);
+    free(bytes);
+    return 0;
+}
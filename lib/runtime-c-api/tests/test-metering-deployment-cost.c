@@ -0,0 +1,43 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// (module (func (export "run") nop nop nop nop nop br 0))
+static uint8_t small_wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x02, 0x01, 0x00,
+    0x07, 0x07, 0x01, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x00,
+    0x0a, 0x0b, 0x01, 0x09, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x0c, 0x00, 0x0b,
+};
+
+// (module (func (export "run") nop nop nop nop nop br 0) (func nop nop nop nop nop))
+static uint8_t large_wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x03, 0x02, 0x00, 0x00,
+    0x07, 0x07, 0x01, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x00,
+    0x0a, 0x13, 0x02, 0x09, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x0c, 0x00, 0x0b,
+    0x07, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x0b,
+};
+
+int main()
+{
+    // The code section of the small module is 11 bytes long, the one of the large module 19.
+    uint64_t small_cost = wasmer_deployment_cost(small_wasm_bytes, sizeof(small_wasm_bytes), 3);
+    uint64_t large_cost = wasmer_deployment_cost(large_wasm_bytes, sizeof(large_wasm_bytes), 3);
+    printf("Deployment costs: %llu, %llu\n", (unsigned long long)small_cost, (unsigned long long)large_cost);
+    assert(small_cost == 3 * 11);
+    assert(large_cost == 3 * 19);
+
+    // Both modules are valid.
+    wasmer_module_t *module = NULL;
+    assert(wasmer_compile_with_gas_metering(&module, large_wasm_bytes, sizeof(large_wasm_bytes)) == WASMER_OK);
+    wasmer_module_destroy(module);
+
+    assert(wasmer_deployment_cost(NULL, 0, 3) == 0);
+    return 0;
+}
@@ -0,0 +1,72 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// (module (func (export "run") (loop br 0)))
+static uint8_t wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x02, 0x01, 0x00,
+    0x07, 0x07, 0x01, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x00,
+    0x0a, 0x09, 0x01, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b,
+};
+
+// Calls `run` until it runs out of gas, and returns the error message, which must be freed.
+static char *run_out_of_gas(wasmer_instance_t *instance)
+{
+    wasmer_instance_set_points_used(instance, 0);
+    wasmer_value_t params[] = {};
+    wasmer_value_t results[] = {};
+    wasmer_result_t call_result = wasmer_instance_call(instance, "run", params, 0, results, 0);
+    printf("Call result:  %d\n", call_result);
+    assert(call_result == WASMER_ERROR);
+
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    return error_str;
+}
+
+int main()
+{
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering(&module, wasm_bytes, sizeof(wasm_bytes));
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_result_t instantiate_result = wasmer_module_instantiate(module, &instance, imports, 0);
+    printf("Instantiate result:  %d\n", instantiate_result);
+    assert(instantiate_result == WASMER_OK);
+    wasmer_instance_set_execution_limit(instance, 100);
+
+    const char *message = "Out of gas, please top up your account";
+    char *error_str = run_out_of_gas(instance);
+    assert(strcmp(error_str, message) != 0);
+    free(error_str);
+
+    assert(wasmer_set_gas_exhaustion_message((const uint8_t *)message, strlen(message)) == WASMER_OK);
+    error_str = run_out_of_gas(instance);
+    assert(strcmp(error_str, message) == 0);
+    free(error_str);
+
+    // A null message restores the default one.
+    assert(wasmer_set_gas_exhaustion_message(NULL, 0) == WASMER_OK);
+    error_str = run_out_of_gas(instance);
+    assert(strcmp(error_str, message) != 0);
+    free(error_str);
+
+    const uint8_t invalid_utf8[] = {0xff, 0xfe};
+    assert(wasmer_set_gas_exhaustion_message(invalid_utf8, sizeof(invalid_utf8)) == WASMER_ERROR);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    return 0;
+}
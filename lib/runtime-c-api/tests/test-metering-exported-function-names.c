@@ -0,0 +1,52 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // (module
+    //   (import "env" "f" (func))
+    //   (func)
+    //   (func)
+    //   (export "second" (func 2))
+    //   (export "first" (func 1)))
+    uint8_t bytes[] = {
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x02, 0x09, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x01, 0x66, 0x00, 0x00,
+        0x03, 0x03, 0x02, 0x00, 0x00,
+        0x07, 0x12, 0x02,
+        0x06, 0x73, 0x65, 0x63, 0x6f, 0x6e, 0x64, 0x00, 0x02,
+        0x05, 0x66, 0x69, 0x72, 0x73, 0x74, 0x00, 0x01,
+        0x0a, 0x07, 0x02, 0x02, 0x00, 0x0b, 0x02, 0x00, 0x0b};
+
+    wasmer_module_t *module = NULL;
+    assert(wasmer_compile_with_gas_metering(&module, bytes, sizeof(bytes)) == WASMER_OK);
+
+    uint32_t count = wasmer_module_exported_function_names(module, NULL, NULL, 0);
+    printf("Exported functions: %u\n", count);
+    assert(count == 2);
+
+    wasmer_byte_array names[2];
+    uint32_t func_indices[2];
+    assert(wasmer_module_exported_function_names(module, names, func_indices, 2) == 2);
+    assert(names[0].bytes_len == 6);
+    assert(0 == memcmp(names[0].bytes, "second", 6));
+    assert(func_indices[0] == 2);
+    assert(names[1].bytes_len == 5);
+    assert(0 == memcmp(names[1].bytes, "first", 5));
+    assert(func_indices[1] == 1);
+
+    // Only the first entries fit.
+    assert(wasmer_module_exported_function_names(module, names, func_indices, 1) == 2);
+    assert(func_indices[0] == 2);
+
+    assert(wasmer_module_exported_function_names(NULL, names, func_indices, 2) == 0);
+
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    return 0;
+}
@@ -0,0 +1,79 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void fingerprint(wasmer_module_t *module, uint8_t *out)
+{
+    wasmer_result_t result = wasmer_module_metered_fingerprint(module, out);
+    printf("Fingerprint result:  %d\n", result);
+    assert(result == WASMER_OK);
+}
+
+static wasmer_module_t *compile_with_base_cost(uint8_t *bytes, long len, uint64_t base_cost)
+{
+    wasmer_cost_table_t *table = wasmer_cost_table_new(base_cost);
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_cost_table(&module, bytes, len, table);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+    wasmer_cost_table_destroy(table);
+    return module;
+}
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_module_t *cheap = compile_with_base_cost(bytes, len, 1);
+    wasmer_module_t *cheap_again = compile_with_base_cost(bytes, len, 1);
+    wasmer_module_t *expensive = compile_with_base_cost(bytes, len, 2);
+    wasmer_module_t *default_table = NULL;
+    assert(wasmer_compile_with_gas_metering(&default_table, bytes, len) == WASMER_OK);
+
+    uint8_t cheap_fingerprint[32];
+    uint8_t cheap_again_fingerprint[32];
+    uint8_t expensive_fingerprint[32];
+    uint8_t default_table_fingerprint[32];
+    fingerprint(cheap, cheap_fingerprint);
+    fingerprint(cheap_again, cheap_again_fingerprint);
+    fingerprint(expensive, expensive_fingerprint);
+    fingerprint(default_table, default_table_fingerprint);
+
+    // The same bytes and pricing give the same fingerprint, a different table a different one.
+    assert(memcmp(cheap_fingerprint, cheap_again_fingerprint, 32) == 0);
+    assert(memcmp(cheap_fingerprint, expensive_fingerprint, 32) != 0);
+    // Without a table every operator costs one point.
+    assert(memcmp(cheap_fingerprint, default_table_fingerprint, 32) == 0);
+
+    // A module compiled without metering has no fingerprint.
+    wasmer_module_t *unmetered = NULL;
+    assert(wasmer_compile(&unmetered, bytes, len) == WASMER_OK);
+    uint8_t unmetered_fingerprint[32];
+    assert(wasmer_module_metered_fingerprint(unmetered, unmetered_fingerprint) == WASMER_ERROR);
+
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(0 == strcmp(error_str, "module was not compiled with gas metering"));
+    free(error_str);
+
+    printf("Destroy modules\n");
+    wasmer_module_destroy(cheap);
+    wasmer_module_destroy(cheap_again);
+    wasmer_module_destroy(expensive);
+    wasmer_module_destroy(default_table);
+    wasmer_module_destroy(unmetered);
+    free(bytes);
+    return 0;
+}
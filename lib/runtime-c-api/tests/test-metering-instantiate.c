@@ -0,0 +1,57 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_result_t instantiate_result = wasmer_instantiate_with_gas_metering(&instance, bytes, len, imports, 0, 1000);
+    printf("Instantiate result:  %d\n", instantiate_result);
+    assert(instantiate_result == WASMER_OK);
+    assert(wasmer_instance_get_execution_limit(instance) == 1000);
+    assert(wasmer_instance_get_points_used(instance) == 0);
+
+    wasmer_value_t param_one;
+    param_one.tag = WASM_I32;
+    param_one.value.I32 = 7;
+    wasmer_value_t param_two;
+    param_two.tag = WASM_I32;
+    param_two.value.I32 = 8;
+    wasmer_value_t params[] = {param_one, param_two};
+
+    wasmer_value_t result_one;
+    wasmer_value_t results[] = {result_one};
+
+    wasmer_result_t call_result = wasmer_instance_call(instance, "sum", params, 2, results, 1);
+    printf("Call result:  %d\n", call_result);
+    assert(call_result == WASMER_OK);
+    assert(results[0].value.I32 == 15);
+
+    // The call was metered.
+    uint64_t points_used = wasmer_instance_get_points_used(instance);
+    printf("Points used: %llu\n", (unsigned long long)points_used);
+    assert(points_used > 0);
+
+    wasmer_instance_t *invalid = NULL;
+    assert(wasmer_instantiate_with_gas_metering(&invalid, bytes, 4, imports, 0, 1000) == WASMER_ERROR);
+    assert(invalid == NULL);
+    assert(wasmer_last_error_length() > 0);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,80 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// (module (memory 1) (func (export "run")) (data (i32.const 0) "\01\02\03\04"))
+static uint8_t small_wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x02, 0x01, 0x00,
+    0x05, 0x03, 0x01, 0x00, 0x01,
+    0x07, 0x07, 0x01, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x00,
+    0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+    0x0b, 0x0a, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x04, 0x01, 0x02, 0x03, 0x04,
+};
+
+// The same module with 16 bytes of data.
+static uint8_t large_wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x02, 0x01, 0x00,
+    0x05, 0x03, 0x01, 0x00, 0x01,
+    0x07, 0x07, 0x01, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x00,
+    0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+    0x0b, 0x16, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x10,
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10,
+};
+
+static wasmer_instance_t *instantiate(uint8_t *bytes, uint32_t len)
+{
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering(&module, bytes, len);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_result_t instantiate_result = wasmer_module_instantiate(module, &instance, imports, 0);
+    printf("Instantiate result:  %d\n", instantiate_result);
+    assert(instantiate_result == WASMER_OK);
+    wasmer_module_destroy(module);
+    return instance;
+}
+
+int main()
+{
+    wasmer_instance_t *small = instantiate(small_wasm_bytes, sizeof(small_wasm_bytes));
+    wasmer_instance_t *large = instantiate(large_wasm_bytes, sizeof(large_wasm_bytes));
+    assert(wasmer_instance_get_instantiation_cost(small) == 0);
+
+    // Two points per byte of data.
+    assert(wasmer_instance_charge_instantiation(small, 2, 0, 0) == 4 * 2);
+    assert(wasmer_instance_charge_instantiation(large, 2, 0, 0) == 16 * 2);
+    uint64_t small_cost = wasmer_instance_get_instantiation_cost(small);
+    uint64_t large_cost = wasmer_instance_get_instantiation_cost(large);
+    printf("Instantiation costs: %llu, %llu\n", (unsigned long long)small_cost, (unsigned long long)large_cost);
+    assert(small_cost == 4 * 2);
+    assert(large_cost == 16 * 2);
+    assert(wasmer_instance_get_points_used(small) == small_cost);
+
+    // The calls cost the same, whatever the instantiation cost.
+    wasmer_value_t params[] = {};
+    wasmer_value_t results[] = {};
+    wasmer_instance_set_execution_limit(small, 100);
+    wasmer_instance_set_execution_limit(large, 100);
+    assert(wasmer_instance_call(small, "run", params, 0, results, 0) == WASMER_OK);
+    assert(wasmer_instance_call(large, "run", params, 0, results, 0) == WASMER_OK);
+    assert(wasmer_instance_get_last_call_cost(small) == wasmer_instance_get_last_call_cost(large));
+    assert(wasmer_instance_get_points_used(small) - small_cost == wasmer_instance_get_points_used(large) - large_cost);
+    assert(wasmer_instance_get_instantiation_cost(small) == small_cost);
+
+    assert(wasmer_instance_get_instantiation_cost(NULL) == 0);
+
+    printf("Destroy instances\n");
+    wasmer_instance_destroy(small);
+    wasmer_instance_destroy(large);
+    return 0;
+}
@@ -0,0 +1,95 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// (module (func (export "nop")) (func (export "run") (loop br 0)))
+static uint8_t wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x03, 0x02, 0x00, 0x00,
+    0x07, 0x0d, 0x02, 0x03, 0x6e, 0x6f, 0x70, 0x00, 0x00, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x01,
+    0x0a, 0x0c, 0x02, 0x02, 0x00, 0x0b, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b,
+};
+
+#define MAX_EVENTS 16
+
+struct event_log
+{
+    int len;
+    wasmer_metering_event_t events[MAX_EVENTS];
+    uint64_t payloads[MAX_EVENTS];
+};
+
+static void log_event(wasmer_metering_event_t event, const wasmer_instance_t *instance, uint64_t payload, void *user_data)
+{
+    struct event_log *log = (struct event_log *)user_data;
+    printf("Event: %d, payload: %llu\n", event, (unsigned long long)payload);
+    assert(instance != NULL);
+    assert(log->len < MAX_EVENTS);
+    log->events[log->len] = event;
+    log->payloads[log->len] = payload;
+    log->len++;
+}
+
+int main()
+{
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering(&module, wasm_bytes, sizeof(wasm_bytes));
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_result_t instantiate_result = wasmer_module_instantiate(module, &instance, imports, 0);
+    printf("Instantiate result:  %d\n", instantiate_result);
+    assert(instantiate_result == WASMER_OK);
+
+    struct event_log log = {0};
+    wasmer_set_metering_log_callback(log_event, &log);
+
+    wasmer_value_t params[] = {};
+    wasmer_value_t results[] = {};
+    wasmer_instance_set_execution_limit(instance, 100);
+    assert(wasmer_instance_call(instance, "nop", params, 0, results, 0) == WASMER_OK);
+    wasmer_instance_set_points_used(instance, 0);
+    assert(wasmer_instance_call(instance, "run", params, 0, results, 0) == WASMER_ERROR);
+
+    assert(log.len == 5);
+    assert(log.events[0] == METERING_EVENT_LIMIT_SET);
+    assert(log.payloads[0] == 100);
+    // The `end` of `nop` costs a point.
+    assert(log.events[1] == METERING_EVENT_CHARGE);
+    assert(log.payloads[1] == 1);
+    assert(log.events[2] == METERING_EVENT_RESET);
+    assert(log.payloads[2] == 0);
+    assert(log.events[3] == METERING_EVENT_CHARGE);
+    assert(log.payloads[3] == wasmer_instance_get_points_used(instance));
+    assert(log.events[4] == METERING_EVENT_TRAP);
+    assert(log.payloads[4] == wasmer_instance_get_gas_overrun(instance));
+
+    // The calls through the exports are logged too. `nop` is the first export.
+    wasmer_exports_t *exports = NULL;
+    wasmer_instance_exports(instance, &exports);
+    wasmer_export_t *export = wasmer_exports_get(exports, 0);
+    const wasmer_export_func_t *nop = wasmer_export_to_func(export);
+    wasmer_instance_set_points_used(instance, 0);
+    assert(wasmer_export_func_call(nop, params, 0, results, 0) == WASMER_OK);
+    assert(log.len == 7);
+    assert(log.events[6] == METERING_EVENT_CHARGE);
+    assert(log.payloads[6] == 1);
+    wasmer_exports_destroy(exports);
+
+    // Once removed, the callback is not called anymore.
+    wasmer_set_metering_log_callback(NULL, NULL);
+    wasmer_instance_set_points_used(instance, 0);
+    assert(log.len == 7);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    return 0;
+}
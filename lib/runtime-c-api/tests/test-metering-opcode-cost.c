@@ -0,0 +1,59 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// The indices of `nop` and `call` in the opcode indices used by cost tables.
+#define OPCODE_NOP 1
+#define OPCODE_CALL 11
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_cost_table_t *table = wasmer_cost_table_new(1);
+    assert(wasmer_cost_table_set_cost(table, OPCODE_CALL, 50) == WASMER_OK);
+
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_cost_table(&module, bytes, len, table);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    uint64_t cost = 0;
+    assert(wasmer_module_opcode_cost(module, OPCODE_CALL, &cost) == WASMER_OK);
+    printf("Cost of call: %llu\n", (unsigned long long)cost);
+    assert(cost == 50);
+    // The opcodes without an explicit cost cost the base cost.
+    assert(wasmer_module_opcode_cost(module, OPCODE_NOP, &cost) == WASMER_OK);
+    assert(cost == 1);
+    assert(wasmer_module_opcode_cost(module, UINT32_MAX, &cost) == WASMER_ERROR);
+
+    // A module compiled without metering has no cost table.
+    wasmer_module_t *unmetered_module = NULL;
+    compile_result = wasmer_compile(&unmetered_module, bytes, len);
+    assert(compile_result == WASMER_OK);
+    assert(wasmer_module_opcode_cost(unmetered_module, OPCODE_CALL, &cost) == WASMER_ERROR);
+
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(strcmp(error_str, "the module has no cost table") == 0);
+    free(error_str);
+
+    printf("Destroy modules\n");
+    wasmer_module_destroy(module);
+    wasmer_module_destroy(unmetered_module);
+    wasmer_cost_table_destroy(table);
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,67 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_module_t *module = NULL;
+    assert(wasmer_compile_with_gas_metering(&module, bytes, len) == WASMER_OK);
+    assert(wasmer_module_instantiate(module, &instance, imports, 0) == WASMER_OK);
+
+    // Asking with an empty buffer gives the length of the histogram.
+    uint32_t written = 0;
+    assert(wasmer_instance_opcode_histogram(instance, NULL, 0, &written) == WASMER_ERROR);
+    printf("Histogram length: %u\n", written);
+    assert(written > 0);
+
+    uint8_t *histogram = malloc(written);
+    uint32_t histogram_len = written;
+    assert(wasmer_instance_opcode_histogram(instance, histogram, histogram_len, &written) == WASMER_OK);
+    assert(written == histogram_len);
+
+    // The histogram can be restored into another instance.
+    wasmer_instance_t *restored = NULL;
+    assert(wasmer_module_instantiate(module, &restored, imports, 0) == WASMER_OK);
+    assert(wasmer_instance_restore_opcode_histogram(restored, histogram, histogram_len) == WASMER_OK);
+
+    uint8_t *restored_histogram = malloc(histogram_len);
+    assert(wasmer_instance_opcode_histogram(restored, restored_histogram, histogram_len, &written) == WASMER_OK);
+    assert(written == histogram_len);
+    assert(memcmp(histogram, restored_histogram, histogram_len) == 0);
+
+    // A truncated histogram is refused.
+    assert(wasmer_instance_restore_opcode_histogram(restored, histogram, histogram_len - 1) == WASMER_ERROR);
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(0 == strcmp(error_str, "unexpected end of opcode histogram"));
+    free(error_str);
+
+    assert(wasmer_instance_opcode_histogram(NULL, histogram, histogram_len, &written) == WASMER_ERROR);
+    assert(wasmer_instance_restore_opcode_histogram(NULL, histogram, histogram_len) == WASMER_ERROR);
+
+    free(restored_histogram);
+    free(histogram);
+    printf("Destroy instances\n");
+    wasmer_instance_destroy(restored);
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,42 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void assert_opcode_name(uint32_t index, const char *expected)
+{
+    char name[32];
+    uint32_t written = 0;
+    wasmer_result_t result = wasmer_opcode_name(index, name, sizeof(name), &written);
+    printf("Opcode %u: `%s`\n", index, name);
+    assert(result == WASMER_OK);
+    assert(strcmp(name, expected) == 0);
+    assert(written == strlen(expected));
+}
+
+int main()
+{
+    assert_opcode_name(0, "unreachable");
+    assert_opcode_name(1, "nop");
+    assert_opcode_name(15, "local.get");
+    assert_opcode_name(44, "memory.grow");
+    assert_opcode_name(88, "i32.add");
+
+    // The buffer must hold the name and the trailing null.
+    char small[4];
+    uint32_t written = 0;
+    assert(wasmer_opcode_name(0, small, sizeof(small), &written) == WASMER_ERROR);
+    assert(wasmer_opcode_name(1, small, sizeof(small), &written) == WASMER_OK);
+    assert(written == 3);
+
+    assert(wasmer_opcode_name(100000, small, sizeof(small), &written) == WASMER_ERROR);
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(strstr(error_str, "unknown opcode index") != NULL);
+    free(error_str);
+    return 0;
+}
@@ -0,0 +1,42 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// The index of `nop` in the opcode indices used by cost tables.
+#define OPCODE_NOP 1
+
+int main()
+{
+    wasmer_cost_table_t *table = wasmer_cost_table_new(2);
+    assert(wasmer_cost_table_set_cost(table, OPCODE_NOP, 7) == WASMER_OK);
+
+    // i32.const 5, drop, nop, nop, end
+    uint8_t snippet[] = {0x41, 0x05, 0x1a, 0x01, 0x01, 0x0b};
+    uint64_t cost = 0;
+    wasmer_result_t price_result = wasmer_price_opcodes(table, snippet, sizeof(snippet), &cost);
+    printf("Price result:  %d\n", price_result);
+    assert(price_result == WASMER_OK);
+    printf("Cost: %llu\n", (unsigned long long)cost);
+    assert(cost == 2 + 2 + 7 + 7 + 2);
+
+    // An `i32.const` missing its immediate.
+    uint8_t malformed[] = {0x41};
+    price_result = wasmer_price_opcodes(table, malformed, sizeof(malformed), &cost);
+    assert(price_result == WASMER_ERROR);
+
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(error_len > 0);
+    free(error_str);
+
+    price_result = wasmer_price_opcodes(NULL, snippet, sizeof(snippet), &cost);
+    assert(price_result == WASMER_ERROR);
+
+    wasmer_cost_table_destroy(table);
+    return 0;
+}
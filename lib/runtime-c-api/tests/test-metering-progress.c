@@ -0,0 +1,51 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+struct progress
+{
+    uint32_t calls;
+    uint32_t last_current;
+    uint32_t last_total;
+};
+
+static void on_progress(uint32_t current, uint32_t total, void *data)
+{
+    struct progress *progress = data;
+    printf("Function %u of %u instrumented\n", current, total);
+    assert(current == progress->last_current + 1);
+    progress->calls++;
+    progress->last_current = current;
+    progress->last_total = total;
+}
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/exports.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    struct progress progress = {0, 0, 0};
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering_progress(&module, bytes, len, on_progress, &progress);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    // Every function was reported, and the last call reports the total.
+    assert(progress.calls > 1);
+    assert(progress.calls == progress.last_total);
+    assert(progress.last_current == progress.last_total);
+
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(bytes);
+    return 0;
+}
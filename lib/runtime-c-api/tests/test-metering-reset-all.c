@@ -0,0 +1,54 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// (module (func (export "run") nop nop nop nop nop br 0))
+static uint8_t wasm_bytes[] = {
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    0x03, 0x02, 0x01, 0x00,
+    0x07, 0x07, 0x01, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x00,
+    0x0a, 0x0b, 0x01, 0x09, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x0c, 0x00, 0x0b,
+};
+
+int main()
+{
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering(&module, wasm_bytes, sizeof(wasm_bytes));
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_result_t instantiate_result = wasmer_module_instantiate(module, &instance, imports, 0);
+    printf("Instantiate result:  %d\n", instantiate_result);
+    assert(instantiate_result == WASMER_OK);
+
+    wasmer_instance_set_execution_limit(instance, 2);
+    wasmer_instance_set_points_used(instance, 0);
+    wasmer_value_t params[] = {};
+    wasmer_value_t results[] = {};
+    assert(wasmer_instance_call(instance, "run", params, 0, results, 0) == WASMER_ERROR);
+    assert(wasmer_instance_get_points_used(instance) == 6);
+    assert(wasmer_instance_get_gas_overrun(instance) == 4);
+
+    wasmer_instance_reset_all_metering(instance);
+    printf("Points used after reset: %llu\n", (unsigned long long)wasmer_instance_get_points_used(instance));
+    assert(wasmer_instance_get_points_used(instance) == 0);
+    assert(wasmer_instance_get_gas_overrun(instance) == 0);
+    // The limit is kept, so the call exceeds it again.
+    assert(wasmer_instance_get_execution_limit(instance) == 2);
+    assert(wasmer_instance_call(instance, "run", params, 0, results, 0) == WASMER_ERROR);
+    assert(wasmer_instance_get_gas_overrun(instance) == 4);
+
+    wasmer_instance_reset_all_metering(NULL);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    return 0;
+}
@@ -0,0 +1,63 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_module_t *module = NULL;
+    assert(wasmer_compile_with_gas_metering(&module, bytes, len) == WASMER_OK);
+    assert(wasmer_module_instantiate(module, &instance, imports, 0) == WASMER_OK);
+
+    wasmer_instance_set_points_used(instance, 5);
+    wasmer_instance_set_execution_limit(instance, 1000);
+
+    wasmer_value_t param_one;
+    param_one.tag = WASM_I32;
+    param_one.value.I32 = 7;
+    wasmer_value_t param_two;
+    param_two.tag = WASM_I32;
+    param_two.value.I32 = 8;
+    wasmer_value_t params[] = {param_one, param_two};
+
+    uint64_t gas = 0;
+    wasmer_result_t simulate_result = wasmer_simulate_gas(instance, "sum", params, 2, &gas);
+    printf("Simulate result:  %d, gas: %llu\n", simulate_result, (unsigned long long)gas);
+    assert(simulate_result == WASMER_OK);
+    assert(gas > 0);
+
+    // The simulation leaves the metering state as it was.
+    assert(wasmer_instance_get_points_used(instance) == 5);
+    assert(wasmer_instance_get_execution_limit(instance) == 1000);
+
+    // A real call uses the simulated gas.
+    wasmer_instance_set_points_used(instance, 0);
+    wasmer_value_t result_one;
+    wasmer_value_t results[] = {result_one};
+    assert(wasmer_instance_call(instance, "sum", params, 2, results, 1) == WASMER_OK);
+    assert(results[0].value.I32 == 15);
+    assert(wasmer_instance_get_points_used(instance) == gas);
+
+    assert(wasmer_simulate_gas(instance, "missing", params, 2, &gas) == WASMER_ERROR);
+    assert(wasmer_last_error_length() > 0);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(bytes);
+    return 0;
+}
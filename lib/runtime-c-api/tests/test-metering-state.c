@@ -0,0 +1,51 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_module_t *module = NULL;
+    assert(wasmer_compile_with_gas_metering(&module, bytes, len) == WASMER_OK);
+    assert(wasmer_module_instantiate(module, &instance, imports, 0) == WASMER_OK);
+
+    wasmer_instance_set_points_used(instance, 300);
+    wasmer_instance_set_execution_limit(instance, 1000);
+
+    uint64_t used = 0;
+    uint64_t limit = 0;
+    assert(wasmer_instance_get_metering_state(instance, &used, &limit) == WASMER_OK);
+    printf("Used: %llu, limit: %llu\n", (unsigned long long)used, (unsigned long long)limit);
+    assert(used == 300);
+    assert(limit == 1000);
+
+    assert(wasmer_instance_get_metering_state(NULL, &used, &limit) == WASMER_ERROR);
+    assert(wasmer_instance_get_metering_state(instance, &used, NULL) == WASMER_ERROR);
+    assert(wasmer_instance_get_metering_state(instance, NULL, &limit) == WASMER_ERROR);
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(0 == strcmp(error_str, "used_out ptr is null"));
+    free(error_str);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,95 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void assert_last_error_contains(const char *expected)
+{
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(strstr(error_str, expected) != NULL);
+    free(error_str);
+}
+
+// Calls `sum(7, 8)` on an instance of the module, returning the number of points it used.
+static uint64_t call_sum(wasmer_module_t *module)
+{
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_result_t instantiate_result = wasmer_module_instantiate(module, &instance, imports, 0);
+    assert(instantiate_result == WASMER_OK);
+    wasmer_instance_set_execution_limit(instance, 1000);
+
+    wasmer_value_t param_one;
+    param_one.tag = WASM_I32;
+    param_one.value.I32 = 7;
+    wasmer_value_t param_two;
+    param_two.tag = WASM_I32;
+    param_two.value.I32 = 8;
+    wasmer_value_t params[] = {param_one, param_two};
+
+    wasmer_value_t result_one;
+    wasmer_value_t results[] = {result_one};
+
+    wasmer_result_t call_result = wasmer_instance_call(instance, "sum", params, 2, results, 1);
+    assert(call_result == WASMER_OK);
+    assert(results[0].value.I32 == 15);
+
+    uint64_t points_used = wasmer_instance_get_points_used(instance);
+    wasmer_instance_destroy(instance);
+    return points_used;
+}
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_module_t *one_shot = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering(&one_shot, bytes, len);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    wasmer_metered_streaming_compiler_t *compiler = wasmer_metered_streaming_compiler_new();
+    long half = len / 2;
+    assert(wasmer_metered_streaming_compiler_feed(compiler, bytes, half) == WASMER_OK);
+
+    wasmer_module_t *streamed = NULL;
+    assert(wasmer_metered_streaming_compiler_finalize(compiler, &streamed) == WASMER_ERROR);
+    assert_last_error_contains("incomplete module");
+    assert(streamed == NULL);
+
+    // Feed the rest of the module in chunks of 7 bytes.
+    for (long offset = half; offset < len; offset += 7)
+    {
+        long chunk_len = len - offset < 7 ? len - offset : 7;
+        assert(wasmer_metered_streaming_compiler_feed(compiler, bytes + offset, chunk_len) == WASMER_OK);
+    }
+    wasmer_result_t finalize_result = wasmer_metered_streaming_compiler_finalize(compiler, &streamed);
+    printf("Finalize result:  %d\n", finalize_result);
+    assert(finalize_result == WASMER_OK);
+
+    uint64_t one_shot_points = call_sum(one_shot);
+    uint64_t streamed_points = call_sum(streamed);
+    printf("Points used: %llu and %llu\n", (unsigned long long)one_shot_points, (unsigned long long)streamed_points);
+    assert(one_shot_points > 0);
+    assert(one_shot_points == streamed_points);
+
+    printf("Destroy compiler\n");
+    wasmer_metered_streaming_compiler_destroy(compiler);
+    printf("Destroy modules\n");
+    wasmer_module_destroy(one_shot);
+    wasmer_module_destroy(streamed);
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,43 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_module_t *module = NULL;
+    assert(wasmer_compile_with_gas_metering(&module, bytes, len) == WASMER_OK);
+    assert(wasmer_module_instantiate(module, &instance, imports, 0) == WASMER_OK);
+    wasmer_instance_set_execution_limit(instance, 1000);
+
+    wasmer_instance_context_t *ctx = (wasmer_instance_context_t *)wasmer_instance_context_get(instance);
+    uint64_t old_limit = wasmer_instance_context_swap_execution_limit(ctx, 200);
+    printf("Old limit: %llu\n", (unsigned long long)old_limit);
+    assert(old_limit == 1000);
+    assert(wasmer_instance_get_execution_limit(instance) == 200);
+
+    assert(wasmer_instance_context_swap_execution_limit(ctx, old_limit) == 200);
+    assert(wasmer_instance_get_execution_limit(instance) == 1000);
+
+    assert(wasmer_instance_context_swap_execution_limit(NULL, 200) == 0);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(bytes);
+    return 0;
+}
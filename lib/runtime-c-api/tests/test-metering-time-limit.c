@@ -0,0 +1,50 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main()
+{
+    // Read the wasm file bytes
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    uint8_t *bytes = malloc(len);
+    fseek(file, 0, SEEK_SET);
+    fread(bytes, 1, len, file);
+    fclose(file);
+
+    wasmer_import_t imports[] = {};
+    wasmer_instance_t *instance = NULL;
+    wasmer_module_t *module = NULL;
+    assert(wasmer_compile_with_gas_metering(&module, bytes, len) == WASMER_OK);
+    assert(wasmer_module_instantiate(module, &instance, imports, 0) == WASMER_OK);
+
+    wasmer_result_t limit_result = wasmer_instance_set_time_limit(instance, 5, 100);
+    printf("Time limit result:  %d\n", limit_result);
+    assert(limit_result == WASMER_OK);
+    printf("Execution limit: %llu\n", (unsigned long long)wasmer_instance_get_execution_limit(instance));
+    assert(wasmer_instance_get_execution_limit(instance) == 500);
+
+    assert(wasmer_instance_set_time_limit(instance, UINT64_MAX, 2) == WASMER_OK);
+    assert(wasmer_instance_get_execution_limit(instance) == UINT64_MAX);
+
+    limit_result = wasmer_instance_set_time_limit(NULL, 5, 100);
+    assert(limit_result == WASMER_ERROR);
+
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(0 == strcmp(error_str, "instance ptr is null"));
+    free(error_str);
+
+    printf("Destroy instance\n");
+    wasmer_instance_destroy(instance);
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(bytes);
+    return 0;
+}
@@ -0,0 +1,108 @@
+#include <stdio.h>
+#include "../wasmer.h"
+#include <assert.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define FUNCTION_COUNT 10000
+#define FUNCTION_NOPS 1000
+
+static uint8_t *write_leb128(uint8_t *cursor, uint32_t value)
+{
+    do {
+        uint8_t byte = value & 0x7f;
+        value >>= 7;
+        if (value != 0) {
+            byte |= 0x80;
+        }
+        *cursor++ = byte;
+    } while (value != 0);
+    return cursor;
+}
+
+// Builds a module with `FUNCTION_COUNT` functions of `FUNCTION_NOPS` nops each,
+// which takes far longer than a millisecond to compile.
+static uint8_t *build_large_module(uint32_t *len)
+{
+    uint32_t body_len = 1 + FUNCTION_NOPS + 1;
+    uint32_t code_len = 5 + FUNCTION_COUNT * (5 + body_len);
+    uint8_t *bytes = malloc(64 + FUNCTION_COUNT + code_len);
+    uint8_t *cursor = bytes;
+
+    // Magic and version.
+    memcpy(cursor, "\0asm\1\0\0\0", 8);
+    cursor += 8;
+
+    // Type section: one `() -> ()` signature.
+    uint8_t type_section[] = {0x01, 0x04, 0x01, 0x60, 0x00, 0x00};
+    memcpy(cursor, type_section, sizeof(type_section));
+    cursor += sizeof(type_section);
+
+    // Function section: every function has the signature 0.
+    uint8_t function_entries[8];
+    uint32_t function_entries_len = write_leb128(function_entries, FUNCTION_COUNT) - function_entries;
+    *cursor++ = 0x03;
+    cursor = write_leb128(cursor, function_entries_len + FUNCTION_COUNT);
+    memcpy(cursor, function_entries, function_entries_len);
+    cursor += function_entries_len;
+    memset(cursor, 0x00, FUNCTION_COUNT);
+    cursor += FUNCTION_COUNT;
+
+    // Code section: no locals, `FUNCTION_NOPS` nops, then `end`.
+    uint8_t body_header[8];
+    uint32_t body_header_len = write_leb128(body_header, body_len) - body_header;
+    *cursor++ = 0x0a;
+    cursor = write_leb128(cursor, function_entries_len + FUNCTION_COUNT * (body_header_len + body_len));
+    memcpy(cursor, function_entries, function_entries_len);
+    cursor += function_entries_len;
+    for (int i = 0; i < FUNCTION_COUNT; ++i) {
+        memcpy(cursor, body_header, body_header_len);
+        cursor += body_header_len;
+        *cursor++ = 0x00;
+        memset(cursor, 0x01, FUNCTION_NOPS);
+        cursor += FUNCTION_NOPS;
+        *cursor++ = 0x0b;
+    }
+
+    *len = cursor - bytes;
+    return bytes;
+}
+
+int main()
+{
+    uint32_t len;
+    uint8_t *bytes = build_large_module(&len);
+
+    wasmer_module_t *module = NULL;
+    wasmer_result_t compile_result = wasmer_compile_with_gas_metering_timeout(&module, bytes, len, 1);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_ERROR);
+    assert(module == NULL);
+
+    int error_len = wasmer_last_error_length();
+    char *error_str = malloc(error_len);
+    wasmer_last_error_message(error_str, error_len);
+    printf("Error str: `%s`\n", error_str);
+    assert(strstr(error_str, "timed out") != NULL);
+    free(error_str);
+    free(bytes);
+
+    // A small module compiles well within the deadline.
+    FILE *file = fopen("assets/sum.wasm", "r");
+    fseek(file, 0, SEEK_END);
+    long sum_len = ftell(file);
+    uint8_t *sum_bytes = malloc(sum_len);
+    fseek(file, 0, SEEK_SET);
+    fread(sum_bytes, 1, sum_len, file);
+    fclose(file);
+
+    compile_result = wasmer_compile_with_gas_metering_timeout(&module, sum_bytes, sum_len, 10000);
+    printf("Compile result:  %d\n", compile_result);
+    assert(compile_result == WASMER_OK);
+
+    printf("Destroy module\n");
+    wasmer_module_destroy(module);
+    free(sum_bytes);
+    return 0;
+}
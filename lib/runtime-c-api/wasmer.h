@@ -36,6 +36,45 @@ enum wasmer_import_export_kind {
 };
 typedef uint32_t wasmer_import_export_kind;
 
+#if defined(WASMER_METERING)
+/**
+ * A backend compiling metered modules.
+ */
+enum wasmer_metering_backend_t {
+  METERING_SINGLEPASS = 0,
+  METERING_LLVM = 1,
+};
+typedef uint32_t wasmer_metering_backend_t;
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * The kind of an event told to the callback set with `wasmer_set_metering_log_callback`, with
+ * the meaning of the payload it is given.
+ */
+enum wasmer_metering_event_t {
+  /**
+   * A call with `wasmer_instance_call` or `wasmer_export_func_call` returned, having charged
+   * the payload in points.
+   */
+  METERING_EVENT_CHARGE = 0,
+  /**
+   * The points limit of the instance was set to the payload.
+   */
+  METERING_EVENT_LIMIT_SET = 1,
+  /**
+   * The points used by the instance were set to the payload.
+   */
+  METERING_EVENT_RESET = 2,
+  /**
+   * A call with `wasmer_instance_call` or `wasmer_export_func_call` exceeded the limit of the
+   * instance by the payload.
+   */
+  METERING_EVENT_TRAP = 3,
+};
+typedef uint32_t wasmer_metering_event_t;
+#endif
+
 typedef enum {
   WASMER_OK = 1,
   WASMER_ERROR = 2,
@@ -53,18 +92,24 @@ typedef struct {
 
 } wasmer_module_t;
 
-/**
- * Opaque pointer to `NamedExportDescriptor`.
- */
+#if defined(WASMER_METERING)
 typedef struct {
 
-} wasmer_export_descriptor_t;
+} wasmer_cost_table_t;
+#endif
 
 typedef struct {
   const uint8_t *bytes;
   uint32_t bytes_len;
 } wasmer_byte_array;
 
+/**
+ * Opaque pointer to `NamedExportDescriptor`.
+ */
+typedef struct {
+
+} wasmer_export_descriptor_t;
+
 /**
  * Opaque pointer to `NamedExportDescriptors`.
  */
@@ -177,6 +222,12 @@ typedef struct {
   wasmer_limit_option_t max;
 } wasmer_limits_t;
 
+#if defined(WASMER_METERING)
+typedef struct {
+
+} wasmer_metered_streaming_compiler_t;
+#endif
+
 typedef struct {
 
 } wasmer_serialized_module_t;
@@ -214,6 +265,43 @@ typedef struct {
   wasmer_byte_array host_file_path;
 } wasmer_wasi_map_dir_entry_t;
 
+#if defined(WASMER_METERING)
+/**
+ * Creates a new Module from the given wasm bytes, with gas metering, and sets `*static_cost` to
+ * its static cost, for admitting a module in one call.
+ *
+ * The module is compiled as `wasmer_compile_with_gas_metering` does, and records
+ * `default_limit` as the points limit its instances start with, which
+ * `wasmer_instance_set_execution_limit` can change afterwards. The static cost is the sum of the
+ * points charged for the operators of its functions by that metering, as if each ran once.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, in which case neither output is set. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_admit_module(wasmer_module_t **module,
+                                    uint64_t *static_cost,
+                                    const uint8_t *wasm_bytes,
+                                    uint32_t wasm_bytes_len,
+                                    uint64_t default_limit);
+#endif
+
+/**
+ * Clears the last error, so that `wasmer_last_error_length` returns 0 until another error
+ * occurs.
+ *
+ * The metering functions clear the last error when they succeed, so that an error they
+ * reported before is not read back after a later call.
+ *
+ * # Example
+ *
+ * ```c
+ * wasmer_clear_last_error();
+ * ```
+ */
+void wasmer_clear_last_error(void);
+
 /**
  * Creates a new Module from the given wasm bytes.
  *
@@ -226,6 +314,168 @@ wasmer_result_t wasmer_compile(wasmer_module_t **module,
                                uint8_t *wasm_bytes,
                                uint32_t wasm_bytes_len);
 
+#if defined(WASMER_METERING)
+/**
+ * Creates a new Module from the given wasm bytes, with gas metering priced by `table`.
+ *
+ * The table is embedded in the module, see `wasmer_module_cost_tables_equal`. The points limit
+ * of the instances of the module must be set with `wasmer_instance_set_execution_limit` before
+ * calling them.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_compile_with_cost_table(wasmer_module_t **module,
+                                               uint8_t *wasm_bytes,
+                                               uint32_t wasm_bytes_len,
+                                               const wasmer_cost_table_t *table);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Creates a new Module from the given wasm bytes, with gas metering.
+ *
+ * The points limit of the instances of the module is not compiled in, it must be set with
+ * `wasmer_instance_set_execution_limit` before calling them.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_compile_with_gas_metering(wasmer_module_t **module,
+                                                 uint8_t *wasm_bytes,
+                                                 uint32_t wasm_bytes_len);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Creates a new Module from the given wasm bytes, with gas metering, calling `callback` as each
+ * function is compiled.
+ *
+ * `callback` is given the number of the function among the functions defined by the module,
+ * counting from one, their total and `data`. It is called on the thread compiling the module,
+ * before `wasmer_compile_with_gas_metering_progress` returns.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_compile_with_gas_metering_progress(wasmer_module_t **module,
+                                                          uint8_t *wasm_bytes,
+                                                          uint32_t wasm_bytes_len,
+                                                          void (*callback)(uint32_t current, uint32_t total, void *data),
+                                                          void *data);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Creates a new Module from the given wasm bytes, with gas metering, giving up if the
+ * compilation takes longer than `timeout_millis` milliseconds.
+ *
+ * The deadline is checked as the checkpoints of each function are counted for
+ * `wasmer_module_function_checkpoint_count`, then as each function is compiled, so the call may
+ * return slightly after it.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including a timeout. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_compile_with_gas_metering_timeout(wasmer_module_t **module,
+                                                         uint8_t *wasm_bytes,
+                                                         uint32_t wasm_bytes_len,
+                                                         uint64_t timeout_millis);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Frees memory for the given cost table.
+ */
+void wasmer_cost_table_destroy(wasmer_cost_table_t *table);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Parses a cost table from the TOML in `bytes`, see `CostTable::from_toml`.
+ *
+ * On success, `*table` is set to the new table, which must be freed with
+ * `wasmer_cost_table_destroy`.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_cost_table_from_toml(const uint8_t *bytes,
+                                            uint32_t bytes_len,
+                                            wasmer_cost_table_t **table);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the metadata of the cost table, which is not null-terminated and is valid until the
+ * table is modified or freed.
+ *
+ * Returns an empty byte array if the table is null or has no metadata.
+ */
+wasmer_byte_array wasmer_cost_table_metadata(const wasmer_cost_table_t *table);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Creates a new cost table where every operator costs `base_cost` points.
+ *
+ * The table must be freed with `wasmer_cost_table_destroy`.
+ */
+wasmer_cost_table_t *wasmer_cost_table_new(uint64_t base_cost);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the cost of the operators of the given opcode index.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_cost_table_set_cost(wasmer_cost_table_t *table,
+                                           uint32_t opcode_index,
+                                           uint64_t cost);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the metadata of the cost table to the UTF-8 string in `bytes`, see
+ * `CostTable::with_metadata`.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_cost_table_set_metadata(wasmer_cost_table_t *table,
+                                               const uint8_t *bytes,
+                                               uint32_t bytes_len);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the one-time cost of deploying the module of the given wasm bytes,
+ * `cost_per_code_byte` points for each byte of its code section, or 0 if the bytes are null.
+ *
+ * The cost is not charged to any instance, it is meant to be charged by the host when the
+ * module is deployed, before or after compiling it.
+ */
+uint64_t wasmer_deployment_cost(const uint8_t *wasm_bytes,
+                                uint32_t wasm_bytes_len,
+                                uint64_t cost_per_code_byte);
+#endif
+
 /**
  * Gets export descriptor kind
  */
@@ -571,6 +821,39 @@ wasmer_result_t wasmer_instance_call(wasmer_instance_t *instance,
                                      wasmer_value_t *results,
                                      uint32_t results_len);
 
+#if defined(WASMER_METERING)
+/**
+ * Charges the instance for its instantiation, `cost_per_data_byte` points for each byte of the
+ * data segments of its module, `cost_per_element` points for each element of its element
+ * segments and `cost_per_import` points for each of its imports, and returns the points charged.
+ *
+ * This is meant to be called right after instantiation. The points are added to the points used
+ * and to `wasmer_instance_get_instantiation_cost`.
+ *
+ * Returns 0 if the instance is null.
+ */
+uint64_t wasmer_instance_charge_instantiation(wasmer_instance_t *instance,
+                                              uint64_t cost_per_data_byte,
+                                              uint64_t cost_per_element,
+                                              uint64_t cost_per_import);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Computes the fee of the points used by the instance at `price_per_point` each, and stores it
+ * as the high and the low 64 bits of a 128-bit integer, so that it does not overflow.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` if a pointer is null. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_instance_compute_fee(const wasmer_instance_t *instance,
+                                            uint64_t price_per_point,
+                                            uint64_t *fee_hi_out,
+                                            uint64_t *fee_lo_out);
+#endif
+
 /**
  * Gets the `data` field within the context.
  */
@@ -594,6 +877,19 @@ const wasmer_instance_context_t *wasmer_instance_context_get(wasmer_instance_t *
 const wasmer_memory_t *wasmer_instance_context_memory(const wasmer_instance_context_t *ctx,
                                                       uint32_t _memory_idx);
 
+#if defined(WASMER_METERING)
+/**
+ * Sets the points limit of the instance of `ctx`, and returns the limit it replaces.
+ *
+ * This is meant for imported functions running code under a tighter limit, then restoring the
+ * outer limit.
+ *
+ * Returns 0 and does nothing if the context is null.
+ */
+uint64_t wasmer_instance_context_swap_execution_limit(wasmer_instance_context_t *ctx,
+                                                      uint64_t new_limit);
+#endif
+
 /**
  * Frees memory for the given Instance
  */
@@ -606,6 +902,197 @@ void wasmer_instance_destroy(wasmer_instance_t *instance);
  */
 void wasmer_instance_exports(wasmer_instance_t *instance, wasmer_exports_t **exports);
 
+#if defined(WASMER_METERING)
+/**
+ * Returns the points limit of the instance.
+ *
+ * Returns 0 if the instance is null.
+ */
+uint64_t wasmer_instance_get_execution_limit(const wasmer_instance_t *instance);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the points by which the last call on the instance exceeding its execution limit
+ * exceeded it.
+ *
+ * The overrun is recorded by the metering of the instance, so it does not depend on the message
+ * set with `wasmer_set_gas_exhaustion_message`.
+ *
+ * Returns 0 if no call exceeded the limit yet, or if the instance is null.
+ */
+uint64_t wasmer_instance_get_gas_overrun(const wasmer_instance_t *instance);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the points charged to the instance by `wasmer_instance_charge_instantiation`, which
+ * are part of its points used.
+ *
+ * Returns 0 if the instance is null.
+ */
+uint64_t wasmer_instance_get_instantiation_cost(const wasmer_instance_t *instance);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the number of points used by the last call to an exported function of the instance,
+ * including the calls it made in turn.
+ *
+ * Returns 0 if the instance is null.
+ */
+uint64_t wasmer_instance_get_last_call_cost(const wasmer_instance_t *instance);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Writes the number of points used by the instance to `used_out`, and its points limit to
+ * `limit_out`, as `wasmer_instance_get_points_used` and `wasmer_instance_get_execution_limit`
+ * return them.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` if the instance or an output pointer is null, without
+ * writing anything. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an
+ * error message.
+ */
+wasmer_result_t wasmer_instance_get_metering_state(const wasmer_instance_t *instance,
+                                                   uint64_t *used_out,
+                                                   uint64_t *limit_out);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the number of points used by the instance.
+ *
+ * Returns 0 if the instance is null.
+ */
+uint64_t wasmer_instance_get_points_used(const wasmer_instance_t *instance);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Stores the serialized opcode histogram of the instance into `buffer`, of `buffer_len` bytes,
+ * so that it can be restored with `wasmer_instance_restore_opcode_histogram`. The histogram is
+ * kept by the modules compiled with the opcode profiler.
+ *
+ * `*written` is set to the length of the serialized histogram, including when the buffer is
+ * too small to hold it, so that a buffer of the right size can be allocated. `buffer` may be
+ * null if `buffer_len` is 0.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the buffer is too small.
+ * Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_instance_opcode_histogram(const wasmer_instance_t *instance,
+                                                 uint8_t *buffer,
+                                                 uint32_t buffer_len,
+                                                 uint32_t *written);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Resets every metering counter of the instance at once: the points used, the cost of the last
+ * call, the category and surcharge breakdowns, the instantiation cost, the gas overrun and the
+ * running functions. The execution limit and the cost table are kept.
+ *
+ * See `metering::reset_all` for the full list. Does nothing if the instance is null.
+ */
+void wasmer_instance_reset_all_metering(wasmer_instance_t *instance);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Zeroes the opcode histogram of the instance, so that it only counts the operators executed
+ * from now on. The histogram is kept by the modules compiled with the opcode profiler.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` if the instance is null. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_instance_reset_opcode_histogram(wasmer_instance_t *instance);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Replaces the opcode histogram of the instance with the one serialized in `bytes`, of
+ * `bytes_len` bytes, by `wasmer_instance_opcode_histogram`, so that the operators executed from
+ * now on are added to it.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the bytes are not a
+ * serialized histogram. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an
+ * error message.
+ */
+wasmer_result_t wasmer_instance_restore_opcode_histogram(wasmer_instance_t *instance,
+                                                         const uint8_t *bytes,
+                                                         uint32_t bytes_len);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the points limit of the instance. Calls trap once the points used reach the limit.
+ *
+ * Does nothing if the instance is null.
+ */
+void wasmer_instance_set_execution_limit(wasmer_instance_t *instance, uint64_t limit);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the points limit of the instance, as `wasmer_instance_set_execution_limit` does.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` if the instance is null. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_instance_set_execution_limit_checked(wasmer_instance_t *instance,
+                                                            uint64_t limit);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the number of points used by the instance.
+ *
+ * Does nothing if the instance is null.
+ */
+void wasmer_instance_set_points_used(wasmer_instance_t *instance, uint64_t new_gas);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the number of points used by the instance, as `wasmer_instance_set_points_used` does.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` if the instance is null. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_instance_set_points_used_checked(wasmer_instance_t *instance,
+                                                        uint64_t new_gas);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the points limit of the instance to a time budget of `millis` milliseconds, at a rate of
+ * `points_per_ms` points per millisecond, see `metering::set_time_limit`.
+ *
+ * The limit is approximate, as points price operators rather than time.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` if the instance is null. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_instance_set_time_limit(wasmer_instance_t *instance,
+                                               uint64_t millis,
+                                               uint64_t points_per_ms);
+#endif
+
 /**
  * Creates a new Instance from the given wasm bytes and imports.
  *
@@ -620,6 +1107,28 @@ wasmer_result_t wasmer_instantiate(wasmer_instance_t **instance,
                                    wasmer_import_t *imports,
                                    int imports_len);
 
+#if defined(WASMER_METERING)
+/**
+ * Creates a new Instance from the given wasm bytes and imports, with gas metering and a points
+ * limit of `limit`.
+ *
+ * This compiles the bytes as `wasmer_compile_with_gas_metering` does, instantiates the module
+ * as `wasmer_module_instantiate` does and sets the limit of the instance, for one-shot
+ * executions that do not need the module.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_instantiate_with_gas_metering(wasmer_instance_t **instance,
+                                                     uint8_t *wasm_bytes,
+                                                     uint32_t wasm_bytes_len,
+                                                     wasmer_import_t *imports,
+                                                     int imports_len,
+                                                     uint64_t limit);
+#endif
+
 /**
  * Gets the length in bytes of the last error.
  * This can be used to dynamically allocate a buffer with the correct number of
@@ -695,6 +1204,109 @@ uint32_t wasmer_memory_length(const wasmer_memory_t *memory);
  */
 wasmer_result_t wasmer_memory_new(wasmer_memory_t **memory, wasmer_limits_t limits);
 
+#if defined(WASMER_METERING)
+/**
+ * Frees memory for the given streaming compiler.
+ */
+void wasmer_metered_streaming_compiler_destroy(wasmer_metered_streaming_compiler_t *compiler);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Feeds the next `chunk_len` bytes of the module to the streaming compiler.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure. Use `wasmer_last_error_length`
+ * and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_metered_streaming_compiler_feed(wasmer_metered_streaming_compiler_t *compiler,
+                                                       const uint8_t *chunk,
+                                                       uint32_t chunk_len);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Compiles the bytes fed to the streaming compiler into a new Module, with gas metering.
+ *
+ * The function bodies are only compiled once the whole module has been fed, as the compiler
+ * parses a module in one pass. Finalizing before the end of the module fails without
+ * discarding the bytes fed so far, so more can be fed before finalizing again. The compiler
+ * must still be freed with `wasmer_metered_streaming_compiler_destroy`.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module is
+ * incomplete. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an error
+ * message.
+ */
+wasmer_result_t wasmer_metered_streaming_compiler_finalize(wasmer_metered_streaming_compiler_t *compiler,
+                                                           wasmer_module_t **module);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Creates a new streaming compiler, compiling the bytes fed to it with gas metering as
+ * `wasmer_compile_with_gas_metering` does.
+ *
+ * The compiler must be freed with `wasmer_metered_streaming_compiler_destroy`.
+ */
+wasmer_metered_streaming_compiler_t *wasmer_metered_streaming_compiler_new(void);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the current version of the metering instrumentation, the version of the modules
+ * compiled with gas metering.
+ */
+uint32_t wasmer_metering_abi_version(void);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the backend compiling metered modules: the one set with
+ * `wasmer_set_metering_backend`, or else the first compiled-in backend among singlepass and
+ * LLVM, in that order.
+ */
+wasmer_metering_backend_t wasmer_metering_backend(void);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns true if `backend` is compiled in, so that it can be set with
+ * `wasmer_set_metering_backend`.
+ */
+bool wasmer_metering_backend_available(wasmer_metering_backend_t backend);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Stores the metadata of the cost table of a module compiled with
+ * `wasmer_compile_with_cost_table` into the provided buffer, followed by a trailing null. On
+ * success, `*written` is set to the length of the metadata, without the trailing null.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module has no cost
+ * table or the buffer is too small to hold the metadata and the trailing null. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_module_cost_table_metadata(const wasmer_module_t *module,
+                                                  char *buffer,
+                                                  uint32_t buffer_len,
+                                                  uint32_t *written);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns true if both modules were compiled with `wasmer_compile_with_cost_table` and equal
+ * cost tables.
+ *
+ * Returns false if either module is null or has no cost table.
+ */
+bool wasmer_module_cost_tables_equal(const wasmer_module_t *a, const wasmer_module_t *b);
+#endif
+
 /**
  * Deserialize the given serialized module.
  *
@@ -711,6 +1323,45 @@ wasmer_result_t wasmer_module_deserialize(wasmer_module_t **module,
  */
 void wasmer_module_destroy(wasmer_module_t *module);
 
+#if defined(WASMER_METERING)
+/**
+ * Stores the names and the function indices of the exported functions of the module, in the
+ * order of the module, into the provided arrays of `len` entries.
+ *
+ * The function indices count the imported functions, as the function indices reported by
+ * metering do. The names point into the module and are valid as long as it is.
+ *
+ * Returns the number of exported functions, which may be more than `len`, in which case only
+ * the first `len` are stored. The arrays may be null if `len` is 0, to get the number of
+ * exported functions first. Returns 0 if the module is null.
+ */
+uint32_t wasmer_module_exported_function_names(const wasmer_module_t *module,
+                                               wasmer_byte_array *names,
+                                               uint32_t *func_indices,
+                                               uint32_t len);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Stores into `count_out` the number of checkpoints of the function `func_index` of the module,
+ * counting the imported functions.
+ *
+ * A checkpoint is a point where the metered code adds the points of the straight-line code
+ * before it to the points used, so the count is a measure of the overhead of metering the
+ * function. It is recorded by the functions recording a fingerprint, see
+ * `wasmer_module_metered_fingerprint`.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module has no
+ * checkpoint counts or does not define the function, such as an imported function. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_module_function_checkpoint_count(const wasmer_module_t *module,
+                                                        uint32_t func_index,
+                                                        uint32_t *count_out);
+#endif
+
 /**
  * Given:
  * * A prepared `wasmer` import-object
@@ -735,6 +1386,55 @@ wasmer_result_t wasmer_module_instantiate(const wasmer_module_t *module,
                                           wasmer_import_t *imports,
                                           int imports_len);
 
+#if defined(WASMER_METERING)
+/**
+ * Stores the 32-byte fingerprint of the module into `out`.
+ *
+ * The fingerprint is a hash of the bytes the module was compiled from and of the cost table it
+ * is metered with, so that modules with equal fingerprints charge the same points. It is only
+ * recorded by `wasmer_compile_with_gas_metering`, `wasmer_compile_with_gas_metering_timeout`,
+ * `wasmer_compile_with_gas_metering_progress`, `wasmer_compile_with_cost_table` and the metered
+ * streaming compiler, which all price every operator at one point unless given a table.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module has no
+ * fingerprint. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an error
+ * message.
+ */
+wasmer_result_t wasmer_module_metered_fingerprint(const wasmer_module_t *module, uint8_t *out);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Returns the version of the metering instrumentation the module was compiled with, or 0 if
+ * the module was not compiled with gas metering or is null.
+ *
+ * The version is recorded by the functions recording a fingerprint, see
+ * `wasmer_module_metered_fingerprint`. A host can refuse modules compiled with another
+ * version than `wasmer_metering_abi_version`.
+ */
+uint32_t wasmer_module_metering_abi_version(const wasmer_module_t *module);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets `*cost` to the cost of the operators of the given opcode index in the cost table of a
+ * module compiled with `wasmer_compile_with_cost_table`.
+ *
+ * The opcode indices are the ones of `wasmer_cost_table_set_cost`. The cost is read from the
+ * table embedded in the module, without the special cases of metering.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the module has no cost
+ * table. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_module_opcode_cost(const wasmer_module_t *module,
+                                          uint32_t opcode_index,
+                                          uint64_t *cost);
+#endif
+
 /**
  * Serialize the given Module.
  *
@@ -748,6 +1448,45 @@ wasmer_result_t wasmer_module_instantiate(const wasmer_module_t *module,
 wasmer_result_t wasmer_module_serialize(wasmer_serialized_module_t **serialized_module,
                                         const wasmer_module_t *module);
 
+#if defined(WASMER_METERING)
+/**
+ * Stores the name of the operators of the given opcode index, such as `i32.add`, into the
+ * provided buffer, followed by a trailing null.
+ *
+ * The opcode indices are the ones of `wasmer_cost_table_set_cost`. On success, `*written` is
+ * set to the length of the name, without the trailing null.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the buffer is too small
+ * to hold the name and the trailing null. Use `wasmer_last_error_length` and
+ * `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_opcode_name(uint32_t opcode_index,
+                                   char *buffer,
+                                   uint32_t buffer_len,
+                                   uint32_t *written);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets `*cost_out` to the summed cost under the table of the operators encoded in
+ * `opcode_bytes`, as in a function body without its local declarations.
+ *
+ * The operators are decoded, not validated, so the sequence need not be a whole body.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the bytes are not a
+ * sequence of operators. Use `wasmer_last_error_length` and `wasmer_last_error_message` to get
+ * an error message.
+ */
+wasmer_result_t wasmer_price_opcodes(const wasmer_cost_table_t *table,
+                                     const uint8_t *opcode_bytes,
+                                     uint32_t opcode_bytes_len,
+                                     uint64_t *cost_out);
+#endif
+
 /**
  * Get bytes of the serialized module.
  */
@@ -772,6 +1511,73 @@ wasmer_result_t wasmer_serialized_module_from_bytes(wasmer_serialized_module_t *
                                                     const uint8_t *serialized_module_bytes,
                                                     uint32_t serialized_module_bytes_length);
 
+#if defined(WASMER_METERING)
+/**
+ * Sets the error message of the calls that exceed the execution limit of their instance, for
+ * all the instances of the process.
+ *
+ * The message is copied. Passing a null pointer restores the default message.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` if the message is not valid UTF-8. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_set_gas_exhaustion_message(const uint8_t *bytes, uint32_t bytes_len);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the backend compiling metered modules, for all the compilations of the process, in place
+ * of the default one returned by `wasmer_metering_backend`.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` if the backend is not compiled in. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_set_metering_backend(wasmer_metering_backend_t backend);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Sets the callback told of the metering events of all the instances of the process, or
+ * removes it if `callback` is null. No callback is set by default.
+ *
+ * `callback` is given the kind of the event, the instance, a payload whose meaning depends on
+ * the kind, see `wasmer_metering_event_t`, and `user_data`. It is called on the thread causing
+ * the event, before the function causing it returns: the calls with `wasmer_instance_call` and
+ * `wasmer_export_func_call`, and the setters of the points used and of the limit of an
+ * instance.
+ */
+void wasmer_set_metering_log_callback(void (*callback)(wasmer_metering_event_t event, const wasmer_instance_t *instance, uint64_t payload, void *user_data),
+                                      void *user_data);
+#endif
+
+#if defined(WASMER_METERING)
+/**
+ * Calls the exported function `name` of the instance with the provided parameters and no
+ * execution limit, and sets `*gas_out` to the points the call used.
+ *
+ * This is meant to learn the cost of a call before charging for it. The points used and the
+ * limit of the instance are restored afterwards, but the call is otherwise a real call: its
+ * writes to memories, tables and globals happen, and its imported functions run. Calling the
+ * function again on the same state uses the same points, provided it is deterministic. The
+ * results of the call are discarded, and `wasmer_instance_get_last_call_cost` is not meaningful
+ * until the next call.
+ *
+ * Returns `wasmer_result_t::WASMER_OK` upon success.
+ *
+ * Returns `wasmer_result_t::WASMER_ERROR` upon failure, including when the call traps. Use
+ * `wasmer_last_error_length` and `wasmer_last_error_message` to get an error message.
+ */
+wasmer_result_t wasmer_simulate_gas(wasmer_instance_t *instance,
+                                    const char *name,
+                                    const wasmer_value_t *params,
+                                    uint32_t params_len,
+                                    uint64_t *gas_out);
+#endif
+
 /**
  * Frees memory for the given Table
  */
@@ -8,6 +8,7 @@ use crate::{
     module::{ModuleInfo, ModuleInner},
     structures::Map,
     types::{FuncIndex, FuncSig, SigIndex},
+    vm,
 };
 use smallvec::SmallVec;
 use std::any::Any;
@@ -67,6 +68,24 @@ impl fmt::Debug for InternalEvent {
 pub struct BreakpointInfo<'a> {
     /// Fault.
     pub fault: Option<&'a dyn Any>,
+    ctx: Option<&'a mut vm::Ctx>,
+}
+
+impl<'a> BreakpointInfo<'a> {
+    /// Creates the information for a breakpoint hit with `fault`, by the instance of `ctx` if
+    /// the backend can provide it.
+    pub fn new(fault: Option<&'a dyn Any>, ctx: Option<&'a mut vm::Ctx>) -> BreakpointInfo<'a> {
+        BreakpointInfo { fault, ctx }
+    }
+
+    /// Returns the `Ctx` of the instance that hit the breakpoint.
+    ///
+    /// Singlepass reads it from the code that hit the breakpoint. LLVM gives the `Ctx` of the
+    /// instance the host called into instead, which differs when the breakpoint is hit in a
+    /// function of another instance called through an import.
+    pub fn ctx(&mut self) -> Option<&mut vm::Ctx> {
+        self.ctx.as_mut().map(|ctx| &mut **ctx)
+    }
 }
 
 /// A trait that represents the functions needed to be implemented to generate code for a module.
@@ -312,6 +331,26 @@ impl MiddlewareChain {
 
         Ok(())
     }
+
+    /// Feeds the declaration of `n` locals of type `ty` to each middleware of this chain.
+    pub(crate) fn feed_local(
+        &mut self,
+        ty: WpType,
+        n: usize,
+        module_info: &ModuleInfo,
+    ) -> Result<(), String> {
+        for m in &mut self.chain {
+            m.feed_local(ty, n, module_info)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_precondition(&mut self, module_info: &ModuleInfo) -> Result<(), String> {
+        for m in &mut self.chain {
+            m.check_precondition(module_info)?;
+        }
+        Ok(())
+    }
 }
 
 /// A trait that represents the signature required to implement middleware for a function.
@@ -325,6 +364,23 @@ pub trait FunctionMiddleware {
         module_info: &ModuleInfo,
         sink: &mut EventSink<'a, 'b>,
     ) -> Result<(), Self::Error>;
+
+    /// Processes the declaration of `n` locals of type `ty` of the next function, before its
+    /// `FunctionBegin` event.
+    fn feed_local(
+        &mut self,
+        _ty: WpType,
+        _n: usize,
+        _module_info: &ModuleInfo,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Checks the module once its sections before the code section are read, before the events
+    /// of its first function, or at the end of a module defining no function.
+    fn check_precondition(&mut self, _module_info: &ModuleInfo) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 pub(crate) trait GenericFunctionMiddleware {
@@ -334,6 +390,10 @@ pub(crate) trait GenericFunctionMiddleware {
         module_info: &ModuleInfo,
         sink: &mut EventSink<'a, 'b>,
     ) -> Result<(), String>;
+
+    fn feed_local(&mut self, ty: WpType, n: usize, module_info: &ModuleInfo) -> Result<(), String>;
+
+    fn check_precondition(&mut self, module_info: &ModuleInfo) -> Result<(), String>;
 }
 
 impl<E: Debug, T: FunctionMiddleware<Error = E>> GenericFunctionMiddleware for T {
@@ -346,6 +406,16 @@ impl<E: Debug, T: FunctionMiddleware<Error = E>> GenericFunctionMiddleware for T
         <Self as FunctionMiddleware>::feed_event(self, op, module_info, sink)
             .map_err(|x| format!("{:?}", x))
     }
+
+    fn feed_local(&mut self, ty: WpType, n: usize, module_info: &ModuleInfo) -> Result<(), String> {
+        <Self as FunctionMiddleware>::feed_local(self, ty, n, module_info)
+            .map_err(|x| format!("{:?}", x))
+    }
+
+    fn check_precondition(&mut self, module_info: &ModuleInfo) -> Result<(), String> {
+        <Self as FunctionMiddleware>::check_precondition(self, module_info)
+            .map_err(|x| format!("{:?}", x))
+    }
 }
 
 /// The function-scope code generator trait.
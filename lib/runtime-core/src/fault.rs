@@ -115,6 +115,11 @@ pub unsafe fn with_ctx<R, F: FnOnce() -> R>(ctx: *mut vm::Ctx, cb: F) -> R {
     ret
 }
 
+/// Gets the `Ctx` of the instance the host called into on this thread, if any.
+pub unsafe fn get_current_ctx<'a>() -> Option<&'a mut vm::Ctx> {
+    (*CURRENT_CTX.with(|x| x.get())).as_mut()
+}
+
 /// Pushes a new `CodeVersion` to the current code versions.
 pub fn push_code_version(version: CodeVersion) {
     CURRENT_CODE_VERSIONS.with(|x| x.borrow_mut().push(version));
@@ -260,9 +265,12 @@ extern "C" fn signal_trap_handler(
                     // breakpoint
                     let out: Option<Result<(), Box<dyn Any>>> = with_breakpoint_map(|bkpt_map| {
                         bkpt_map.and_then(|x| x.get(&(fault.ip as usize))).map(|x| {
-                            x(BreakpointInfo {
-                                fault: Some(&fault),
-                            })
+                            // Singlepass keeps the `Ctx` of the running function in its vmctx
+                            // register, `r15`.
+                            let ctx = fault.known_registers
+                                [X64Register::GPR(GPR::R15).to_index().0]
+                                .and_then(|ctx| (ctx as *mut vm::Ctx).as_mut());
+                            x(BreakpointInfo::new(Some(&fault), ctx))
                         })
                     });
                     match out {
@@ -676,16 +676,18 @@ pub(crate) fn call_func_with_index_inner(
         let mut trap_info = WasmTrapInfo::Unknown;
         let mut user_error = None;
 
-        let success = invoke(
-            trampoline,
-            ctx_ptr,
-            func_ptr,
-            raw_args.as_ptr(),
-            result_space,
-            &mut trap_info,
-            &mut user_error,
-            invoke_env,
-        );
+        let success = vm::with_current_ctx(ctx_ptr, || {
+            invoke(
+                trampoline,
+                ctx_ptr,
+                func_ptr,
+                raw_args.as_ptr(),
+                result_space,
+                &mut trap_info,
+                &mut user_error,
+                invoke_env,
+            )
+        });
 
         if success {
             Ok(())
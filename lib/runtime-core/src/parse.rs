@@ -220,6 +220,9 @@ pub fn read_module<
                         .map_err(|x| LoadError::Codegen(format!("{:?}", x)))?;
                     mcg.check_precondition(&info.read().unwrap())
                         .map_err(|x| LoadError::Codegen(format!("{:?}", x)))?;
+                    middlewares
+                        .check_precondition(&info.read().unwrap())
+                        .map_err(|x| LoadError::Codegen(x))?;
                 }
 
                 let fcg = mcg
@@ -259,6 +262,9 @@ pub fn read_module<
                             for &(count, ty) in locals.iter() {
                                 fcg.feed_local(ty, count as usize)
                                     .map_err(|x| LoadError::Codegen(format!("{:?}", x)))?;
+                                middlewares
+                                    .feed_local(ty, count as usize, &info.read().unwrap())
+                                    .map_err(|x| LoadError::Codegen(x))?;
                             }
                         }
                         ParserState::CodeOperator(op) => {
@@ -393,6 +399,9 @@ pub fn read_module<
                         .map_err(|x| LoadError::Codegen(format!("{:?}", x)))?;
                     mcg.check_precondition(&info.read().unwrap())
                         .map_err(|x| LoadError::Codegen(format!("{:?}", x)))?;
+                    middlewares
+                        .check_precondition(&info.read().unwrap())
+                        .map_err(|x| LoadError::Codegen(x))?;
                 }
                 break;
             }
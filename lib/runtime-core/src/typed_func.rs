@@ -404,7 +404,7 @@ macro_rules! impl_traits {
                 let mut trap = WasmTrapInfo::Unknown;
                 let mut user_error = None;
 
-                if (wasm.invoke)(
+                let success = vm::with_current_ctx(ctx, || (wasm.invoke)(
                     wasm.trampoline,
                     ctx,
                     f,
@@ -413,7 +413,9 @@ macro_rules! impl_traits {
                     &mut trap,
                     &mut user_error,
                     wasm.invoke_env
-                ) {
+                ));
+
+                if success {
                     Ok(Rets::from_ret_array(rets))
                 } else {
                     if let Some(data) = user_error {
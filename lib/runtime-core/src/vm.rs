@@ -65,6 +65,10 @@ pub struct Ctx {
     /// when the context is destructed, e.g. when an `Instance`
     /// is dropped.
     pub data_finalizer: Option<fn(data: *mut c_void)>,
+
+    /// The functions registered with `Ctx::add_finalizer`, called
+    /// when the context is destructed, after `data_finalizer`.
+    pub(crate) finalizers: Finalizers,
 }
 
 /// When an instance context is destructed, we're calling its `data_finalizer`
@@ -78,6 +82,19 @@ impl Drop for Ctx {
         if let Some(ref finalizer) = self.data_finalizer {
             finalizer(self.data);
         }
+        for finalizer in self.finalizers.0.drain(..) {
+            finalizer();
+        }
+    }
+}
+
+/// The functions called when an instance context is destructed.
+#[derive(Default)]
+pub(crate) struct Finalizers(Vec<Box<dyn FnOnce()>>);
+
+impl std::fmt::Debug for Finalizers {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "Finalizers({})", self.0.len())
     }
 }
 
@@ -173,6 +190,21 @@ impl InternalField {
     }
 }
 
+/// Runs `f` with `ctx` recorded as the context of the instance the host called into on this
+/// thread, so that the breakpoint handlers of the backends that cannot tell the context of the
+/// code hitting a breakpoint can reach it.
+pub(crate) unsafe fn with_current_ctx<R, F: FnOnce() -> R>(ctx: *mut Ctx, f: F) -> R {
+    #[cfg(all(unix, target_arch = "x86_64"))]
+    {
+        crate::fault::with_ctx(ctx, f)
+    }
+    #[cfg(not(all(unix, target_arch = "x86_64")))]
+    {
+        let _ = ctx;
+        f()
+    }
+}
+
 /// A container for VM instrinsic functions
 #[repr(C)]
 pub struct Intrinsics {
@@ -312,6 +344,7 @@ impl Ctx {
 
             data: ptr::null_mut(),
             data_finalizer: None,
+            finalizers: Finalizers::default(),
         }
     }
 
@@ -365,6 +398,7 @@ impl Ctx {
 
             data,
             data_finalizer: Some(data_finalizer),
+            finalizers: Finalizers::default(),
         }
     }
 
@@ -423,6 +457,16 @@ impl Ctx {
         }
     }
 
+    /// Registers `finalizer` to be called when the context is destructed,
+    /// e.g. when its `Instance` is dropped.
+    ///
+    /// Unlike `data_finalizer`, any number of finalizers can be registered,
+    /// so that the state kept elsewhere for an instance, such as by a
+    /// middleware, can be released with it.
+    pub fn add_finalizer<F: FnOnce() + 'static>(&mut self, finalizer: F) {
+        self.finalizers.0.push(Box::new(finalizer));
+    }
+
     /// Calls a host or Wasm function at the given table index
     pub fn call_with_table_index(
         &mut self,
@@ -1023,7 +1067,7 @@ mod vm_ctx_tests {
 
         let module = generate_module();
         let data_ptr = &mut data as *mut _ as *mut c_void;
-        let ctx = unsafe {
+        let mut ctx = unsafe {
             Ctx::new_with_data(
                 &mut local_backing,
                 &mut import_backing,
@@ -1038,7 +1082,13 @@ mod vm_ctx_tests {
         assert_eq!(true, ctx_test_data.y);
         assert_eq!("Test".to_string(), ctx_test_data.str);
 
+        let finalized = std::rc::Rc::new(std::cell::Cell::new(0));
+        for _ in 0..2 {
+            let finalized = finalized.clone();
+            ctx.add_finalizer(move || finalized.set(finalized.get() + 1));
+        }
         drop(ctx);
+        assert_eq!(finalized.get(), 2);
     }
 
     fn cast_test_data(data: *mut c_void) -> &'static mut TestData {